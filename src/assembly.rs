@@ -0,0 +1,525 @@
+use crate::message::SyncMessage;
+use crate::message::SyncMessageType;
+use crate::Data;
+use gnome::prelude::sha_hash;
+use gnome::prelude::GnomeId;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+// SyncMessages that do not fit into a single SyncData are split
+// into a header part and up to seven following parts (see SyncMessage::into_parts).
+// Every part of such message starts with:
+// 1-5 bytes - SyncMessageType
+// 1 byte    - part_no, 0 for header
+// 1 byte    - total_parts, not counting header
+// 8 bytes   - message hash
+// Header additionally lists hashes of all following parts, in order.
+//
+// Parts can arrive in any order, from different Gnomes and more than once,
+// so we collect them under their message hash.
+// Parts received before their header can not be verified, once
+// header arrives those not matching it's hashes are dropped.
+// Header is signed by message's originator, so only a header received
+// from Swarm (not from a Neighbor during sync) can complete a message,
+// and it's signer is reported as message's sender.
+// A header from a Neighbor might have been forged, so when a different one
+// arrives from Swarm it replaces it and parts are checked again.
+//
+// Assemblies that made no progress for a while are reported by audit,
+// so that we can ask Neighbors to re-post missing parts.
+// Every Gnome keeps parts of recently completed messages, so that
+// it can re-post them and also to ignore those parts when they arrive again.
+// Assemblies that are too old or do not fit into memory budget are evicted,
+// oldest first.
+const PARTIAL_TTL: Duration = Duration::from_secs(300);
+const ASK_AFTER: Duration = Duration::from_secs(15);
+const MAX_PARTIAL_BYTES: usize = 512 * 1024;
+const MAX_COMPLETED: usize = 64;
+
+struct Assembly {
+    total_parts: u8,
+    // Header part and it's signer, None when received from a Neighbor
+    header: Option<(Data, Option<GnomeId>)>,
+    // Hashes of following parts, known once header arrives
+    part_hashes: Vec<u64>,
+    parts: HashMap<u8, Data>,
+    bytes: usize,
+    started: Instant,
+    last_progress: Instant,
+    asked: Option<Instant>,
+}
+
+impl Assembly {
+    fn new(total_parts: u8, now: Instant) -> Self {
+        Assembly {
+            total_parts,
+            header: None,
+            part_hashes: Vec::with_capacity(total_parts as usize),
+            parts: HashMap::with_capacity(total_parts as usize),
+            bytes: 0,
+            started: now,
+            last_progress: now,
+            asked: None,
+        }
+    }
+
+    fn missing(&self) -> Vec<u8> {
+        (1..=self.total_parts)
+            .filter(|part_no| !self.parts.contains_key(part_no))
+            .collect()
+    }
+
+    fn is_complete(&self) -> bool {
+        matches!(self.header, Some((_, Some(_)))) && self.missing().is_empty()
+    }
+}
+
+pub struct Assembler {
+    partials: HashMap<u64, Assembly>,
+    // Message hash and all parts of recently completed messages
+    completed: VecDeque<(u64, Vec<Data>)>,
+    bytes: usize,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Assembler {
+            partials: HashMap::new(),
+            completed: VecDeque::with_capacity(MAX_COMPLETED),
+            bytes: 0,
+        }
+    }
+
+    /// Add a part received from Swarm (signed_by is Some) or from a Neighbor.
+    /// Returns assembled SyncMessage with it's originator once all parts are present.
+    pub fn insert(
+        &mut self,
+        data: Data,
+        signed_by: Option<GnomeId>,
+    ) -> Option<(SyncMessage, GnomeId)> {
+        let now = Instant::now();
+        let (part_no, total_parts, msg_hash) = part_info(data.ref_bytes())?;
+        if total_parts == 0 {
+            let originator = signed_by?;
+            let mut hm = HashMap::new();
+            hm.insert(0, data);
+            return SyncMessage::from_data(vec![0], hm)
+                .ok()
+                .map(|msg| (msg, originator));
+        }
+        if part_no > total_parts {
+            eprintln!("Part {} of {} is out of range", part_no, total_parts);
+            return None;
+        }
+        if self
+            .completed
+            .iter()
+            .any(|(hash, _parts)| *hash == msg_hash)
+        {
+            // eprintln!("Part {} of completed message {}", part_no, msg_hash);
+            return None;
+        }
+        let assembly = self
+            .partials
+            .entry(msg_hash)
+            .or_insert_with(|| Assembly::new(total_parts, now));
+        if assembly.total_parts != total_parts {
+            eprintln!("Message {} parts count mismatch", msg_hash);
+            return None;
+        }
+        let d_len = data.len();
+        if part_no == 0 {
+            let replace = match assembly.header.as_mut() {
+                // Only a header from Swarm can replace one from a Neighbor
+                Some((_header, Some(_signer))) => return None,
+                Some((_header, None)) if signed_by.is_none() => return None,
+                Some((header, signer)) => {
+                    if header.get_hash() == data.get_hash() {
+                        // Same header, this time from Swarm
+                        *signer = signed_by;
+                        false
+                    } else {
+                        eprintln!("Replacing unsigned header of message {}", msg_hash);
+                        true
+                    }
+                }
+                None => true,
+            };
+            if replace {
+                assembly.part_hashes = header_hashes(data.ref_bytes(), total_parts)?;
+                if let Some((old_header, _signer)) = assembly.header.take() {
+                    assembly.bytes -= old_header.len();
+                    self.bytes -= old_header.len();
+                }
+                let hashes = &assembly.part_hashes;
+                let before = assembly.parts.len();
+                let mut dropped = 0;
+                assembly.parts.retain(|p_no, part| {
+                    let keep = hashes[*p_no as usize - 1] == part.get_hash();
+                    if !keep {
+                        dropped += part.len();
+                    }
+                    keep
+                });
+                if assembly.parts.len() < before {
+                    eprintln!(
+                        "Dropped {} parts not matching header",
+                        before - assembly.parts.len()
+                    );
+                }
+                assembly.bytes -= dropped;
+                self.bytes -= dropped;
+                assembly.header = Some((data, signed_by));
+                assembly.bytes += d_len;
+                self.bytes += d_len;
+            }
+        } else {
+            if assembly.parts.contains_key(&part_no) {
+                return None;
+            }
+            if !assembly.part_hashes.is_empty()
+                && assembly.part_hashes[part_no as usize - 1] != data.get_hash()
+            {
+                eprintln!("Part {} does not match message {}", part_no, msg_hash);
+                return None;
+            }
+            assembly.parts.insert(part_no, data);
+            assembly.bytes += d_len;
+            self.bytes += d_len;
+        }
+        assembly.last_progress = now;
+
+        if assembly.is_complete() {
+            let assembly = self.partials.remove(&msg_hash).unwrap();
+            self.bytes -= assembly.bytes;
+            return self.complete(msg_hash, assembly);
+        }
+        self.evict(now);
+        None
+    }
+
+    fn complete(
+        &mut self,
+        msg_hash: u64,
+        mut assembly: Assembly,
+    ) -> Option<(SyncMessage, GnomeId)> {
+        let (header, signer) = assembly.header.take()?;
+        let originator = signer?;
+        let mut idx = Vec::with_capacity(assembly.total_parts as usize + 1);
+        let mut hm = HashMap::with_capacity(assembly.total_parts as usize + 1);
+        let mut all_parts = Vec::with_capacity(assembly.total_parts as usize + 1);
+        idx.push(0);
+        all_parts.push(header.clone());
+        hm.insert(0, header);
+        for part_no in 1..=assembly.total_parts {
+            let part = assembly.parts.remove(&part_no)?;
+            let hash = assembly.part_hashes[part_no as usize - 1];
+            idx.push(hash);
+            all_parts.push(part.clone());
+            hm.insert(hash, part);
+        }
+        let Ok(msg) = SyncMessage::from_data(idx, hm) else {
+            eprintln!("Failed to assemble message {}", msg_hash);
+            return None;
+        };
+        // Only messages that were actually assembled can be re-posted
+        if self.completed.len() >= MAX_COMPLETED {
+            self.completed.pop_front();
+        }
+        self.completed.push_back((msg_hash, all_parts));
+        Some((msg, originator))
+    }
+
+    // Drop assemblies that are too old, then oldest ones
+    // until we fit into memory budget.
+    fn evict(&mut self, now: Instant) {
+        let expired: Vec<u64> = self
+            .partials
+            .iter()
+            .filter(|(_hash, asm)| now.duration_since(asm.started) > PARTIAL_TTL)
+            .map(|(hash, _asm)| *hash)
+            .collect();
+        for hash in expired {
+            eprintln!("Evicting expired partial message {}", hash);
+            let asm = self.partials.remove(&hash).unwrap();
+            self.bytes -= asm.bytes;
+        }
+        while self.bytes > MAX_PARTIAL_BYTES {
+            let Some(oldest) = self
+                .partials
+                .iter()
+                .min_by_key(|(_hash, asm)| asm.started)
+                .map(|(hash, _asm)| *hash)
+            else {
+                break;
+            };
+            eprintln!("Evicting partial message {} over memory budget", oldest);
+            let asm = self.partials.remove(&oldest).unwrap();
+            self.bytes -= asm.bytes;
+        }
+    }
+
+    /// Evict stale assemblies and list missing parts of those
+    /// that made no progress recently.
+    pub fn audit(&mut self) -> Vec<(u64, Vec<u8>)> {
+        let now = Instant::now();
+        self.evict(now);
+        let mut stalled = vec![];
+        for (hash, asm) in self.partials.iter_mut() {
+            // Only header's signer can be trusted, so we do not ask for it
+            if asm.header.is_none() || now.duration_since(asm.last_progress) < ASK_AFTER {
+                continue;
+            }
+            if asm
+                .asked
+                .is_some_and(|asked| now.duration_since(asked) < ASK_AFTER)
+            {
+                continue;
+            }
+            asm.asked = Some(now);
+            stalled.push((*hash, asm.missing()));
+        }
+        stalled
+    }
+
+    /// Parts of a completed message that can be re-posted to Swarm.
+    pub fn parts_for_repost(&self, msg_hash: u64, part_nos: &[u8]) -> Vec<Data> {
+        let Some((_hash, parts)) = self
+            .completed
+            .iter()
+            .find(|(hash, _parts)| *hash == msg_hash)
+        else {
+            return vec![];
+        };
+        part_nos
+            .iter()
+            .filter(|part_no| **part_no > 0)
+            .filter_map(|part_no| parts.get(*part_no as usize).cloned())
+            .collect()
+    }
+
+    pub fn headers(&self) -> Vec<Data> {
+        self.partials
+            .values()
+            .filter_map(|asm| asm.header.as_ref().map(|(header, _signer)| header.clone()))
+            .collect()
+    }
+
+    pub fn parts(&self) -> Vec<Data> {
+        self.partials
+            .values()
+            .flat_map(|asm| asm.parts.values().cloned())
+            .collect()
+    }
+}
+
+/// Decode a part of our own message that Swarm refused, without recording it,
+/// so that the same message can be sent again once Policy is met.
+/// Single part messages are decoded whole, for multi part ones only header
+/// is reported, as is, and remaining parts are ignored.
+pub fn refused_message(data: Data) -> Option<(SyncMessageType, Data)> {
+    let (part_no, total_parts, _msg_hash) = part_info(data.ref_bytes())?;
+    if total_parts == 0 {
+        let mut hm = HashMap::new();
+        hm.insert(0, data);
+        return SyncMessage::from_data(vec![0], hm)
+            .ok()
+            .map(|msg| (msg.m_type, msg.data));
+    }
+    if part_no > 0 {
+        return None;
+    }
+    let m_type = SyncMessageType::try_from(&mut data.ref_bytes().iter().copied())?;
+    Some((m_type, data))
+}
+
+/// Hash identifying a multi part message, shared by all it's parts.
+pub fn message_hash(m_type: &SyncMessageType, payload: &[u8]) -> u64 {
+    let mut bytes = m_type.as_bytes();
+    bytes.extend_from_slice(payload);
+    sha_hash(&bytes)
+}
+
+// Returns part_no, total_parts and message hash (0 for single part messages)
+fn part_info(bytes: &[u8]) -> Option<(u8, u8, u64)> {
+    let t_len = SyncMessageType::try_from(&mut bytes.iter().copied())?
+        .as_bytes()
+        .len();
+    let part_no = *bytes.get(t_len)?;
+    let total_parts = *bytes.get(t_len + 1)?;
+    if total_parts == 0 {
+        return Some((part_no, total_parts, 0));
+    }
+    let hash_bytes = bytes.get(t_len + 2..t_len + 10)?;
+    let msg_hash = u64::from_be_bytes(hash_bytes.try_into().unwrap());
+    Some((part_no, total_parts, msg_hash))
+}
+
+fn header_hashes(bytes: &[u8], total_parts: u8) -> Option<Vec<u64>> {
+    let start = SyncMessageType::try_from(&mut bytes.iter().copied())?
+        .as_bytes()
+        .len()
+        + 10;
+    let hash_bytes = bytes.get(start..start + 8 * total_parts as usize)?;
+    Some(
+        hash_bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_be_bytes(chunk.try_into().unwrap()))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::SyncRequirements;
+
+    fn parts(msg: SyncMessage) -> Vec<Data> {
+        msg.into_parts()
+            .into_iter()
+            .map(|part| Data::new(part.ref_bytes().to_vec()).unwrap())
+            .collect()
+    }
+
+    fn large_message() -> SyncMessage {
+        let requirements = SyncRequirements {
+            pre: (0..200).map(|c_id| (c_id, c_id as u64)).collect(),
+            post: vec![(1, 1)],
+        };
+        SyncMessage::new(
+            SyncMessageType::AppendData(1),
+            requirements,
+            Data::new(vec![5; 1000]).unwrap(),
+        )
+    }
+
+    #[test]
+    fn single_part_needs_a_signer() {
+        let msg = SyncMessage::new(
+            SyncMessageType::AppendData(3),
+            SyncRequirements {
+                pre: vec![],
+                post: vec![],
+            },
+            Data::new(b"small".to_vec()).unwrap(),
+        );
+        let part = parts(msg).pop().unwrap();
+        let mut assembler = Assembler::new();
+        assert!(assembler.insert(part.clone(), None).is_none());
+        let (msg, originator) = assembler.insert(part, Some(GnomeId(3))).unwrap();
+        assert_eq!(originator, GnomeId(3));
+        assert_eq!(msg.data.ref_bytes(), b"small");
+    }
+
+    #[test]
+    fn parts_are_assembled_in_any_order() {
+        let parts = parts(large_message());
+        assert!(parts.len() > 2);
+        let mut assembler = Assembler::new();
+        // Header from a Neighbor can not complete a message
+        assert!(assembler.insert(parts[0].clone(), None).is_none());
+        for part in parts.iter().skip(1).rev() {
+            assert!(assembler.insert(part.clone(), None).is_none());
+        }
+        assert_eq!(assembler.headers().len(), 1);
+        let (msg, originator) = assembler
+            .insert(parts[0].clone(), Some(GnomeId(9)))
+            .unwrap();
+        assert_eq!(originator, GnomeId(9));
+        assert_eq!(msg.requirements.pre.len(), 200);
+        assert_eq!(msg.data.ref_bytes(), &[5; 1000]);
+        assert!(assembler.headers().is_empty());
+        assert!(assembler.parts().is_empty());
+
+        // Parts of completed message are ignored and can be re-posted
+        assert!(assembler.insert(parts[1].clone(), None).is_none());
+        assert!(assembler.parts().is_empty());
+        let (_, _, msg_hash) = part_info(parts[0].ref_bytes()).unwrap();
+        let reposted = assembler.parts_for_repost(msg_hash, &[0, 2]);
+        assert_eq!(reposted, vec![parts[2].clone()]);
+    }
+
+    #[test]
+    fn parts_not_matching_header_are_dropped() {
+        let parts = parts(large_message());
+        let mut forged = parts[1].clone().bytes();
+        let last = forged.len() - 1;
+        forged[last] ^= 1;
+        let forged = Data::new(forged).unwrap();
+
+        // Arriving before header
+        let mut assembler = Assembler::new();
+        assert!(assembler.insert(forged.clone(), None).is_none());
+        assert_eq!(assembler.parts().len(), 1);
+        assert!(assembler
+            .insert(parts[0].clone(), Some(GnomeId(1)))
+            .is_none());
+        assert!(assembler.parts().is_empty());
+
+        // Arriving after header
+        assert!(assembler.insert(forged, None).is_none());
+        assert!(assembler.parts().is_empty());
+        for part in parts.iter().skip(2) {
+            assert!(assembler.insert(part.clone(), None).is_none());
+        }
+        assert!(assembler.insert(parts[1].clone(), None).is_some());
+    }
+
+    #[test]
+    fn refused_messages_are_decoded() {
+        let small = SyncMessage::new(
+            SyncMessageType::UpdateData(3, 4),
+            SyncRequirements {
+                pre: vec![],
+                post: vec![],
+            },
+            Data::new(b"small".to_vec()).unwrap(),
+        );
+        let (m_type, data) = refused_message(parts(small).pop().unwrap()).unwrap();
+        assert_eq!(
+            m_type.as_bytes(),
+            SyncMessageType::UpdateData(3, 4).as_bytes()
+        );
+        assert_eq!(data.ref_bytes(), b"small");
+
+        let parts = parts(large_message());
+        let (m_type, header) = refused_message(parts[0].clone()).unwrap();
+        assert_eq!(m_type.as_bytes(), SyncMessageType::AppendData(1).as_bytes());
+        assert_eq!(header, parts[0]);
+        assert!(refused_message(parts[1].clone()).is_none());
+        assert!(refused_message(Data::new(vec![253, 0]).unwrap()).is_none());
+    }
+
+    #[test]
+    fn swarm_header_replaces_forged_one() {
+        let parts = parts(large_message());
+        let mut forged = parts[0].clone().bytes();
+        let t_len = SyncMessageType::AppendData(1).as_bytes().len();
+        // Corrupt hash of first part
+        forged[t_len + 10] ^= 1;
+        let forged = Data::new(forged).unwrap();
+
+        let mut assembler = Assembler::new();
+        assert!(assembler.insert(forged.clone(), None).is_none());
+        for part in parts.iter().skip(1) {
+            assert!(assembler.insert(part.clone(), None).is_none());
+        }
+        assert_eq!(assembler.parts().len(), parts.len() - 2);
+        // Another unsigned header does not replace it
+        assert!(assembler.insert(parts[0].clone(), None).is_none());
+        assert_eq!(assembler.headers(), vec![forged.clone()]);
+
+        assert!(assembler
+            .insert(parts[0].clone(), Some(GnomeId(4)))
+            .is_none());
+        assert_eq!(assembler.headers(), vec![parts[0].clone()]);
+        assert_eq!(assembler.parts().len(), parts.len() - 2);
+        // Forged header is now rejected even from Swarm
+        assert!(assembler.insert(forged, Some(GnomeId(5))).is_none());
+        let (msg, originator) = assembler.insert(parts[1].clone(), None).unwrap();
+        assert_eq!(originator, GnomeId(4));
+        assert_eq!(msg.data.ref_bytes(), &[5; 1000]);
+    }
+}
@@ -0,0 +1,292 @@
+use crate::schema::DataSchema;
+use crate::schema::SchemaError;
+use gnome::prelude::Capabilities;
+use gnome::prelude::Requirement;
+use std::fmt;
+
+// Every Swarm can use up to MAX_AVAIL_APP_MSG_ID + 1 AppDefined message types.
+// Manifest holds a catalogue that names those messages, so that every App
+// synchronizing given Swarm knows what they mean.
+// Each declaration can also define what payload a message carries
+// and who is expected to send it.
+//
+// Once a catalogue is not empty, only declared messages are accepted.
+// Messages that are not declared, do not follow declared payload
+// or were signed by someone without declared Capability are not
+// pushed onto heap, instead App gets notified they were rejected.
+// An empty catalogue means every message is accepted, as before.
+//
+// Capabilities are checked against Manifest's capability registry.
+// Requirements depend on Swarm's running state and can not be enforced,
+// so they are refused when declaring and messages of such a declaration
+// are always rejected.
+//
+// Serialized declaration layout:
+// 1 byte  - message id
+// 1 byte  - name len + this many bytes of name
+// 1 byte  - sender kind: 0 - anyone, 1 - Capability, 2 - Requirement
+//           for Capability 1 more byte follows,
+//           for Requirement 2 bytes of len followed by Requirement bytes
+// 2 bytes - payload schema len (0 means no schema) + schema bytes
+#[derive(Clone, Debug)]
+pub enum MessageSender {
+    Anyone,
+    Capability(Capabilities),
+    Requirement(Requirement),
+}
+
+#[derive(Clone, Debug)]
+pub struct MessageDecl {
+    pub name: String,
+    pub sender: MessageSender,
+    // Payload is validated as if it was page 1, so no FirstPage layout applies
+    pub payload: Option<DataSchema>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CatalogueError {
+    Undeclared(u8),
+    NotAllowed(u8),
+    Payload(u8, SchemaError),
+}
+impl fmt::Display for CatalogueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Undeclared(m_type) => write!(f, "Message {} is not declared", m_type),
+            Self::NotAllowed(m_type) => write!(f, "Sender not allowed to send message {}", m_type),
+            Self::Payload(m_type, err) => write!(f, "Message {} payload: {}", m_type, err),
+        }
+    }
+}
+
+impl MessageDecl {
+    pub fn new(name: String) -> Self {
+        MessageDecl {
+            name,
+            sender: MessageSender::Anyone,
+            payload: None,
+        }
+    }
+
+    pub fn validate_payload(&self, m_type: u8, bytes: &[u8]) -> Result<(), CatalogueError> {
+        if let Some(schema) = &self.payload {
+            schema
+                .validate(1, bytes)
+                .map_err(|e| CatalogueError::Payload(m_type, e))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn append_bytes_to(&self, m_type: u8, bytes: &mut Vec<u8>) {
+        bytes.push(m_type);
+        let name = if self.name.len() > 255 {
+            // names are truncated on a char boundary
+            let mut end = 255;
+            while !self.name.is_char_boundary(end) {
+                end -= 1;
+            }
+            &self.name[..end]
+        } else {
+            &self.name[..]
+        };
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+        match &self.sender {
+            MessageSender::Anyone => bytes.push(0),
+            MessageSender::Capability(cap) => {
+                bytes.push(1);
+                bytes.push(cap.byte());
+            }
+            MessageSender::Requirement(req) => {
+                bytes.push(2);
+                let mut r_bytes = vec![];
+                req.append_bytes_to(&mut r_bytes);
+                bytes.extend_from_slice(&(r_bytes.len() as u16).to_be_bytes());
+                bytes.append(&mut r_bytes);
+            }
+        }
+        if let Some(schema) = &self.payload {
+            let mut s_bytes = vec![];
+            schema.append_bytes_to(m_type, &mut s_bytes);
+            bytes.extend_from_slice(&(s_bytes.len() as u16).to_be_bytes());
+            bytes.append(&mut s_bytes);
+        } else {
+            bytes.push(0);
+            bytes.push(0);
+        }
+    }
+
+    /// Returns message id with it's declaration.
+    pub fn from(bytes: &mut impl Iterator<Item = u8>) -> Option<(u8, Self)> {
+        let m_type = bytes.next()?;
+        let name_len = bytes.next()?;
+        let mut name_bytes = Vec::with_capacity(name_len as usize);
+        for _i in 0..name_len {
+            name_bytes.push(bytes.next()?);
+        }
+        let name = String::from_utf8(name_bytes).ok()?;
+        let sender = match bytes.next()? {
+            0 => MessageSender::Anyone,
+            1 => MessageSender::Capability(Capabilities::from(bytes.next()?)),
+            2 => {
+                let r_len = u16::from_be_bytes([bytes.next()?, bytes.next()?]);
+                let mut r_bytes = Vec::with_capacity(r_len as usize);
+                for _i in 0..r_len {
+                    r_bytes.push(bytes.next()?);
+                }
+                MessageSender::Requirement(Requirement::from(&mut r_bytes))
+            }
+            _other => return None,
+        };
+        let s_len = u16::from_be_bytes([bytes.next()?, bytes.next()?]);
+        let payload = if s_len == 0 {
+            None
+        } else {
+            let mut s_bytes = Vec::with_capacity(s_len as usize);
+            for _i in 0..s_len {
+                s_bytes.push(bytes.next()?);
+            }
+            let (_m_type, schema) = DataSchema::from(&mut s_bytes.into_iter())?;
+            Some(schema)
+        };
+        Some((
+            m_type,
+            MessageDecl {
+                name,
+                sender,
+                payload,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_type::AppType;
+    use crate::manifest::Manifest;
+    use crate::schema::Encoding;
+    use crate::schema::FieldSize;
+    use crate::schema::SchemaField;
+    use gnome::prelude::CapabiLeaf;
+    use gnome::prelude::GnomeId;
+    use std::collections::HashMap;
+
+    fn vote_schema() -> DataSchema {
+        DataSchema {
+            standard_first_page: false,
+            last_repeats: false,
+            fields: vec![SchemaField {
+                name: "c_id".to_string(),
+                encoding: Encoding::Unsigned,
+                size: FieldSize::Fixed(2),
+            }],
+        }
+    }
+
+    fn decl(name: &str, sender: MessageSender, payload: Option<DataSchema>) -> MessageDecl {
+        MessageDecl {
+            name: name.to_string(),
+            sender,
+            payload,
+        }
+    }
+
+    fn manifest() -> Manifest {
+        let mut manifest = Manifest::new(AppType::Forum, HashMap::new());
+        let mut admins = CapabiLeaf::create();
+        admins.insert(GnomeId(1));
+        manifest.capability_reg.insert(Capabilities::Admin, admins);
+        assert!(manifest.declare_message(
+            0,
+            Some(decl("vote", MessageSender::Anyone, Some(vote_schema())))
+        ));
+        assert!(manifest.declare_message(
+            1,
+            Some(decl(
+                "pin",
+                MessageSender::Capability(Capabilities::Admin),
+                None
+            ))
+        ));
+        manifest
+    }
+
+    #[test]
+    fn declarations_survive_bytes_round_trip() {
+        let decls = [
+            decl("vote", MessageSender::Anyone, Some(vote_schema())),
+            decl("pin", MessageSender::Capability(Capabilities::Admin), None),
+        ];
+        let mut bytes = vec![];
+        for (m_type, decl) in decls.iter().enumerate() {
+            decl.append_bytes_to(m_type as u8, &mut bytes);
+        }
+        let mut iter = bytes.into_iter();
+        let (m_type, vote) = MessageDecl::from(&mut iter).unwrap();
+        assert_eq!(m_type, 0);
+        assert_eq!(vote.name, "vote");
+        assert!(matches!(vote.sender, MessageSender::Anyone));
+        assert_eq!(vote.payload, Some(vote_schema()));
+        let (m_type, pin) = MessageDecl::from(&mut iter).unwrap();
+        assert_eq!(m_type, 1);
+        assert_eq!(pin.name, "pin");
+        let MessageSender::Capability(cap) = pin.sender else {
+            panic!("Expected Capability sender");
+        };
+        assert_eq!(cap.byte(), Capabilities::Admin.byte());
+        assert!(pin.payload.is_none());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn long_names_are_truncated_on_char_boundary() {
+        let name = "ż".repeat(200);
+        let mut bytes = vec![];
+        decl(&name, MessageSender::Anyone, None).append_bytes_to(0, &mut bytes);
+        let (_m_type, read) = MessageDecl::from(&mut bytes.into_iter()).unwrap();
+        assert_eq!(read.name, "ż".repeat(127));
+    }
+
+    #[test]
+    fn requirement_senders_can_not_be_declared() {
+        let mut manifest = manifest();
+        let sender = MessageSender::Requirement(Requirement::None);
+        assert!(!manifest.declare_message(2, Some(decl("poll", sender, None))));
+        assert!(manifest.message_decl(2).is_none());
+    }
+
+    #[test]
+    fn app_messages_are_checked_against_catalogue() {
+        // Empty catalogue accepts everything
+        let empty = Manifest::new(AppType::Forum, HashMap::new());
+        assert!(empty.check_app_msg(5, &[1], GnomeId(9)).is_ok());
+
+        let mut manifest = manifest();
+        assert_eq!(
+            manifest.check_app_msg(5, &[], GnomeId(1)),
+            Err(CatalogueError::Undeclared(5))
+        );
+        assert!(manifest.check_app_msg(0, &[0, 3], GnomeId(9)).is_ok());
+        assert!(matches!(
+            manifest.check_app_msg(0, &[0, 3, 4], GnomeId(9)),
+            Err(CatalogueError::Payload(0, _))
+        ));
+        assert!(manifest.check_app_msg(1, &[7], GnomeId(1)).is_ok());
+        assert_eq!(
+            manifest.check_app_msg(1, &[7], GnomeId(9)),
+            Err(CatalogueError::NotAllowed(1))
+        );
+
+        // Declarations from older versions can not be enforced
+        manifest.messages.insert(
+            2,
+            decl("poll", MessageSender::Requirement(Requirement::None), None),
+        );
+        assert_eq!(
+            manifest.check_app_msg(2, &[], GnomeId(1)),
+            Err(CatalogueError::NotAllowed(2))
+        );
+    }
+}
@@ -13,6 +13,10 @@ pub struct Configuration {
     pub work_dir: PathBuf,
     pub storage: PathBuf,
     pub search: PathBuf,
+    pub search_stemming: bool,
+    pub search_crawl_bandwidth: u64,
+    pub search_full_text: Vec<String>,
+    pub search_federated: bool,
     pub neighbors: Option<Vec<NetworkSettings>>,
     pub max_connected_swarms: u8,
     pub upload_bandwidth: u64,
@@ -33,6 +37,10 @@ impl Configuration {
         let mut storage = dir.join("storage");
         let mut search = dir.join("search");
         let mut autosave = false;
+        let mut search_stemming = false;
+        let mut search_crawl_bandwidth = 65536;
+        let mut search_full_text = vec![];
+        let mut search_federated = false;
         let mut max_connected_swarms = 8;
         let mut upload_bandwidth = 8192;
         let mut store_data_on_disk = vec![(StorageCondition::Default, StoragePolicy::All)];
@@ -44,6 +52,10 @@ impl Configuration {
                 work_dir: dir.clone(),
                 storage,
                 search,
+                search_stemming,
+                search_crawl_bandwidth,
+                search_full_text,
+                search_federated,
                 neighbors,
                 max_connected_swarms,
                 upload_bandwidth,
@@ -119,6 +131,35 @@ impl Configuration {
                             search = PathBuf::from(search_str);
                         }
                     }
+                    "SEARCH_STEMMING" => {
+                        eprintln!("Enabling SEARCH_STEMMING");
+                        search_stemming = true;
+                    }
+                    "SEARCH_CRAWL_BYTES_PER_SECOND" => {
+                        if let Some(number_str) = split.next() {
+                            if let Ok(number) = number_str.parse::<u64>() {
+                                eprintln!(
+                                    "Updating SEARCH_CRAWL_BYTES_PER_SECOND from {} to {}",
+                                    search_crawl_bandwidth, number
+                                );
+                                search_crawl_bandwidth = number;
+                            }
+                        }
+                    }
+                    "SEARCH_FULL_TEXT" => {
+                        // Names of DataTypes, as defined in Swarms' Manifests,
+                        // whose entire bodies should be indexed
+                        for d_type_name in split.by_ref() {
+                            eprintln!("Enabling SEARCH_FULL_TEXT for {}", d_type_name);
+                            search_full_text.push(d_type_name.to_string());
+                        }
+                    }
+                    "SEARCH_FEDERATION" => {
+                        // Query Neighbors' indexes
+                        // and answer their Queries about Swarms we share
+                        eprintln!("Enabling federated search");
+                        search_federated = true;
+                    }
                     other => {
                         eprintln!("Unrecognized config line: {}", other);
                     }
@@ -130,6 +171,10 @@ impl Configuration {
             work_dir: dir.clone(),
             storage,
             search,
+            search_stemming,
+            search_crawl_bandwidth,
+            search_full_text,
+            search_federated,
             neighbors,
             max_connected_swarms,
             upload_bandwidth,
@@ -976,6 +976,19 @@ impl Content {
             Self::Data(_type, mem, _tree) => *mem as usize,
         }
     }
+    // Sibling hashes from given Data up to Content's root hash
+    pub fn inclusion_path(&self, d_id: u16) -> Result<Vec<(bool, u64)>, AppError> {
+        match self {
+            Self::Link(_at, _s, _c, _descr, _data, _ti) => {
+                if d_id == 0 {
+                    Ok(vec![])
+                } else {
+                    Err(AppError::IndexingError)
+                }
+            }
+            Self::Data(_type, _mem, c_tree) => c_tree.inclusion_path(d_id),
+        }
+    }
     fn get_data_hash(&self, d_id: u16) -> Result<u64, AppError> {
         match self {
             Self::Link(_at, _s, _c, _descr, _data, _ti) => {
@@ -1285,6 +1298,21 @@ impl ContentTree {
         }
     }
 
+    // Sibling hashes on the way from given Data up to tree's root,
+    // bottom first, with a flag set when sibling is on the left.
+    pub fn inclusion_path(&self, idx: u16) -> Result<Vec<(bool, u64)>, AppError> {
+        match self {
+            Self::Empty(_) | Self::Filled(_) => {
+                if idx == 0 {
+                    Ok(vec![])
+                } else {
+                    Err(AppError::IndexingError)
+                }
+            }
+            Self::Hashed(sub_tree) => sub_tree.inclusion_path(idx),
+        }
+    }
+
     pub fn read(&self, idx: u16) -> Result<Data, AppError> {
         match self {
             Self::Filled(data) => {
@@ -1645,6 +1673,21 @@ impl Subtree {
             }
         }
     }
+    pub fn inclusion_path(&self, idx: u16) -> Result<Vec<(bool, u64)>, AppError> {
+        if idx >= self.data_count {
+            return Err(AppError::IndexingError);
+        }
+        let left_count = self.left.len();
+        if idx >= left_count {
+            let mut path = self.right.inclusion_path(idx - left_count)?;
+            path.push((true, self.left.hash()));
+            Ok(path)
+        } else {
+            let mut path = self.left.inclusion_path(idx)?;
+            path.push((false, self.right.hash()));
+            Ok(path)
+        }
+    }
     pub fn read(&self, idx: u16) -> Result<Data, AppError> {
         if idx >= self.data_count {
             eprintln!("Req read {}, when data count: {}", idx, self.data_count);
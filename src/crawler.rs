@@ -0,0 +1,237 @@
+use crate::ContentID;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+// Crawler decides which first pages search Engine should request next.
+// Instead of asking for all first pages of a Swarm at once
+// (which can be up to 64MiB), Contents to be indexed are split
+// into ranges of at most CHUNK_SIZE CIDs, each requested with
+// a single ReadAllFirstPages.
+// At most MAX_IN_FLIGHT ranges can be requested from a Swarm at once.
+// After every received range we delay next request proportionally
+// to number of bytes received, so that crawling does not take
+// more than configured bandwidth.
+// Every requested range has to be answered within REPLY_TIMEOUT,
+// otherwise it is requested again, up to MAX_ATTEMPTS times.
+// After that it is abandoned, so that a lost reply can not stall
+// crawling of a Swarm.
+//
+// Crawler only keeps track of what was requested.
+// What was already indexed is stored in SearchIndex as Content hashes,
+// so when a Swarm is swapped out and back in, we only request
+// those Contents that were not indexed yet.
+const CHUNK_SIZE: u16 = 64;
+const MAX_IN_FLIGHT: usize = 2;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_ATTEMPTS: u8 = 3;
+
+struct InFlight {
+    range: (ContentID, ContentID),
+    attempt: u8,
+    reply_by: Instant,
+}
+
+pub struct Crawler {
+    bytes_per_sec: u64,
+    // Ranges waiting to be requested, along with number of past attempts
+    ranges: VecDeque<((ContentID, ContentID), u8)>,
+    in_flight: Vec<InFlight>,
+    next_request_at: Instant,
+}
+
+impl Crawler {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Crawler {
+            bytes_per_sec,
+            ranges: VecDeque::new(),
+            in_flight: Vec::new(),
+            next_request_at: Instant::now(),
+        }
+    }
+
+    /// Start crawling given CIDs, in ascending order.
+    pub fn start(&mut self, c_ids: &[ContentID]) {
+        self.stop();
+        let mut c_ids = c_ids.to_vec();
+        c_ids.sort();
+        c_ids.dedup();
+        let mut iter = c_ids.iter();
+        let Some(first) = iter.next() else {
+            return;
+        };
+        let mut range = (*first, *first);
+        for c_id in iter {
+            if *c_id - range.0 < CHUNK_SIZE {
+                range.1 = *c_id;
+            } else {
+                self.ranges.push_back((range, 0));
+                range = (*c_id, *c_id);
+            }
+        }
+        self.ranges.push_back((range, 0));
+    }
+
+    pub fn stop(&mut self) {
+        self.ranges.clear();
+        self.in_flight.clear();
+    }
+
+    /// Ranges that should be requested now.
+    pub fn next_ranges(&mut self) -> Vec<(ContentID, ContentID)> {
+        let mut ranges = vec![];
+        if Instant::now() < self.next_request_at {
+            return ranges;
+        }
+        while self.in_flight.len() < MAX_IN_FLIGHT {
+            let Some((range, attempts)) = self.ranges.pop_front() else {
+                break;
+            };
+            self.in_flight.push(InFlight {
+                range,
+                attempt: attempts + 1,
+                reply_by: Instant::now() + REPLY_TIMEOUT,
+            });
+            ranges.push(range);
+        }
+        ranges
+    }
+
+    /// Given range was answered, returns false if it was not expected,
+    /// for example when it was already abandoned or answered before.
+    pub fn range_received(&mut self, range: (ContentID, ContentID), bytes: usize) -> bool {
+        let Some(idx) = self.in_flight.iter().position(|i_f| i_f.range == range) else {
+            return false;
+        };
+        self.in_flight.swap_remove(idx);
+        if self.bytes_per_sec > 0 {
+            let delay = Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+            self.next_request_at = self.next_request_at.max(Instant::now()) + delay;
+        }
+        true
+    }
+
+    /// Ranges not answered in time are queued to be requested again.
+    /// Returns those that were not answered after MAX_ATTEMPTS.
+    pub fn expire(&mut self) -> Vec<(ContentID, ContentID)> {
+        let now = Instant::now();
+        let mut abandoned = vec![];
+        let mut i = 0;
+        while i < self.in_flight.len() {
+            if self.in_flight[i].reply_by > now {
+                i += 1;
+                continue;
+            }
+            let expired = self.in_flight.swap_remove(i);
+            if expired.attempt < MAX_ATTEMPTS {
+                self.ranges.push_front((expired.range, expired.attempt));
+            } else {
+                abandoned.push(expired.range);
+            }
+        }
+        abandoned
+    }
+
+    /// When to wake up in order to request next range
+    /// or to check for unanswered ones, None if there is nothing to wait for.
+    pub fn deadline(&self) -> Option<Instant> {
+        let reply_by = self.in_flight.iter().map(|i_f| i_f.reply_by).min();
+        let request_at = if self.ranges.is_empty()
+            || self.in_flight.len() >= MAX_IN_FLIGHT
+            || self.next_request_at <= Instant::now()
+        {
+            None
+        } else {
+            Some(self.next_request_at)
+        };
+        match (reply_by, request_at) {
+            (Some(reply_by), Some(request_at)) => Some(reply_by.min(request_at)),
+            (reply_by, request_at) => reply_by.or(request_at),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expire_all(crawler: &mut Crawler) {
+        let now = Instant::now();
+        for i_f in crawler.in_flight.iter_mut() {
+            i_f.reply_by = now;
+        }
+    }
+
+    #[test]
+    fn c_ids_are_split_into_ranges() {
+        let mut crawler = Crawler::new(0);
+        let mut c_ids: Vec<ContentID> = (0..150).rev().collect();
+        c_ids.push(70);
+        c_ids.push(1000);
+        crawler.start(&c_ids);
+        let ranges: Vec<(ContentID, ContentID)> =
+            crawler.ranges.iter().map(|(range, _a)| *range).collect();
+        assert_eq!(ranges, vec![(0, 63), (64, 127), (128, 149), (1000, 1000)]);
+
+        crawler.start(&[]);
+        assert!(crawler.next_ranges().is_empty());
+        assert!(crawler.deadline().is_none());
+    }
+
+    #[test]
+    fn in_flight_ranges_are_limited() {
+        let mut crawler = Crawler::new(0);
+        crawler.start(&(0..300).collect::<Vec<ContentID>>());
+        assert_eq!(crawler.next_ranges(), vec![(0, 63), (64, 127)]);
+        assert!(crawler.next_ranges().is_empty());
+        assert!(!crawler.range_received((128, 191), 100));
+        assert!(crawler.range_received((64, 127), 100));
+        assert!(!crawler.range_received((64, 127), 100));
+        assert_eq!(crawler.next_ranges(), vec![(128, 191)]);
+    }
+
+    #[test]
+    fn requests_are_paced_by_received_bytes() {
+        let mut crawler = Crawler::new(1000);
+        crawler.start(&(0..200).collect::<Vec<ContentID>>());
+        assert_eq!(crawler.next_ranges().len(), 2);
+        assert!(crawler.range_received((0, 63), 60_000));
+        // A minute worth of bandwidth was used
+        assert!(crawler.next_ranges().is_empty());
+        let deadline = crawler.deadline().unwrap();
+        assert!(deadline <= crawler.in_flight[0].reply_by);
+        assert!(crawler.range_received((64, 127), 0));
+        assert!(crawler.deadline().unwrap() > Instant::now() + Duration::from_secs(50));
+    }
+
+    #[test]
+    fn unanswered_ranges_are_retried_then_abandoned() {
+        let mut crawler = Crawler::new(0);
+        crawler.start(&[5, 6, 7]);
+        for _attempt in 1..MAX_ATTEMPTS {
+            assert_eq!(crawler.next_ranges(), vec![(5, 7)]);
+            expire_all(&mut crawler);
+            assert!(crawler.expire().is_empty());
+        }
+        assert_eq!(crawler.next_ranges(), vec![(5, 7)]);
+        assert!(crawler.expire().is_empty());
+        expire_all(&mut crawler);
+        assert_eq!(crawler.expire(), vec![(5, 7)]);
+        assert!(crawler.next_ranges().is_empty());
+        // Late reply to an abandoned range
+        assert!(!crawler.range_received((5, 7), 10));
+    }
+
+    #[test]
+    fn restart_requests_only_given_c_ids() {
+        let mut crawler = Crawler::new(0);
+        crawler.start(&(0..200).collect::<Vec<ContentID>>());
+        assert_eq!(crawler.next_ranges().len(), 2);
+        // Swarm swapped out and back in, with first range already indexed
+        crawler.start(&(64..200).collect::<Vec<ContentID>>());
+        assert!(!crawler.range_received((0, 63), 10));
+        assert_eq!(crawler.next_ranges(), vec![(64, 127), (128, 191)]);
+        assert!(crawler.range_received((64, 127), 10));
+        assert_eq!(crawler.next_ranges(), vec![(192, 199)]);
+    }
+}
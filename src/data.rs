@@ -4,6 +4,7 @@ use gnome::prelude::CastData;
 use gnome::prelude::SyncData;
 // use std::hash::{DefaultHasher, Hasher};
 // use std::{fmt, hash::Hash};
+use std::collections::HashMap;
 use std::fmt;
 
 use crate::prelude::data_to_link;
@@ -113,6 +114,37 @@ impl fmt::Display for Data {
     }
 }
 
+// Both Data and Link first pages start with TagLen|Tags,
+// so we can replace or drop tag ids without parsing the rest.
+// Returns None when no id from mapping is present.
+pub fn retag_first_page(data: &Data, mapping: &HashMap<u8, Option<u8>>) -> Option<Data> {
+    let bytes = data.ref_bytes();
+    let (t_len, rest) = bytes.split_first()?;
+    if rest.len() < *t_len as usize {
+        return None;
+    }
+    let (tag_ids, rest) = rest.split_at(*t_len as usize);
+    if !tag_ids.iter().any(|t_id| mapping.contains_key(t_id)) {
+        return None;
+    }
+    let mut new_ids: Vec<u8> = Vec::with_capacity(tag_ids.len());
+    for t_id in tag_ids {
+        let new_id = match mapping.get(t_id) {
+            Some(Some(new_id)) => *new_id,
+            Some(None) => continue,
+            None => *t_id,
+        };
+        if !new_ids.contains(&new_id) {
+            new_ids.push(new_id);
+        }
+    }
+    let mut new_bytes = Vec::with_capacity(bytes.len());
+    new_bytes.push(new_ids.len() as u8);
+    new_bytes.append(&mut new_ids);
+    new_bytes.extend_from_slice(rest);
+    Data::new(new_bytes).ok()
+}
+
 pub fn read_tags_and_header(d_type: DataType, data: Data) -> (Vec<u8>, String) {
     if data.is_empty() {
         return (vec![], String::new());
@@ -157,3 +189,22 @@ pub fn read_tags_and_header(d_type: DataType, data: Data) -> (Vec<u8>, String) {
     eprintln!("Hdr: {}", header);
     (tag_ids, header)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retag_first_page_replaces_and_drops_ids() {
+        let data = Data::new(vec![3, 1, 2, 4, 0, 2, b'h', b'i']).unwrap();
+        let mapping = HashMap::from([(1, Some(4)), (2, None)]);
+        let retagged = retag_first_page(&data, &mapping).unwrap();
+        // merged ids are not repeated
+        assert_eq!(retagged.ref_bytes(), &[1, 4, 0, 2, b'h', b'i']);
+
+        let untouched = HashMap::from([(9, None)]);
+        assert!(retag_first_page(&data, &untouched).is_none());
+        let truncated = Data::new(vec![3, 1]).unwrap();
+        assert!(retag_first_page(&truncated, &mapping).is_none());
+    }
+}
@@ -155,6 +155,33 @@ impl Datastore {
         }
     }
 
+    // this fn should be used for removing last Content from Datastore,
+    // first Content can not be removed
+    pub fn pop(&mut self) -> Result<Content, AppError> {
+        let myself = std::mem::replace(self, Datastore::Empty);
+        match myself {
+            Self::Hashed(s_store) => match *s_store {
+                Substore {
+                    left,
+                    right: Self::Filled(content),
+                    ..
+                } => {
+                    *self = left;
+                    Ok(content)
+                }
+                mut s_store => {
+                    let result = s_store.pop();
+                    *self = Self::Hashed(Box::new(s_store));
+                    result
+                }
+            },
+            other => {
+                *self = other;
+                Err(AppError::IndexingError)
+            }
+        }
+    }
+
     //  This fn should be used for taking given content out of Datastore,
     //       replacing it with it's shell representation.
     //       This is useful when we frequently use given Content in our App.
@@ -413,6 +440,24 @@ impl Datastore {
         }
     }
 
+    // Sibling hashes from selected datachunk up to it's Content root hash
+    pub fn inclusion_path(
+        &self,
+        (c_id, data_id): (ContentID, u16),
+    ) -> Result<Vec<(bool, u64)>, AppError> {
+        match self {
+            Self::Empty => Err(AppError::ContentEmpty),
+            Self::Filled(content) => {
+                if c_id == 0 {
+                    content.inclusion_path(data_id)
+                } else {
+                    Err(AppError::IndexingError)
+                }
+            }
+            Self::Hashed(s_store) => s_store.inclusion_path((c_id, data_id)),
+        }
+    }
+
     // This fn should be used for reading selected datachunk
     pub fn read_link_data(
         &self,
@@ -585,6 +630,14 @@ impl Substore {
             Err(AppError::DatastoreFull)
         }
     }
+    pub fn pop(&mut self) -> Result<Content, AppError> {
+        let content = self.right.pop()?;
+        let mem_used = 1 + content.used_memory_pages() + (content.len() as usize >> 7);
+        self.content_count -= 1;
+        self.used_memory_slots = self.used_memory_slots.saturating_sub(mem_used);
+        self.hash();
+        Ok(content)
+    }
     pub fn type_and_len(&self, c_id: ContentID) -> Result<(DataType, u16), AppError> {
         if c_id >= self.content_count {
             return Err(AppError::IndexingError);
@@ -671,6 +724,20 @@ impl Substore {
             self.left.read_link_data((c_id, data_id), d_type)
         }
     }
+    pub fn inclusion_path(
+        &self,
+        (c_id, data_id): (ContentID, u16),
+    ) -> Result<Vec<(bool, u64)>, AppError> {
+        if c_id >= self.content_count {
+            return Err(AppError::IndexingError);
+        }
+        let left_len = self.left.len();
+        if c_id >= left_len {
+            self.right.inclusion_path((c_id - left_len, data_id))
+        } else {
+            self.left.inclusion_path((c_id, data_id))
+        }
+    }
     pub fn read_data(&self, (c_id, data_id): (ContentID, u16)) -> Result<Data, AppError> {
         if c_id >= self.content_count {
             return Err(AppError::IndexingError);
@@ -798,3 +865,36 @@ impl Substore {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content(byte: u8) -> Content {
+        let mut content_tree = ContentTree::empty(0);
+        let _ = content_tree.append(Data::new(vec![byte]).unwrap());
+        Content::Data(DataType::Data(0), 1, content_tree)
+    }
+
+    #[test]
+    fn pop_restores_previous_root_hash() {
+        let mut datastore = Datastore::new(AppType::Forum);
+        let mut hashes = vec![datastore.hash()];
+        for byte in 1..5 {
+            datastore.append(content(byte)).unwrap();
+            hashes.push(datastore.hash());
+        }
+        for byte in (1..5).rev() {
+            let popped = datastore.pop().unwrap();
+            assert_eq!(popped.read_data(0).unwrap().ref_bytes(), &[byte]);
+            assert_eq!(datastore.len(), byte as u16);
+            assert_eq!(datastore.hash(), hashes[byte as usize - 1]);
+        }
+        assert!(datastore.pop().is_err());
+        assert_eq!(datastore.len(), 1);
+
+        // Can be appended to again
+        datastore.append(content(1)).unwrap();
+        assert_eq!(datastore.hash(), hashes[1]);
+    }
+}
@@ -15,6 +15,10 @@ pub enum AppError {
     DatastoreFull,
     DatastoreInsertCalledOnFilled,
     AppDataNotSynced,
+    NotInUndoLog,
+    VersionUnavailable,
+    RequestTimedOut,
+    MalformedRequest,
 }
 impl Error for AppError {}
 impl Display for AppError {
@@ -30,6 +34,10 @@ impl Display for AppError {
             Self::DatastoreFull => write!(f, "DatastoreFull"),
             Self::DatastoreInsertCalledOnFilled => write!(f, "DatastoreInsertCalledOnFilled"),
             Self::AppDataNotSynced => write!(f, "AppDataNotSynced"),
+            Self::NotInUndoLog => write!(f, "NotInUndoLog"),
+            Self::VersionUnavailable => write!(f, "VersionUnavailable"),
+            Self::RequestTimedOut => write!(f, "RequestTimedOut"),
+            Self::MalformedRequest => write!(f, "MalformedRequest"),
         }
     }
 }
@@ -0,0 +1,232 @@
+use crate::ContentID;
+use gnome::prelude::GnomeId;
+use gnome::prelude::SwarmName;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+// Federated search lets us ask our Neighbors to run a Query
+// against their local search index, so that we can find Contents
+// in Swarms we have not joined or synced.
+//
+// Request is sent as a CustomNeighborRequest with SEARCH_REQUEST m_type:
+// 1 byte  - max number of results we want back
+// rest    - Query text
+//
+// Response is sent as a CustomNeighborResponse with SEARCH_RESPONSE m_type:
+// 8 bytes - sha_hash of Query text, so that we know which Query it answers
+// 1 byte  - results count
+// for every result:
+// 1 byte  - SwarmName bytes len
+// this many bytes with SwarmName
+// 2 bytes - ContentID
+// 4 bytes - Score value
+// 8 bytes - hash of Content's first page
+//
+// Results reported by a Neighbor are only hints, they remain marked as remote
+// until we verify given Content's first page ourselves.
+
+// Those are taken from range available for App defined requests and responses,
+// so App's requests now have to be below 244 and it's responses below 242
+// (used to be below 245 and 243).
+// This is a breaking change: Apps that used 244 for requests or 242 for responses
+// have to move them to lower ids, since those are no longer sent.
+// Neighbors running older versions pass our SEARCH_REQUEST to their App,
+// and their App's 244 requests are answered by us as searches,
+// so every Gnome in a Swarm should be upgraded before searching Neighbors.
+pub const SEARCH_REQUEST: u8 = 244;
+pub const SEARCH_RESPONSE: u8 = 242;
+// How many results we ask for and how many we are willing to send back
+pub const MAX_REMOTE_RESULTS: u8 = 32;
+// Response has to fit into a single CastData
+const MAX_RESPONSE_BYTES: usize = 1024;
+// How many Queries a single Neighbor can ask us for within RATE_WINDOW
+const MAX_QUERIES_PER_WINDOW: usize = 10;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+pub struct RemoteResult {
+    pub s_name: SwarmName,
+    pub c_id: ContentID,
+    pub score: f32,
+    pub page_hash: u64,
+}
+
+pub fn encode_request(limit: u8, text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len() + 1);
+    bytes.push(limit);
+    bytes.extend_from_slice(text.as_bytes());
+    bytes
+}
+
+pub fn decode_request(bytes: Vec<u8>) -> Option<(u8, String)> {
+    let mut iter = bytes.into_iter();
+    let limit = iter.next()?;
+    let text = String::from_utf8(iter.collect()).ok()?;
+    Some((limit.min(MAX_REMOTE_RESULTS), text))
+}
+
+/// Results that do not fit into a single response are dropped.
+pub fn encode_response(q_hash: u64, results: Vec<RemoteResult>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(MAX_RESPONSE_BYTES);
+    bytes.extend_from_slice(&q_hash.to_be_bytes());
+    bytes.push(0);
+    let mut count = 0u8;
+    for result in results {
+        let name_bytes = result.s_name.as_bytes();
+        let result_len = 1 + name_bytes.len() + 2 + 4 + 8;
+        if name_bytes.len() > 255
+            || bytes.len() + result_len > MAX_RESPONSE_BYTES
+            || count == u8::MAX
+        {
+            break;
+        }
+        bytes.push(name_bytes.len() as u8);
+        bytes.extend(name_bytes);
+        bytes.extend_from_slice(&result.c_id.to_be_bytes());
+        bytes.extend_from_slice(&result.score.to_be_bytes());
+        bytes.extend_from_slice(&result.page_hash.to_be_bytes());
+        count += 1;
+    }
+    bytes[8] = count;
+    bytes
+}
+
+pub fn decode_response(bytes: Vec<u8>) -> Option<(u64, Vec<RemoteResult>)> {
+    let mut iter = bytes.into_iter();
+    let mut hash_bytes = [0u8; 8];
+    for byte in hash_bytes.iter_mut() {
+        *byte = iter.next()?;
+    }
+    let q_hash = u64::from_be_bytes(hash_bytes);
+    let count = iter.next()?;
+    let mut results = Vec::with_capacity(count as usize);
+    for _i in 0..count {
+        let name_len = iter.next()?;
+        let mut name_bytes = Vec::with_capacity(name_len as usize);
+        for _j in 0..name_len {
+            name_bytes.push(iter.next()?);
+        }
+        let s_name = SwarmName::from(&name_bytes).ok()?;
+        let c_id = u16::from_be_bytes([iter.next()?, iter.next()?]);
+        let score = f32::from_be_bytes([iter.next()?, iter.next()?, iter.next()?, iter.next()?]);
+        let mut hash_bytes = [0u8; 8];
+        for byte in hash_bytes.iter_mut() {
+            *byte = iter.next()?;
+        }
+        results.push(RemoteResult {
+            s_name,
+            c_id,
+            score,
+            page_hash: u64::from_be_bytes(hash_bytes),
+        });
+    }
+    Some((q_hash, results))
+}
+
+// Limits how often each Neighbor can query our index
+#[derive(Default)]
+pub struct RateLimiter {
+    asked: HashMap<u64, VecDeque<Instant>>,
+}
+impl RateLimiter {
+    /// Returns true if given Neighbor is allowed to ask now.
+    pub fn allow(&mut self, g_id: GnomeId) -> bool {
+        let now = Instant::now();
+        self.asked.retain(|_g_id, times| {
+            while times
+                .front()
+                .is_some_and(|t| now.duration_since(*t) > RATE_WINDOW)
+            {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+        let times = self.asked.entry(g_id.0).or_default();
+        if times.len() >= MAX_QUERIES_PER_WINDOW {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(name: &str, c_id: ContentID) -> RemoteResult {
+        RemoteResult {
+            s_name: SwarmName::new(GnomeId(3), name.to_string()).unwrap(),
+            c_id,
+            score: 1.5,
+            page_hash: 77,
+        }
+    }
+
+    #[test]
+    fn request_round_trip() {
+        let bytes = encode_request(5, "gnome \"swarm sync\"");
+        assert_eq!(
+            decode_request(bytes),
+            Some((5, "gnome \"swarm sync\"".to_string()))
+        );
+        // Limit is capped
+        let bytes = encode_request(200, "gnome");
+        assert_eq!(decode_request(bytes).unwrap().0, MAX_REMOTE_RESULTS);
+        assert!(decode_request(vec![]).is_none());
+        assert!(decode_request(vec![1, 0xff, 0xfe]).is_none());
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let bytes = encode_response(9, vec![result("/a", 1), result("/bb", 2)]);
+        let (q_hash, results) = decode_response(bytes).unwrap();
+        assert_eq!(q_hash, 9);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].s_name, result("/bb", 2).s_name);
+        assert_eq!(results[1].c_id, 2);
+        assert_eq!(results[1].score, 1.5);
+        assert_eq!(results[1].page_hash, 77);
+    }
+
+    #[test]
+    fn response_fits_into_single_cast() {
+        let results = (0..200)
+            .map(|c_id| result("/long-swarm-name", c_id))
+            .collect();
+        let bytes = encode_response(1, results);
+        assert!(bytes.len() <= MAX_RESPONSE_BYTES);
+        let (_q_hash, results) = decode_response(bytes).unwrap();
+        assert!(results.len() > 10 && results.len() < 200);
+    }
+
+    #[test]
+    fn truncated_response_is_rejected() {
+        let bytes = encode_response(9, vec![result("/a", 1)]);
+        for len in 0..bytes.len() {
+            assert!(decode_response(bytes[..len].to_vec()).is_none());
+        }
+        // Count claims more results than there are
+        let mut bytes = bytes;
+        bytes[8] = 2;
+        assert!(decode_response(bytes).is_none());
+    }
+
+    #[test]
+    fn neighbors_are_rate_limited_separately() {
+        let mut limiter = RateLimiter::default();
+        for _i in 0..MAX_QUERIES_PER_WINDOW {
+            assert!(limiter.allow(GnomeId(1)));
+        }
+        assert!(!limiter.allow(GnomeId(1)));
+        assert!(limiter.allow(GnomeId(2)));
+        // Window has passed
+        let past = Instant::now() - RATE_WINDOW - Duration::from_secs(1);
+        for time in limiter.asked.get_mut(&1).unwrap().iter_mut() {
+            *time = past;
+        }
+        assert!(limiter.allow(GnomeId(1)));
+        assert_eq!(limiter.asked[&1].len(), 1);
+    }
+}
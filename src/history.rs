@@ -0,0 +1,340 @@
+use crate::content::ContentID;
+use crate::content::DataType;
+use crate::util::now;
+use crate::Data;
+use gnome::prelude::GnomeId;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+// For every Content that was changed by a message from Swarm we keep a list
+// of it's recent versions. A version is identified by a number that only grows,
+// and consists of Content's root hash, who signed the message and when
+// we have applied it, together with hashes of all it's pages.
+//
+// Pages are stored content-addressed, named after their hash, so a page
+// that did not change is never stored twice. We only write to disk pages
+// that have changed in given version and only when storage policy
+// keeps given Content on disk. Pages that were still shells when their
+// version was recorded (like after ChangeContent) are stored once filled.
+// An old version can be reconstructed
+// only if all of it's pages are either still in Datastore or on disk.
+//
+// The same page can be shared by different Contents, so page files
+// are not removed when a version gets dropped from history.
+// Instead once enough versions were dropped, we remove every page
+// not referenced by any history file on disk.
+//
+// On disk:
+// history/<CID>.hst - list of versions of given Content
+// pages/<hash>      - page contents
+const MAX_VERSIONS: usize = 64;
+pub const PAGES_GC_AFTER: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct ContentVersion {
+    pub version: u32,
+    pub d_type: DataType,
+    pub root_hash: u64,
+    pub signed_by: GnomeId,
+    pub timestamp: u64, // seconds since UNIX epoch
+    pub page_hashes: Vec<u64>,
+}
+
+impl ContentVersion {
+    fn bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(31 + 8 * self.page_hashes.len());
+        bytes.extend_from_slice(&self.version.to_be_bytes());
+        bytes.push(self.d_type.byte());
+        bytes.extend_from_slice(&self.root_hash.to_be_bytes());
+        bytes.extend_from_slice(&self.signed_by.bytes());
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        bytes.extend_from_slice(&(self.page_hashes.len() as u16).to_be_bytes());
+        for hash in &self.page_hashes {
+            bytes.extend_from_slice(&hash.to_be_bytes());
+        }
+        bytes
+    }
+
+    fn from(iter: &mut impl Iterator<Item = u8>) -> Option<Self> {
+        let version = u32::from_be_bytes(read_array(iter)?);
+        let d_type = DataType::from(iter.next()?);
+        let root_hash = u64::from_be_bytes(read_array(iter)?);
+        let signed_by = GnomeId::from(read_array::<8>(iter)?);
+        let timestamp = u64::from_be_bytes(read_array(iter)?);
+        let count = u16::from_be_bytes(read_array(iter)?);
+        let mut page_hashes = Vec::with_capacity(count as usize);
+        for _i in 0..count {
+            page_hashes.push(u64::from_be_bytes(read_array(iter)?));
+        }
+        Some(ContentVersion {
+            version,
+            d_type,
+            root_hash,
+            signed_by,
+            timestamp,
+            page_hashes,
+        })
+    }
+}
+
+pub struct ContentHistory {
+    next_version: u32,
+    versions: VecDeque<ContentVersion>,
+}
+
+impl ContentHistory {
+    pub fn new() -> Self {
+        ContentHistory {
+            next_version: 0,
+            versions: VecDeque::with_capacity(MAX_VERSIONS),
+        }
+    }
+
+    pub fn last(&self) -> Option<&ContentVersion> {
+        self.versions.back()
+    }
+
+    pub fn get(&self, version: u32) -> Option<&ContentVersion> {
+        self.versions.iter().find(|v| v.version == version)
+    }
+
+    /// Whether any version consists of page with given hash.
+    pub fn references(&self, hash: u64) -> bool {
+        self.versions.iter().any(|v| v.page_hashes.contains(&hash))
+    }
+
+    pub fn versions(&self) -> Vec<ContentVersion> {
+        self.versions.iter().cloned().collect()
+    }
+
+    /// Add a new version, returns oldest version if it was dropped.
+    pub fn push(
+        &mut self,
+        d_type: DataType,
+        root_hash: u64,
+        signed_by: GnomeId,
+        page_hashes: Vec<u64>,
+    ) -> Option<ContentVersion> {
+        let dropped = if self.versions.len() >= MAX_VERSIONS {
+            self.versions.pop_front()
+        } else {
+            None
+        };
+        let version = self.next_version;
+        self.next_version += 1;
+        self.versions.push_back(ContentVersion {
+            version,
+            d_type,
+            root_hash,
+            signed_by,
+            timestamp: now(),
+            page_hashes,
+        });
+        dropped
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        let mut bytes = self.next_version.to_be_bytes().to_vec();
+        for version in &self.versions {
+            bytes.append(&mut version.bytes());
+        }
+        bytes
+    }
+
+    fn from(bytes: Vec<u8>) -> Option<Self> {
+        let mut iter = bytes.into_iter().peekable();
+        let next_version = u32::from_be_bytes(read_array(&mut iter)?);
+        let mut versions = VecDeque::with_capacity(MAX_VERSIONS);
+        while iter.peek().is_some() {
+            versions.push_back(ContentVersion::from(&mut iter)?);
+        }
+        Some(ContentHistory {
+            next_version,
+            versions,
+        })
+    }
+}
+
+fn read_array<const N: usize>(iter: &mut impl Iterator<Item = u8>) -> Option<[u8; N]> {
+    let mut bytes = [0; N];
+    for byte in bytes.iter_mut() {
+        *byte = iter.next()?;
+    }
+    Some(bytes)
+}
+
+pub async fn store_history_on_disk(s_storage: &Path, c_id: ContentID, history: &ContentHistory) {
+    let dir = s_storage.join("history");
+    if !dir.exists() {
+        let _ = smol::fs::create_dir_all(&dir).await;
+    }
+    let f_path = dir.join(format!("{}.hst", c_id));
+    if let Err(e) = smol::fs::write(f_path, history.bytes()).await {
+        eprintln!("Failed to store CID-{} history: {}", c_id, e);
+    }
+}
+
+pub async fn load_history_from_disk(s_storage: &Path, c_id: ContentID) -> Option<ContentHistory> {
+    let f_path = s_storage.join("history").join(format!("{}.hst", c_id));
+    if !f_path.exists() {
+        return None;
+    }
+    let bytes = smol::fs::read(f_path).await.ok()?;
+    let history = ContentHistory::from(bytes);
+    if history.is_none() {
+        eprintln!("CID-{} history file is malformed", c_id);
+    }
+    history
+}
+
+pub async fn store_pages_on_disk(s_storage: &Path, pages: Vec<Data>) {
+    let dir = s_storage.join("pages");
+    if !dir.exists() {
+        let _ = smol::fs::create_dir_all(&dir).await;
+    }
+    for page in pages {
+        let f_path = dir.join(format!("{:016x}", page.get_hash()));
+        if f_path.exists() {
+            continue;
+        }
+        if let Err(e) = smol::fs::write(f_path, page.bytes()).await {
+            eprintln!("Failed to store page: {}", e);
+        }
+    }
+}
+
+/// Remove pages that no history file on disk refers to.
+/// If any history file can not be read, nothing is removed.
+pub async fn remove_unreferenced_pages(s_storage: &Path) {
+    let Ok(pages) = fs::read_dir(s_storage.join("pages")) else {
+        return;
+    };
+    let mut referenced = HashSet::new();
+    if let Ok(histories) = fs::read_dir(s_storage.join("history")) {
+        for entry in histories {
+            let history = match entry {
+                Ok(entry) => smol::fs::read(entry.path())
+                    .await
+                    .ok()
+                    .and_then(ContentHistory::from),
+                Err(_e) => None,
+            };
+            let Some(history) = history else {
+                eprintln!("Unable to read history, pages are kept");
+                return;
+            };
+            for version in history.versions {
+                referenced.extend(version.page_hashes);
+            }
+        }
+    }
+    for entry in pages.flatten() {
+        let f_name = entry.file_name();
+        let Some(hash) = f_name
+            .to_str()
+            .and_then(|name| u64::from_str_radix(name, 16).ok())
+        else {
+            continue;
+        };
+        if !referenced.contains(&hash) {
+            let _ = smol::fs::remove_file(entry.path()).await;
+        }
+    }
+}
+
+pub async fn load_page_from_disk(s_storage: &Path, hash: u64) -> Option<Data> {
+    let f_path = s_storage.join("pages").join(format!("{:016x}", hash));
+    if !f_path.exists() {
+        return None;
+    }
+    let bytes = smol::fs::read(f_path).await.ok()?;
+    let data = Data::new(bytes).ok()?;
+    if data.get_hash() == hash {
+        Some(data)
+    } else {
+        eprintln!("Page {:016x} on disk does not match it's hash", hash);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(byte: u8) -> Data {
+        Data::new(vec![byte; 4]).unwrap()
+    }
+
+    #[test]
+    fn history_survives_bytes_round_trip() {
+        let mut history = ContentHistory::new();
+        history.push(DataType::Data(1), 10, GnomeId(7), vec![1, 2, 3]);
+        history.push(DataType::Link, 11, GnomeId(8), vec![]);
+        let read = ContentHistory::from(history.bytes()).unwrap();
+        assert_eq!(read.next_version, 2);
+        let versions = read.versions();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].version, 0);
+        assert_eq!(versions[0].d_type.byte(), DataType::Data(1).byte());
+        assert_eq!(versions[0].root_hash, 10);
+        assert_eq!(versions[0].signed_by, GnomeId(7));
+        assert_eq!(versions[0].page_hashes, vec![1, 2, 3]);
+        assert_eq!(versions[1].d_type.byte(), DataType::Link.byte());
+        assert!(versions[1].page_hashes.is_empty());
+
+        let mut truncated = history.bytes();
+        truncated.pop();
+        assert!(ContentHistory::from(truncated).is_none());
+    }
+
+    #[test]
+    fn oldest_version_is_dropped() {
+        let mut history = ContentHistory::new();
+        for i in 0..MAX_VERSIONS as u64 {
+            assert!(history
+                .push(DataType::Data(0), i, GnomeId(1), vec![i])
+                .is_none());
+        }
+        let dropped = history.push(DataType::Data(0), 99, GnomeId(1), vec![99]);
+        assert_eq!(dropped.unwrap().version, 0);
+        assert_eq!(history.versions().len(), MAX_VERSIONS);
+        assert!(history.get(0).is_none());
+        assert_eq!(history.last().unwrap().version, MAX_VERSIONS as u32);
+        assert!(!history.references(0));
+        assert!(history.references(99));
+    }
+
+    #[test]
+    fn only_unreferenced_pages_are_removed() {
+        let dir = std::env::temp_dir().join(format!("dapp-lib-history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (kept, shared, removed) = (page(1), page(2), page(3));
+        let mut first = ContentHistory::new();
+        first.push(DataType::Data(0), 1, GnomeId(1), vec![kept.get_hash()]);
+        let mut second = ContentHistory::new();
+        second.push(DataType::Data(0), 2, GnomeId(1), vec![shared.get_hash()]);
+        smol::block_on(async {
+            store_pages_on_disk(&dir, vec![kept.clone(), shared.clone(), removed.clone()]).await;
+            store_history_on_disk(&dir, 1, &first).await;
+            store_history_on_disk(&dir, 2, &second).await;
+            remove_unreferenced_pages(&dir).await;
+            assert_eq!(load_page_from_disk(&dir, kept.get_hash()).await, Some(kept));
+            assert_eq!(
+                load_page_from_disk(&dir, shared.get_hash()).await,
+                Some(shared.clone())
+            );
+            assert!(load_page_from_disk(&dir, removed.get_hash())
+                .await
+                .is_none());
+
+            // Unreadable history keeps all pages
+            fs::write(dir.join("history").join("3.hst"), [0]).unwrap();
+            fs::remove_file(dir.join("history").join("2.hst")).unwrap();
+            remove_unreferenced_pages(&dir).await;
+            assert!(load_page_from_disk(&dir, shared.get_hash()).await.is_some());
+        });
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,1454 @@
+use crate::prelude::AppType;
+use crate::prelude::DataType;
+use crate::search::MatchedOn;
+use crate::search::Score;
+use crate::search::Snippet;
+use crate::ContentID;
+use crate::Data;
+use gnome::prelude::sha_hash;
+use gnome::prelude::SwarmName;
+use std::collections::HashMap;
+use std::collections::HashSet;
+// TODO: make everything FS-related async
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use unicode_normalization::UnicodeNormalization;
+
+// Inverted index used by search Engine.
+// For every normalized term we keep a set of Postings,
+// each pointing to a (SwarmName, ContentID, Field) triple.
+// We also keep terms of every indexed document in their original order,
+// so that we can tell which terms to remove when a Content changes
+// and so that we can check if a phrase is contained within a document.
+//
+// Index is stored on disk, in a dedicated directory, one file per Swarm.
+// Changed Swarms are only marked as dirty, and their files get rewritten
+// on flush, which Engine calls once per crawled range or after a short delay,
+// so that indexing many Contents does not rewrite the same file over and over.
+// This way we can answer Queries for Swarms we are not currently joined to.
+//
+// Swarm file layout:
+// 2 bytes - SwarmName bytes len
+// this many bytes with SwarmName
+// then a sequence of records, each starting with:
+// 2 bytes - ContentID
+// 1 byte  - record kind
+// For a Field (kind 0-4):
+// 2 bytes - terms count
+// for every term: 1 byte len + this many bytes of term
+// For a ContentMeta (kind 254):
+// 1 byte  - DataType
+// 2 bytes - tags count
+// this many bytes with tag ids
+// For a SwarmMeta (kind 255, ContentID is always 0):
+// 1 byte  - 1 if AppType is known, 0 otherwise
+// 1 byte  - AppType
+// 2 bytes - tags count
+// for every tag: 1 byte tag id, 1 byte len + this many bytes of tag name
+// 2 bytes - data types count
+// for every data type: 1 byte id, 1 byte len + this many bytes of name
+// 2 bytes - translated tags count
+// for every translated tag: 1 byte tag id, 1 byte names count,
+//           for every name 1 byte len + this many bytes of name
+// For a Body positions (kind 251), must follow Body Field of given Content:
+// 2 bytes - positions count
+// for every Body term: 2 bytes page index, 4 bytes byte offset within that page
+// For a Content hash (kind 253):
+// 1 byte  - DataType
+// 8 bytes - Content root hash at the time it was indexed
+// For a Swarm hash (kind 252, ContentID is always 0):
+// 8 bytes - Datastore root hash at the time entire Swarm was indexed
+// For a first page hash (kind 250):
+// 8 bytes - sha_hash of Content's first page at the time it was indexed
+//
+// With those hashes we can skip Swarms that did not change since last time
+// they were indexed, and for those that did, only re-read changed Contents.
+// First page hashes are sent along with federated search results,
+// so that a Neighbor can verify them against it's own first pages.
+//
+// Contents are ranked with BM25, with per field weights applied
+// to term frequencies and document lengths (sometimes called BM25F).
+// Document and term statistics are kept separately for every Swarm,
+// since every Swarm has it's own vocabulary.
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+// Applied when entire Query is found as a phrase within a single field
+const PHRASE_BOOST: f32 = 2.0;
+// Body of a Content can be up to 64MiB, we only index this many terms of it
+const MAX_BODY_TERMS: usize = u16::MAX as usize;
+// How many terms around a matching one are included in a Snippet
+const SNIPPET_RADIUS: usize = 5;
+const PAGE_HASH: u8 = 250;
+const BODY_POSITIONS: u8 = 251;
+const SWARM_HASH: u8 = 252;
+const CONTENT_HASH: u8 = 253;
+const CONTENT_META: u8 = 254;
+const SWARM_META: u8 = 255;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Field {
+    Description,
+    ManifestTag,
+    Header,
+    ContentTag,
+    Body,
+}
+impl Field {
+    pub fn byte(&self) -> u8 {
+        match self {
+            Self::Description => 0,
+            Self::ManifestTag => 1,
+            Self::Header => 2,
+            Self::ContentTag => 3,
+            Self::Body => 4,
+        }
+    }
+    pub fn from(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Description),
+            1 => Some(Self::ManifestTag),
+            2 => Some(Self::Header),
+            3 => Some(Self::ContentTag),
+            4 => Some(Self::Body),
+            _o => None,
+        }
+    }
+    // Title(Header) is more important than a Description,
+    // and Manifest's Tags are more important than Content's Tags.
+    pub fn weight(&self) -> f32 {
+        match self {
+            Self::Description => 1.0,
+            Self::ManifestTag => 3.0,
+            Self::Header => 2.5,
+            Self::ContentTag => 1.5,
+            Self::Body => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Posting(pub SwarmName, pub ContentID, pub Field);
+
+// Used for filtering Query results, also for Swarms we are not joined to.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SwarmMeta {
+    pub app_type: Option<AppType>,
+    pub tags: HashMap<u8, String>,
+    pub d_types: HashMap<u8, String>,
+    // Tag names from Manifest's localizations, other than default ones
+    pub tag_translations: HashMap<u8, Vec<String>>,
+}
+impl SwarmMeta {
+    /// Default name of given Tag followed by it's translations.
+    pub fn tag_names(&self, t_id: u8) -> impl Iterator<Item = &String> {
+        self.tags
+            .get(&t_id)
+            .into_iter()
+            .chain(self.tag_translations.get(&t_id).into_iter().flatten())
+    }
+}
+#[derive(Clone, PartialEq, Debug)]
+pub struct ContentMeta {
+    pub d_type: DataType,
+    pub tag_ids: Vec<u8>,
+}
+
+enum Record {
+    Terms(ContentID, Field, Vec<String>),
+    Positions(ContentID, Vec<(u16, u32)>),
+    Content(ContentID, ContentMeta),
+    Swarm(SwarmMeta),
+    ContentHash(ContentID, (DataType, u64)),
+    SwarmHash(u64),
+    PageHash(ContentID, u64),
+}
+
+// Hashes of a Swarm's Datastore at the time it was indexed
+#[derive(Default)]
+struct SwarmHashes {
+    root: Option<u64>,
+    contents: HashMap<ContentID, (DataType, u64)>,
+    pages: HashMap<ContentID, u64>,
+}
+
+// Terms of every indexed Field of a Content
+type Fields = Vec<(Field, Vec<String>)>;
+
+// Inverted index, terms are also grouped by their length in chars,
+// so that fuzzy matching only compares terms of similar length
+// instead of entire vocabulary.
+#[derive(Default)]
+struct Vocabulary {
+    postings: HashMap<String, HashSet<Posting>>,
+    by_len: HashMap<usize, HashSet<String>>,
+}
+
+impl Vocabulary {
+    fn insert(&mut self, term: &str, posting: Posting) {
+        if let Some(postings) = self.postings.get_mut(term) {
+            postings.insert(posting);
+            return;
+        }
+        self.by_len
+            .entry(term.chars().count())
+            .or_default()
+            .insert(term.to_string());
+        self.postings
+            .insert(term.to_string(), HashSet::from([posting]));
+    }
+
+    fn remove(&mut self, term: &str, posting: &Posting) {
+        let Some(postings) = self.postings.get_mut(term) else {
+            return;
+        };
+        postings.remove(posting);
+        if !postings.is_empty() {
+            return;
+        }
+        self.postings.remove(term);
+        let len = term.chars().count();
+        if let Some(terms) = self.by_len.get_mut(&len) {
+            terms.remove(term);
+            if terms.is_empty() {
+                self.by_len.remove(&len);
+            }
+        }
+    }
+
+    // Postings of given term and of those within allowed edit distance
+    fn matching(&self, q_term: &str) -> Vec<&HashSet<Posting>> {
+        let mut matching: Vec<&HashSet<Posting>> = self.postings.get(q_term).into_iter().collect();
+        let q_len = q_term.chars().count();
+        if !(FUZZY_MIN_LEN..=FUZZY_MAX_LEN).contains(&q_len) {
+            return matching;
+        }
+        for len in q_len - FUZZY_MAX_DISTANCE..=q_len + FUZZY_MAX_DISTANCE {
+            for term in self.by_len.get(&len).into_iter().flatten() {
+                if term != q_term && term_similarity(q_term, term) > 0.0 {
+                    matching.push(&self.postings[term]);
+                }
+            }
+        }
+        matching
+    }
+}
+
+pub struct SearchIndex {
+    index_path: PathBuf,
+    tokenizer: Tokenizer,
+    terms: Vocabulary,
+    docs: HashMap<SwarmName, HashMap<ContentID, Fields>>,
+    stats: HashMap<SwarmName, SwarmStats>,
+    swarms: HashMap<SwarmName, SwarmMeta>,
+    contents: HashMap<SwarmName, HashMap<ContentID, ContentMeta>>,
+    // For every Body term: page index and byte offset within that page
+    positions: HashMap<SwarmName, HashMap<ContentID, Vec<(u16, u32)>>>,
+    hashes: HashMap<SwarmName, SwarmHashes>,
+    // Swarms changed since they were last written to disk
+    dirty: HashSet<SwarmName>,
+}
+
+// Statistics required for BM25, kept for every Swarm
+#[derive(Default)]
+struct SwarmStats {
+    // sum of weighted lengths of all documents
+    total_len: f32,
+    // how many documents contain given term
+    doc_freq: HashMap<String, u32>,
+}
+impl SwarmStats {
+    fn add(&mut self, fields: &Fields) {
+        let mut distinct = HashSet::new();
+        for (field, terms) in fields {
+            self.total_len += field.weight() * terms.len() as f32;
+            for term in terms {
+                distinct.insert(term);
+            }
+        }
+        for term in distinct {
+            *self.doc_freq.entry(term.clone()).or_default() += 1;
+        }
+    }
+    fn remove(&mut self, fields: &Fields) {
+        let mut distinct = HashSet::new();
+        for (field, terms) in fields {
+            self.total_len -= field.weight() * terms.len() as f32;
+            for term in terms {
+                distinct.insert(term);
+            }
+        }
+        for term in distinct {
+            if let Some(count) = self.doc_freq.get_mut(term) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    self.doc_freq.remove(term);
+                }
+            }
+        }
+        if self.total_len < 0.0 {
+            self.total_len = 0.0;
+        }
+    }
+}
+
+// Tokenizer pipeline, same one has to be used for both indexing and querying:
+// 1. Unicode NFKC normalization, so that for example "ﬁ" becomes "fi",
+// 2. case folding,
+// 3. punctuation stripping - text is split on every non alphanumeric char,
+//    apostrophes are simply removed, so "don't" becomes "dont",
+// 4. optional stemming of english words.
+#[derive(Clone, Copy, Debug)]
+pub struct Tokenizer {
+    stemming: bool,
+}
+impl Tokenizer {
+    pub fn new(stemming: bool) -> Self {
+        Tokenizer { stemming }
+    }
+
+    pub fn stemming(&self) -> bool {
+        self.stemming
+    }
+
+    pub fn tokenize(&self, text: &str) -> Vec<String> {
+        let normalized: String = text.nfkc().collect();
+        let folded = normalized.to_lowercase().replace(['\'', '’'], "");
+        let mut terms = vec![];
+        for word in folded.split(|c: char| !c.is_alphanumeric()) {
+            if word.is_empty() {
+                continue;
+            }
+            if self.stemming {
+                terms.push(stem(word));
+            } else {
+                terms.push(word.to_string());
+            }
+        }
+        terms
+    }
+
+    /// Same as tokenize, but every term comes with a byte offset
+    /// of the word it was produced from within given text.
+    pub fn tokenize_with_offsets(&self, text: &str) -> Vec<(usize, String)> {
+        let mut terms = vec![];
+        let mut word_start = None;
+        for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+            if c.is_alphanumeric() || c == '\'' || c == '’' {
+                if word_start.is_none() {
+                    word_start = Some(i);
+                }
+            } else if let Some(start) = word_start.take() {
+                for term in self.tokenize(&text[start..i]) {
+                    terms.push((start, term));
+                }
+            }
+        }
+        terms
+    }
+}
+
+// A light english suffix stripper,
+// it only needs to map different forms of a word onto the same term,
+// resulting term does not have to be a valid word.
+fn stem(word: &str) -> String {
+    // Words containing non ascii chars are probably not english
+    if !word.is_ascii() || word.len() < 4 {
+        return word.to_string();
+    }
+    let mut stem = word.to_string();
+    if let Some(s) = stem.strip_suffix("sses") {
+        stem = format!("{}ss", s);
+    } else if let Some(s) = stem.strip_suffix("ies") {
+        stem = format!("{}y", s);
+    } else if !stem.ends_with("ss") && !stem.ends_with("us") && !stem.ends_with("is") {
+        if let Some(s) = stem.strip_suffix('s') {
+            stem = s.to_string();
+        }
+    }
+    for suffix in ["ingly", "edly", "ing", "ed", "ly", "ment", "ness"] {
+        if let Some(s) = stem.strip_suffix(suffix) {
+            if s.len() >= 3 {
+                stem = s.to_string();
+                // "running" -> "runn" -> "run"
+                let b = stem.as_bytes();
+                let l = b.len();
+                if b[l - 1] == b[l - 2] && !matches!(b[l - 1], b'l' | b's' | b'z') {
+                    stem.pop();
+                }
+            }
+            break;
+        }
+    }
+    // "parse", "parses" and "parsed" all become "pars"
+    if stem.len() > 4 && stem.ends_with('e') {
+        stem.pop();
+    }
+    stem
+}
+
+// Short terms are more likely to be mistyped without user noticing,
+// so we allow a single edit for those.
+// Terms shorter than FUZZY_MIN_LEN are matched exactly,
+// since almost every other short term is within a single edit.
+const FUZZY_MIN_LEN: usize = 4;
+const FUZZY_MAX_LEN: usize = 8;
+const FUZZY_MAX_DISTANCE: usize = 1;
+// Fuzzy matched terms are worth less than exact matches
+const FUZZY_WEIGHT: f32 = 0.5;
+
+/// Returns how well given document term matches given query term:
+/// 1.0 for exact match, FUZZY_WEIGHT when within allowed edit distance,
+/// 0.0 otherwise.
+pub fn term_similarity(q_term: &str, d_term: &str) -> f32 {
+    if q_term == d_term {
+        return 1.0;
+    }
+    let q_len = q_term.chars().count();
+    if !(FUZZY_MIN_LEN..=FUZZY_MAX_LEN).contains(&q_len) {
+        return 0.0;
+    }
+    if edit_distance(q_term, d_term, FUZZY_MAX_DISTANCE) <= FUZZY_MAX_DISTANCE {
+        FUZZY_WEIGHT
+    } else {
+        0.0
+    }
+}
+
+// Levenshtein distance, calculation stops once it is certain
+// that distance exceeds max, in such case max + 1 is returned.
+fn edit_distance(a: &str, b: &str, max: usize) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return max + 1;
+    }
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        curr[0] = i;
+        let mut row_min = curr[0];
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            curr[j] = (prev[j] + 1).min(curr[j - 1] + 1).min(prev[j - 1] + cost);
+            row_min = row_min.min(curr[j]);
+        }
+        if row_min > max {
+            return max + 1;
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    prev[b.len()]
+}
+
+impl SearchIndex {
+    pub fn new(search_path: &Path, tokenizer: Tokenizer) -> Self {
+        // Terms produced with and without stemming are different,
+        // so we keep a separate index for each mode.
+        let index_path = if tokenizer.stemming {
+            search_path.join("index_stemmed")
+        } else {
+            search_path.join("index")
+        };
+        if !fs::exists(index_path.clone()).unwrap_or(false) {
+            let _ = fs::create_dir_all(index_path.clone());
+        }
+        let mut index = SearchIndex {
+            index_path: index_path.clone(),
+            tokenizer,
+            terms: Vocabulary::default(),
+            docs: HashMap::new(),
+            stats: HashMap::new(),
+            swarms: HashMap::new(),
+            contents: HashMap::new(),
+            positions: HashMap::new(),
+            hashes: HashMap::new(),
+            dirty: HashSet::new(),
+        };
+        if let Ok(dir) = fs::read_dir(index_path) {
+            for f_name in dir.into_iter().flatten() {
+                let mut bytes = vec![];
+                if let Ok(mut fl) = File::open(f_name.path()) {
+                    if fl.read_to_end(&mut bytes).is_ok() {
+                        index.load_swarm(bytes);
+                    }
+                }
+            }
+        }
+        index
+    }
+
+    fn load_swarm(&mut self, bytes: Vec<u8>) {
+        let mut iter = bytes.into_iter();
+        let Some(s_name) = read_swarm_name(&mut iter) else {
+            eprintln!("Unable to read SwarmName from index file");
+            return;
+        };
+        while let Some(record) = read_record(&mut iter) {
+            match record {
+                Record::Terms(c_id, field, terms) => {
+                    for term in &terms {
+                        self.terms
+                            .insert(term, Posting(s_name.clone(), c_id, field));
+                    }
+                    self.docs
+                        .entry(s_name.clone())
+                        .or_default()
+                        .entry(c_id)
+                        .or_default()
+                        .push((field, terms));
+                }
+                Record::Positions(c_id, positions) => {
+                    self.positions
+                        .entry(s_name.clone())
+                        .or_default()
+                        .insert(c_id, positions);
+                }
+                Record::Content(c_id, c_meta) => {
+                    self.contents
+                        .entry(s_name.clone())
+                        .or_default()
+                        .insert(c_id, c_meta);
+                }
+                Record::Swarm(s_meta) => {
+                    self.swarms.insert(s_name.clone(), s_meta);
+                }
+                Record::ContentHash(c_id, typed_hash) => {
+                    self.hashes
+                        .entry(s_name.clone())
+                        .or_default()
+                        .contents
+                        .insert(c_id, typed_hash);
+                }
+                Record::SwarmHash(root_hash) => {
+                    self.hashes.entry(s_name.clone()).or_default().root = Some(root_hash);
+                }
+                Record::PageHash(c_id, page_hash) => {
+                    self.hashes
+                        .entry(s_name.clone())
+                        .or_default()
+                        .pages
+                        .insert(c_id, page_hash);
+                }
+            }
+        }
+        if let Some(s_docs) = self.docs.get(&s_name) {
+            let mut s_stats = SwarmStats::default();
+            for fields in s_docs.values() {
+                s_stats.add(fields);
+            }
+            self.stats.insert(s_name, s_stats);
+        }
+    }
+
+    pub fn tokenizer(&self) -> Tokenizer {
+        self.tokenizer
+    }
+
+    /// Store AppType and Tags defined in Swarm's Manifest.
+    /// Changes are written to disk with next flush.
+    pub fn set_swarm_meta(&mut self, s_name: &SwarmName, s_meta: SwarmMeta) {
+        self.dirty.insert(s_name.clone());
+        self.swarms.insert(s_name.clone(), s_meta);
+    }
+
+    pub fn swarm_meta(&self, s_name: &SwarmName) -> Option<&SwarmMeta> {
+        self.swarms.get(s_name)
+    }
+
+    pub fn content_meta(&self, s_name: &SwarmName, c_id: ContentID) -> Option<&ContentMeta> {
+        self.contents
+            .get(s_name)
+            .and_then(|c_metas| c_metas.get(&c_id))
+    }
+
+    /// Datastore root hash of given Swarm from the last time
+    /// all of it's Contents were indexed.
+    pub fn root_hash(&self, s_name: &SwarmName) -> Option<u64> {
+        self.hashes.get(s_name).and_then(|s_hashes| s_hashes.root)
+    }
+
+    /// Returns ids of Contents that have different typed hashes
+    /// than those they were indexed with, including Manifest.
+    pub fn changed_contents(
+        &self,
+        s_name: &SwarmName,
+        c_hashes: &[(DataType, u64)],
+    ) -> Vec<ContentID> {
+        let empty = HashMap::new();
+        let indexed = self
+            .hashes
+            .get(s_name)
+            .map(|s_hashes| &s_hashes.contents)
+            .unwrap_or(&empty);
+        let mut changed = vec![];
+        for (c_id, typed_hash) in c_hashes.iter().enumerate() {
+            let c_id = c_id as ContentID;
+            if indexed.get(&c_id) != Some(typed_hash) {
+                changed.push(c_id);
+            }
+        }
+        changed
+    }
+
+    /// Typed hash given Content had when it was indexed.
+    pub fn content_hash(&self, s_name: &SwarmName, c_id: ContentID) -> Option<(DataType, u64)> {
+        self.hashes
+            .get(s_name)
+            .and_then(|s_hashes| s_hashes.contents.get(&c_id))
+            .copied()
+    }
+
+    /// Store typed hash of a Content that was just indexed.
+    /// Changes are written to disk with next flush.
+    pub fn set_content_hash(
+        &mut self,
+        s_name: &SwarmName,
+        c_id: ContentID,
+        typed_hash: (DataType, u64),
+    ) {
+        self.dirty.insert(s_name.clone());
+        self.hashes
+            .entry(s_name.clone())
+            .or_default()
+            .contents
+            .insert(c_id, typed_hash);
+    }
+
+    /// Hash of given Content's first page from when it was indexed.
+    pub fn page_hash(&self, s_name: &SwarmName, c_id: ContentID) -> Option<u64> {
+        self.hashes
+            .get(s_name)
+            .and_then(|s_hashes| s_hashes.pages.get(&c_id))
+            .copied()
+    }
+
+    /// Store hash of a Content's first page that was just indexed.
+    /// Changes are written to disk with next flush.
+    pub fn set_page_hash(&mut self, s_name: &SwarmName, c_id: ContentID, page_hash: u64) {
+        self.dirty.insert(s_name.clone());
+        self.hashes
+            .entry(s_name.clone())
+            .or_default()
+            .pages
+            .insert(c_id, page_hash);
+    }
+
+    /// Store Datastore root hash of given Swarm, but only when
+    /// all of it's Contents were indexed with provided typed hashes.
+    /// Otherwise root hash is cleared, so that we do not skip this Swarm
+    /// next time it gets synced.
+    /// Changes are written to disk with next flush.
+    pub fn set_root_hash(
+        &mut self,
+        s_name: &SwarmName,
+        root_hash: u64,
+        c_hashes: &[(DataType, u64)],
+    ) -> bool {
+        let all_indexed = self.changed_contents(s_name, c_hashes).is_empty();
+        // Contents past Datastore's end were removed
+        let mut removed: HashSet<ContentID> = HashSet::new();
+        if let Some(s_docs) = self.docs.get(s_name) {
+            removed.extend(s_docs.keys());
+        }
+        if let Some(c_metas) = self.contents.get(s_name) {
+            removed.extend(c_metas.keys());
+        }
+        if let Some(s_positions) = self.positions.get(s_name) {
+            removed.extend(s_positions.keys());
+        }
+        removed.retain(|c_id| (*c_id as usize) >= c_hashes.len());
+        for c_id in removed {
+            self.remove_content(s_name, c_id);
+        }
+        let s_hashes = self.hashes.entry(s_name.clone()).or_default();
+        s_hashes
+            .contents
+            .retain(|c_id, _h| (*c_id as usize) < c_hashes.len());
+        s_hashes
+            .pages
+            .retain(|c_id, _h| (*c_id as usize) < c_hashes.len());
+        s_hashes.root = if all_indexed { Some(root_hash) } else { None };
+        self.dirty.insert(s_name.clone());
+        all_indexed
+    }
+
+    // Drop all terms, metadata and positions of given Content
+    fn remove_content(&mut self, s_name: &SwarmName, c_id: ContentID) {
+        if let Some(old_fields) = self
+            .docs
+            .get_mut(s_name)
+            .and_then(|s_docs| s_docs.remove(&c_id))
+        {
+            if let Some(s_stats) = self.stats.get_mut(s_name) {
+                s_stats.remove(&old_fields);
+            }
+            for (field, terms) in old_fields {
+                let posting = Posting(s_name.clone(), c_id, field);
+                for term in terms {
+                    self.terms.remove(&term, &posting);
+                }
+            }
+        }
+        if let Some(c_metas) = self.contents.get_mut(s_name) {
+            c_metas.remove(&c_id);
+        }
+        if let Some(s_positions) = self.positions.get_mut(s_name) {
+            s_positions.remove(&c_id);
+        }
+        self.dirty.insert(s_name.clone());
+    }
+
+    /// Replace all indexed fields of given Content with provided texts.
+    /// When body pages are provided, they are indexed as a Body field.
+    /// Changes are written to disk with next flush.
+    pub fn update(
+        &mut self,
+        s_name: &SwarmName,
+        c_id: ContentID,
+        c_meta: ContentMeta,
+        fields: Vec<(Field, String)>,
+        body: Vec<Data>,
+    ) {
+        let mut new_fields = Vec::with_capacity(fields.len() + 1);
+        for (field, text) in fields {
+            let terms = self.tokenizer.tokenize(&text);
+            if !terms.is_empty() {
+                new_fields.push((field, terms));
+            }
+        }
+        let (body_terms, new_positions) = self.tokenize_body(body);
+        if !body_terms.is_empty() {
+            new_fields.push((Field::Body, body_terms));
+        }
+        let s_positions = self.positions.entry(s_name.clone()).or_default();
+        let positions_changed = match s_positions.get(&c_id) {
+            Some(old_positions) => *old_positions != new_positions,
+            None => !new_positions.is_empty(),
+        };
+        if new_positions.is_empty() {
+            s_positions.remove(&c_id);
+        } else {
+            s_positions.insert(c_id, new_positions);
+        }
+        let c_metas = self.contents.entry(s_name.clone()).or_default();
+        let meta_changed = c_metas.get(&c_id) != Some(&c_meta);
+        c_metas.insert(c_id, c_meta);
+        let s_docs = self.docs.entry(s_name.clone()).or_default();
+        if let Some(old_fields) = s_docs.get(&c_id) {
+            if *old_fields == new_fields && !meta_changed && !positions_changed && c_id > 0 {
+                return;
+            }
+        }
+        let s_stats = self.stats.entry(s_name.clone()).or_default();
+        if let Some(old_fields) = s_docs.remove(&c_id) {
+            s_stats.remove(&old_fields);
+            for (field, terms) in old_fields {
+                let posting = Posting(s_name.clone(), c_id, field);
+                for term in terms {
+                    self.terms.remove(&term, &posting);
+                }
+            }
+        }
+        for (field, terms) in &new_fields {
+            for term in terms {
+                self.terms
+                    .insert(term, Posting(s_name.clone(), c_id, *field));
+            }
+        }
+        if !new_fields.is_empty() {
+            s_stats.add(&new_fields);
+            s_docs.insert(c_id, new_fields);
+        }
+        self.dirty.insert(s_name.clone());
+    }
+
+    // Body pages may contain invalid UTF-8 sequences,
+    // so we only tokenize valid parts, keeping their byte offsets.
+    fn tokenize_body(&self, body: Vec<Data>) -> (Vec<String>, Vec<(u16, u32)>) {
+        let mut terms = vec![];
+        let mut positions = vec![];
+        'pages: for (page_no, page) in body.into_iter().enumerate() {
+            let mut base = 0;
+            for chunk in page.ref_bytes().utf8_chunks() {
+                for (offset, term) in self.tokenizer.tokenize_with_offsets(chunk.valid()) {
+                    if terms.len() >= MAX_BODY_TERMS {
+                        break 'pages;
+                    }
+                    if term.len() > 255 {
+                        continue;
+                    }
+                    terms.push(term);
+                    positions.push((page_no as u16, (base + offset) as u32));
+                }
+                base += chunk.valid().len() + chunk.invalid().len();
+            }
+        }
+        (terms, positions)
+    }
+
+    /// Write every Swarm that changed since last flush to disk.
+    pub async fn flush(&mut self) {
+        for s_name in std::mem::take(&mut self.dirty) {
+            self.store_swarm(&s_name).await;
+        }
+    }
+
+    async fn store_swarm(&self, s_name: &SwarmName) {
+        let mut bytes = Vec::with_capacity(1024);
+        let name_bytes = s_name.as_bytes();
+        bytes.extend_from_slice(&(name_bytes.len() as u16).to_be_bytes());
+        bytes.extend(name_bytes);
+        if let Some(s_meta) = self.swarms.get(s_name) {
+            bytes.extend_from_slice(&0u16.to_be_bytes());
+            bytes.push(SWARM_META);
+            if let Some(app_type) = s_meta.app_type {
+                bytes.push(1);
+                bytes.push(app_type.byte());
+            } else {
+                bytes.push(0);
+                bytes.push(0);
+            }
+            let mut stored_tags = Vec::with_capacity(s_meta.tags.len());
+            for (t_id, t_name) in &s_meta.tags {
+                if t_name.len() <= 255 {
+                    stored_tags.push((t_id, t_name));
+                }
+            }
+            bytes.extend_from_slice(&(stored_tags.len() as u16).to_be_bytes());
+            for (t_id, t_name) in stored_tags {
+                bytes.push(*t_id);
+                bytes.push(t_name.len() as u8);
+                bytes.extend_from_slice(t_name.as_bytes());
+            }
+            let mut stored_d_types = Vec::with_capacity(s_meta.d_types.len());
+            for (d_id, d_name) in &s_meta.d_types {
+                if d_name.len() <= 255 {
+                    stored_d_types.push((d_id, d_name));
+                }
+            }
+            bytes.extend_from_slice(&(stored_d_types.len() as u16).to_be_bytes());
+            for (d_id, d_name) in stored_d_types {
+                bytes.push(*d_id);
+                bytes.push(d_name.len() as u8);
+                bytes.extend_from_slice(d_name.as_bytes());
+            }
+            bytes.extend_from_slice(&(s_meta.tag_translations.len() as u16).to_be_bytes());
+            for (t_id, t_names) in &s_meta.tag_translations {
+                let stored_names: Vec<&String> = t_names
+                    .iter()
+                    .filter(|t_name| t_name.len() <= 255)
+                    .take(255)
+                    .collect();
+                bytes.push(*t_id);
+                bytes.push(stored_names.len() as u8);
+                for t_name in stored_names {
+                    bytes.push(t_name.len() as u8);
+                    bytes.extend_from_slice(t_name.as_bytes());
+                }
+            }
+        }
+        if let Some(c_metas) = self.contents.get(s_name) {
+            for (c_id, c_meta) in c_metas {
+                bytes.extend_from_slice(&c_id.to_be_bytes());
+                bytes.push(CONTENT_META);
+                bytes.push(c_meta.d_type.byte());
+                bytes.extend_from_slice(&(c_meta.tag_ids.len() as u16).to_be_bytes());
+                bytes.extend_from_slice(&c_meta.tag_ids);
+            }
+        }
+        if let Some(s_hashes) = self.hashes.get(s_name) {
+            if let Some(root_hash) = s_hashes.root {
+                bytes.extend_from_slice(&0u16.to_be_bytes());
+                bytes.push(SWARM_HASH);
+                bytes.extend_from_slice(&root_hash.to_be_bytes());
+            }
+            for (c_id, (d_type, c_hash)) in &s_hashes.contents {
+                bytes.extend_from_slice(&c_id.to_be_bytes());
+                bytes.push(CONTENT_HASH);
+                bytes.push(d_type.byte());
+                bytes.extend_from_slice(&c_hash.to_be_bytes());
+            }
+            for (c_id, page_hash) in &s_hashes.pages {
+                bytes.extend_from_slice(&c_id.to_be_bytes());
+                bytes.push(PAGE_HASH);
+                bytes.extend_from_slice(&page_hash.to_be_bytes());
+            }
+        }
+        let empty = HashMap::new();
+        let s_docs = self.docs.get(s_name).unwrap_or(&empty);
+        for (c_id, fields) in s_docs {
+            for (field, terms) in fields {
+                bytes.extend_from_slice(&c_id.to_be_bytes());
+                bytes.push(field.byte());
+                let mut stored_terms = Vec::with_capacity(terms.len());
+                for term in terms {
+                    if term.len() <= 255 {
+                        stored_terms.push(term);
+                    }
+                }
+                bytes.extend_from_slice(&(stored_terms.len() as u16).to_be_bytes());
+                for term in stored_terms {
+                    bytes.push(term.len() as u8);
+                    bytes.extend_from_slice(term.as_bytes());
+                }
+                if *field != Field::Body {
+                    continue;
+                }
+                if let Some(positions) = self
+                    .positions
+                    .get(s_name)
+                    .and_then(|s_positions| s_positions.get(c_id))
+                {
+                    bytes.extend_from_slice(&c_id.to_be_bytes());
+                    bytes.push(BODY_POSITIONS);
+                    bytes.extend_from_slice(&(positions.len() as u16).to_be_bytes());
+                    for (page_no, offset) in positions {
+                        bytes.extend_from_slice(&page_no.to_be_bytes());
+                        bytes.extend_from_slice(&offset.to_be_bytes());
+                    }
+                }
+            }
+        }
+        let f_path = self.index_path.join(swarm_file_name(s_name));
+        if let Err(e) = smol::fs::write(f_path, bytes).await {
+            eprintln!("Failed to store search index for {}: {:?}", s_name, e);
+        }
+    }
+
+    /// Returns every Content containing at least one of given terms.
+    /// When no terms are given every indexed Content is returned.
+    pub fn candidates(&self, q_terms: &[String]) -> HashSet<(SwarmName, ContentID)> {
+        let mut candidates = HashSet::new();
+        if q_terms.is_empty() {
+            for (s_name, c_metas) in &self.contents {
+                for c_id in c_metas.keys() {
+                    candidates.insert((s_name.clone(), *c_id));
+                }
+            }
+            for (s_name, s_docs) in &self.docs {
+                for c_id in s_docs.keys() {
+                    candidates.insert((s_name.clone(), *c_id));
+                }
+            }
+            return candidates;
+        }
+        for q_term in q_terms {
+            for postings in self.terms.matching(q_term) {
+                for Posting(s_name, c_id, _field) in postings {
+                    candidates.insert((s_name.clone(), *c_id));
+                }
+            }
+        }
+        candidates
+    }
+
+    /// Check if given terms occur one after another within a single field
+    pub fn contains_phrase(&self, s_name: &SwarmName, c_id: ContentID, phrase: &[String]) -> bool {
+        if phrase.is_empty() {
+            return true;
+        }
+        if let Some(fields) = self.docs.get(s_name).and_then(|s_docs| s_docs.get(&c_id)) {
+            for (_field, terms) in fields {
+                if terms.windows(phrase.len()).any(|w| w == phrase) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Returns all fields of given Content that contain any of given terms.
+    /// For Tags we also return names of matching Tags.
+    pub fn matched_on(
+        &self,
+        q_terms: &[String],
+        s_name: &SwarmName,
+        c_id: ContentID,
+    ) -> Vec<MatchedOn> {
+        let mut matched_on = vec![];
+        let Some(fields) = self.docs.get(s_name).and_then(|s_docs| s_docs.get(&c_id)) else {
+            return matched_on;
+        };
+        let is_similar = |terms: &Vec<String>| {
+            terms.iter().any(|term| {
+                q_terms
+                    .iter()
+                    .any(|q_term| term_similarity(q_term, term) > 0.0)
+            })
+        };
+        let empty = SwarmMeta::default();
+        let s_meta = self.swarms.get(s_name).unwrap_or(&empty);
+        for (field, terms) in fields {
+            if !is_similar(terms) {
+                continue;
+            }
+            match field {
+                Field::Description => matched_on.push(MatchedOn::Description),
+                Field::Header => matched_on.push(MatchedOn::Header),
+                Field::ManifestTag => {
+                    for t_id in s_meta.tags.keys() {
+                        for t_name in s_meta.tag_names(*t_id) {
+                            if is_similar(&self.tokenizer.tokenize(t_name)) {
+                                matched_on.push(MatchedOn::ManifestTag(t_name.clone()));
+                            }
+                        }
+                    }
+                }
+                Field::ContentTag => {
+                    let Some(c_meta) = self.content_meta(s_name, c_id) else {
+                        continue;
+                    };
+                    for t_id in &c_meta.tag_ids {
+                        for t_name in s_meta.tag_names(*t_id) {
+                            if is_similar(&self.tokenizer.tokenize(t_name)) {
+                                matched_on.push(MatchedOn::ContentTag(t_name.clone()));
+                            }
+                        }
+                    }
+                }
+                Field::Body => {
+                    if let Some(snippet) = self.snippet(q_terms, s_name, c_id, terms) {
+                        matched_on.push(MatchedOn::Body(snippet));
+                    }
+                }
+            }
+        }
+        matched_on
+    }
+
+    // Snippet is built around first Body term similar to any of Query terms.
+    // We do not keep original text, so Snippet consists of normalized terms,
+    // page index and offset can be used to locate original text.
+    fn snippet(
+        &self,
+        q_terms: &[String],
+        s_name: &SwarmName,
+        c_id: ContentID,
+        body_terms: &[String],
+    ) -> Option<Snippet> {
+        let positions = self.positions.get(s_name)?.get(&c_id)?;
+        let idx = body_terms.iter().position(|term| {
+            q_terms
+                .iter()
+                .any(|q_term| term_similarity(q_term, term) > 0.0)
+        })?;
+        let (page, offset) = *positions.get(idx)?;
+        let first = idx.saturating_sub(SNIPPET_RADIUS);
+        let last = (idx + SNIPPET_RADIUS + 1).min(body_terms.len());
+        Some(Snippet {
+            page,
+            offset,
+            text: body_terms[first..last].join(" "),
+        })
+    }
+
+    /// Calculate BM25 score of given Content against given Query terms.
+    /// Returns None if none of the terms was found.
+    pub fn score(&self, q_terms: &[String], s_name: &SwarmName, c_id: ContentID) -> Option<Score> {
+        if q_terms.is_empty() {
+            return None;
+        }
+        let s_docs = self.docs.get(s_name)?;
+        let fields = s_docs.get(&c_id)?;
+        let s_stats = self.stats.get(s_name)?;
+        let doc_count = s_docs.len() as f32;
+        let avg_len = if s_stats.total_len > 0.0 {
+            s_stats.total_len / doc_count
+        } else {
+            1.0
+        };
+        let mut doc_len = 0.0;
+        let mut phrase = false;
+        for (field, terms) in fields {
+            doc_len += field.weight() * terms.len() as f32;
+            if terms.windows(q_terms.len()).any(|w| w == q_terms) {
+                phrase = true;
+            }
+        }
+        let mut distinct = HashSet::new();
+        for term in q_terms {
+            distinct.insert(term);
+        }
+        let mut value = 0.0;
+        let mut matched = 0;
+        for q_term in &distinct {
+            let mut tf = 0.0;
+            // In case there is no exact match we use document frequency
+            // of a fuzzy matched term
+            let mut df_term = *q_term;
+            for (field, terms) in fields {
+                for term in terms {
+                    let similarity = term_similarity(q_term, term);
+                    if similarity > 0.0 {
+                        tf += field.weight() * similarity;
+                        if similarity < 1.0 && !s_stats.doc_freq.contains_key(df_term) {
+                            df_term = term;
+                        }
+                    }
+                }
+            }
+            if tf == 0.0 {
+                continue;
+            }
+            matched += 1;
+            let df = *s_stats.doc_freq.get(df_term).unwrap_or(&0) as f32;
+            let idf = ((doc_count - df + 0.5) / (df + 0.5) + 1.0).ln();
+            value += idf * (tf * (K1 + 1.0)) / (tf + K1 * (1.0 - B + B * doc_len / avg_len));
+        }
+        if matched == 0 {
+            return None;
+        }
+        if phrase {
+            value *= PHRASE_BOOST;
+        }
+        Some(Score {
+            value,
+            matched,
+            total: distinct.len() as u8,
+            phrase,
+        })
+    }
+}
+
+fn swarm_file_name(s_name: &SwarmName) -> String {
+    format!("{}", sha_hash(&s_name.as_bytes()))
+}
+
+fn read_swarm_name(iter: &mut impl Iterator<Item = u8>) -> Option<SwarmName> {
+    let len = u16::from_be_bytes([iter.next()?, iter.next()?]);
+    let mut name_bytes = Vec::with_capacity(len as usize);
+    for _i in 0..len {
+        name_bytes.push(iter.next()?);
+    }
+    SwarmName::from(&name_bytes).ok()
+}
+
+fn read_u64(iter: &mut impl Iterator<Item = u8>) -> Option<u64> {
+    let mut bytes = [0u8; 8];
+    for byte in bytes.iter_mut() {
+        *byte = iter.next()?;
+    }
+    Some(u64::from_be_bytes(bytes))
+}
+
+fn read_record(iter: &mut impl Iterator<Item = u8>) -> Option<Record> {
+    let c_id = u16::from_be_bytes([iter.next()?, iter.next()?]);
+    let kind = iter.next()?;
+    match kind {
+        BODY_POSITIONS => {
+            let count = u16::from_be_bytes([iter.next()?, iter.next()?]);
+            let mut positions = Vec::with_capacity(count as usize);
+            for _i in 0..count {
+                let page_no = u16::from_be_bytes([iter.next()?, iter.next()?]);
+                let offset =
+                    u32::from_be_bytes([iter.next()?, iter.next()?, iter.next()?, iter.next()?]);
+                positions.push((page_no, offset));
+            }
+            Some(Record::Positions(c_id, positions))
+        }
+        PAGE_HASH => Some(Record::PageHash(c_id, read_u64(iter)?)),
+        SWARM_HASH => Some(Record::SwarmHash(read_u64(iter)?)),
+        CONTENT_HASH => {
+            let d_type = DataType::from(iter.next()?);
+            Some(Record::ContentHash(c_id, (d_type, read_u64(iter)?)))
+        }
+        CONTENT_META => {
+            let d_type = DataType::from(iter.next()?);
+            let count = u16::from_be_bytes([iter.next()?, iter.next()?]);
+            let mut tag_ids = Vec::with_capacity(count as usize);
+            for _i in 0..count {
+                tag_ids.push(iter.next()?);
+            }
+            Some(Record::Content(c_id, ContentMeta { d_type, tag_ids }))
+        }
+        SWARM_META => {
+            let has_app_type = iter.next()? == 1;
+            let app_type_byte = iter.next()?;
+            let app_type = if has_app_type {
+                Some(AppType::from(app_type_byte))
+            } else {
+                None
+            };
+            let count = u16::from_be_bytes([iter.next()?, iter.next()?]);
+            let mut tags = HashMap::with_capacity(count as usize);
+            for _i in 0..count {
+                let t_id = iter.next()?;
+                let t_len = iter.next()?;
+                let mut t_bytes = Vec::with_capacity(t_len as usize);
+                for _j in 0..t_len {
+                    t_bytes.push(iter.next()?);
+                }
+                tags.insert(t_id, String::from_utf8(t_bytes).ok()?);
+            }
+            let count = u16::from_be_bytes([iter.next()?, iter.next()?]);
+            let mut d_types = HashMap::with_capacity(count as usize);
+            for _i in 0..count {
+                let d_id = iter.next()?;
+                let d_len = iter.next()?;
+                let mut d_bytes = Vec::with_capacity(d_len as usize);
+                for _j in 0..d_len {
+                    d_bytes.push(iter.next()?);
+                }
+                d_types.insert(d_id, String::from_utf8(d_bytes).ok()?);
+            }
+            let count = u16::from_be_bytes([iter.next()?, iter.next()?]);
+            let mut tag_translations = HashMap::with_capacity(count as usize);
+            for _i in 0..count {
+                let t_id = iter.next()?;
+                let names_count = iter.next()?;
+                let mut t_names = Vec::with_capacity(names_count as usize);
+                for _j in 0..names_count {
+                    let t_len = iter.next()?;
+                    let mut t_bytes = Vec::with_capacity(t_len as usize);
+                    for _k in 0..t_len {
+                        t_bytes.push(iter.next()?);
+                    }
+                    t_names.push(String::from_utf8(t_bytes).ok()?);
+                }
+                tag_translations.insert(t_id, t_names);
+            }
+            Some(Record::Swarm(SwarmMeta {
+                app_type,
+                tags,
+                d_types,
+                tag_translations,
+            }))
+        }
+        other => {
+            let field = Field::from(other)?;
+            let count = u16::from_be_bytes([iter.next()?, iter.next()?]);
+            let mut terms = Vec::with_capacity(count as usize);
+            for _i in 0..count {
+                let t_len = iter.next()?;
+                let mut t_bytes = Vec::with_capacity(t_len as usize);
+                for _j in 0..t_len {
+                    t_bytes.push(iter.next()?);
+                }
+                terms.push(String::from_utf8(t_bytes).ok()?);
+            }
+            Some(Record::Terms(c_id, field, terms))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gnome::prelude::GnomeId;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dapp-lib-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn swarm_name() -> SwarmName {
+        SwarmName::new(GnomeId(1), "/test".to_string()).unwrap()
+    }
+
+    fn meta() -> ContentMeta {
+        ContentMeta {
+            d_type: DataType::from(0),
+            tag_ids: vec![],
+        }
+    }
+
+    #[test]
+    fn tokenizer_normalizes_and_folds() {
+        let tokenizer = Tokenizer::new(false);
+        assert_eq!(
+            tokenizer.tokenize("Don't ﬁnd THE-cat, 42!"),
+            vec!["dont", "find", "the", "cat", "42"]
+        );
+        assert!(tokenizer.tokenize(" ,.- ").is_empty());
+    }
+
+    #[test]
+    fn tokenizer_stems_english_words() {
+        let tokenizer = Tokenizer::new(true);
+        assert_eq!(
+            tokenizer.tokenize("running parses parsed classes"),
+            vec!["run", "pars", "pars", "class"]
+        );
+        // Non ascii and short words are left alone
+        assert_eq!(tokenizer.tokenize("żółwie cats"), vec!["żółwie", "cat"]);
+    }
+
+    #[test]
+    fn tokenizer_keeps_byte_offsets() {
+        let tokenizer = Tokenizer::new(false);
+        assert_eq!(
+            tokenizer.tokenize_with_offsets("zażółć gęślą it's"),
+            vec![
+                (0, "zażółć".to_string()),
+                (11, "gęślą".to_string()),
+                (20, "its".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn similarity_allows_single_edit_for_mid_length_terms() {
+        assert_eq!(term_similarity("search", "search"), 1.0);
+        assert_eq!(term_similarity("search", "serch"), FUZZY_WEIGHT);
+        assert_eq!(term_similarity("search", "srch"), 0.0);
+        assert_eq!(term_similarity("cat", "cut"), 0.0);
+        assert_eq!(edit_distance("kitten", "sitting", 5), 3);
+        assert_eq!(edit_distance("kitten", "sitting", 1), 2);
+    }
+
+    #[test]
+    fn vocabulary_matches_exact_and_similar_length_terms() {
+        let s_name = swarm_name();
+        let mut vocabulary = Vocabulary::default();
+        for (c_id, term) in [(1, "search"), (2, "serch"), (3, "searches"), (4, "cat")] {
+            vocabulary.insert(term, Posting(s_name.clone(), c_id, Field::Header));
+        }
+        let c_ids = |vocabulary: &Vocabulary, q_term: &str| {
+            let mut c_ids: Vec<ContentID> = vocabulary
+                .matching(q_term)
+                .into_iter()
+                .flatten()
+                .map(|posting| posting.1)
+                .collect();
+            c_ids.sort();
+            c_ids
+        };
+        assert_eq!(c_ids(&vocabulary, "search"), vec![1, 2]);
+        assert_eq!(c_ids(&vocabulary, "cat"), vec![4]);
+        assert!(c_ids(&vocabulary, "cut").is_empty());
+
+        vocabulary.remove("serch", &Posting(s_name.clone(), 2, Field::Header));
+        assert!(!vocabulary.postings.contains_key("serch"));
+        assert!(!vocabulary.by_len.contains_key(&5));
+        assert_eq!(c_ids(&vocabulary, "search"), vec![1]);
+    }
+
+    #[test]
+    fn bm25_ranks_more_frequent_and_heavier_fields_higher() {
+        let dir = test_dir("bm25");
+        let mut index = SearchIndex::new(&dir, Tokenizer::new(false));
+        let s_name = swarm_name();
+        index.update(
+            &s_name,
+            1,
+            meta(),
+            vec![(Field::Description, "gnome gnome swarm".to_string())],
+            vec![],
+        );
+        index.update(
+            &s_name,
+            2,
+            meta(),
+            vec![(Field::Description, "gnome swarm network".to_string())],
+            vec![],
+        );
+        index.update(
+            &s_name,
+            3,
+            meta(),
+            vec![(Field::Header, "gnome swarm network".to_string())],
+            vec![],
+        );
+        index.update(
+            &s_name,
+            4,
+            meta(),
+            vec![(Field::Description, "unrelated text".to_string())],
+            vec![],
+        );
+        let q_terms = vec!["gnome".to_string()];
+        let s1 = index.score(&q_terms, &s_name, 1).unwrap();
+        let s2 = index.score(&q_terms, &s_name, 2).unwrap();
+        let s3 = index.score(&q_terms, &s_name, 3).unwrap();
+        assert!(s1.value > s2.value);
+        assert!(s3.value > s2.value);
+        assert!(index.score(&q_terms, &s_name, 4).is_none());
+
+        let q_terms = vec!["swarm".to_string(), "network".to_string()];
+        let score = index.score(&q_terms, &s_name, 2).unwrap();
+        assert_eq!((score.matched, score.total), (2, 2));
+        assert!(score.phrase);
+        assert!(!index.score(&q_terms, &s_name, 1).unwrap().phrase);
+        let candidates = index.candidates(&q_terms);
+        assert_eq!(candidates.len(), 3);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn update_replaces_previous_terms() {
+        let dir = test_dir("update");
+        let mut index = SearchIndex::new(&dir, Tokenizer::new(false));
+        let s_name = swarm_name();
+        let text = |t: &str| vec![(Field::Header, t.to_string())];
+        index.update(&s_name, 1, meta(), text("old title"), vec![]);
+        index.update(&s_name, 1, meta(), text("new title"), vec![]);
+        assert!(index.candidates(&["old".to_string()]).is_empty());
+        assert!(index.score(&["new".to_string()], &s_name, 1).is_some());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn contents_past_datastore_end_are_removed() {
+        let dir = test_dir("shrink");
+        let mut index = SearchIndex::new(&dir, Tokenizer::new(false));
+        let s_name = swarm_name();
+        for c_id in 0..3 {
+            let text = vec![(Field::Header, format!("gnome {}", c_id))];
+            index.update(&s_name, c_id, meta(), text, vec![]);
+        }
+        let c_hashes = vec![(DataType::from(0), 0); 2];
+        index.set_root_hash(&s_name, 7, &c_hashes);
+        let candidates = index.candidates(&["gnome".to_string()]);
+        assert_eq!(candidates.len(), 2);
+        assert!(!candidates.contains(&(s_name.clone(), 2)));
+        assert!(index.candidates(&["2".to_string()]).is_empty());
+        assert!(index.content_meta(&s_name, 2).is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn flushed_index_is_loaded_back() {
+        let dir = test_dir("flush");
+        let s_name = swarm_name();
+        let mut s_meta = SwarmMeta {
+            app_type: Some(AppType::Forum),
+            ..Default::default()
+        };
+        s_meta.tags.insert(3, "music".to_string());
+        s_meta
+            .tag_translations
+            .insert(3, vec!["muzyka".to_string(), "Musik".to_string()]);
+        s_meta.d_types.insert(0, "Post".to_string());
+        let c_meta = ContentMeta {
+            d_type: DataType::from(0),
+            tag_ids: vec![3],
+        };
+        let body = vec![Data::new(b"first page".to_vec()).unwrap()];
+        {
+            let mut index = SearchIndex::new(&dir, Tokenizer::new(false));
+            index.set_swarm_meta(&s_name, s_meta.clone());
+            index.update(
+                &s_name,
+                1,
+                c_meta.clone(),
+                vec![(Field::Header, "Some header".to_string())],
+                body,
+            );
+            index.set_page_hash(&s_name, 1, 77);
+            smol::block_on(index.flush());
+        }
+        let index = SearchIndex::new(&dir, Tokenizer::new(false));
+        assert_eq!(index.swarm_meta(&s_name), Some(&s_meta));
+        assert_eq!(index.content_meta(&s_name, 1), Some(&c_meta));
+        assert_eq!(index.page_hash(&s_name, 1), Some(77));
+        assert!(index.score(&["header".to_string()], &s_name, 1).is_some());
+        assert!(index.contains_phrase(&s_name, 1, &["first".to_string(), "page".to_string()]));
+        let names: Vec<&String> = s_meta.tag_names(3).collect();
+        assert_eq!(names, vec!["music", "muzyka", "Musik"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
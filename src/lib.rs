@@ -1,7 +1,38 @@
+use crate::assembly::message_hash;
+use crate::assembly::refused_message;
+use crate::assembly::Assembler;
+use crate::catalogue::CatalogueError;
 use crate::content::data_to_link;
+use crate::data::retag_first_page;
+use crate::history::load_history_from_disk;
+use crate::history::load_page_from_disk;
+use crate::history::remove_unreferenced_pages;
+use crate::history::store_history_on_disk;
+use crate::history::store_pages_on_disk;
+use crate::history::ContentHistory;
+use crate::history::ContentVersion;
+use crate::history::PAGES_GC_AFTER;
+use crate::manifest::ManifestChange;
+use crate::manifest_text::ImportError;
+use crate::message::RequirementsViolation;
 use crate::message::MAX_AVAIL_APP_MSG_ID;
 use crate::prelude::Manifest;
 use crate::prelude::SyncRequirements;
+use crate::prelude::Tag;
+use crate::prelude::TxOperation;
+use crate::proof::proof_backoff;
+use crate::proof::Offense;
+use crate::proof::PageProof;
+use crate::proof::Proof;
+use crate::proof::ProofError;
+use crate::proof::ProofParts;
+use crate::proof::PROOF_MSG_ID;
+use crate::reconcile::ReconcileReport;
+use crate::reconcile::Reconciler;
+use crate::reconcile::Reconciliation;
+use crate::reconcile::SettingChange;
+use crate::reconcile::RECONCILE_TIMEOUT;
+use crate::schema::SchemaError;
 use crate::search::SearchMsg;
 use crate::search::SwarmLink;
 use crate::storage::load_first_pages_from_disk;
@@ -9,24 +40,42 @@ use crate::storage::store_first_pages_on_disk;
 use crate::storage::write_datastore_to_disk;
 use crate::storage::StorageCondition;
 use crate::sync_message::serialize_requests;
+use crate::tracker::RequestTracker;
+use crate::tracker::Retry;
+use crate::undo::UndoLog;
+use crate::undo::UndoOp;
 use std::array;
 use std::collections::VecDeque;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 mod app_type;
+mod assembly;
+mod catalogue;
 mod config;
 mod content;
+mod crawler;
 mod data;
 mod datastore;
 mod error;
+mod federated;
+mod history;
+mod index;
 mod manager;
 mod manifest;
+mod manifest_text;
 mod message;
+mod proof;
+mod reconcile;
 mod registry;
+mod saved_search;
+mod schema;
 mod search;
 mod storage;
 mod sync_message;
+mod tracker;
+mod undo;
+mod util;
 use app_type::AppType;
 // use async_std::fs::create_dir_all;
 use smol::fs::create_dir_all;
@@ -34,6 +83,7 @@ use smol::fs::create_dir_all;
 // use async_std::task::yield_now;
 use message::ChangeContentOperation;
 use search::Hit;
+use search::QueryError;
 use smol::future::yield_now;
 use smol::Executor;
 use std::collections::HashMap;
@@ -48,7 +98,10 @@ use sync_message::SyncRequest;
 use sync_message::SyncResponse;
 
 use crate::content::double_hash;
+use crate::federated::SEARCH_REQUEST;
+use crate::federated::SEARCH_RESPONSE;
 use crate::search::serve_search_engine;
+use crate::search::SearchSettings;
 pub use config::Configuration;
 use content::ContentTree;
 use content::DataType;
@@ -71,6 +124,9 @@ const SYNC_RESPONSE: u8 = 243;
 
 pub mod prelude {
     pub use crate::app_type::AppType;
+    pub use crate::catalogue::CatalogueError;
+    pub use crate::catalogue::MessageDecl;
+    pub use crate::catalogue::MessageSender;
     pub use crate::config::read_storage_rules_from_file;
     pub use crate::config::write_storage_rules_to_file;
     pub use crate::content::{
@@ -80,13 +136,37 @@ pub mod prelude {
     pub use crate::data::read_tags_and_header;
     pub use crate::data::Data;
     pub use crate::error::AppError;
+    pub use crate::history::ContentVersion;
     pub use crate::initialize;
+    pub use crate::manifest::Localization;
     pub use crate::manifest::Manifest;
+    pub use crate::manifest::ManifestChange;
+    pub use crate::manifest::ManifestError;
     pub use crate::manifest::Tag;
+    pub use crate::manifest_text::ImportError;
+    pub use crate::message::RequirementsViolation;
     pub use crate::message::SyncMessage;
     pub use crate::message::SyncMessageType;
     pub use crate::message::SyncRequirements;
+    pub use crate::message::TxOperation;
+    pub use crate::proof::Offense;
+    pub use crate::proof::PageProof;
+    pub use crate::proof::Proof;
+    pub use crate::proof::ProofError;
+    pub use crate::reconcile::ReconcileReport;
+    pub use crate::reconcile::SettingChange;
+    pub use crate::schema::DataSchema;
+    pub use crate::schema::Encoding;
+    pub use crate::schema::FieldSize;
+    pub use crate::schema::SchemaError;
+    pub use crate::schema::SchemaField;
+    pub use crate::schema::Value;
     pub use crate::search::Hit;
+    pub use crate::search::HitState;
+    pub use crate::search::MatchedOn;
+    pub use crate::search::QueryError;
+    pub use crate::search::Score;
+    pub use crate::search::Snippet;
     pub use crate::storage::load_content_from_disk;
     pub use crate::storage::load_first_pages_from_disk;
     pub use crate::storage::read_datastore_from_disk;
@@ -166,6 +246,8 @@ pub enum ToApp {
     ContentChanged(SwarmID, ContentID, DataType, Option<Data>),
     ReadSuccess(SwarmID, SwarmName, ContentID, DataType, u16, Vec<Data>),
     ReadError(SwarmID, ContentID, AppError),
+    ContentVersions(SwarmID, ContentID, Vec<ContentVersion>),
+    ContentAtVersion(SwarmID, ContentID, u32, DataType, Vec<Data>),
     ReadInProgress(SwarmID, ContentID),
     GetCIDsForTags(SwarmID, GnomeId, Vec<u8>, Vec<(ContentID, Data)>),
     FirstPages(SwarmID, Vec<(ContentID, DataType, Data)>),
@@ -175,11 +257,27 @@ pub enum ToApp {
     AllNeighborsGone,
     Disconnected(bool, SwarmID, SwarmName), //bool indicates if we try to reconnect
     SearchQueries(Vec<(String, usize)>),
-    SearchResults(String, bool, Vec<Hit>), // bool = is_permanent
+    SearchResults(String, bool, Vec<Hit>), // bool = is_permanent, Hits sorted by Score
+    SearchQueryError(String, QueryError),
+    SearchHitAdded(String, Hit), // only for permanent queries
+    SearchHitRemoved(String, SwarmName, ContentID), // only for permanent queries
     // SwitchToApp(AppType, SwarmID, SwarmName),
     RunningPolicies(Vec<(Policy, Requirement)>),
     RunningCapabilities(Vec<(Capabilities, Vec<GnomeId>)>),
     RunningByteSets(Vec<(u8, ByteSet)>),
+    ManifestReconciled(ReconcileReport), // when dry_run is false, allowed changes were sent
+    ReconcileError(SwarmName, AppError),
+    DataRejected(SwarmID, DataType, SchemaError), // Data did not follow Manifest's schema
+    AppMsgRejected(SwarmID, u8, CatalogueError),  // AppDefined message not matching catalogue
+    RequirementsViolated(SwarmID, SyncMessageType, RequirementsViolation),
+    HashValidationFailed(SwarmID, SyncMessageType), // message was not applied
+    ProofToValidate(SwarmID, Proof, GnomeId),       // only App can find an Offense
+    ProofRejected(SwarmID, GnomeId, ProofError),
+    DatastoreRolledBack(SwarmID, u64, Vec<ContentID>), // u64 = new root hash
+    RollbackFailed(SwarmID, AppError),
+    ManifestExported(SwarmID, String),
+    ManifestImported(SwarmID, bool, Vec<ManifestChange>), // bool = dry_run
+    ManifestImportError(SwarmID, ImportError),
     HeapData(SwarmID, AppDefinedMsg, GnomeId),
     HeapEmpty(SwarmID),
     CustomNeighborRequest(SwarmID, GnomeId, u8, CastData),
@@ -218,6 +316,15 @@ pub enum ToAppMgr {
     AppendData(SwarmID, ContentID, Data),
     RemoveData(SwarmID, ContentID, u16),
     UpdateData(SwarmID, ContentID, u16, Data),
+    Transaction(SwarmID, Vec<TxOperation>),
+    BroadcastProof(SwarmID, Proof),
+    RollbackDatastore(SwarmID, u64), // u64 = root hash to go back to
+    RevertMessage(SwarmID, u64),     // u64 = message hash
+    DeleteTags(SwarmID, Vec<Tag>),
+    RenameTags(SwarmID, HashMap<Tag, Tag>),
+    ReleaseTagTombstones(SwarmID),
+    ExportManifest(SwarmID),
+    ImportManifest(SwarmID, String, bool), // bool = dry_run
     AppDefined(SwarmID, AppDefinedMsg),
     ContentAdded(SwarmID, ContentID, DataType, Data),
     ContentChanged(SwarmID, ContentID, DataType, Option<Data>),
@@ -233,6 +340,7 @@ pub enum ToAppMgr {
     FromGMgr(FromGnomeManager),
     FromApp(LibRequest),
     SearchSummary(Vec<(SwarmName, Vec<ContentID>)>),
+    ManifestLoaded(SwarmID, Option<Box<Manifest>>),
     FromDatastore(LibResponse),
     NoOp,
 }
@@ -262,8 +370,11 @@ pub enum LibRequest {
     SetHeapAutoForward(SwarmID, bool),
     PeekHeap(SwarmID),
     PopHeap(SwarmID),
+    ListContentVersions(SwarmID, ContentID),
+    ReadContentAtVersion(SwarmID, ContentID, u32),
     NewStoragePolicy(Vec<(StorageCondition, StoragePolicy)>),
     SetPinned(SwarmID, bool),
+    ReconcileManifest(SwarmName, bool), // bool = dry run
 }
 #[derive(Debug)]
 pub enum LibResponse {
@@ -281,10 +392,19 @@ pub enum LibResponse {
         Vec<Data>,
     ),
     ReadError(SwarmID, ContentID, AppError),
+    ContentVersions(SwarmID, ContentID, Vec<ContentVersion>),
+    ContentAtVersion(SwarmID, ContentID, u32, DataType, Vec<Data>),
     DownloadingPages(SwarmID, ContentID, DataType),
     AuditResult(SwarmID, usize, usize, u8),
     HeapData(SwarmID, AppDefinedMsg, GnomeId),
     HeapEmpty(SwarmID),
+    AppMsgRejected(SwarmID, u8, CatalogueError),
+    DataRejected(SwarmID, DataType, SchemaError),
+    RequirementsViolated(SwarmID, SyncMessageType, RequirementsViolation),
+    HashValidationFailed(SwarmID, SyncMessageType),
+    ProofBuilt(SwarmID, u64), // held until backoff passes, u64 = message hash
+    ProofToValidate(SwarmID, Proof, GnomeId),
+    ProofRejected(SwarmID, GnomeId, ProofError),
     PolicyNotMet(SwarmID, SyncMessageType, Data),
     CustomNeighborRequest(SwarmID, GnomeId, u8, CastData),
     CustomNeighborResponse(SwarmID, GnomeId, u8, CastData),
@@ -299,6 +419,8 @@ pub enum TimeoutType {
     AuditMemoryForSwarm(SwarmID, SwarmName),
     IsSwarmSynced(SwarmID, SwarmName),
     AuditReads,
+    ReconcileExpired(SwarmName),
+    ProofBackoff(SwarmID, u64),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -317,6 +439,8 @@ pub enum ToAppData {
     ReadNextChunk(Requestor, ContentID, u16),
     ReadCancel(ContentID),
     ReadRefresh(Vec<(ContentID, DataType)>),
+    AuditPartials,
+    AuditRequests,
     SendFirstPage(GnomeId, ContentID, Data),
     ReadAllFirstPages(Requestor, Option<(ContentID, ContentID)>),
     BroadcastSend(CastID, CastData),
@@ -341,13 +465,25 @@ pub enum ToAppData {
     ChangeContent(ContentID, DataType, Vec<Data>),
     ChangeDiameter(u8),
     UpdateData(ContentID, u16, Data),
-    AppDefined(AppDefinedMsg),
+    Transaction(Vec<TxOperation>),
+    BroadcastProof(Proof),
+    BroadcastHeldProof(u64),
+    RollbackDatastore(u64),
+    RevertMessage(u64),
+    DeleteTags(Vec<Tag>),
+    RenameTags(HashMap<Tag, Tag>),
+    ReleaseTagTombstones,
+    ExportManifest,
+    ImportManifest(String, bool),
+    AppDefined(AppDefinedMsg, GnomeId),
     AppendContent(DataType, Data),
     AppendData(ContentID, Data),
     RemoveData(ContentID, u16),
     AppendShelledDatas(ContentID, Data, Vec<Data>),
     CustomRequest(u8, GnomeId, CastData),
     CustomResponse(u8, GnomeId, CastData),
+    SearchNeighbors(Vec<u8>),
+    SearchRespond(GnomeId, Vec<u8>),
     TransformLinkRequest(SyncData),
     TransformLink(GnomeId, SyncData),
     TimeoutSyncCheck,
@@ -357,10 +493,13 @@ pub enum ToAppData {
     RunningPoliciesReq,
     RunningCapabilitiesReq,
     RunningByteSetsReq,
+    ManifestReq,
     MyName(SwarmName),
     SetHeapAutoForward(bool),
     PeekHeap,
     PopHeap,
+    ListContentVersions(ContentID),
+    ReadContentAtVersion(ContentID, u32),
     PolicyNotMet(SyncData),
     PolicyNotMetRcfg(u8, SyncData),
     SetStoragePolicy(PathBuf, StoragePolicy, Vec<u16>),
@@ -599,6 +738,12 @@ pub async fn initialize<'a>(
     executor
         .spawn(serve_search_engine(
             config.search.clone(),
+            SearchSettings {
+                stemming: config.search_stemming,
+                crawl_bandwidth: config.search_crawl_bandwidth,
+                full_text: config.search_full_text.clone(),
+                federated: config.search_federated,
+            },
             to_user_send.clone(),
             to_app_mgr_send.clone(),
             to_search_engine_recv,
@@ -670,6 +815,7 @@ async fn serve_app_manager<'a>(
     let mut quit_application = false;
     let mut swarm_swap = SwarmSwap::new();
     let mut swarm_start_requested = None;
+    let mut reconciler = Reconciler::default();
     executor
         .spawn(serve_gnome_mgr_requests(from_gnome_mgr, to_app_mgr.clone()))
         .detach();
@@ -847,7 +993,7 @@ async fn serve_app_manager<'a>(
                     req_id,
                     data,
                 )) => {
-                    if req_id < SYNC_REQUEST {
+                    if req_id < SEARCH_REQUEST {
                         let _ = to_gnome_mgr
                             .send(ToGnomeManager::CustomNeighborRequest(
                                 s_name, g_id, req_id, data,
@@ -856,7 +1002,7 @@ async fn serve_app_manager<'a>(
                     } else {
                         eprintln!(
                             "Not sending CustomNeighReq{req_id}, max id = {}",
-                            SYNC_REQUEST - 1
+                            SEARCH_REQUEST - 1
                         );
                     }
                 }
@@ -866,7 +1012,7 @@ async fn serve_app_manager<'a>(
                     req_id,
                     data,
                 )) => {
-                    if req_id < SYNC_RESPONSE {
+                    if req_id < SEARCH_RESPONSE {
                         let _ = to_gnome_mgr
                             .send(ToGnomeManager::CustomNeighborResponse(
                                 s_name, g_id, req_id, data,
@@ -874,7 +1020,7 @@ async fn serve_app_manager<'a>(
                             .await;
                     } else {
                         eprintln!(
-                            "Not sending CustomNeighResp{req_id}, id has to be < {SYNC_RESPONSE}"
+                            "Not sending CustomNeighResp{req_id}, id has to be < {SEARCH_RESPONSE}"
                         );
                     }
                 }
@@ -899,6 +1045,34 @@ async fn serve_app_manager<'a>(
                         let _ = sender.send(ToAppData::PopHeap).await;
                     }
                 }
+                ToAppMgr::FromApp(LibRequest::ListContentVersions(s_id, c_id)) => {
+                    if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
+                        let _ = sender.send(ToAppData::ListContentVersions(c_id)).await;
+                    }
+                }
+                ToAppMgr::FromApp(LibRequest::ReadContentAtVersion(s_id, c_id, version)) => {
+                    if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
+                        let _ = sender
+                            .send(ToAppData::ReadContentAtVersion(c_id, version))
+                            .await;
+                    }
+                }
+                ToAppMgr::FromDatastore(LibResponse::ContentVersions(s_id, c_id, versions)) => {
+                    let _ = to_user
+                        .send(ToApp::ContentVersions(s_id, c_id, versions))
+                        .await;
+                }
+                ToAppMgr::FromDatastore(LibResponse::ContentAtVersion(
+                    s_id,
+                    c_id,
+                    version,
+                    d_type,
+                    pages,
+                )) => {
+                    let _ = to_user
+                        .send(ToApp::ContentAtVersion(s_id, c_id, version, d_type, pages))
+                        .await;
+                }
                 ToAppMgr::FromDatastore(LibResponse::FirstPages(s_id, first_pages)) => {
                     // match requestor {
                     //     Requestor::App => {
@@ -954,6 +1128,43 @@ async fn serve_app_manager<'a>(
                     eprintln!("AppMgr sending HeapEmpty to user",);
                     let _ = to_user.send(ToApp::HeapEmpty(s_id)).await;
                 }
+                ToAppMgr::FromDatastore(LibResponse::DataRejected(s_id, d_type, err)) => {
+                    let _ = to_user.send(ToApp::DataRejected(s_id, d_type, err)).await;
+                }
+                ToAppMgr::FromDatastore(LibResponse::AppMsgRejected(s_id, m_type, err)) => {
+                    let _ = to_user.send(ToApp::AppMsgRejected(s_id, m_type, err)).await;
+                }
+                // We found an Offense ourselves, let everyone know
+                // unless someone else does it first
+                ToAppMgr::FromDatastore(LibResponse::ProofBuilt(s_id, msg_hash)) => {
+                    executor
+                        .spawn(start_a_timer(
+                            to_app_mgr.clone(),
+                            TimeoutType::ProofBackoff(s_id, msg_hash),
+                            proof_backoff(),
+                        ))
+                        .detach();
+                }
+                ToAppMgr::FromDatastore(LibResponse::ProofToValidate(s_id, proof, reporter)) => {
+                    let _ = to_user
+                        .send(ToApp::ProofToValidate(s_id, proof, reporter))
+                        .await;
+                }
+                ToAppMgr::FromDatastore(LibResponse::ProofRejected(s_id, reporter, err)) => {
+                    let _ = to_user
+                        .send(ToApp::ProofRejected(s_id, reporter, err))
+                        .await;
+                }
+                ToAppMgr::FromDatastore(LibResponse::RequirementsViolated(s_id, m_type, viol)) => {
+                    let _ = to_user
+                        .send(ToApp::RequirementsViolated(s_id, m_type, viol))
+                        .await;
+                }
+                ToAppMgr::FromDatastore(LibResponse::HashValidationFailed(s_id, m_type)) => {
+                    let _ = to_user
+                        .send(ToApp::HashValidationFailed(s_id, m_type))
+                        .await;
+                }
                 ToAppMgr::FromDatastore(LibResponse::CustomNeighborRequest(
                     s_id,
                     g_id,
@@ -1293,9 +1504,56 @@ async fn serve_app_manager<'a>(
                         let _ = sender.send(ToAppData::UpdateData(c_id, d_id, data)).await;
                     }
                 }
+                ToAppMgr::Transaction(s_id, ops) => {
+                    if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
+                        let _ = sender.send(ToAppData::Transaction(ops)).await;
+                    }
+                }
+                ToAppMgr::BroadcastProof(s_id, proof) => {
+                    if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
+                        let _ = sender.send(ToAppData::BroadcastProof(proof)).await;
+                    }
+                }
+                ToAppMgr::RollbackDatastore(s_id, root) => {
+                    if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
+                        let _ = sender.send(ToAppData::RollbackDatastore(root)).await;
+                    }
+                }
+                ToAppMgr::RevertMessage(s_id, msg_hash) => {
+                    if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
+                        let _ = sender.send(ToAppData::RevertMessage(msg_hash)).await;
+                    }
+                }
+                ToAppMgr::DeleteTags(s_id, tags) => {
+                    if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
+                        let _ = sender.send(ToAppData::DeleteTags(tags)).await;
+                    }
+                }
+                ToAppMgr::RenameTags(s_id, tags) => {
+                    if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
+                        let _ = sender.send(ToAppData::RenameTags(tags)).await;
+                    }
+                }
+                ToAppMgr::ReleaseTagTombstones(s_id) => {
+                    if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
+                        let _ = sender.send(ToAppData::ReleaseTagTombstones).await;
+                    }
+                }
+                ToAppMgr::ExportManifest(s_id) => {
+                    if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
+                        let _ = sender.send(ToAppData::ExportManifest).await;
+                    }
+                }
+                ToAppMgr::ImportManifest(s_id, text, dry_run) => {
+                    if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
+                        let _ = sender.send(ToAppData::ImportManifest(text, dry_run)).await;
+                    }
+                }
                 ToAppMgr::AppDefined(s_id, app_msg) => {
                     if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
-                        let _ = sender.send(ToAppData::AppDefined(app_msg)).await;
+                        let _ = sender
+                            .send(ToAppData::AppDefined(app_msg, my_name.founder))
+                            .await;
                     }
                 }
                 // ToAppMgr::AppendShelledDatas(_s_id, c_id, data) => {
@@ -1312,6 +1570,14 @@ async fn serve_app_manager<'a>(
                 // }
                 ToAppMgr::ContentAdded(s_id, c_id, d_type, main_page) => {
                     // eprintln!("GENERATE ToApp::NewContent({:?},{:?})", c_id, d_type);
+                    let _ = to_search_enigne
+                        .send(SearchMsg::ContentUpdated(
+                            s_id,
+                            c_id,
+                            d_type,
+                            Some(main_page.clone()),
+                        ))
+                        .await;
                     let _ = to_user
                         .send(ToApp::NewContent(s_id, c_id, d_type, main_page))
                         .await;
@@ -1346,6 +1612,9 @@ async fn serve_app_manager<'a>(
                 }
                 ToAppMgr::ContentChanged(s_id, c_id, d_type, mpo) => {
                     eprintln!("ToApp::ContentChanged({:?})", c_id,);
+                    let _ = to_search_enigne
+                        .send(SearchMsg::ContentUpdated(s_id, c_id, d_type, mpo.clone()))
+                        .await;
                     let _ = to_user
                         .send(ToApp::ContentChanged(s_id, c_id, d_type, mpo))
                         .await;
@@ -1489,7 +1758,31 @@ async fn serve_app_manager<'a>(
                         TimeoutType::AddToWaitList(s_name) => {
                             app_mgr.add_swarm_to_wait_list(s_name);
                         }
+                        TimeoutType::ProofBackoff(s_id, msg_hash) => {
+                            if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
+                                let _ = sender.send(ToAppData::BroadcastHeldProof(msg_hash)).await;
+                            }
+                        }
+                        TimeoutType::ReconcileExpired(s_name) => {
+                            if let Some(s_name) = reconciler.take_expired(&s_name) {
+                                let _ = to_user
+                                    .send(ToApp::ReconcileError(s_name, AppError::RequestTimedOut))
+                                    .await;
+                                start_reconciliation(
+                                    &mut reconciler,
+                                    &app_mgr,
+                                    &to_user,
+                                    &executor,
+                                    &to_app_mgr,
+                                )
+                                .await;
+                            }
+                        }
                         TimeoutType::AuditReads => {
+                            for sender in app_mgr.app_data_store.values() {
+                                let _ = sender.send(ToAppData::AuditPartials).await;
+                                let _ = sender.send(ToAppData::AuditRequests).await;
+                            }
                             for (s_id, c_ids) in app_mgr.read_list() {
                                 if let Some(sender) = app_mgr.app_data_store.get(&s_id) {
                                     let _ = sender.send(ToAppData::ReadRefresh(c_ids)).await;
@@ -2080,14 +2373,55 @@ async fn serve_app_manager<'a>(
                         //     eprintln!("dapp-lib got info about {} {} terminated", swarm_id, s_name);
                         //     to_app_mgr.send(ToAppMgr::SwarmTerminated(swarm_id, s_name));
                         // }
+                        // Running settings we asked for in order to reconcile
+                        // them with Manifest are not sent to App
                         FromGnomeManager::RunningPolicies(policies) => {
-                            let _ = to_user.send(ToApp::RunningPolicies(policies)).await;
+                            if reconciler.set_policies(&policies) {
+                                finish_reconciliation(
+                                    &mut reconciler,
+                                    &app_mgr,
+                                    my_name.founder,
+                                    &to_gnome_mgr,
+                                    &to_user,
+                                    &executor,
+                                    &to_app_mgr,
+                                )
+                                .await;
+                            } else {
+                                let _ = to_user.send(ToApp::RunningPolicies(policies)).await;
+                            }
                         }
                         FromGnomeManager::RunningCapabilities(caps) => {
-                            let _ = to_user.send(ToApp::RunningCapabilities(caps)).await;
+                            if reconciler.set_capabilities(&caps) {
+                                finish_reconciliation(
+                                    &mut reconciler,
+                                    &app_mgr,
+                                    my_name.founder,
+                                    &to_gnome_mgr,
+                                    &to_user,
+                                    &executor,
+                                    &to_app_mgr,
+                                )
+                                .await;
+                            } else {
+                                let _ = to_user.send(ToApp::RunningCapabilities(caps)).await;
+                            }
                         }
                         FromGnomeManager::RunningByteSets(bsets) => {
-                            let _ = to_user.send(ToApp::RunningByteSets(bsets)).await;
+                            if reconciler.set_byte_sets(&bsets) {
+                                finish_reconciliation(
+                                    &mut reconciler,
+                                    &app_mgr,
+                                    my_name.founder,
+                                    &to_gnome_mgr,
+                                    &to_user,
+                                    &executor,
+                                    &to_app_mgr,
+                                )
+                                .await;
+                            } else {
+                                let _ = to_user.send(ToApp::RunningByteSets(bsets)).await;
+                            }
                         }
                         FromGnomeManager::Disconnected(s_ids) => {
                             eprintln!("AppMgr received Disconnected:");
@@ -2102,6 +2436,14 @@ async fn serve_app_manager<'a>(
                                 // let active_id = app_mgr.active_app_data.0;
                                 // let mut expected_id = swarm_swap.is_leaving_a_swarm.take();
                                 for (s_id, s_name) in s_ids {
+                                    for r_name in reconciler.swarm_gone(&s_name) {
+                                        let _ = to_user
+                                            .send(ToApp::ReconcileError(
+                                                r_name,
+                                                AppError::AppDataNotSynced,
+                                            ))
+                                            .await;
+                                    }
                                     app_mgr
                                         .swarm_disconnected(s_id, s_name, has_neighbors)
                                         .await;
@@ -2463,6 +2805,34 @@ async fn serve_app_manager<'a>(
                         let _ = to_user.send(ToApp::RunningByteSets(vec![])).await;
                     }
                 }
+                ToAppMgr::FromApp(LibRequest::ReconcileManifest(s_name, dry_run)) => {
+                    if reconciler.request(s_name.clone(), dry_run) {
+                        start_reconciliation(
+                            &mut reconciler,
+                            &app_mgr,
+                            &to_user,
+                            &executor,
+                            &to_app_mgr,
+                        )
+                        .await;
+                    } else {
+                        eprintln!("{} is already being reconciled", s_name);
+                    }
+                }
+                ToAppMgr::ManifestLoaded(s_id, manifest) => {
+                    if reconciler.set_manifest(s_id, manifest.map(|m| *m)) {
+                        finish_reconciliation(
+                            &mut reconciler,
+                            &app_mgr,
+                            my_name.founder,
+                            &to_gnome_mgr,
+                            &to_user,
+                            &executor,
+                            &to_app_mgr,
+                        )
+                        .await;
+                    }
+                }
                 ToAppMgr::SearchSummary(summary) => {
                     // TODO: update storage policy
                     for (s_name, cid_vec) in summary {
@@ -2507,6 +2877,84 @@ async fn serve_gnome_mgr_requests(
         let _ = to_app_mgr.send(ToAppMgr::FromGMgr(request)).await;
     }
 }
+// Ask for Manifest and running settings of next queued Swarm,
+// unless some other Swarm is being reconciled.
+async fn start_reconciliation<'a>(
+    reconciler: &mut Reconciler,
+    app_mgr: &ApplicationManager,
+    to_user: &ASender<ToApp>,
+    executor: &Arc<Executor<'a>>,
+    to_app_mgr: &ASender<ToAppMgr>,
+) {
+    while let Some((s_name, dry_run)) = reconciler.next() {
+        let to_d_store = app_mgr
+            .get_swarm_id(&s_name)
+            .and_then(|s_id| Some((s_id, app_mgr.app_data_store.get(&s_id)?)));
+        let Some((s_id, to_d_store)) = to_d_store else {
+            let _ = to_user
+                .send(ToApp::ReconcileError(s_name, AppError::AppDataNotSynced))
+                .await;
+            continue;
+        };
+        reconciler.start(Reconciliation::new(s_id, s_name.clone(), dry_run));
+        let _ = to_d_store.send(ToAppData::ManifestReq).await;
+        let _ = to_d_store.send(ToAppData::RunningPoliciesReq).await;
+        let _ = to_d_store.send(ToAppData::RunningCapabilitiesReq).await;
+        let _ = to_d_store.send(ToAppData::RunningByteSetsReq).await;
+        executor
+            .spawn(start_a_timer(
+                to_app_mgr.clone(),
+                TimeoutType::ReconcileExpired(s_name),
+                RECONCILE_TIMEOUT,
+            ))
+            .detach();
+        break;
+    }
+}
+
+// Once Manifest and all running settings were received we send allowed changes
+// to Gnome, unless it is a dry run, and report to App what was found.
+// Then we start reconciling next queued Swarm.
+async fn finish_reconciliation<'a>(
+    reconciler: &mut Reconciler,
+    app_mgr: &ApplicationManager,
+    my_id: GnomeId,
+    to_gnome_mgr: &ASender<ToGnomeManager>,
+    to_user: &ASender<ToApp>,
+    executor: &Arc<Executor<'a>>,
+    to_app_mgr: &ASender<ToAppMgr>,
+) {
+    let Some(rec) = reconciler.take_ready() else {
+        return;
+    };
+    let s_name = rec.s_name.clone();
+    match rec.report(my_id) {
+        Ok(report) => {
+            if !report.dry_run {
+                for change in &report.allowed {
+                    let request = match change.clone() {
+                        SettingChange::Policy(pol, req) => {
+                            ToGnomeManager::SetRunningPolicy(s_name.clone(), pol, req)
+                        }
+                        SettingChange::Capability(cap, g_ids) => {
+                            ToGnomeManager::SetRunningCapability(s_name.clone(), cap, g_ids)
+                        }
+                        SettingChange::ByteSet(b_id, bset) => {
+                            ToGnomeManager::SetRunningByteSet(s_name.clone(), b_id, bset)
+                        }
+                    };
+                    let _ = to_gnome_mgr.send(request).await;
+                }
+            }
+            let _ = to_user.send(ToApp::ManifestReconciled(report)).await;
+        }
+        Err(e) => {
+            let _ = to_user.send(ToApp::ReconcileError(s_name, e)).await;
+        }
+    }
+    start_reconciliation(reconciler, app_mgr, to_user, executor, to_app_mgr).await;
+}
+
 async fn serve_app_data<'a>(
     _executor: Arc<Executor<'a>>,
     io_executor: Arc<Executor<'a>>,
@@ -2590,6 +3038,7 @@ async fn serve_app_data<'a>(
         Some((0, HashMap::new()));
     // let sleep_time = Duration::from_millis(32);
     let mut active_reads: HashMap<ContentID, ReadState> = HashMap::new();
+    let mut requests = RequestTracker::new();
     let mut incomplete_bottom_hashes = HashMap::new();
     while let Ok(resp) = app_data_recv.recv().await {
         match resp {
@@ -2797,7 +3246,10 @@ async fn serve_app_data<'a>(
                 }
             }
             ToAppData::AppendContent(d_type, data) => {
-                if let Some(next_id) = app_data.next_c_id() {
+                if let Err(e) = app_data.validate_page(d_type, 0, &data) {
+                    eprintln!("AppendContent rejected: {}", e);
+                    let _ = to_user.send(ToApp::DataRejected(swarm_id, d_type, e)).await;
+                } else if let Some(next_id) = app_data.next_c_id() {
                     eprintln!("AppendContent: {:?}", data);
                     let pre: Vec<(ContentID, u64)> = vec![(next_id, 0)];
                     let post: Vec<(ContentID, u64)> = vec![(next_id, data.get_hash())];
@@ -2818,6 +3270,18 @@ async fn serve_app_data<'a>(
                 }
             }
             ToAppData::AppendData(c_id, data) => {
+                let (d_type, len) = match app_data.get_type_and_len(c_id) {
+                    Ok(type_and_len) => type_and_len,
+                    Err(e) => {
+                        let _ = to_user.send(ToApp::ReadError(swarm_id, c_id, e)).await;
+                        continue;
+                    }
+                };
+                if let Err(e) = app_data.validate_page(d_type, len, &data) {
+                    eprintln!("AppendData rejected: {}", e);
+                    let _ = to_user.send(ToApp::DataRejected(swarm_id, d_type, e)).await;
+                    continue;
+                }
                 let pre_hash = app_data.content_root_hash(c_id).unwrap();
                 // eprintln!("Initial AppendData PRE hash: {}", pre_hash.1);
                 // let bottom_hashes = app_data.content_bottom_hashes(c_id).unwrap();
@@ -2855,6 +3319,13 @@ async fn serve_app_data<'a>(
                     data.len()
                 );
                 let pre_hash = app_data.content_root_hash(c_id).unwrap();
+                if let Err(e) = app_data.validate_page(pre_hash.0, d_id, &data) {
+                    eprintln!("UpdateData rejected: {}", e);
+                    let _ = to_user
+                        .send(ToApp::DataRejected(swarm_id, pre_hash.0, e))
+                        .await;
+                    continue;
+                }
                 let pre: Vec<(ContentID, u64)> = vec![(c_id, pre_hash.1)];
                 let prev_data = app_data
                     // .update_data(c_id, d_id, Data::empty(data.get_hash()))
@@ -2870,6 +3341,130 @@ async fn serve_app_data<'a>(
                     let _ = to_gnome_sender.send(ToGnome::AddData(part)).await;
                 }
             }
+            ToAppData::Transaction(ops) => {
+                transaction_task(ops, &mut app_data, &to_gnome_sender).await
+            }
+            ToAppData::BroadcastProof(proof) => match proof.into_app_msgs() {
+                Ok(app_msgs) => {
+                    for app_msg in app_msgs {
+                        app_defined_request_task(app_msg, &to_gnome_sender).await;
+                    }
+                }
+                Err(e) => eprintln!("Can not broadcast Proof: {}", e),
+            },
+            ToAppData::BroadcastHeldProof(msg_hash) => {
+                let Some(proof) = app_data.proof_parts.release(msg_hash) else {
+                    eprintln!("Proof of {} was already received", msg_hash);
+                    continue;
+                };
+                match proof.into_app_msgs() {
+                    Ok(app_msgs) => {
+                        for app_msg in app_msgs {
+                            app_defined_request_task(app_msg, &to_gnome_sender).await;
+                        }
+                    }
+                    Err(e) => eprintln!("Can not broadcast Proof: {}", e),
+                }
+            }
+            ToAppData::RollbackDatastore(root) => {
+                rollback_task(root, &mut app_data, swarm_id, &to_app_mgr_send, &to_user).await
+            }
+            ToAppData::RevertMessage(msg_hash) => {
+                if let Some(root) = app_data.undo_log.root_before(msg_hash) {
+                    rollback_task(root, &mut app_data, swarm_id, &to_app_mgr_send, &to_user).await
+                } else {
+                    let _ = to_user
+                        .send(ToApp::RollbackFailed(swarm_id, AppError::NotInUndoLog))
+                        .await;
+                }
+            }
+            // Tag lifecycle: Manifest is changed first, then every Content
+            // labeled with affected ids gets it's first page updated.
+            // Deleted ids stay as tombstones until ReleaseTagTombstones
+            // finds no Content referencing them.
+            ToAppData::DeleteTags(tags) => {
+                let Some(mut manifest) = app_data.manifest() else {
+                    eprintln!("Can not delete Tags, no Manifest");
+                    continue;
+                };
+                let deleted = manifest.del_tags(tags);
+                if deleted.is_empty() {
+                    continue;
+                }
+                let mapping = deleted.into_iter().map(|t_id| (t_id, None)).collect();
+                tag_lifecycle_task(manifest, mapping, &mut app_data, &app_data_send).await;
+            }
+            ToAppData::RenameTags(tags) => {
+                let Some(mut manifest) = app_data.manifest() else {
+                    eprintln!("Can not rename Tags, no Manifest");
+                    continue;
+                };
+                let mapping = manifest
+                    .rename_tags(tags)
+                    .into_iter()
+                    .map(|(old_id, new_id)| (old_id, Some(new_id)))
+                    .collect();
+                tag_lifecycle_task(manifest, mapping, &mut app_data, &app_data_send).await;
+            }
+            ToAppData::ReleaseTagTombstones => {
+                let Some(mut manifest) = app_data.manifest() else {
+                    continue;
+                };
+                let referenced = app_data.referenced_tags();
+                if manifest.release_tag_tombstones(&referenced) {
+                    let d_type = app_data.get_type_and_len(0).unwrap().0;
+                    match manifest.to_data() {
+                        Ok(data_vec) => {
+                            let _ = app_data_send
+                                .send(ToAppData::ChangeContent(0, d_type, data_vec))
+                                .await;
+                        }
+                        Err(e) => eprintln!("Can not store Manifest: {}", e),
+                    }
+                }
+            }
+            ToAppData::ExportManifest => {
+                if let Some(manifest) = app_data.manifest() {
+                    let _ = to_user
+                        .send(ToApp::ManifestExported(swarm_id, manifest.to_text()))
+                        .await;
+                } else {
+                    let _ = to_user
+                        .send(ToApp::ReadError(swarm_id, 0, AppError::AppDataNotSynced))
+                        .await;
+                }
+            }
+            // Imported Manifest replaces CID 0 as a whole,
+            // diff is only there for user to review what changes.
+            ToAppData::ImportManifest(text, dry_run) => {
+                let new = match Manifest::from_text(&text) {
+                    Ok(new) => new,
+                    Err(e) => {
+                        eprintln!("Manifest import failed: {}", e);
+                        let _ = to_user.send(ToApp::ManifestImportError(swarm_id, e)).await;
+                        continue;
+                    }
+                };
+                let current = app_data
+                    .manifest()
+                    .unwrap_or(Manifest::new(new.app_type, HashMap::new()));
+                let changes = current.diff(&new);
+                if !dry_run && !changes.is_empty() {
+                    let d_type = if let Ok((d_type, _len)) = app_data.get_type_and_len(0) {
+                        d_type
+                    } else {
+                        DataType::Data(0)
+                    };
+                    // Already checked by from_text
+                    let data_vec = new.to_data().unwrap();
+                    let _ = app_data_send
+                        .send(ToAppData::ChangeContent(0, d_type, data_vec))
+                        .await;
+                }
+                let _ = to_user
+                    .send(ToApp::ManifestImported(swarm_id, dry_run, changes))
+                    .await;
+            }
             ToAppData::RemoveData(c_id, d_id) => {
                 //TODO:serve this
                 // eprintln!("Got ToAppData::RemoveData({}, {})", c_id, d_id,);
@@ -2919,8 +3514,16 @@ async fn serve_app_data<'a>(
                 )
                 .await
             }
-            ToAppData::AppDefined(app_msg) => {
-                let _ = app_defined_request_task(app_msg, &to_gnome_sender).await;
+            ToAppData::AppDefined(app_msg, my_id) => {
+                // others will verify it too, we only save a round trip
+                if let Err(e) = app_data.check_app_msg(&app_msg, my_id) {
+                    eprintln!("AppDefined rejected: {}", e);
+                    let _ = to_user
+                        .send(ToApp::AppMsgRejected(swarm_id, app_msg.m_type, e))
+                        .await;
+                } else {
+                    let _ = app_defined_request_task(app_msg, &to_gnome_sender).await;
+                }
             }
 
             ToAppData::ChangeDiameter(new_diameter) => {
@@ -3063,6 +3666,7 @@ async fn serve_app_data<'a>(
                             .send(SearchMsg::FirstPages(
                                 swarm_id,
                                 swarm_name.clone(),
+                                range_opt,
                                 first_pages_vec,
                             ))
                             .await;
@@ -3081,6 +3685,46 @@ async fn serve_app_data<'a>(
                 )
                 .await
             }
+            ToAppData::SearchNeighbors(bytes) => {
+                if let Ok(cast_data) = CastData::new(bytes) {
+                    let _ = to_gnome_sender
+                        .send(ToGnome::AskData(
+                            GnomeId::any(),
+                            None,
+                            NeighborRequest::Custom(SEARCH_REQUEST, cast_data),
+                        ))
+                        .await;
+                }
+            }
+            ToAppData::SearchRespond(neighbor_id, bytes) => {
+                if let Ok(cast_data) = CastData::new(bytes) {
+                    let _ = to_gnome_sender
+                        .send(ToGnome::SendData(
+                            neighbor_id,
+                            NeighborResponse::Custom(SEARCH_RESPONSE, cast_data),
+                        ))
+                        .await;
+                }
+            }
+            ToAppData::CustomRequest(SEARCH_REQUEST, neighbor_id, cast_data) => {
+                let _ = to_search_enigne
+                    .send(SearchMsg::RemoteQuery(
+                        app_data_send.clone(),
+                        swarm_name.clone(),
+                        neighbor_id,
+                        cast_data.bytes(),
+                    ))
+                    .await;
+            }
+            ToAppData::CustomResponse(SEARCH_RESPONSE, neighbor_id, cast_data) => {
+                let _ = to_search_enigne
+                    .send(SearchMsg::RemoteResults(
+                        swarm_name.clone(),
+                        neighbor_id,
+                        cast_data.bytes(),
+                    ))
+                    .await;
+            }
             //TODO: CustomResponse & CustomRequest
             // should be handled externally by App
             // or they should not be called Custom
@@ -3110,6 +3754,7 @@ async fn serve_app_data<'a>(
                     swarm_id,
                     &mut app_data,
                     &mut active_reads,
+                    &mut requests,
                     &mut incomplete_bottom_hashes,
                     &swarm_name,
                     &to_gnome_sender,
@@ -3130,6 +3775,7 @@ async fn serve_app_data<'a>(
                     0,
                     None,
                     &mut active_reads,
+                    &mut requests,
                     &datastore_sync,
                     &to_app_mgr_send,
                     swarm_id,
@@ -3149,6 +3795,7 @@ async fn serve_app_data<'a>(
                     p_start,
                     Some(p_end),
                     &mut active_reads,
+                    &mut requests,
                     &datastore_sync,
                     &to_app_mgr_send,
                     swarm_id,
@@ -3168,6 +3815,7 @@ async fn serve_app_data<'a>(
                     starting_page,
                     None,
                     &mut active_reads,
+                    &mut requests,
                     &datastore_sync,
                     &to_app_mgr_send,
                     swarm_id,
@@ -3181,6 +3829,7 @@ async fn serve_app_data<'a>(
             }
             ToAppData::ReadCancel(c_id) => {
                 active_reads.remove(&c_id);
+                requests.cancel_pages(c_id);
             }
             ToAppData::ReadRefresh(mut c_ids) => {
                 let (c_id, d_type) = c_ids.remove(0);
@@ -3204,6 +3853,63 @@ async fn serve_app_data<'a>(
                     let _ = app_data_send.send(ToAppData::ReadRefresh(c_ids)).await;
                 }
             }
+            ToAppData::AuditPartials => {
+                for (msg_hash, part_nos) in app_data.audit_partials() {
+                    let sync_request = SyncRequest::MissingParts(msg_hash, part_nos);
+                    let _ = to_gnome_sender
+                        .send(ToGnome::AskData(
+                            GnomeId::any(),
+                            None,
+                            NeighborRequest::Custom(
+                                SYNC_REQUEST,
+                                CastData::new(serialize_requests(vec![sync_request])).unwrap(),
+                            ),
+                        ))
+                        .await;
+                }
+            }
+            ToAppData::AuditRequests => {
+                for retry in requests.due() {
+                    match retry {
+                        Retry::Resend(target, exclude, sync_request) => {
+                            let _ = to_gnome_sender
+                                .send(ToGnome::AskData(
+                                    target,
+                                    exclude,
+                                    NeighborRequest::Custom(
+                                        SYNC_REQUEST,
+                                        CastData::new(serialize_requests(vec![sync_request]))
+                                            .unwrap(),
+                                    ),
+                                ))
+                                .await;
+                        }
+                        Retry::GiveUp(c_id) => {
+                            // Requests sent while syncing Datastore have no reader
+                            let Some(requestor) = active_reads.remove(&c_id).map(|rs| rs.requestor)
+                            else {
+                                eprintln!("Unable to sync CID-{}, no Neighbor answered", c_id);
+                                continue;
+                            };
+                            let error = AppError::RequestTimedOut;
+                            match requestor {
+                                Requestor::App => {
+                                    let _ = to_app_mgr_send
+                                        .send(ToAppMgr::FromDatastore(LibResponse::ReadError(
+                                            swarm_id, c_id, error,
+                                        )))
+                                        .await;
+                                }
+                                Requestor::Search => {
+                                    let _ = to_search_enigne
+                                        .send(SearchMsg::ReadError(swarm_id, c_id, error))
+                                        .await;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
             ToAppData::BCast(s_id, c_id, recv) => {
                 let _ = to_user.send(ToApp::BCast(s_id, c_id, recv)).await;
             }
@@ -3409,6 +4115,20 @@ async fn serve_app_data<'a>(
             ToAppData::RunningByteSetsReq => {
                 let _ = to_gnome_sender.send(ToGnome::RunningByteSets).await;
             }
+            ToAppData::ManifestReq => {
+                let manifest = if let Ok(d_vec) = app_data.get_all_data(0) {
+                    if d_vec.is_empty() {
+                        None
+                    } else {
+                        Some(Manifest::from(d_vec))
+                    }
+                } else {
+                    None
+                };
+                let _ = to_app_mgr_send
+                    .send(ToAppMgr::ManifestLoaded(swarm_id, manifest.map(Box::new)))
+                    .await;
+            }
             ToAppData::SetHeapAutoForward(new_setting) => {
                 app_data.set_heap_auto_forward(new_setting);
                 if new_setting && !app_data.heap.is_empty() {
@@ -3422,6 +4142,25 @@ async fn serve_app_data<'a>(
                 }
             }
 
+            ToAppData::ListContentVersions(c_id) => {
+                let versions = app_data.content_versions(c_id).await;
+                let _ = to_app_mgr_send
+                    .send(ToAppMgr::FromDatastore(LibResponse::ContentVersions(
+                        swarm_id, c_id, versions,
+                    )))
+                    .await;
+            }
+            ToAppData::ReadContentAtVersion(c_id, version) => {
+                let response = match app_data.content_at_version(c_id, version).await {
+                    Ok((d_type, pages)) => {
+                        LibResponse::ContentAtVersion(swarm_id, c_id, version, d_type, pages)
+                    }
+                    Err(e) => LibResponse::ReadError(swarm_id, c_id, e),
+                };
+                let _ = to_app_mgr_send
+                    .send(ToAppMgr::FromDatastore(response))
+                    .await;
+            }
             ToAppData::PeekHeap => {
                 if let Some((app_msg, orig)) = app_data.heap.peek() {
                     let _ = to_app_mgr_send
@@ -3449,13 +4188,13 @@ async fn serve_app_data<'a>(
                 }
             }
             ToAppData::PolicyNotMet(s_data) => {
-                if let Some(s_msg) = app_data.process(s_data) {
-                    // eprintln!("PolicyNotMet for {:?}", s_msg.m_type);
+                // Our own message, it is not assembled so that it can be retried
+                let refused = Data::new(s_data.bytes()).ok().and_then(refused_message);
+                if let Some((m_type, data)) = refused {
+                    // eprintln!("PolicyNotMet for {:?}", m_type);
                     let _ = to_app_mgr_send
                         .send(ToAppMgr::FromDatastore(LibResponse::PolicyNotMet(
-                            swarm_id,
-                            s_msg.m_type,
-                            s_msg.data,
+                            swarm_id, m_type, data,
                         )))
                         .await;
                 }
@@ -3808,6 +4547,7 @@ async fn request_content_from_any_neighbor(
     all_pages: bool,
     exclude: Option<GnomeId>,
     to_gnome_sender: ASender<ToGnome>,
+    requests: &mut RequestTracker,
 ) {
     eprintln!(
         "Sending a request for CID-{} (hashes: {}, all_pages: {})",
@@ -3816,12 +4556,14 @@ async fn request_content_from_any_neighbor(
     let mut sync_requests: Vec<SyncRequest> = vec![];
     if hashes {
         sync_requests.push(SyncRequest::Hashes(cid, vec![]));
+        requests.track_hashes(cid, GnomeId::any());
     }
     // if first_page {
     //     sync_requests.push(SyncRequest::AllFirstPages(Some(vec![cid])));
     // }
     if all_pages {
         sync_requests.push(SyncRequest::AllPages(vec![cid]));
+        requests.track_all_pages(cid, GnomeId::any());
     }
     let _ = to_gnome_sender
         .send(ToGnome::AskData(
@@ -4196,8 +4938,16 @@ pub struct ApplicationData {
     policy: (StoragePolicy, Vec<u16>),
     change_reg: ChangeRegistry,
     contents: Datastore,
-    hash_to_temp_idx: HashMap<u64, u16>,
-    partial_data: HashMap<u16, (Vec<u64>, HashMap<u64, Data>)>,
+    partials: Assembler,
+    // Contents changed since last take_touched
+    touched: HashSet<ContentID>,
+    // Parsed Manifest, outer Option is None until CID 0 is parsed again
+    manifest: Option<Option<Manifest>>,
+    proof_parts: ProofParts,
+    undo_log: UndoLog,
+    history: HashMap<ContentID, ContentHistory>,
+    // Versions dropped from histories since unreferenced pages were removed
+    dropped_versions: usize,
     disk_root_hash: u64,
     heap_auto_forward: bool,
     heap: Heap,
@@ -4340,8 +5090,13 @@ impl ApplicationData {
             policy,
             change_reg: ChangeRegistry::new(),
             contents,
-            hash_to_temp_idx: HashMap::new(),
-            partial_data: HashMap::new(),
+            partials: Assembler::new(),
+            touched: HashSet::new(),
+            manifest: None,
+            proof_parts: ProofParts::new(),
+            undo_log: UndoLog::new(),
+            history: HashMap::new(),
+            dropped_versions: 0,
             disk_root_hash: 0,
             heap_auto_forward,
             heap: Heap::Small(HeapSmall::new(None)),
@@ -4360,8 +5115,13 @@ impl ApplicationData {
             policy,
             change_reg: ChangeRegistry::new(),
             contents: Datastore::empty(),
-            hash_to_temp_idx: HashMap::new(),
-            partial_data: HashMap::new(),
+            partials: Assembler::new(),
+            touched: HashSet::new(),
+            manifest: None,
+            proof_parts: ProofParts::new(),
+            undo_log: UndoLog::new(),
+            history: HashMap::new(),
+            dropped_versions: 0,
             disk_root_hash: 0,
             heap_auto_forward,
             heap: Heap::Small(HeapSmall::new(None)),
@@ -4376,138 +5136,34 @@ impl ApplicationData {
             None
         }
     }
-    pub fn update_partial(&mut self, is_hash_data: bool, data: Data) {
-        if is_hash_data {
-            let mut next_idx = 0;
-            for i in 0..=u16::MAX {
-                if !self.partial_data.contains_key(&i) {
-                    next_idx = i;
-                    break;
-                }
-            }
-            let total_parts = data.len() >> 3;
-            let mut all_hashes = Vec::with_capacity(total_parts);
-            let mut bytes = data.clone().bytes();
-            all_hashes.push(0);
-            for _i in 0..total_parts {
-                let mut hash: [u8; 8] = [0; 8];
-                for item in &mut hash {
-                    *item = bytes.drain(0..1).next().unwrap();
-                }
-                let hash = u64::from_be_bytes(hash);
-                // eprintln!("Expecting hash: {}", hash);
-                all_hashes.push(hash);
-                self.hash_to_temp_idx.insert(hash, next_idx);
-            }
-            let mut new_hm = HashMap::new();
-            // eprintln!("Inserting data len: {}", data.len());
-            new_hm.insert(0, data);
-            self.partial_data.insert(next_idx, (all_hashes, new_hm));
-        } else {
-            let hash = data.get_hash();
-            // eprintln!("Got hash: {}", hash);
-            if let Some(temp_idx) = self.hash_to_temp_idx.get(&hash) {
-                // println!("Oh yeah");
-                if let Some((vec, mut hm)) = self.partial_data.remove(temp_idx) {
-                    // eprintln!("Inserting data len: {}", data.len());
-                    hm.insert(hash, data);
-                    // println!("{} ==? {}", vec.len(), hm.len());
-                    if vec.len() == hm.len() {
-                        // eprintln!("two");
-                        // Some(SyncMessage::from_data(vec, hm).unwrap())
-                    } else {
-                        self.partial_data.insert(*temp_idx, (vec, hm));
-                        // None
-                    }
-                } else {
-                    // None
-                }
-            } else {
-                // None
-            }
-        }
+    // Parts received from a Neighbor during sync can not complete
+    // a message, header has to arrive from Swarm.
+    pub fn update_partial(&mut self, _is_hash_data: bool, data: Data) {
+        let _ = self.partials.insert(data, None);
     }
-    // TODO: this needs rework as well as SyncMessage::from_data
-    pub fn process(&mut self, data: SyncData) -> Option<SyncMessage> {
-        let d_len = data.len();
-        let d_hash = data.hash();
-        eprintln!("processing data size: {}", d_len);
-        let mut bytes = data.bytes();
-        let m_type = SyncMessageType::new(&mut bytes);
-        let mut drained_bytes = m_type.as_bytes();
-        let part_no = bytes.drain(0..1).next().unwrap();
-        let total_parts = bytes.drain(0..1).next().unwrap();
-        drained_bytes.push(part_no);
-        drained_bytes.push(total_parts);
-        eprintln!(
-            "Process SyncData {:?} [{}/{}] {} bytes",
-            m_type, part_no, total_parts, d_len,
-        );
-        if part_no == 0 {
-            if total_parts == 0 {
-                let mut hm = HashMap::new();
-                drained_bytes.append(&mut bytes);
-                hm.insert(0, Data::new(drained_bytes).unwrap());
-                // eprintln!("one");
-                Some(SyncMessage::from_data(vec![0], hm).unwrap())
-            } else {
-                let mut next_idx = 0;
-                for i in 0..=u16::MAX {
-                    if !self.partial_data.contains_key(&i) {
-                        // eprintln!("no key");
-                        next_idx = i;
-                        break;
-                    }
-                }
-                let mut all_hashes = Vec::with_capacity((total_parts as usize) + 1);
-                all_hashes.push(0);
-                for _i in 0..total_parts {
-                    let mut hash: [u8; 8] = [0; 8];
-                    for item in &mut hash {
-                        *item = bytes.drain(0..1).next().unwrap();
-                        drained_bytes.push(*item);
-                    }
-                    eprintln!("Expecting hash: {:?}", hash);
-                    let hash = u64::from_be_bytes(hash);
-                    eprintln!("Expecting hash: {}", hash);
-                    all_hashes.push(hash);
-                    self.hash_to_temp_idx.insert(hash, next_idx);
-                }
-                let mut new_hm = HashMap::new();
-                drained_bytes.append(&mut bytes);
-                let data = Data::new(drained_bytes).unwrap();
-                eprintln!("Inserting temp idx {} data len: {}", next_idx, data.len());
-                new_hm.insert(0, data);
-                self.partial_data.insert(next_idx, (all_hashes, new_hm));
-                None
-            }
-        } else {
-            // Second byte is non zero, so we received a non-head partial Data
-            drained_bytes.append(&mut bytes);
-            let data = Data::new(drained_bytes).unwrap();
-            // let hash = data.get_hash();
-            eprintln!("Got hash: {} (len: {}\n {})", d_hash, data.len(), data);
-            if let Some(temp_idx) = self.hash_to_temp_idx.get(&d_hash) {
-                eprintln!("tmp idx: {}", temp_idx);
-                if let Some((vec, mut hm)) = self.partial_data.remove(temp_idx) {
-                    eprintln!("Inserting data len: {}", data.len());
-                    hm.insert(d_hash, data);
-                    eprintln!("{} ==? {}", vec.len(), hm.len());
-                    if vec.len() == hm.len() {
-                        eprintln!("processing gave Some results");
-                        Some(SyncMessage::from_data(vec, hm).unwrap())
-                    } else {
-                        eprintln!("processing insert partial data");
-                        self.partial_data.insert(*temp_idx, (vec, hm));
-                        None
-                    }
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        }
+
+    /// Returns assembled SyncMessage with it's originator,
+    /// once all parts of a message were received.
+    pub fn process(
+        &mut self,
+        data: SyncData,
+        signed_by: GnomeId,
+    ) -> Option<(SyncMessage, GnomeId)> {
+        eprintln!("processing data size: {}", data.len());
+        let Ok(data) = Data::new(data.bytes()) else {
+            return None;
+        };
+        self.partials.insert(data, Some(signed_by))
+    }
+
+    /// Evict stale partial messages and list missing parts
+    /// of those that made no progress recently.
+    pub fn audit_partials(&mut self) -> Vec<(u64, Vec<u8>)> {
+        self.partials.audit()
+    }
+
+    pub fn parts_for_repost(&self, msg_hash: u64, part_nos: &[u8]) -> Vec<Data> {
+        self.partials.parts_for_repost(msg_hash, part_nos)
     }
 
     pub fn set_disk_hash(&mut self) {
@@ -4556,6 +5212,7 @@ impl ApplicationData {
     }
     pub fn transform_link(&mut self, content_id: ContentID) -> Result<Content, AppError> {
         self.change_reg.insert(content_id);
+        self.mark_touched(content_id);
         let ti = self.contents.take_transform_info(content_id)?;
         let d_type = ti.d_type;
         let mem_size = ti.data.len() as u16;
@@ -4572,6 +5229,7 @@ impl ApplicationData {
         data: Data,
     ) -> Result<(DataType, Vec<u16>, Vec<u16>), AppError> {
         self.change_reg.insert(content_id);
+        self.mark_touched(content_id);
         self.contents
             .update_transformative_link(is_hash, content_id, part_no, total_parts, data)
     }
@@ -4581,8 +5239,18 @@ impl ApplicationData {
             return Err(AppError::DatastoreFull);
         }
         self.change_reg.insert(index_to_add);
+        self.mark_touched(index_to_add);
         self.contents.append(content)
     }
+    // Only used to revert AppendContent, so c_id has to be the last one
+    pub fn pop_content(&mut self, c_id: ContentID) -> Result<Content, AppError> {
+        if c_id == 0 || c_id + 1 != self.contents.len() {
+            return Err(AppError::IndexingError);
+        }
+        self.change_reg.insert(c_id);
+        self.mark_touched(c_id);
+        self.contents.pop()
+    }
     pub fn get_type_and_len(&self, c_id: ContentID) -> Result<(DataType, u16), AppError> {
         self.contents.type_and_len(c_id)
     }
@@ -4600,14 +5268,17 @@ impl ApplicationData {
     }
     pub fn insert_data(&mut self, c_id: ContentID, d_id: u16, data: Data) -> Result<u64, AppError> {
         self.change_reg.insert(c_id);
+        self.mark_touched(c_id);
         self.contents.insert_data(c_id, d_id, data)
     }
     pub fn append_data(&mut self, c_id: ContentID, data: Data) -> Result<u64, AppError> {
         self.change_reg.insert(c_id);
+        self.mark_touched(c_id);
         self.contents.append_data(c_id, data)
     }
     pub fn pop_data(&mut self, c_id: ContentID) -> Result<Data, AppError> {
         self.change_reg.insert(c_id);
+        self.mark_touched(c_id);
         self.contents.pop_data(c_id)
     }
     pub fn update_data(
@@ -4617,6 +5288,7 @@ impl ApplicationData {
         data: Data,
     ) -> Result<Data, AppError> {
         self.change_reg.insert(c_id);
+        self.mark_touched(c_id);
         // if self.app_type == AppType::Other(0) && c_id == 0 && d_id == 0 {
         //     eprintln!("Maybe we should update AppType?");
         // }
@@ -4624,12 +5296,416 @@ impl ApplicationData {
     }
     pub fn remove_data(&mut self, c_id: ContentID, d_id: u16) -> Result<Data, AppError> {
         self.change_reg.insert(c_id);
+        self.mark_touched(c_id);
         self.contents.remove_data(c_id, d_id)
     }
     pub fn update(&mut self, c_id: ContentID, content: Content) -> Result<Content, AppError> {
         self.change_reg.insert(c_id);
+        self.mark_touched(c_id);
         self.contents.update(c_id, content)
     }
+
+    /// Apply a Transaction received from Swarm, all of it's operations or none.
+    /// Requirements have to cover every Content touched.
+    /// Returns ids of changed Contents.
+    pub fn apply_transaction(
+        &mut self,
+        ops: Vec<TxOperation>,
+        requirements: &SyncRequirements,
+    ) -> Result<Vec<ContentID>, AppError> {
+        let touched = tx_content_ids(&ops);
+        let covered = |reqs: &Vec<(ContentID, u64)>| {
+            touched
+                .iter()
+                .all(|c_id| reqs.iter().any(|(r_id, _hash)| r_id == c_id))
+        };
+        if touched.is_empty() || !covered(&requirements.pre) || !covered(&requirements.post) {
+            eprintln!("Transaction requirements do not cover {:?}", touched);
+            return Err(AppError::HashMismatch);
+        }
+        if !requirements.pre_validate(touched[0], self) {
+            eprintln!("PRE validation failed for Transaction");
+            return Err(AppError::HashMismatch);
+        }
+        let snapshot = self.tx_apply(ops)?;
+        if !requirements.post_validate(touched[0], self) {
+            eprintln!("POST validation failed for Transaction");
+            self.tx_rollback(snapshot);
+            return Err(AppError::HashMismatch);
+        }
+        Ok(touched)
+    }
+
+    // Root hashes of every Content touched by given operations
+    fn tx_hashes(&self, ops: &[TxOperation]) -> Vec<(ContentID, u64)> {
+        tx_content_ids(ops)
+            .into_iter()
+            .map(|c_id| {
+                let hash = self.content_root_hash(c_id).map_or(0, |(_t, hash)| hash);
+                (c_id, hash)
+            })
+            .collect()
+    }
+
+    // Apply operations in order, on first failure every touched
+    // Content is restored. On success returns touched Contents
+    // as they were before, so that those can be restored later.
+    fn tx_apply(&mut self, ops: Vec<TxOperation>) -> Result<Vec<(ContentID, Content)>, AppError> {
+        let mut snapshot = vec![];
+        for c_id in tx_content_ids(&ops) {
+            snapshot.push((c_id, self.clone_content(c_id)?));
+        }
+        for op in ops {
+            let res = match op {
+                TxOperation::AppendData(c_id, data) => self.append_data(c_id, data).map(|_h| ()),
+                TxOperation::RemoveData(c_id, d_id) => self.remove_data(c_id, d_id).map(|_d| ()),
+                TxOperation::UpdateData(c_id, d_id, data) => {
+                    self.update_data(c_id, d_id, data).map(|_d| ())
+                }
+                TxOperation::InsertData(c_id, d_id, data) => {
+                    self.insert_data(c_id, d_id, data).map(|_h| ())
+                }
+            };
+            if let Err(e) = res {
+                self.tx_rollback(snapshot);
+                return Err(e);
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Page with sibling hashes leading to it's Content root hash.
+    pub fn page_proof(&self, c_id: ContentID, d_id: u16) -> Result<PageProof, AppError> {
+        let path = self.contents.inclusion_path((c_id, d_id))?;
+        let data = self.read_data(c_id, d_id)?;
+        Ok(PageProof {
+            c_id,
+            d_id,
+            data,
+            path,
+        })
+    }
+
+    /// Ok(None) means Proof's pages are valid, but it is up to
+    /// application to find an Offense.
+    pub fn verify_proof(&mut self, proof: &Proof) -> Result<Option<Offense>, ProofError> {
+        proof.check_pages()?;
+        if let Some(offense) = proof.offense() {
+            return Ok(Some(offense));
+        }
+        // We can only check against current catalogue
+        if let SyncMessageType::AppDefined(m_type, c_id, d_id) = proof.message.m_type {
+            if let Ok(app_msg) = AppDefinedMsg::new(m_type, c_id, d_id, proof.message.data.clone())
+            {
+                if let Err(e) = self.check_app_msg(&app_msg, proof.offender) {
+                    return Ok(Some(Offense::AppMsgRejected(e)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn proof_part(&mut self, app_msg: &AppDefinedMsg) -> Option<Result<Proof, ProofError>> {
+        self.proof_parts.insert(app_msg)
+    }
+
+    /// Revert recently applied messages, until Datastore's root hash
+    /// is equal to given one. Returns Contents that were changed.
+    pub fn rollback_to(&mut self, root: u64) -> Result<Vec<ContentID>, AppError> {
+        let current_root = self.root_hash();
+        let Some(entries) = self.undo_log.take_until(current_root, root) else {
+            return Err(AppError::NotInUndoLog);
+        };
+        let mut c_ids = vec![];
+        for entry in entries {
+            match self.revert(entry.ops) {
+                Ok(mut reverted) => c_ids.append(&mut reverted),
+                Err(e) => {
+                    // We are stuck somewhere in between, no way back
+                    self.undo_log.clear();
+                    return Err(e);
+                }
+            }
+            if self.root_hash() != entry.root_before {
+                eprintln!("Message {} not reverted properly", entry.msg_hash);
+                self.undo_log.clear();
+                return Err(AppError::HashMismatch);
+            }
+        }
+        c_ids.sort();
+        c_ids.dedup();
+        Ok(c_ids)
+    }
+
+    // Apply undo operations of a single message, last one first.
+    // Returns Contents that were changed.
+    fn revert(&mut self, ops: Vec<UndoOp>) -> Result<Vec<ContentID>, AppError> {
+        let mut c_ids = Vec::with_capacity(ops.len());
+        for op in ops.into_iter().rev() {
+            c_ids.push(op.c_id());
+            match op {
+                UndoOp::PopData(c_id) => self.pop_data(c_id).map(|_d| ())?,
+                UndoOp::InsertData(c_id, d_id, data) => {
+                    self.insert_data(c_id, d_id, data).map(|_h| ())?
+                }
+                UndoOp::UpdateData(c_id, d_id, data) => {
+                    self.update_data(c_id, d_id, data).map(|_d| ())?
+                }
+                UndoOp::Restore(c_id, content) => self.update(c_id, content).map(|_c| ())?,
+                UndoOp::RemoveLastContent(c_id) => self.pop_content(c_id).map(|_c| ())?,
+            }
+        }
+        Ok(c_ids)
+    }
+
+    // History is loaded from disk on first use
+    async fn history_mut(&mut self, c_id: ContentID) -> &mut ContentHistory {
+        if !self.history.contains_key(&c_id) {
+            let history = load_history_from_disk(&self.storage, c_id)
+                .await
+                .unwrap_or_else(ContentHistory::new);
+            self.history.insert(c_id, history);
+        }
+        self.history.get_mut(&c_id).unwrap()
+    }
+
+    /// Add a new version to history of every given Content
+    /// whose root hash has changed.
+    // A shell was filled with given page, which does not change any hashes,
+    // so store it if a recorded version refers to it.
+    async fn store_filled_page(&mut self, c_id: ContentID, data: Data) {
+        if data.is_empty() {
+            return;
+        }
+        let (should_store, _max_page) = should_store_content_on_disk(&self.policy, c_id);
+        if !should_store {
+            return;
+        }
+        let hash = data.get_hash();
+        // History is not cached here, pages of most Contents are filled
+        // during initial sync
+        let referenced = match self.history.get(&c_id) {
+            Some(history) => history.references(hash),
+            None => load_history_from_disk(&self.storage, c_id)
+                .await
+                .is_some_and(|history| history.references(hash)),
+        };
+        if referenced {
+            store_pages_on_disk(&self.storage, vec![data]).await;
+        }
+    }
+
+    async fn record_versions(&mut self, c_ids: &HashSet<ContentID>, signed_by: GnomeId) {
+        for c_id in c_ids {
+            let c_id = *c_id;
+            let Ok((d_type, root_hash)) = self.content_root_hash(c_id) else {
+                continue;
+            };
+            let Ok(page_hashes) = self.content_bottom_hashes(c_id) else {
+                continue;
+            };
+            let history = self.history_mut(c_id).await;
+            let prev_hashes: HashSet<u64> = match history.last() {
+                Some(last) if last.root_hash == root_hash => continue,
+                Some(last) => last.page_hashes.iter().copied().collect(),
+                None => HashSet::new(),
+            };
+            let changed: Vec<u16> = page_hashes
+                .iter()
+                .enumerate()
+                .filter(|(_idx, hash)| !prev_hashes.contains(hash))
+                .map(|(idx, _hash)| idx as u16)
+                .collect();
+            if history
+                .push(d_type, root_hash, signed_by, page_hashes)
+                .is_some()
+            {
+                self.dropped_versions += 1;
+            }
+            let (should_store, _max_page) = should_store_content_on_disk(&self.policy, c_id);
+            if !should_store {
+                continue;
+            }
+            // Shelled pages are not stored
+            let pages: Vec<Data> = changed
+                .into_iter()
+                .filter_map(|d_id| self.read_data(c_id, d_id).ok())
+                .filter(|data| !data.is_empty())
+                .collect();
+            store_pages_on_disk(&self.storage, pages).await;
+            store_history_on_disk(&self.storage, c_id, &self.history[&c_id]).await;
+        }
+        if self.dropped_versions >= PAGES_GC_AFTER {
+            self.dropped_versions = 0;
+            remove_unreferenced_pages(&self.storage).await;
+        }
+    }
+
+    pub async fn content_versions(&mut self, c_id: ContentID) -> Vec<ContentVersion> {
+        self.history_mut(c_id).await.versions()
+    }
+
+    /// Pages of given Content as they were in given version.
+    pub async fn content_at_version(
+        &mut self,
+        c_id: ContentID,
+        version: u32,
+    ) -> Result<(DataType, Vec<Data>), AppError> {
+        let Some(c_version) = self.history_mut(c_id).await.get(version).cloned() else {
+            return Err(AppError::VersionUnavailable);
+        };
+        let mut pages = Vec::with_capacity(c_version.page_hashes.len());
+        for (idx, hash) in c_version.page_hashes.iter().enumerate() {
+            // Unchanged pages are taken from Datastore
+            if let Ok(data) = self.read_data(c_id, idx as u16) {
+                if !data.is_empty() && data.get_hash() == *hash {
+                    pages.push(data);
+                    continue;
+                }
+            }
+            let Some(data) = load_page_from_disk(&self.storage, *hash).await else {
+                eprintln!("CID-{} v{} page {} unavailable", c_id, version, idx);
+                return Err(AppError::VersionUnavailable);
+            };
+            pages.push(data);
+        }
+        Ok((c_version.d_type, pages))
+    }
+
+    /// Contents changed since previous call.
+    pub fn take_touched(&mut self) -> HashSet<ContentID> {
+        std::mem::take(&mut self.touched)
+    }
+
+    fn mark_touched(&mut self, c_id: ContentID) {
+        self.touched.insert(c_id);
+        if c_id == 0 {
+            self.manifest = None;
+        }
+    }
+
+    fn tx_rollback(&mut self, snapshot: Vec<(ContentID, Content)>) {
+        for (c_id, content) in snapshot {
+            if let Err(e) = self.update(c_id, content) {
+                eprintln!("Failed to restore CID-{}: {}", c_id, e);
+            }
+        }
+    }
+    /// Check given page against schema declared in Manifest for it's DataType,
+    /// DataTypes without a schema accept any Data.
+    pub fn validate_page(
+        &mut self,
+        d_type: DataType,
+        page_no: u16,
+        data: &Data,
+    ) -> Result<(), SchemaError> {
+        if d_type.is_link() {
+            return Ok(());
+        }
+        let Some(manifest) = self.cached_manifest() else {
+            return Ok(());
+        };
+        if let Some(schema) = manifest.schema(d_type) {
+            schema.validate(page_no, data.ref_bytes())
+        } else {
+            Ok(())
+        }
+    }
+
+    // AppDefined messages are checked against Manifest's catalogue
+    pub fn check_app_msg(
+        &mut self,
+        app_msg: &AppDefinedMsg,
+        signed_by: GnomeId,
+    ) -> Result<(), CatalogueError> {
+        let Some(manifest) = self.cached_manifest() else {
+            return Ok(());
+        };
+        manifest.check_app_msg(app_msg.m_type, app_msg.data.ref_bytes(), signed_by)
+    }
+
+    /// Check a page carried by a message from Swarm against Manifest's schema,
+    /// messages not adding or replacing a single page are not checked.
+    pub fn validate_synced(
+        &mut self,
+        m_type: &SyncMessageType,
+        data: &Data,
+    ) -> Result<(), (DataType, SchemaError)> {
+        let (d_type, page_no) = match m_type {
+            SyncMessageType::AppendContent(d_type) => (*d_type, 0),
+            SyncMessageType::AppendData(c_id) => {
+                let Ok((d_type, len)) = self.get_type_and_len(*c_id) else {
+                    return Ok(());
+                };
+                (d_type, len)
+            }
+            SyncMessageType::UpdateData(c_id, d_id) => {
+                let Ok((d_type, _len)) = self.get_type_and_len(*c_id) else {
+                    return Ok(());
+                };
+                (d_type, *d_id)
+            }
+            _other => return Ok(()),
+        };
+        self.validate_page(d_type, page_no, data)
+            .map_err(|e| (d_type, e))
+    }
+
+    pub fn manifest(&mut self) -> Option<Manifest> {
+        self.cached_manifest().cloned()
+    }
+
+    // Manifest is parsed once and kept until CID 0 changes.
+    // Pages are read directly from Datastore, so that frequent validation
+    // does not mark CID 0 as recently used in ChangeRegistry.
+    fn cached_manifest(&mut self) -> Option<&Manifest> {
+        if self.manifest.is_none() {
+            let mut d_vec = vec![];
+            while let Ok(data) = self.contents.read_data((0, d_vec.len() as u16)) {
+                d_vec.push(data);
+                if d_vec.len() == u16::MAX as usize {
+                    break;
+                }
+            }
+            self.manifest = Some(if d_vec.is_empty() {
+                None
+            } else {
+                Some(Manifest::from(d_vec))
+            });
+        }
+        self.manifest.as_ref().and_then(|m| m.as_ref())
+    }
+
+    // Returns updated first pages of all Contents labeled with mapped tag ids
+    fn retag_contents(&mut self, mapping: &HashMap<u8, Option<u8>>) -> Vec<(ContentID, Data)> {
+        let mut retagged = vec![];
+        let last_c_id = self.next_c_id().unwrap_or(u16::MAX);
+        for c_id in 1..last_c_id {
+            if let Ok(first_page) = self.read_data(c_id, 0) {
+                if let Some(new_page) = retag_first_page(&first_page, mapping) {
+                    retagged.push((c_id, new_page));
+                }
+            }
+        }
+        retagged
+    }
+
+    // Tag ids used by locally available Contents
+    fn referenced_tags(&mut self) -> HashSet<u8> {
+        let mut referenced = HashSet::new();
+        let last_c_id = self.next_c_id().unwrap_or(u16::MAX);
+        for c_id in 1..last_c_id {
+            if let Ok(first_page) = self.read_data(c_id, 0) {
+                if let Some((t_len, rest)) = first_page.ref_bytes().split_first() {
+                    for t_id in rest.iter().take(*t_len as usize) {
+                        referenced.insert(*t_id);
+                    }
+                }
+            }
+        }
+        referenced
+    }
+
     pub fn get_all_data(&mut self, c_id: ContentID) -> Result<Vec<Data>, AppError> {
         self.change_reg.insert(c_id);
         let read_result = self.contents.read_data((c_id, 0));
@@ -4728,26 +5804,10 @@ impl ApplicationData {
     }
 
     pub fn get_partial_hashes(&self) -> Vec<Data> {
-        let mut results = Vec::with_capacity(self.partial_data.len());
-        for (_vec, pdata) in self.partial_data.values() {
-            if let Some(hdata) = pdata.get(&0) {
-                results.push(hdata.clone());
-            }
-        }
-        results
+        self.partials.headers()
     }
     pub fn get_partial_data(&self) -> Vec<Data> {
-        let mut results = Vec::with_capacity(self.partial_data.len());
-        for (_vec, pdata) in self.partial_data.values() {
-            for (h, data) in pdata {
-                if *h == 0 {
-                    continue;
-                } else {
-                    results.push(data.clone());
-                }
-            }
-        }
-        results
+        self.partials.parts()
     }
 
     pub async fn process_change_content(
@@ -5709,6 +6769,110 @@ async fn change_content_task(
         eprintln!("Unable to change non existing content");
     }
 }
+async fn tag_lifecycle_task(
+    manifest: Manifest,
+    mapping: HashMap<u8, Option<u8>>,
+    app_data: &mut ApplicationData,
+    app_data_send: &ASender<ToAppData>,
+) {
+    let Ok((d_type, _len)) = app_data.get_type_and_len(0) else {
+        return;
+    };
+    let data_vec = match manifest.to_data() {
+        Ok(data_vec) => data_vec,
+        Err(e) => {
+            eprintln!("Can not store Manifest: {}", e);
+            return;
+        }
+    };
+    let _ = app_data_send
+        .send(ToAppData::ChangeContent(0, d_type, data_vec))
+        .await;
+    // Contents we do not have yet keep their ids tombstoned
+    let retagged = app_data.retag_contents(&mapping);
+    for (c_id, data) in retagged {
+        let _ = app_data_send
+            .send(ToAppData::UpdateData(c_id, 0, data))
+            .await;
+    }
+}
+fn tx_content_ids(ops: &[TxOperation]) -> Vec<ContentID> {
+    let mut c_ids: Vec<ContentID> = ops.iter().map(|op| op.c_id()).collect();
+    c_ids.sort();
+    c_ids.dedup();
+    c_ids
+}
+// Operations are applied locally only to compute post hashes,
+// then reverted until Swarm agrees on them.
+async fn transaction_task(
+    ops: Vec<TxOperation>,
+    app_data: &mut ApplicationData,
+    to_gnome_sender: &ASender<ToGnome>,
+) {
+    let Some(first_c_id) = ops.first().map(|op| op.c_id()) else {
+        return;
+    };
+    let Some(data) = TxOperation::to_data(&ops) else {
+        eprintln!("Transaction does not fit into a single Data");
+        return;
+    };
+    let pre = app_data.tx_hashes(&ops);
+    let snapshot = match app_data.tx_apply(ops) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            eprintln!("Transaction can not be applied: {}", e);
+            return;
+        }
+    };
+    let post = snapshot
+        .iter()
+        .map(|(c_id, _content)| {
+            let hash = app_data
+                .content_root_hash(*c_id)
+                .map_or(0, |(_t, hash)| hash);
+            (*c_id, hash)
+        })
+        .collect();
+    app_data.tx_rollback(snapshot);
+    let reqs = SyncRequirements { pre, post };
+    let msg = SyncMessage::new(SyncMessageType::Transaction(first_c_id), reqs, data);
+    for part in msg.into_parts() {
+        let _ = to_gnome_sender.send(ToGnome::AddData(part)).await;
+    }
+}
+async fn rollback_task(
+    root: u64,
+    app_data: &mut ApplicationData,
+    swarm_id: SwarmID,
+    to_app_mgr_send: &ASender<ToAppMgr>,
+    to_user: &ASender<ToApp>,
+) {
+    match app_data.rollback_to(root) {
+        Ok(c_ids) => {
+            eprintln!("Datastore rolled back to {}, changed: {:?}", root, c_ids);
+            for c_id in &c_ids {
+                // Reverted AppendContent is no longer there
+                let Ok((d_type, _len)) = app_data.get_type_and_len(*c_id) else {
+                    continue;
+                };
+                if app_data.autosave {
+                    app_data.save_content_to_disk(*c_id, None).await;
+                }
+                let main_page = app_data.read_data(*c_id, 0).ok();
+                let _ = to_app_mgr_send
+                    .send(ToAppMgr::ContentChanged(swarm_id, *c_id, d_type, main_page))
+                    .await;
+            }
+            let _ = to_user
+                .send(ToApp::DatastoreRolledBack(swarm_id, root, c_ids))
+                .await;
+        }
+        Err(e) => {
+            eprintln!("Rollback to {} failed: {}", root, e);
+            let _ = to_user.send(ToApp::RollbackFailed(swarm_id, e)).await;
+        }
+    }
+}
 async fn app_defined_request_task(app_msg: AppDefinedMsg, to_gnome_sender: &ASender<ToGnome>) {
     let msg = SyncMessage::new(
         SyncMessageType::AppDefined(app_msg.m_type, app_msg.c_id, app_msg.d_id),
@@ -5734,16 +6898,46 @@ async fn response_task(
     missing_pages: &mut (u16, HashMap<u64, usize>),
 ) {
     // println!("Processing data...");
-    let process_result = app_data.process(data);
-    if process_result.is_none() {
+    // For multi part messages it is header's signer, not the one
+    // who posted the last part
+    let Some((s_msg, signed_by)) = app_data.process(data, signed_by) else {
         return;
-    }
-    // println!("Process response: {:?}", process_result);
+    };
     let SyncMessage {
         m_type,
         requirements,
         data,
-    } = process_result.unwrap();
+    } = s_msg;
+    if let Err((d_type, e)) = app_data.validate_synced(&m_type, &data) {
+        eprintln!("{:?} from {} rejected: {}", m_type, signed_by.0, e);
+        let _ = to_app_mgr_send
+            .send(ToAppMgr::FromDatastore(LibResponse::DataRejected(
+                swarm_id, d_type, e,
+            )))
+            .await;
+        return;
+    }
+    let changed = changed_c_ids(&m_type, &data, app_data.next_c_id());
+    if let Some(changed) = &changed {
+        if let Err(violation) = requirements.check_sets(changed) {
+            eprintln!("{:?} rejected, {}", m_type, violation);
+            let _ = to_app_mgr_send
+                .send(ToAppMgr::FromDatastore(LibResponse::RequirementsViolated(
+                    swarm_id, m_type, violation,
+                )))
+                .await;
+            return;
+        }
+    }
+    let _ = app_data.take_touched();
+    let root_before = app_data.root_hash();
+    let msg_hash = {
+        let mut payload = requirements.clone().bytes();
+        payload.extend_from_slice(data.ref_bytes());
+        message_hash(&m_type, &payload)
+    };
+    // Operations reverting this message, in order of application
+    let mut undo = vec![];
 
     // println!("Received m_type: {:?}", m_type);
     match m_type {
@@ -5811,6 +7005,7 @@ async fn response_task(
                     let _res = app_data.append(content);
                     if _res.is_ok() {
                         eprintln!("Content added: {:?}", _res);
+                        undo.push(UndoOp::RemoveLastContent(recv_id));
                         // let hash = app_data.root_hash();
                         // eprintln!("Sending updated hash: {}", hash);
                         // let _res = to_gnome_sender.send(ToGnome::UpdateAppRootHash(hash));
@@ -5833,12 +7028,15 @@ async fn response_task(
             }
         }
         SyncMessageType::ChangeContent(c_id, d_type, operation) => {
+            if let Ok(content) = app_data.clone_content(c_id) {
+                undo.push(UndoOp::Restore(c_id, content));
+            }
             app_data
                 .process_change_content(
                     c_id,
                     d_type,
                     operation,
-                    requirements,
+                    requirements.clone(),
                     data,
                     missing_pages,
                     swarm_id,
@@ -5892,6 +7090,9 @@ async fn response_task(
                         "Data shells appended successfully ({}, added: {})",
                         hash, added_count
                     );
+                    for _i in 0..added_count {
+                        undo.push(UndoOp::PopData(c_id));
+                    }
                 }
             } else {
                 for i in 0..added_count {
@@ -5925,6 +7126,7 @@ async fn response_task(
                     // eprintln!("Sending updated hash: {}", hash);
                     // let _res = to_gnome_sender.send(ToGnome::UpdateAppRootHash(hash));
                     // eprintln!("Send res: {:?}", res);
+                    undo.push(UndoOp::PopData(c_id));
                     if app_data.autosave {
                         app_data.save_content_to_disk(c_id, None).await;
                     }
@@ -5963,6 +7165,7 @@ async fn response_task(
                     // );
                     eprintln!("Restore result: {:?}", res);
                 } else {
+                    undo.push(UndoOp::InsertData(c_id, d_id, removed_data));
                     if app_data.autosave {
                         app_data.save_content_to_disk(c_id, None).await;
                     }
@@ -5998,14 +7201,37 @@ async fn response_task(
             // }
             let main_page_option = if d_id == 0 { Some(data.clone()) } else { None };
             let data_hash = data.get_hash();
+            // In case declared post hash is wrong we can prove it
+            let proof_opt = app_data
+                .page_proof(c_id, d_id)
+                .ok()
+                .map(|page| (page, data.clone()));
+            let filled = data.clone();
             let res = app_data.update_data(c_id, d_id, data);
             if let Ok(updated_data) = res {
                 if !requirements.post_validate(c_id, &app_data) {
                     eprintln!("POST validation failed for UpdateData");
+                    if let Some((page, data)) = proof_opt {
+                        let message = SyncMessage::new(m_type, requirements.clone(), data);
+                        let proof = Proof::new(signed_by, message, vec![page]);
+                        if proof.check_pages().is_ok() && proof.offense().is_some() {
+                            if let Some(msg_hash) = app_data.proof_parts.hold(proof) {
+                                let _ = to_app_mgr_send
+                                    .send(ToAppMgr::FromDatastore(LibResponse::ProofBuilt(
+                                        swarm_id, msg_hash,
+                                    )))
+                                    .await;
+                            }
+                        }
+                    }
                     // TODO: restore previous order
                     let res = app_data.update_data(c_id, d_id, updated_data);
                     eprintln!("Restore result: {:?}", res);
                 } else {
+                    if updated_data.is_empty() {
+                        app_data.store_filled_page(c_id, filled).await;
+                    }
+                    undo.push(UndoOp::UpdateData(c_id, d_id, updated_data));
                     if app_data.autosave {
                         app_data.save_content_to_disk(c_id, None).await;
                     }
@@ -6054,10 +7280,89 @@ async fn response_task(
             // app_data.save_content_to_disk(c_id, None).await;
             eprintln!("SyncMessageType::ExtendData ");
         }
+        SyncMessageType::Transaction(_c_id) => {
+            let Some(ops) = TxOperation::from_data(&data) else {
+                eprintln!("Malformed Transaction");
+                return;
+            };
+            for c_id in tx_content_ids(&ops) {
+                if let Ok(content) = app_data.clone_content(c_id) {
+                    undo.push(UndoOp::Restore(c_id, content));
+                }
+            }
+            let main_pages: HashSet<ContentID> = ops
+                .iter()
+                .filter_map(|op| match op {
+                    TxOperation::AppendData(_c_id, _data) => None,
+                    TxOperation::RemoveData(c_id, d_id)
+                    | TxOperation::UpdateData(c_id, d_id, _)
+                    | TxOperation::InsertData(c_id, d_id, _) => (*d_id == 0).then_some(*c_id),
+                })
+                .collect();
+            match app_data.apply_transaction(ops, &requirements) {
+                Ok(c_ids) => {
+                    for c_id in c_ids {
+                        if app_data.autosave {
+                            app_data.save_content_to_disk(c_id, None).await;
+                        }
+                        let (d_type, _len) = app_data.get_type_and_len(c_id).unwrap();
+                        let main_page = if main_pages.contains(&c_id) {
+                            app_data.read_data(c_id, 0).ok()
+                        } else {
+                            None
+                        };
+                        let _to_mgr_res = to_app_mgr_send
+                            .send(ToAppMgr::ContentChanged(swarm_id, c_id, d_type, main_page))
+                            .await;
+                    }
+                }
+                Err(e) => eprintln!("Transaction rejected: {}", e),
+            }
+        }
         SyncMessageType::AppDefined(m_type, c_id, d_id) => {
             //TODO: should we do req check?
-            let app_msg = AppDefinedMsg::new(m_type, c_id, d_id, data).unwrap();
-            if app_data.should_auto_forward_heap_msg() {
+            let app_msg = AppDefinedMsg {
+                m_type,
+                c_id,
+                d_id,
+                data,
+            };
+            if m_type == PROOF_MSG_ID {
+                // Proofs are not put on heap, we verify them once all parts are here
+                if let Some(result) = app_data.proof_part(&app_msg) {
+                    let response = match result.and_then(|proof| {
+                        app_data
+                            .verify_proof(&proof)
+                            .map(|offense_opt| (proof, offense_opt))
+                    }) {
+                        // Offense is valid, but Proof does not carry offender's
+                        // signature, so we can not tell who sent it
+                        Ok((_proof, Some(offense))) => {
+                            eprintln!("Proof from {} shows: {}", signed_by.0, offense);
+                            None
+                        }
+                        Ok((proof, None)) => {
+                            Some(LibResponse::ProofToValidate(swarm_id, proof, signed_by))
+                        }
+                        Err(e) => {
+                            eprintln!("Proof from {} rejected: {}", signed_by.0, e);
+                            Some(LibResponse::ProofRejected(swarm_id, signed_by, e))
+                        }
+                    };
+                    if let Some(response) = response {
+                        let _ = to_app_mgr_send
+                            .send(ToAppMgr::FromDatastore(response))
+                            .await;
+                    }
+                }
+            } else if let Err(e) = app_data.check_app_msg(&app_msg, signed_by) {
+                eprintln!("AppDefined from {} rejected: {}", signed_by.0, e);
+                let _ = to_app_mgr_send
+                    .send(ToAppMgr::FromDatastore(LibResponse::AppMsgRejected(
+                        swarm_id, m_type, e,
+                    )))
+                    .await;
+            } else if app_data.should_auto_forward_heap_msg() {
                 // send immediately to App
                 eprintln!("forwarding heap data directly to app");
                 let _ = to_app_mgr_send
@@ -6071,6 +7376,103 @@ async fn response_task(
             eprintln!("SyncMessageType::UserDefined({})", m_type);
         }
     }
+    let root_after = app_data.root_hash();
+    let touched = app_data.take_touched();
+    if root_after == root_before {
+        // Nothing to check nor record, but if message was expected to
+        // change Datastore it's pre or post hashes did not match
+        if changed.is_some() && !requirements.post_validate(0, app_data) {
+            eprintln!("{:?} not applied, hashes do not match", m_type);
+            let _ = to_app_mgr_send
+                .send(ToAppMgr::FromDatastore(LibResponse::HashValidationFailed(
+                    swarm_id, m_type,
+                )))
+                .await;
+        }
+        return;
+    }
+    // Application code can also change Contents, and applying can fail
+    // half way, so we compare declared sets with what was actually touched.
+    // On mismatch message is reverted before it is reported.
+    if changed.is_some() {
+        if let Err(violation) = requirements.check_sets(&touched) {
+            eprintln!("{:?} rejected after applying, {}", m_type, violation);
+            revert_message(undo, root_before, app_data, swarm_id, to_app_mgr_send).await;
+            let _ = to_app_mgr_send
+                .send(ToAppMgr::FromDatastore(LibResponse::RequirementsViolated(
+                    swarm_id, m_type, violation,
+                )))
+                .await;
+            return;
+        }
+    }
+    app_data
+        .undo_log
+        .record(msg_hash, root_before, root_after, undo);
+    app_data.record_versions(&touched, signed_by).await;
+}
+// Message that was just applied is reverted, if it can not be
+// we are out of sync with Swarm and undo log is no longer valid
+async fn revert_message(
+    undo: Vec<UndoOp>,
+    root_before: u64,
+    app_data: &mut ApplicationData,
+    swarm_id: SwarmID,
+    to_app_mgr_send: &ASender<ToAppMgr>,
+) {
+    let reverted = if undo.is_empty() {
+        Err(AppError::NotInUndoLog)
+    } else {
+        app_data.revert(undo)
+    };
+    let _ = app_data.take_touched();
+    let c_ids = match reverted {
+        Ok(c_ids) if app_data.root_hash() == root_before => c_ids,
+        Ok(_c_ids) => {
+            eprintln!("Message not reverted properly, clearing undo log");
+            app_data.undo_log.clear();
+            return;
+        }
+        Err(e) => {
+            eprintln!("Message can not be reverted: {}, clearing undo log", e);
+            app_data.undo_log.clear();
+            return;
+        }
+    };
+    for c_id in c_ids {
+        if app_data.autosave {
+            app_data.save_content_to_disk(c_id, None).await;
+        }
+        let Ok((d_type, _len)) = app_data.get_type_and_len(c_id) else {
+            continue;
+        };
+        let main_page = app_data.read_data(c_id, 0).ok();
+        let _ = to_app_mgr_send
+            .send(ToAppMgr::ContentChanged(swarm_id, c_id, d_type, main_page))
+            .await;
+    }
+}
+// Contents given message is going to change,
+// None for message types that are not applied yet
+pub(crate) fn changed_c_ids(
+    m_type: &SyncMessageType,
+    data: &Data,
+    next_c_id: Option<ContentID>,
+) -> Option<HashSet<ContentID>> {
+    let c_ids = match m_type {
+        SyncMessageType::AppendContent(_d_type) => vec![next_c_id?],
+        SyncMessageType::ChangeContent(c_id, _, _)
+        | SyncMessageType::AppendShelledDatas(c_id)
+        | SyncMessageType::AppendData(c_id)
+        | SyncMessageType::RemoveData(c_id, _)
+        | SyncMessageType::UpdateData(c_id, _) => vec![*c_id],
+        SyncMessageType::Transaction(_c_id) => tx_content_ids(&TxOperation::from_data(data)?),
+        SyncMessageType::InsertData(_c_id, _d_id) | SyncMessageType::ExtendData(_c_id, _d_id) => {
+            return None;
+        }
+        SyncMessageType::AppDefined(_m_type, _c_id, _d_id) => vec![],
+    };
+    Some(c_ids.into_iter().collect())
 }
 async fn custom_request_task(
     m_type: u8,
@@ -6113,7 +7515,13 @@ async fn custom_request_task(
     }
     // match m_type {
     //     0 => {
-    let sync_requests = deserialize_requests(cast_data.bytes());
+    let sync_requests = match deserialize_requests(cast_data.bytes()) {
+        Ok(sync_requests) => sync_requests,
+        Err(e) => {
+            eprintln!("Sync request from {} rejected: {}", neighbor_id, e);
+            return;
+        }
+    };
     // TODO: we have to unconditionally sync partial_data!!!
     let partial_hashes = app_data.get_partial_hashes();
     for hdata in partial_hashes.into_iter() {
@@ -6429,6 +7837,16 @@ async fn custom_request_task(
                     }
                 }
             }
+            // A Neighbor is missing parts of a message we have assembled,
+            // we post them again so that whole Swarm can complete it
+            SyncRequest::MissingParts(msg_hash, part_nos) => {
+                let parts = app_data.parts_for_repost(msg_hash, &part_nos);
+                for part in parts {
+                    if let Ok(s_data) = SyncData::new(part.bytes()) {
+                        let _ = to_gnome_sender.send(ToGnome::AddData(s_data)).await;
+                    }
+                }
+            }
         }
     }
     //     }
@@ -6447,6 +7865,7 @@ async fn custom_response_task(
     swarm_id: SwarmID,
     app_data: &mut ApplicationData,
     active_reads: &mut HashMap<ContentID, ReadState>,
+    requests: &mut RequestTracker,
     incomplete_bottom_hashes: &mut HashMap<ContentID, PartialHashes>,
     swarm_name: &SwarmName,
     to_gnome_sender: &ASender<ToGnome>,
@@ -6465,6 +7884,7 @@ async fn custom_response_task(
             .await;
         return;
     }
+    requests.heard_from(neighbor_id);
     // match m_type {
     //     0 => {
     if let Ok(response) = SyncResponse::deserialize(cast_data.bytes()) {
@@ -6588,6 +8008,7 @@ async fn custom_response_task(
                                             true,
                                             None,
                                             to_gnome_sender.clone(),
+                                            requests,
                                         )
                                         .await;
                                         let _ = to_app_mgr_send
@@ -6637,6 +8058,7 @@ async fn custom_response_task(
                                         true,
                                         Some(neighbor_id),
                                         to_gnome_sender.clone(),
+                                        requests,
                                     )
                                     .await;
                                     let _ = to_app_mgr_send
@@ -6665,6 +8087,7 @@ async fn custom_response_task(
                                     true,
                                     None,
                                     to_gnome_sender.clone(),
+                                    requests,
                                 )
                                 .await;
                                 let _ = to_app_mgr_send
@@ -6690,6 +8113,7 @@ async fn custom_response_task(
                                 true,
                                 None,
                                 to_gnome_sender.clone(),
+                                requests,
                             )
                             .await;
                             let _ = to_app_mgr_send
@@ -6759,6 +8183,15 @@ async fn custom_response_task(
                         max_cid,
                         sender: app_data_send.clone(),
                         root_hash: app_data.root_hash(),
+                        full_pages_stored: matches!(
+                            app_data.policy.0,
+                            StoragePolicy::All | StoragePolicy::Manifest
+                        ),
+                        c_hashes: app_data
+                            .all_content_typed_root_hashes()
+                            .into_iter()
+                            .flatten()
+                            .collect(),
                         s_descr: String::new(),
                         s_tags: HashMap::new(),
                     };
@@ -6777,6 +8210,7 @@ async fn custom_response_task(
                     c_id,
                     hashes.len()
                 );
+                requests.hashes_received(c_id, part_no, total);
                 if let Ok((d_type, _len)) = app_data.get_type_and_len(c_id) {
                     if matches!(d_type, DataType::Link) {
                         let _upd_res =
@@ -6855,6 +8289,7 @@ async fn custom_response_task(
             }
             SyncResponse::Page(c_id, data_type, page_no, total, data) => {
                 eprintln!("CID-{} Page #{}/{}", c_id, page_no, total);
+                requests.page_received(c_id, page_no, total);
                 let d_empty = data.is_empty();
                 if d_empty && data.get_hash() == 0 {
                     eprintln!("Ignoring zero hashed empty data.");
@@ -6907,6 +8342,9 @@ async fn custom_response_task(
 
                             eprintln!("New page #0 hash: {} [{:?}]", data.get_hash(), data);
                             let res = app_data.update_data(c_id, page_no, data.clone());
+                            if res.as_ref().is_ok_and(|old| old.is_empty()) {
+                                app_data.store_filled_page(c_id, data.clone()).await;
+                            }
                             if res.is_ok() && !d_empty {
                                 update_active_reads(active_reads, &c_id, page_no, app_data_send)
                                     .await;
@@ -6915,6 +8353,7 @@ async fn custom_response_task(
                                     .send(SearchMsg::FirstPages(
                                         swarm_id,
                                         swarm_name.clone(),
+                                        None,
                                         vec![(c_id, d_type, data.clone())],
                                     ))
                                     .await;
@@ -7005,7 +8444,10 @@ async fn custom_response_task(
                 } else if let Ok((d_type, len)) = app_data.get_type_and_len(c_id) {
                     if d_type == data_type {
                         // let res = app_data.append_data(c_id, data);
-                        let res = app_data.update_data(c_id, page_no, data);
+                        let res = app_data.update_data(c_id, page_no, data.clone());
+                        if res.as_ref().is_ok_and(|old| old.is_empty()) {
+                            app_data.store_filled_page(c_id, data).await;
+                        }
                         if res.is_ok() && !d_empty {
                             update_active_reads(active_reads, &c_id, page_no, app_data_send).await;
                             eprintln!("C-{} Page #{} update result: ok", c_id, page_no,);
@@ -7050,6 +8492,7 @@ async fn read_data_task(
     mut starting_page: u16,
     last_page: Option<u16>,
     active_reads: &mut HashMap<ContentID, ReadState>,
+    requests: &mut RequestTracker,
     datastore_sync: &Option<(u16, HashMap<u16, Vec<(DataType, u64)>>)>,
     to_app_mgr_send: &ASender<ToAppMgr>,
     swarm_id: SwarmID,
@@ -7200,6 +8643,7 @@ async fn read_data_task(
                 let dmall = data_missing.chunks_exact(680);
                 let rmd = dmall.remainder();
                 for dmc in dmall {
+                    requests.track_pages(c_id, d_type, dmc);
                     let sync_requests: Vec<SyncRequest> =
                         vec![SyncRequest::Pages(c_id, d_type, dmc.to_vec())];
                     let _res = to_gnome_sender
@@ -7214,6 +8658,7 @@ async fn read_data_task(
                         .await;
                 }
                 if !rmd.is_empty() {
+                    requests.track_pages(c_id, d_type, rmd);
                     let sync_requests: Vec<SyncRequest> =
                         vec![SyncRequest::Pages(c_id, d_type, rmd.to_vec())];
                     let _rem = to_gnome_sender
@@ -7279,15 +8724,17 @@ async fn read_data_task(
                     .await;
             }
             Requestor::Search => {
+                // Search needs to know if there will be another chunk
+                // for this request, not if this is the last page of Content
+                let no_more_chunks = is_last || read_to_page_incl <= starting_page + 63;
                 let _ = to_search_enigne
-                    // ReadSuccess(SwarmID, SwarmName, ContentID, DataType, Vec<Data>),
                     .send(SearchMsg::ReadSuccess(
                         swarm_id,
                         swarm_name.clone(),
                         c_id,
                         t,
-                        // chunk_no,
-                        // is_last,
+                        starting_page,
+                        no_more_chunks,
                         data_vec,
                     ))
                     .await;
@@ -7540,6 +8987,7 @@ fn determine_storage_policy(
 // Datastore, and can run the required validation on it's own.
 // Once given message is proven to be invalid it gets reverted and Gnome is being
 // Suspended.
+// Proof structure and it's verification live in proof.rs.
 
 // When syncing, all content should be stored in byte chunks of up to 1024 bytes each.
 // Those chunks should be stored in a BHTree containing as many Leafs as needed to store
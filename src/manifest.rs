@@ -1,4 +1,12 @@
 use crate::app_type::AppType;
+use crate::catalogue::CatalogueError;
+use crate::catalogue::MessageDecl;
+use crate::catalogue::MessageSender;
+use crate::content::DataType;
+use crate::message::MAX_AVAIL_APP_MSG_ID;
+use crate::schema::DataSchema;
+use crate::schema::SchemaError;
+use crate::schema::Value;
 use crate::Data;
 use gnome::prelude::sha_hash;
 use gnome::prelude::ByteSet;
@@ -12,12 +20,98 @@ use gnome::prelude::PortAllocationRule;
 use gnome::prelude::Requirement;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::fmt;
 use std::hash::Hash;
 // use std::hash::{DefaultHasher, Hasher};
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
 use std::net::Ipv6Addr;
 
+const DIRECTORY_MARKER: u8 = 255;
+const SECTION_TAGS: u8 = 0;
+const SECTION_DATA_TYPES: u8 = 1;
+const SECTION_POLICIES: u8 = 2;
+const SECTION_CAPABILITIES: u8 = 3;
+const SECTION_BYTE_SETS: u8 = 4;
+const SECTION_NETWORK: u8 = 5;
+const SECTION_SCHEMAS: u8 = 6;
+const SECTION_MESSAGES: u8 = 7;
+const SECTION_LOCALES: u8 = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ManifestError {
+    // Bytes required to store first page
+    FirstPageTooLong(usize),
+    // Pages required to store all sections
+    TooManyPages(usize),
+}
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FirstPageTooLong(len) => {
+                write!(f, "Manifest first page needs {} bytes, max is 1024", len)
+            }
+            Self::TooManyPages(count) => {
+                write!(f, "Manifest needs {} pages, max is {}", count, u16::MAX)
+            }
+        }
+    }
+}
+
+// What differs between two Manifests, used to review an imported Manifest
+// before it replaces the one stored under CID 0.
+// Tags and DataTypes are listed one by one, other sections are
+// compared by their serialized pages and only reported as changed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ManifestChange {
+    AppType(AppType),
+    Description(String),
+    // id, old, new
+    Tag(u8, Option<Tag>, Option<Tag>),
+    DataType(u8, Option<Tag>, Option<Tag>),
+    TagTombstones,
+    Policies,
+    Capabilities,
+    ByteSets,
+    NetworkSettings,
+    Schemas,
+    Messages,
+    Locales,
+    Extensions,
+}
+impl fmt::Display for ManifestChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AppType(a_type) => write!(f, "AppType: {:?}", a_type),
+            Self::Description(text) => write!(f, "Description: {}", text),
+            Self::Tag(id, old, new) | Self::DataType(id, old, new) => {
+                let kind = if matches!(self, Self::Tag(..)) {
+                    "Tag"
+                } else {
+                    "DataType"
+                };
+                match (old, new) {
+                    (None, Some(new)) => write!(f, "{} {} added: {}", kind, id, new.0),
+                    (Some(old), None) => write!(f, "{} {} removed: {}", kind, id, old.0),
+                    (Some(old), Some(new)) => {
+                        write!(f, "{} {} renamed: {} -> {}", kind, id, old.0, new.0)
+                    }
+                    (None, None) => write!(f, "{} {} unchanged", kind, id),
+                }
+            }
+            Self::TagTombstones => write!(f, "Tag tombstones changed"),
+            Self::Policies => write!(f, "Policies changed"),
+            Self::Capabilities => write!(f, "Capabilities changed"),
+            Self::ByteSets => write!(f, "ByteSets changed"),
+            Self::NetworkSettings => write!(f, "Network settings changed"),
+            Self::Schemas => write!(f, "Schemas changed"),
+            Self::Messages => write!(f, "Message catalogue changed"),
+            Self::Locales => write!(f, "Localizations changed"),
+            Self::Extensions => write!(f, "Unknown sections changed"),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct CombinedNetworkSettings {
     pub udp_ip4: Option<NetworkSettings>,
@@ -105,6 +199,11 @@ pub struct Manifest {
     pub_ips: CombinedNetworkSettings,
     pub description: String,
     pub tags: HashMap<u8, Tag>,
+    // Ids of deleted Tags that some Contents might still reference,
+    // those are not reused until released.
+    // Stored in tag pages as a blank Tag, so older Manifest readers
+    // also consider those ids taken.
+    pub tag_tombstones: HashSet<u8>,
     pub d_types: HashMap<u8, Tag>,
     // TODO: store Swarm's Policy
     pub policy_reg: HashMap<Policy, Requirement>,
@@ -112,17 +211,19 @@ pub struct Manifest {
     pub capability_reg: HashMap<Capabilities, CapabiLeaf>,
     // TODO: store Swarm's ByteSets
     pub byteset_reg: HashMap<u8, ByteSet>,
+    // What pages of given DataType::Data(n) contain
+    pub schemas: HashMap<u8, DataSchema>,
+    // Names, payloads and senders of AppDefined messages
+    pub messages: HashMap<u8, MessageDecl>,
+    // Translated descriptions and Tag names, by language code
+    pub locales: HashMap<String, Localization>,
+    // Sections we do not understand, kept as they were read
+    extensions: HashMap<u8, Vec<Data>>,
     // Above should be loaded only when needed,
     // for many users this might not be the case.
     //
-    // TODO: once we have above in place we have to derive
-    // logic for making sure what Swarm has in it's running settings
-    // matches that in Manifest data.
-    // For this we need a way to retrieve Swarm's running PRCBs values.
-    // Now we can check if running settings allow us to make changes.
-    // If so, we compare running settings with Manifest settings to see,
-    // if there needs to be a change.
-    // If that is true we prepare Reconfigure messages and send them to Gnome.
+    // Making sure Swarm's running settings match those in Manifest
+    // is done on request, see reconcile.rs.
 }
 // TODO: a new Manifest definition, with attributes being added as needed during development
 //  Manifest should apply to a Swarm, not an Application, application is defined in code
@@ -168,6 +269,59 @@ impl Tag {
         bytes
     }
 }
+// Language codes are expected in BCP 47 form, like "en" or "pt-BR".
+// Serialized localization layout:
+// 1 byte  - language code len + this many bytes of code
+// 2 bytes - description len + this many bytes of description
+// 1 byte  - translated Tags count
+// for every Tag:
+// 1 byte  - Tag id
+// 1 byte  - name len + this many bytes of name
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Localization {
+    pub description: String,
+    pub tags: HashMap<u8, Tag>,
+}
+impl Localization {
+    fn append_bytes_to(&self, code: &str, bytes: &mut Vec<u8>) {
+        bytes.push(code.len() as u8);
+        bytes.extend_from_slice(code.as_bytes());
+        bytes.extend_from_slice(&(self.description.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(self.description.as_bytes());
+        bytes.push(self.tags.len() as u8);
+        for t_id in 0..=255 {
+            if let Some(tag) = self.tags.get(&t_id) {
+                bytes.push(t_id);
+                bytes.push(tag.0.len() as u8);
+                bytes.extend_from_slice(tag.0.as_bytes());
+            }
+        }
+    }
+
+    fn from(bytes: &mut impl Iterator<Item = u8>) -> Option<(String, Self)> {
+        let code_len = bytes.next()? as usize;
+        let code = read_string(bytes, code_len)?;
+        let d_len = u16::from_be_bytes([bytes.next()?, bytes.next()?]) as usize;
+        let description = read_string(bytes, d_len)?;
+        let t_count = bytes.next()?;
+        let mut tags = HashMap::with_capacity(t_count as usize);
+        for _i in 0..t_count {
+            let t_id = bytes.next()?;
+            let t_len = bytes.next()? as usize;
+            tags.insert(t_id, Tag::new(read_string(bytes, t_len)?).ok()?);
+        }
+        Some((code, Localization { description, tags }))
+    }
+}
+
+fn read_string(bytes: &mut impl Iterator<Item = u8>, len: usize) -> Option<String> {
+    let mut s_bytes = Vec::with_capacity(len);
+    for _i in 0..len {
+        s_bytes.push(bytes.next()?);
+    }
+    String::from_utf8(s_bytes).ok()
+}
+
 impl Manifest {
     pub fn new(app_type: AppType, tags: HashMap<u8, Tag>) -> Self {
         Manifest {
@@ -175,16 +329,20 @@ impl Manifest {
             pub_ips: CombinedNetworkSettings::new(),
             description: String::new(),
             tags,
+            tag_tombstones: HashSet::new(),
             d_types: HashMap::new(),
             policy_reg: HashMap::new(),
             capability_reg: HashMap::new(),
             byteset_reg: HashMap::new(),
+            schemas: HashMap::new(),
+            messages: HashMap::new(),
+            locales: HashMap::new(),
+            extensions: HashMap::new(),
         }
     }
 
     pub fn set_description(&mut self, text: String) -> bool {
-        // TODO: count how many bytes we have left
-        if text.len() > 1000 {
+        if text.len() > self.description_budget() {
             return false;
         }
         self.description = text;
@@ -223,13 +381,33 @@ impl Manifest {
         sha_hash(&bytes)
     }
 
-    //TODO: There can be up to 256 Tags defined in a manifest,
-    // each 32 bytes long, so Tags will use at most 9 Data chunks
-    // If we add a single Tag, then Manifest has to expand to 2 Datas.
-    // Tags are ordered from 0 to 255.
-    // Each Tag is stored as a sequence of 32 bytes in Data,
-    // if all of these bytes are 0,
-    // then given Tag is not defined, otherwise given Tag is defined.
+    // Manifest is stored as a Content with multiple pages.
+    // First page follows standard FirstPage layout, with no Tags:
+    // 1 byte  - TagLen, always 0
+    // 2 bytes - description len + this many bytes of description
+    // 1 byte  - AppType
+    // 1 byte  - DIRECTORY_MARKER, older Manifests have page pointers here
+    // 1 byte  - sections count
+    // for every section:
+    // 1 byte  - section id
+    // 2 bytes - first page of given section
+    // 2 bytes - how many pages given section spans
+    // remaining bytes hold network settings: 0|len|NetworkSettings bytes,
+    // those are kept on first page, so that they are available
+    // when only first pages of Contents are synced.
+    //
+    // Sections are stored on subsequent pages, in order they are listed.
+    // Only non empty sections are listed, except network settings,
+    // which always point to page 0.
+    // Sections with unknown ids are kept as they are, so that
+    // a Manifest written by newer software survives being
+    // modified by an older one.
+    //
+    // Tags and Data types are stored as 32 byte slots, id = slot index,
+    // all zeros means not defined, all spaces means a tombstoned Tag id.
+    // Other sections are a sequence of records that can span pages.
+    //
+    // Manifests written before the directory was introduced are still read.
     pub fn from(data_vec: Vec<Data>) -> Self {
         // eprintln!(
         //     "In Manifest::from data count: {}, first data len: {}",
@@ -238,36 +416,13 @@ impl Manifest {
         // );
         let data_count = data_vec.len();
         if data_count == 0 || data_vec[0].is_empty() {
-            return Manifest {
-                app_type: AppType::Other(0),
-                pub_ips: CombinedNetworkSettings::new(),
-                description: String::new(),
-                tags: HashMap::new(),
-                d_types: HashMap::new(),
-                policy_reg: HashMap::new(),
-                capability_reg: HashMap::new(),
-                byteset_reg: HashMap::new(),
-            };
+            return Manifest::new(AppType::Other(0), HashMap::new());
         }
         // eprintln!("Constructing manifest from: {} Data blocks", data_count);
-        let mut data_iter = data_vec.into_iter();
-        let first_data = data_iter.next().unwrap();
-        let mut iter = first_data.bytes().into_iter();
+        let mut iter = data_vec[0].clone().bytes().into_iter();
         if data_count == 1 && iter.len() == 1 {
             // In case Manifest has only 1 byte then it is AppType
-            // let _tcount = iter.next();
-            // let _dlen0 = iter.next();
-            // let _dlen1 = iter.next();
-            return Manifest {
-                app_type: AppType::from(iter.next().unwrap()),
-                pub_ips: CombinedNetworkSettings::new(),
-                description: String::new(),
-                tags: HashMap::new(),
-                d_types: HashMap::new(),
-                policy_reg: HashMap::new(),
-                capability_reg: HashMap::new(),
-                byteset_reg: HashMap::new(),
-            };
+            return Manifest::new(AppType::from(iter.next().unwrap()), HashMap::new());
         }
         let _tcount = iter.next(); //Always zero
 
@@ -284,18 +439,83 @@ impl Manifest {
 
         let app_type_byte = iter.next();
         if app_type_byte.is_none() {
-            return Manifest {
-                app_type: AppType::Other(0),
-                pub_ips: CombinedNetworkSettings::new(),
-                description: String::new(),
-                tags: HashMap::new(),
-                d_types: HashMap::new(),
-                policy_reg: HashMap::new(),
-                capability_reg: HashMap::new(),
-                byteset_reg: HashMap::new(),
+            return Manifest::new(AppType::Other(0), HashMap::new());
+        }
+        let mut manifest = Manifest::new(AppType::from(app_type_byte.unwrap()), HashMap::new());
+        manifest.description = description;
+        if iter.clone().next() == Some(DIRECTORY_MARKER) {
+            let _marker = iter.next();
+            manifest.read_sections(&mut iter, &data_vec);
+        } else {
+            manifest.read_legacy_sections(&mut iter, data_vec);
+        }
+        manifest
+    }
+
+    fn read_sections(&mut self, iter: &mut std::vec::IntoIter<u8>, data_vec: &[Data]) {
+        let count = iter.next().unwrap_or(0);
+        let mut directory = Vec::with_capacity(count as usize);
+        for _i in 0..count {
+            let (Some(s_id), Some(p0), Some(p1), Some(c0), Some(c1)) = (
+                iter.next(),
+                iter.next(),
+                iter.next(),
+                iter.next(),
+                iter.next(),
+            ) else {
+                eprintln!("Manifest section directory truncated");
+                break;
+            };
+            let first_page = u16::from_be_bytes([p0, p1]) as usize;
+            let page_count = u16::from_be_bytes([c0, c1]) as usize;
+            directory.push((s_id, first_page, page_count));
+        }
+        for (s_id, first_page, page_count) in directory {
+            if s_id == SECTION_NETWORK {
+                self.pub_ips = read_pub_ips(iter);
+                continue;
+            }
+            let last_page = (first_page + page_count).min(data_vec.len());
+            if first_page == 0 || first_page > last_page || last_page - first_page < page_count {
+                eprintln!("Missing Data for Manifest section {s_id}");
+            }
+            let pages = if first_page == 0 || first_page > last_page {
+                &data_vec[0..0]
+            } else {
+                &data_vec[first_page..last_page]
             };
+            let bytes = section_bytes(pages.iter().cloned(), pages.len(), s_id);
+            self.read_section(s_id, bytes, pages);
         }
-        let app_type = AppType::from(app_type_byte.unwrap());
+    }
+
+    fn read_section(&mut self, s_id: u8, bytes: Vec<u8>, pages: &[Data]) {
+        match s_id {
+            SECTION_TAGS => {
+                let (tags, tombstones) = read_tag_slots(&bytes);
+                self.tags = tags;
+                self.tag_tombstones = tombstones;
+            }
+            SECTION_DATA_TYPES => self.d_types = read_tag_slots(&bytes).0,
+            SECTION_POLICIES => self.policy_reg = read_policies(bytes),
+            SECTION_CAPABILITIES => self.capability_reg = read_capabilities(bytes),
+            SECTION_BYTE_SETS => self.byteset_reg = read_byte_sets(bytes),
+            SECTION_SCHEMAS => self.schemas = read_schemas(bytes),
+            SECTION_MESSAGES => self.messages = read_messages(bytes),
+            SECTION_LOCALES => self.locales = read_locales(bytes),
+            other => {
+                eprintln!("Keeping unknown Manifest section {other}");
+                self.extensions.insert(other, pages.to_vec());
+            }
+        }
+    }
+
+    // Before section directory was introduced, first page held
+    // a 2 byte first page pointer for tags, data types, policies,
+    // capabilities and byte sets, followed by network settings
+    // and pointers to schemas and message catalogue.
+    fn read_legacy_sections(&mut self, iter: &mut std::vec::IntoIter<u8>, data_vec: Vec<Data>) {
+        let data_count = data_vec.len();
         let first_tags_page = u16::from_be_bytes([iter.next().unwrap(), iter.next().unwrap()]);
         eprintln!("first_tags_page: {first_tags_page}");
         let first_dt_page = u16::from_be_bytes([iter.next().unwrap(), iter.next().unwrap()]);
@@ -315,7 +535,7 @@ impl Manifest {
         };
         eprintln!("first_bsets_page: {first_bsets_page}");
 
-        let tag_pages_count = if first_tags_page == 0 {
+        let mut tag_pages_count = if first_tags_page == 0 {
             0
         } else {
             if first_dt_page == 0
@@ -334,7 +554,7 @@ impl Manifest {
                 first_bsets_page as usize - first_tags_page as usize
             }
         };
-        let dt_page_count = if first_dt_page == 0 {
+        let mut dt_page_count = if first_dt_page == 0 {
             0
         } else {
             if first_policy_page == 0 && first_caps_page == 0 && first_bsets_page == 0 {
@@ -347,7 +567,7 @@ impl Manifest {
                 first_bsets_page as usize - first_dt_page as usize
             }
         };
-        let policy_page_count = if first_policy_page == 0 {
+        let mut policy_page_count = if first_policy_page == 0 {
             0
         } else {
             if first_caps_page == 0 && first_bsets_page == 0 {
@@ -358,7 +578,7 @@ impl Manifest {
                 first_bsets_page as usize - first_policy_page as usize
             }
         };
-        let caps_page_count = if first_caps_page == 0 {
+        let mut caps_page_count = if first_caps_page == 0 {
             0
         } else {
             if first_bsets_page == 0 {
@@ -367,505 +587,148 @@ impl Manifest {
                 first_bsets_page as usize - first_caps_page as usize
             }
         };
-        let bsets_page_count = if first_bsets_page == 0 {
+        let mut bsets_page_count = if first_bsets_page == 0 {
             0
         } else {
             eprintln!("data_count: {data_count}– {first_bsets_page}");
             data_count - (first_bsets_page as usize)
         };
 
-        let mut pub_ips = CombinedNetworkSettings::new();
-        let mut tags = HashMap::with_capacity(tag_pages_count << 5);
-        let mut d_types = HashMap::with_capacity(dt_page_count << 5);
-        let mut policy_reg = HashMap::new();
-        let mut capability_reg = HashMap::new();
-        let mut byteset_reg = HashMap::new();
-
-        // First byte in UTF-8 encoded String can not start with a 1 value,
-        // so we are good
-        // Read one byte and check its value:
-        // – if it's higher than 251 use old logic
-        // – otherwise it indicates how many following bytes
-        //   need to be read in order to retrieve pub_ips.
-        // Read that many and update pub_ips
-        if let Some(next_byte) = iter.clone().peekable().next() {
-            eprintln!("Next_byte: {}", next_byte);
-            if next_byte == 255 || next_byte == 254 || next_byte == 253 || next_byte == 252 {
-                match iter.next().unwrap() {
-                    255 => {
-                        eprintln!("255 we have IPv4 & IPv6");
-                        // first read IPv4 address, port,Nat,PortAllocationRule,step
-                        let (pub_ip, pub_port, nat_type, port_allocation) = read_ipv4(&mut iter);
-                        let ns = NetworkSettings {
-                            pub_ip,
-                            pub_port,
-                            nat_type,
-                            port_allocation,
-                            transport: gnome::prelude::Transport::UDPoverIP4,
-                        };
-                        // pub_ips.push(read_ipv4(&mut iter));
-                        pub_ips.update(vec![ns]);
-                        // second read IPv6 address and port, we assume all IPv6 to be public
-                        // pub_ips.push(read_ipv6(&mut iter));
-                        let (pub_ip, pub_port, nat_type, port_allocation) = read_ipv6(&mut iter);
-                        let ns = NetworkSettings {
-                            pub_ip,
-                            pub_port,
-                            nat_type,
-                            port_allocation,
-                            transport: gnome::prelude::Transport::UDPoverIP4,
-                        };
-                        pub_ips.update(vec![ns]);
-                    }
-                    254 => {
-                        eprintln!("254 IPv6 only");
-                        //     254 IPv6 only
-                        // pub_ips.push(read_ipv6(&mut iter));
-                        let (pub_ip, pub_port, nat_type, port_allocation) = read_ipv6(&mut iter);
-                        let ns = NetworkSettings {
-                            pub_ip,
-                            pub_port,
-                            nat_type,
-                            port_allocation,
-                            transport: gnome::prelude::Transport::UDPoverIP4,
-                        };
-                        pub_ips.update(vec![ns]);
-                    }
-                    253 => {
-                        eprintln!("253 IPv4 only");
-                        //     253 IPv4 only
-                        let (pub_ip, pub_port, nat_type, port_allocation) = read_ipv4(&mut iter);
-                        let ns = NetworkSettings {
-                            pub_ip,
-                            pub_port,
-                            nat_type,
-                            port_allocation,
-                            transport: gnome::prelude::Transport::UDPoverIP4,
-                        };
-                        // pub_ips.push(read_ipv4(&mut iter));
-                        pub_ips.update(vec![ns]);
-                    }
-                    _ => {
-                        eprintln!("252 No Public IPs defined");
-                        //     252 No Public IPs defined
-                    }
-                }
-            } else if next_byte == 0 {
-                let _byte = iter.next().unwrap();
-                let ns_bytesize = iter.next().unwrap();
-                let mut ns_bytes = Vec::with_capacity(ns_bytesize as usize);
-                eprintln!(
-                    "We are using new method to load pub_ips from {} bytes",
-                    ns_bytesize
-                );
-                for _i in 0..ns_bytesize {
-                    ns_bytes.push(iter.next().unwrap());
-                }
-                let nss = NetworkSettings::from(&ns_bytes);
-                pub_ips.update(nss);
-            } else {
-                eprintln!("Uncecognized next_byte: {next_byte}");
-            }
-        }
-        let mut current_tag_id: u8 = 0;
-        // let mut adding_tags = true;
-        // let mut tag_pages_read = 0;
-        for _page in 0..tag_pages_count {
-            let data = data_iter.next().unwrap();
-            let bytes = data.bytes();
-            for chunk in bytes.chunks_exact(32) {
-                let mut all_zeros = true;
-                let mut non_space_byte_occured = false;
-                let mut name_bytes = Vec::with_capacity(32);
-                for byte in chunk {
-                    if *byte > 0 {
-                        all_zeros = false;
-                    }
-                    if *byte == 32 {
-                        if non_space_byte_occured {
-                            name_bytes.push(*byte);
-                        }
-                    } else {
-                        non_space_byte_occured = true;
-                        name_bytes.push(*byte);
-                    }
-                }
-                if !all_zeros {
-                    let tag = Tag::new(String::from_utf8(name_bytes).unwrap()).unwrap();
-                    tags.insert(current_tag_id, tag);
-                }
-                current_tag_id = current_tag_id.saturating_add(1);
-            }
-        }
-        current_tag_id = 0;
-        // let mut dtype_pages_read = 0;
-        for _page in 0..dt_page_count {
-            // TODO: Rework parsing bytes logic
-            let data = data_iter.next().unwrap();
-            let bytes = data.bytes();
-            for chunk in bytes.chunks_exact(32) {
-                let mut all_zeros = true;
-                let mut non_space_byte_occured = false;
-                let mut name_bytes = Vec::with_capacity(32);
-                for byte in chunk {
-                    if *byte > 0 {
-                        all_zeros = false;
-                    }
-                    if *byte == 32 {
-                        if non_space_byte_occured {
-                            name_bytes.push(*byte);
-                        }
-                    } else {
-                        non_space_byte_occured = true;
-                        name_bytes.push(*byte);
-                    }
-                }
-                if !all_zeros {
-                    let tag = Tag::new(String::from_utf8(name_bytes).unwrap()).unwrap();
-                    d_types.insert(current_tag_id, tag);
-                }
-                current_tag_id = current_tag_id.saturating_add(1);
-            }
-        }
-        //  read policy
-        let mut p_bytes = Vec::with_capacity(policy_page_count * 1024);
-        for _page in 0..policy_page_count {
-            if let Some(data) = data_iter.next() {
-                let mut bts = data.bytes();
-                if !bts.is_empty() {
-                    p_bytes.append(&mut bts);
-                } else {
-                    eprintln!("Data empty for Policy");
-                    break;
-                }
-            } else {
-                eprintln!("Missing Data for Policy");
-                break;
-            }
-        }
-        while !p_bytes.is_empty() {
-            let pol = Policy::from(&mut p_bytes);
-            let req = Requirement::from(&mut p_bytes);
-            policy_reg.insert(pol, req);
+        self.pub_ips = read_pub_ips(iter);
+        // Schemas were added after pub_ips, so older Manifests do not have them.
+        // They are stored on last pages, so we need to shorten
+        // whichever section was considered last.
+        let first_schema_page = if let (Some(b0), Some(b1)) = (iter.next(), iter.next()) {
+            u16::from_be_bytes([b0, b1])
+        } else {
+            0
+        };
+        // Message catalogue was added after schemas, and is stored after them.
+        let first_msg_page = if let (Some(b0), Some(b1)) = (iter.next(), iter.next()) {
+            u16::from_be_bytes([b0, b1])
+        } else {
+            0
+        };
+        let msg_page_count = if first_msg_page == 0 {
+            0
+        } else {
+            data_count.saturating_sub(first_msg_page as usize)
+        };
+        let schema_page_count = if first_schema_page == 0 {
+            0
+        } else {
+            data_count
+                .saturating_sub(first_schema_page as usize)
+                .saturating_sub(msg_page_count)
+        };
+        let tail_page_count = schema_page_count + msg_page_count;
+        if bsets_page_count > 0 {
+            bsets_page_count = bsets_page_count.saturating_sub(tail_page_count);
+        } else if caps_page_count > 0 {
+            caps_page_count = caps_page_count.saturating_sub(tail_page_count);
+        } else if policy_page_count > 0 {
+            policy_page_count = policy_page_count.saturating_sub(tail_page_count);
+        } else if dt_page_count > 0 {
+            dt_page_count = dt_page_count.saturating_sub(tail_page_count);
+        } else {
+            tag_pages_count = tag_pages_count.saturating_sub(tail_page_count);
         }
 
-        //  read capabilities
-        let mut c_bytes = Vec::with_capacity(caps_page_count * 1024);
-        for _page in 0..caps_page_count {
-            if let Some(data) = data_iter.next() {
-                let mut bts = data.bytes();
-                if !bts.is_empty() {
-                    c_bytes.append(&mut bts);
-                } else {
-                    eprintln!("Data empty for Capabilities");
-                    break;
-                }
-            } else {
-                eprintln!("Missing Data for Capabilities");
-                break;
-            }
+        let mut data_iter = data_vec.into_iter().skip(1);
+        let sections = [
+            (SECTION_TAGS, tag_pages_count),
+            (SECTION_DATA_TYPES, dt_page_count),
+            (SECTION_POLICIES, policy_page_count),
+            (SECTION_CAPABILITIES, caps_page_count),
+            (SECTION_BYTE_SETS, bsets_page_count),
+            (SECTION_SCHEMAS, schema_page_count),
+            (SECTION_MESSAGES, msg_page_count),
+        ];
+        for (s_id, page_count) in sections {
+            let bytes = section_bytes(&mut data_iter, page_count, s_id);
+            self.read_section(s_id, bytes, &[]);
         }
-        while !c_bytes.is_empty() {
-            let cap = Capabilities::from(c_bytes.remove(0));
-            let how_many = c_bytes.remove(0);
-            let mut ctree = if let Some(ct) = capability_reg.remove(&cap) {
-                ct
-            } else {
-                CapabiLeaf::create()
-            };
+    }
 
-            for _i in 0..how_many {
-                let mut b_arr: [u8; 8] = [0; 8];
-                for j in 0..8 {
-                    b_arr[j] = c_bytes.remove(0);
-                }
-                ctree.insert(GnomeId::from(b_arr));
-            }
-            capability_reg.insert(cap, ctree);
-        }
-        //  read byte sets
-        let mut b_bytes = Vec::with_capacity(bsets_page_count * 1024);
-        for _page in 0..bsets_page_count {
-            // TODO: Rework parsing bytes logic
-            if let Some(data) = data_iter.next() {
-                let mut bts = data.bytes();
-                if !bts.is_empty() {
-                    b_bytes.append(&mut bts);
-                } else {
-                    eprintln!("Data empty for ByteSet");
-                    break;
-                }
-            } else {
-                eprintln!("Missing Data for ByteSet");
-                break;
-            }
-        }
+    /// How many bytes of description fit on first page.
+    pub fn description_budget(&self) -> usize {
+        // TagLen, description len, AppType, marker, sections count
+        let header = 6;
+        // every known section, network settings and kept extensions
+        let directory = 5 * (SECTION_LOCALES as usize + 1 + self.extensions.len());
+        1024usize.saturating_sub(header + directory + self.pub_ips.get_bytes().len())
+    }
 
-        if !b_bytes.is_empty() {
-            let mut b_idx = b_bytes.remove(0);
-            let mut b_set = ByteSet::empty();
-            while !b_bytes.is_empty() {
-                let b_type = b_bytes.remove(0);
-                let b_s_count = u16::from_be_bytes([b_bytes.remove(0), b_bytes.remove(0)]);
-                if b_type == 1 {
-                    let mut h_set = HashSet::with_capacity(b_s_count as usize);
-                    for _i in 0..b_s_count {
-                        h_set.insert(b_bytes.remove(0));
-                    }
-                    b_set = ByteSet::new(h_set);
-                } else if b_type == 2 {
-                    b_set = if let Some(b_s) = byteset_reg.remove(&b_idx) {
-                        b_s
-                    } else {
-                        ByteSet::empty()
-                    };
-                    let mut val: [u8; 2] = [0, 0];
-                    for _i in 0..b_s_count >> 1 {
-                        val[0] = b_bytes.remove(0);
-                        val[1] = b_bytes.remove(0);
-                        b_set.add_pair(u16::from_be_bytes(val))
-                    }
-                } else if b_type == 0 {
-                    //todo
-                } else {
-                    eprintln!("unexpected ByteSet type: {b_type}");
-                }
-                let curr_bset = std::mem::replace(&mut b_set, ByteSet::empty());
-                byteset_reg.insert(b_idx, curr_bset);
-                if !b_bytes.is_empty() {
-                    b_idx = b_bytes.remove(0);
-                }
+    pub fn to_data(&self) -> Result<Vec<Data>, ManifestError> {
+        eprintln!("{:?} Manifest to_data", self.app_type);
+        let mut sections: Vec<(u8, Vec<Data>)> = vec![
+            (SECTION_TAGS, self.get_tags_data_vec()),
+            (SECTION_DATA_TYPES, self.get_dtypes_data_vec()),
+            (SECTION_POLICIES, self.get_policy_data_vec()),
+            (SECTION_CAPABILITIES, self.get_capabilities_data_vec()),
+            (SECTION_BYTE_SETS, self.get_bsets_data_vec()),
+            (SECTION_SCHEMAS, self.get_schemas_data_vec()),
+            (SECTION_MESSAGES, self.get_messages_data_vec()),
+            (SECTION_LOCALES, self.get_locales_data_vec()),
+        ];
+        for s_id in 0..=255 {
+            if let Some(pages) = self.extensions.get(&s_id) {
+                sections.push((s_id, pages.clone()));
             }
         }
+        sections.retain(|(_s_id, pages)| !pages.is_empty());
 
-        // TODO: read other data if any!
-        Self {
-            app_type,
-            pub_ips,
-            description,
-            tags,
-            d_types,
-            policy_reg,
-            capability_reg,
-            byteset_reg,
-        }
-    }
-
-    pub fn to_data(&self) -> Vec<Data> {
-        eprintln!("{:?} Manifest to_data", self.app_type);
-        let mut res = Vec::with_capacity(1024);
-        // TODO: here we also need to respect data format:
         // TagLen|Tags|DescrLen|Descr|Remainder
-        // but for Manifest TagLen is always 0, so Tags is empty,
-        // then goes 2byte DescrLen and Description followed by other information
+        // for Manifest TagLen is always 0, so Tags is empty
+        let mut res = Vec::with_capacity(1024);
         res.push(0);
-        let dlen = (self.description.len() as u16).to_be_bytes();
-        res.push(dlen[0]);
-        res.push(dlen[1]);
-        for byte in self.description.bytes() {
-            res.push(byte);
+        if self.description.len() > u16::MAX as usize {
+            return Err(ManifestError::FirstPageTooLong(self.description.len()));
         }
+        res.extend_from_slice(&(self.description.len() as u16).to_be_bytes());
+        res.extend_from_slice(self.description.as_bytes());
         res.push(self.app_type.byte());
-        let tags_len = self.tags.len() as u16;
-        eprintln!("tags_len: {tags_len}");
-        let dtypes_len = self.d_types.len() as u16;
-        res.push(0);
-        let tags_page_count = if tags_len == 0 {
-            res.push(0);
-            0
-        } else {
-            res.push(1);
-            eprintln!("{tags_len}%32: {}", tags_len % 32);
-            if (tags_len % 32) > 0 {
-                1 + (tags_len >> 5)
-            } else {
-                tags_len >> 5
-            }
-        };
-        eprintln!("tags_page_count: {tags_page_count}");
-        let d_types_len = self.d_types.len();
-        let dtypes_page_count = if d_types_len == 0 {
-            res.push(0);
-            res.push(0);
-            0
-        } else {
-            let next_free_page: [u8; 2] = (1 + tags_page_count).to_be_bytes();
-            res.push(next_free_page[0]);
-            res.push(next_free_page[1]);
-            (dtypes_len >> 4 + if dtypes_len % 32 > 0 { 1 } else { 0 }) as u16
-        };
-        // TODO: index of other data after Data type definitions, 0 if none
-        //
-        // policy
-        let mut policy_pages = self.get_policy_data_vec();
-        let policy_page_count: u16 = policy_pages.len() as u16;
-        if policy_page_count == 0 {
-            res.push(0);
-            res.push(0);
-        } else {
-            let next_free_page: [u8; 2] = (1 + tags_page_count + dtypes_page_count).to_be_bytes();
-            res.push(next_free_page[0]);
-            res.push(next_free_page[1]);
-        };
-        //
-        // TODO: capabilities
-        let mut caps_pages = self.get_capabilities_data_vec();
-        let caps_page_count = caps_pages.len() as u16;
-        if caps_page_count == 0 {
-            res.push(0);
-            res.push(0);
-        } else {
-            let next_free_page: [u8; 2] =
-                (1 + tags_page_count + dtypes_page_count + policy_page_count).to_be_bytes();
-            res.push(next_free_page[0]);
-            res.push(next_free_page[1]);
-        };
-        //
-        // TODO: ByteSets
-        let mut bsets_pages = self.get_bsets_data_vec();
-        let bsets_page_count = bsets_pages.len();
-        if bsets_page_count == 0 {
-            res.push(0);
-            res.push(0);
-        } else {
-            let next_free_page: [u8; 2] =
-                (1 + tags_page_count + dtypes_page_count + policy_page_count + caps_page_count)
-                    .to_be_bytes();
-            res.push(next_free_page[0]);
-            res.push(next_free_page[1]);
-        };
-        res.append(&mut self.pub_ips.get_bytes());
-        // match self.pub_ips.len() {
-        //     0 => res.push(252),
-        //     1 => {
-        //         // TODO: store multiple IP addresses
-        //         // TODO: store trasnport protocol for each address
-        //         // let (pub_ip, port, nat, (rule, delta)) = self.pub_ips[0];
-        //         let ns = self.pub_ips[0];
-        //         match ns.pub_ip {
-        //             IpAddr::V4(ip) => {
-        //                 res.push(253);
-        //                 for octet in ip.octets() {
-        //                     res.push(octet);
-        //                 }
-        //                 for byte in ns.pub_port.to_be_bytes() {
-        //                     res.push(byte);
-        //                 }
-        //                 res.push(ns.nat_type as u8);
-        //                 res.push(ns.port_allocation.0 as u8);
-        //                 res.push(ns.port_allocation.1 as u8);
-        //             }
-        //             IpAddr::V6(ip) => {
-        //                 res.push(254);
-        //                 for octet in ip.octets() {
-        //                     res.push(octet);
-        //                 }
-        //                 for byte in ns.pub_port.to_be_bytes() {
-        //                     res.push(byte);
-        //                 }
-        //                 res.push(ns.nat_type as u8);
-        //                 res.push(ns.port_allocation.0 as u8);
-        //                 res.push(ns.port_allocation.1 as u8);
-        //             }
-        //         }
-        //     }
-        //     2 => {
-        //         res.push(255);
-        //         let (ip4, ip6) = if self.pub_ips[0].pub_ip.is_ipv4() {
-        //             (self.pub_ips[0], self.pub_ips[1])
-        //         } else {
-        //             (self.pub_ips[1], self.pub_ips[0])
-        //         };
-        //         if let IpAddr::V4(ip) = ip4.pub_ip {
-        //             for octet in ip.octets() {
-        //                 res.push(octet);
-        //             }
-        //             for byte in ip4.pub_port.to_be_bytes() {
-        //                 res.push(byte);
-        //             }
-        //             res.push(ip4.nat_type as u8);
-        //             res.push(ip4.port_allocation.0 as u8);
-        //             res.push(ip4.port_allocation.1 as u8);
-        //         }
-        //         if let IpAddr::V6(ip) = ip6.pub_ip {
-        //             for octet in ip.octets() {
-        //                 res.push(octet);
-        //             }
-        //             for byte in ip6.pub_port.to_be_bytes() {
-        //                 res.push(byte);
-        //             }
-        //             res.push(ip6.nat_type as u8);
-        //             res.push(ip6.port_allocation.0 as u8);
-        //             res.push(ip6.port_allocation.1 as u8);
-        //         }
-        //     }
-        //     other => {
-        //         eprintln!("Too many public IPs: {}", other);
-        //     }
-        // }
-        let first_data_bytes = std::mem::replace(&mut res, Vec::with_capacity(1024));
-        let mut output = vec![Data::new(first_data_bytes).unwrap()];
-        let mut tags_to_add = self.tags.len();
-        eprintln!("o-len 0: {},tags: {}", output.len(), tags_to_add);
-        let mut elements_pushed = 0;
-        for i in 0..=255 {
-            if tags_to_add == 0 {
-                break;
-            }
-            if let Some(tag) = self.tags.get(&i) {
-                res.append(&mut tag.bytes());
-                tags_to_add -= 1;
-            } else {
-                res.append(&mut vec![0; 32]);
-            }
-            elements_pushed += 1;
-            if elements_pushed >= 32 {
-                elements_pushed = 0;
-                let next_data_bytes = std::mem::replace(&mut res, Vec::with_capacity(1024));
-                eprintln!("output push tag data");
-                output.push(Data::new(next_data_bytes).unwrap());
+        res.push(DIRECTORY_MARKER);
+        res.push(sections.len() as u8 + 1);
+        res.push(SECTION_NETWORK);
+        res.extend_from_slice(&[0, 0, 0, 1]);
+        let mut next_page: usize = 1;
+        for (s_id, pages) in &sections {
+            if next_page + pages.len() > u16::MAX as usize {
+                return Err(ManifestError::TooManyPages(next_page + pages.len()));
             }
+            res.push(*s_id);
+            res.extend_from_slice(&(next_page as u16).to_be_bytes());
+            res.extend_from_slice(&(pages.len() as u16).to_be_bytes());
+            next_page += pages.len();
         }
-        if res.len() > 0 {
-            let next_data_bytes = std::mem::replace(&mut res, Vec::with_capacity(1024));
-            eprintln!("output push tag data rem");
-            output.push(Data::new(next_data_bytes).unwrap());
+        res.append(&mut self.pub_ips.get_bytes());
+        let Ok(first_page) = Data::new(res) else {
+            return Err(ManifestError::FirstPageTooLong(
+                self.description.len() + 1024 - self.description_budget(),
+            ));
+        };
+        let mut output = Vec::with_capacity(next_page);
+        output.push(first_page);
+        for (_s_id, mut pages) in sections {
+            output.append(&mut pages);
         }
-        let mut d_types_to_add = self.d_types.len();
-        let mut elements_pushed = 0;
-        for i in 0..=255 {
-            if d_types_to_add == 0 {
-                break;
-            }
-            if let Some(tag) = self.d_types.get(&i) {
-                eprintln!("DType: {}", tag.0);
-                res.append(&mut tag.bytes());
-                d_types_to_add -= 1;
-            } else {
-                res.append(&mut vec![0; 32]);
-            }
-            elements_pushed += 1;
-            if elements_pushed >= 32 {
-                elements_pushed = 0;
-                let next_data_bytes = std::mem::replace(&mut res, Vec::with_capacity(1024));
-                eprintln!("output push dtype data");
-                output.push(Data::new(next_data_bytes).unwrap());
-            }
+        Ok(output)
+    }
+
+    fn get_tags_data_vec(&self) -> Vec<Data> {
+        let mut slots = HashMap::with_capacity(self.tags.len() + self.tag_tombstones.len());
+        for (id, tag) in &self.tags {
+            slots.insert(*id, tag.clone());
         }
-        if res.len() > 0 {
-            let next_data_bytes = std::mem::replace(&mut res, Vec::with_capacity(1024));
-            eprintln!("output push dtype data rem");
-            output.push(Data::new(next_data_bytes).unwrap());
+        for id in &self.tag_tombstones {
+            slots.insert(*id, Tag::empty());
         }
-        eprintln!("o-len 1: {}", output.len());
-        output.append(&mut policy_pages);
-        eprintln!("o-len 2: {}", output.len());
-        output.append(&mut caps_pages);
-        eprintln!("o-len 3: {}", output.len());
-        output.append(&mut bsets_pages);
-        eprintln!("o-len 4: {}", output.len());
+        tag_slots_data_vec(&slots)
+    }
 
-        output
+    fn get_dtypes_data_vec(&self) -> Vec<Data> {
+        tag_slots_data_vec(&self.d_types)
     }
 
     pub fn update_tag(&mut self, id: u8, tag: Tag) -> bool {
@@ -900,7 +763,7 @@ impl Manifest {
         let mut last_id_checked = 0;
         while let Some(tag) = tags_iter.next() {
             for i in last_id_checked..=255 {
-                if self.tags.contains_key(&i) {
+                if self.tags.contains_key(&i) || self.tag_tombstones.contains(&i) {
                     continue;
                 }
                 any_tag_added = true;
@@ -959,30 +822,63 @@ impl Manifest {
         tstring
     }
 
-    // TODO: before deleting a Tag make sure there is no Content labeled with it
-    // If there is any, first modify all Content's headers to drop that label
-    // on only then call this fn
-    pub fn del_tags(&mut self, tags: Vec<Tag>) {
+    // Deleted Tag ids become tombstones, so that Contents still labeled
+    // with them are not suddenly labeled with a different Tag.
+    // Returned ids should be stripped from all Contents, then
+    // tombstones can be released.
+    pub fn del_tags(&mut self, tags: Vec<Tag>) -> Vec<u8> {
+        let mut deleted = vec![];
         for id in 0..=255 {
             if let Some(tag) = self.tags.get(&id) {
                 if tags.contains(tag) {
                     self.tags.remove(&id);
+                    self.tag_tombstones.insert(id);
+                    for loc in self.locales.values_mut() {
+                        loc.tags.remove(&id);
+                    }
+                    deleted.push(id);
                 }
             }
         }
+        deleted
     }
-    pub fn rename_tags(&mut self, mut tags: HashMap<Tag, Tag>) {
+    // Renaming a Tag to a name that is already defined merges both,
+    // returned mapping tells which ids should be replaced in Contents.
+    pub fn rename_tags(&mut self, mut tags: HashMap<Tag, Tag>) -> HashMap<u8, u8> {
+        let mut merged = HashMap::new();
         for id in 0..=255 {
             if let Some(tag) = self.tags.get(&id) {
                 if tags.contains_key(tag) {
                     let new_tag = tags.remove(&tag).unwrap();
-                    self.tags.insert(id, new_tag);
+                    let existing = self
+                        .tags
+                        .iter()
+                        .find(|(e_id, e_tag)| **e_id != id && **e_tag == new_tag)
+                        .map(|(e_id, _e_tag)| *e_id);
+                    if let Some(e_id) = existing {
+                        self.tags.remove(&id);
+                        self.tag_tombstones.insert(id);
+                        for loc in self.locales.values_mut() {
+                            loc.tags.remove(&id);
+                        }
+                        merged.insert(id, e_id);
+                    } else {
+                        self.tags.insert(id, new_tag);
+                    }
                 }
             }
             if tags.is_empty() {
                 break;
             }
         }
+        merged
+    }
+    /// Release tombstones that are no longer referenced by any Content,
+    /// returns true if any was released.
+    pub fn release_tag_tombstones(&mut self, referenced: &HashSet<u8>) -> bool {
+        let before = self.tag_tombstones.len();
+        self.tag_tombstones.retain(|id| referenced.contains(id));
+        before != self.tag_tombstones.len()
     }
     pub fn tag_names(&self, filter: Option<Vec<u8>>) -> Vec<String> {
         if let Some(filter) = filter {
@@ -1187,6 +1083,321 @@ impl Manifest {
         r_vec
     }
 
+    fn get_schemas_data_vec(&self) -> Vec<Data> {
+        let mut r_vec = vec![];
+        let mut bytes = Vec::with_capacity(1024);
+        for i in 0..=255 {
+            if let Some(schema) = self.schemas.get(&i) {
+                schema.append_bytes_to(i, &mut bytes);
+            }
+        }
+        for chunk in bytes.chunks(1024) {
+            r_vec.push(Data::new(chunk.to_vec()).unwrap());
+        }
+        r_vec
+    }
+
+    /// Define what pages of given DataType contain,
+    /// DataType has to be already defined in Manifest.
+    pub fn set_schema(&mut self, d_id: u8, schema: Option<DataSchema>) -> bool {
+        if !self.d_types.contains_key(&d_id) {
+            return false;
+        }
+        if let Some(schema) = schema {
+            self.schemas.insert(d_id, schema);
+        } else {
+            self.schemas.remove(&d_id);
+        }
+        true
+    }
+
+    pub fn schema(&self, d_type: DataType) -> Option<&DataSchema> {
+        let DataType::Data(d_id) = d_type else {
+            return None;
+        };
+        self.schemas.get(&d_id)
+    }
+
+    fn get_locales_data_vec(&self) -> Vec<Data> {
+        let mut codes: Vec<&String> = self.locales.keys().collect();
+        codes.sort();
+        let mut bytes = Vec::with_capacity(1024);
+        for code in codes {
+            self.locales[code].append_bytes_to(code, &mut bytes);
+        }
+        bytes
+            .chunks(1024)
+            .map(|chunk| Data::new(chunk.to_vec()).unwrap())
+            .collect()
+    }
+
+    /// Add or replace translations for given language code.
+    /// Only Tags defined in Manifest can be translated.
+    pub fn set_localization(&mut self, code: String, localization: Localization) -> bool {
+        if code.is_empty() || code.len() > 35 || !code.is_ascii() {
+            return false;
+        }
+        if localization.description.len() > u16::MAX as usize
+            || localization.tags.len() > 255
+            || localization
+                .tags
+                .keys()
+                .any(|t_id| !self.tags.contains_key(t_id))
+        {
+            return false;
+        }
+        self.locales.insert(code, localization);
+        true
+    }
+
+    pub fn remove_localization(&mut self, code: &str) -> Option<Localization> {
+        self.locales.remove(code)
+    }
+
+    // Preferred locales are checked in order, first for an exact match,
+    // then for a match of primary language only, so "pt-BR" matches "pt".
+    fn localization(&self, preferred: &[&str]) -> Option<&Localization> {
+        for wanted in preferred {
+            if let Some((_code, loc)) = self
+                .locales
+                .iter()
+                .find(|(code, _loc)| code.eq_ignore_ascii_case(wanted))
+            {
+                return Some(loc);
+            }
+            let primary = wanted.split('-').next().unwrap_or(wanted);
+            if let Some((_code, loc)) = self.locales.iter().find(|(code, _loc)| {
+                code.split('-')
+                    .next()
+                    .is_some_and(|c_primary| c_primary.eq_ignore_ascii_case(primary))
+            }) {
+                return Some(loc);
+            }
+        }
+        None
+    }
+
+    /// Description in first of preferred locales that has one,
+    /// falls back to default description.
+    pub fn description_for(&self, preferred: &[&str]) -> &str {
+        match self.localization(preferred) {
+            Some(loc) if !loc.description.is_empty() => &loc.description,
+            _other => &self.description,
+        }
+    }
+
+    /// Tag name in first of preferred locales that translates it,
+    /// falls back to default name.
+    pub fn tag_name_for(&self, t_id: u8, preferred: &[&str]) -> Option<&str> {
+        let default = self.tags.get(&t_id)?;
+        for wanted in preferred {
+            if let Some(tag) = self
+                .localization(&[*wanted])
+                .and_then(|loc| loc.tags.get(&t_id))
+            {
+                return Some(&tag.0);
+            }
+        }
+        Some(&default.0)
+    }
+
+    /// Default Tag name followed by all it's translations.
+    pub fn all_tag_names(&self, t_id: u8) -> Vec<&str> {
+        let mut names = vec![];
+        if let Some(tag) = self.tags.get(&t_id) {
+            names.push(&tag.0[..]);
+        }
+        for loc in self.locales.values() {
+            if let Some(tag) = loc.tags.get(&t_id) {
+                if !names.contains(&&tag.0[..]) {
+                    names.push(&tag.0);
+                }
+            }
+        }
+        names
+    }
+
+    fn get_messages_data_vec(&self) -> Vec<Data> {
+        let mut r_vec = vec![];
+        let mut bytes = Vec::with_capacity(1024);
+        for i in 0..=MAX_AVAIL_APP_MSG_ID {
+            if let Some(decl) = self.messages.get(&i) {
+                decl.append_bytes_to(i, &mut bytes);
+            }
+        }
+        for chunk in bytes.chunks(1024) {
+            r_vec.push(Data::new(chunk.to_vec()).unwrap());
+        }
+        r_vec
+    }
+
+    /// Declare an AppDefined message, or remove it's declaration.
+    /// Returns false for ids out of range and for Requirement senders,
+    /// since those can not be enforced.
+    pub fn declare_message(&mut self, m_type: u8, decl: Option<MessageDecl>) -> bool {
+        if m_type > MAX_AVAIL_APP_MSG_ID {
+            return false;
+        }
+        if decl
+            .as_ref()
+            .is_some_and(|d| matches!(d.sender, MessageSender::Requirement(_)))
+        {
+            return false;
+        }
+        if let Some(decl) = decl {
+            self.messages.insert(m_type, decl);
+        } else {
+            self.messages.remove(&m_type);
+        }
+        true
+    }
+
+    pub fn message_decl(&self, m_type: u8) -> Option<&MessageDecl> {
+        self.messages.get(&m_type)
+    }
+
+    /// Check an AppDefined message against catalogue.
+    /// Messages declared with a Requirement sender are always rejected.
+    pub fn check_app_msg(
+        &self,
+        m_type: u8,
+        payload: &[u8],
+        signed_by: GnomeId,
+    ) -> Result<(), CatalogueError> {
+        if self.messages.is_empty() {
+            return Ok(());
+        }
+        let Some(decl) = self.messages.get(&m_type) else {
+            return Err(CatalogueError::Undeclared(m_type));
+        };
+        let allowed = match &decl.sender {
+            MessageSender::Anyone => true,
+            MessageSender::Capability(cap) => self.capability_reg.get(cap).is_some_and(|c_tree| {
+                c_tree
+                    .get_all_members()
+                    .iter()
+                    .any(|member| member.0 == signed_by.0)
+            }),
+            // declared by an older version, we can not tell so we refuse
+            MessageSender::Requirement(_) => false,
+        };
+        if !allowed {
+            return Err(CatalogueError::NotAllowed(m_type));
+        }
+        decl.validate_payload(m_type, payload)
+    }
+
+    /// Decode a page of given DataType using it's schema,
+    /// returns None if no schema was declared for that DataType.
+    pub fn decode_page(
+        &self,
+        d_type: DataType,
+        page_no: u16,
+        data: &Data,
+    ) -> Option<Result<Vec<(String, Value)>, SchemaError>> {
+        self.schema(d_type)
+            .map(|schema| schema.decode(page_no, data.ref_bytes()))
+    }
+
+    /// List what has to change for this Manifest to become new one.
+    pub fn diff(&self, new: &Manifest) -> Vec<ManifestChange> {
+        let mut changes = vec![];
+        if self.app_type.byte() != new.app_type.byte() {
+            changes.push(ManifestChange::AppType(new.app_type));
+        }
+        if self.description != new.description {
+            changes.push(ManifestChange::Description(new.description.clone()));
+        }
+        for id in 0..=255 {
+            let (old, new) = (self.tags.get(&id), new.tags.get(&id));
+            if old != new {
+                changes.push(ManifestChange::Tag(id, old.cloned(), new.cloned()));
+            }
+        }
+        for id in 0..=255 {
+            let (old, new) = (self.d_types.get(&id), new.d_types.get(&id));
+            if old != new {
+                changes.push(ManifestChange::DataType(id, old.cloned(), new.cloned()));
+            }
+        }
+        if self.tag_tombstones != new.tag_tombstones {
+            changes.push(ManifestChange::TagTombstones);
+        }
+        let sections = [
+            (
+                self.get_policy_data_vec(),
+                new.get_policy_data_vec(),
+                ManifestChange::Policies,
+            ),
+            (
+                self.get_capabilities_data_vec(),
+                new.get_capabilities_data_vec(),
+                ManifestChange::Capabilities,
+            ),
+            (
+                self.get_bsets_data_vec(),
+                new.get_bsets_data_vec(),
+                ManifestChange::ByteSets,
+            ),
+            (
+                self.get_schemas_data_vec(),
+                new.get_schemas_data_vec(),
+                ManifestChange::Schemas,
+            ),
+            (
+                self.get_messages_data_vec(),
+                new.get_messages_data_vec(),
+                ManifestChange::Messages,
+            ),
+            (
+                self.get_locales_data_vec(),
+                new.get_locales_data_vec(),
+                ManifestChange::Locales,
+            ),
+        ];
+        for (old, new, change) in sections {
+            if !same_pages(&old, &new) {
+                changes.push(change);
+            }
+        }
+        if self.pub_ips.get_bytes() != new.pub_ips.get_bytes() {
+            changes.push(ManifestChange::NetworkSettings);
+        }
+        let old_ext = self.extension_pages();
+        let new_ext = new.extension_pages();
+        if old_ext.len() != new_ext.len()
+            || old_ext
+                .iter()
+                .zip(new_ext.iter())
+                .any(|((o_id, o_pages), (n_id, n_pages))| {
+                    o_id != n_id || !same_pages(o_pages, n_pages)
+                })
+        {
+            changes.push(ManifestChange::Extensions);
+        }
+        changes
+    }
+
+    // Sections we do not understand, ordered by id
+    pub(crate) fn extension_pages(&self) -> Vec<(u8, &Vec<Data>)> {
+        let mut pages: Vec<(u8, &Vec<Data>)> = self
+            .extensions
+            .iter()
+            .map(|(s_id, pages)| (*s_id, pages))
+            .collect();
+        pages.sort_by_key(|(s_id, _pages)| *s_id);
+        pages
+    }
+
+    // Known section ids can not be used for extensions
+    pub(crate) fn set_extension_pages(&mut self, s_id: u8, pages: Vec<Data>) -> bool {
+        if s_id <= SECTION_LOCALES || s_id == DIRECTORY_MARKER {
+            return false;
+        }
+        self.extensions.insert(s_id, pages);
+        true
+    }
+
     // pub fn update_pub_ips(
     //     &mut self,
     //     // ips: Vec<(IpAddr, u16, Nat, (PortAllocationRule, i8))>,
@@ -1245,6 +1456,300 @@ impl Manifest {
     //     true
     // }
 }
+fn same_pages(old: &[Data], new: &[Data]) -> bool {
+    old.len() == new.len()
+        && old
+            .iter()
+            .zip(new.iter())
+            .all(|(o, n)| o.ref_bytes() == n.ref_bytes())
+}
+
+fn section_bytes(
+    mut data_iter: impl Iterator<Item = Data>,
+    page_count: usize,
+    s_id: u8,
+) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(page_count * 1024);
+    for _page in 0..page_count {
+        if let Some(data) = data_iter.next() {
+            bytes.append(&mut data.bytes());
+        } else {
+            eprintln!("Missing Data for Manifest section {s_id}");
+            break;
+        }
+    }
+    bytes
+}
+
+// Returns defined Tags and ids of blank ones
+fn read_tag_slots(bytes: &[u8]) -> (HashMap<u8, Tag>, HashSet<u8>) {
+    let mut tags = HashMap::with_capacity(bytes.len() >> 5);
+    let mut blanks = HashSet::new();
+    for (id, chunk) in bytes.chunks_exact(32).enumerate().take(256) {
+        let mut all_zeros = true;
+        let mut non_space_byte_occured = false;
+        let mut name_bytes = Vec::with_capacity(32);
+        for byte in chunk {
+            if *byte > 0 {
+                all_zeros = false;
+            }
+            if *byte == 32 {
+                if non_space_byte_occured {
+                    name_bytes.push(*byte);
+                }
+            } else {
+                non_space_byte_occured = true;
+                name_bytes.push(*byte);
+            }
+        }
+        if all_zeros {
+            continue;
+        }
+        if name_bytes.is_empty() {
+            blanks.insert(id as u8);
+        } else if let Ok(name) = String::from_utf8(name_bytes) {
+            tags.insert(id as u8, Tag(name));
+        } else {
+            eprintln!("Invalid Tag name at {id}");
+        }
+    }
+    (tags, blanks)
+}
+
+// Every id up to the highest one defined takes a 32 byte slot
+fn tag_slots_data_vec(slots: &HashMap<u8, Tag>) -> Vec<Data> {
+    let Some(max_id) = slots.keys().max() else {
+        return vec![];
+    };
+    let mut bytes = Vec::with_capacity((*max_id as usize + 1) << 5);
+    for i in 0..=*max_id {
+        if let Some(tag) = slots.get(&i) {
+            bytes.append(&mut tag.bytes());
+        } else {
+            bytes.append(&mut vec![0; 32]);
+        }
+    }
+    bytes
+        .chunks(1024)
+        .map(|chunk| Data::new(chunk.to_vec()).unwrap())
+        .collect()
+}
+
+fn read_policies(mut bytes: Vec<u8>) -> HashMap<Policy, Requirement> {
+    let mut policy_reg = HashMap::new();
+    while !bytes.is_empty() {
+        let pol = Policy::from(&mut bytes);
+        let req = Requirement::from(&mut bytes);
+        policy_reg.insert(pol, req);
+    }
+    policy_reg
+}
+
+fn read_capabilities(mut bytes: Vec<u8>) -> HashMap<Capabilities, CapabiLeaf> {
+    let mut capability_reg = HashMap::new();
+    while bytes.len() >= 2 {
+        let cap = Capabilities::from(bytes.remove(0));
+        let how_many = bytes.remove(0);
+        let mut ctree = if let Some(ct) = capability_reg.remove(&cap) {
+            ct
+        } else {
+            CapabiLeaf::create()
+        };
+        for _i in 0..how_many {
+            if bytes.len() < 8 {
+                eprintln!("Capability {:?} truncated", cap);
+                break;
+            }
+            let mut b_arr: [u8; 8] = [0; 8];
+            for j in 0..8 {
+                b_arr[j] = bytes.remove(0);
+            }
+            ctree.insert(GnomeId::from(b_arr));
+        }
+        capability_reg.insert(cap, ctree);
+    }
+    capability_reg
+}
+
+fn read_byte_sets(mut b_bytes: Vec<u8>) -> HashMap<u8, ByteSet> {
+    let mut byteset_reg = HashMap::new();
+    if b_bytes.is_empty() {
+        return byteset_reg;
+    }
+    let mut b_idx = b_bytes.remove(0);
+    let mut b_set = ByteSet::empty();
+    while b_bytes.len() >= 3 {
+        let b_type = b_bytes.remove(0);
+        let b_s_count = u16::from_be_bytes([b_bytes.remove(0), b_bytes.remove(0)]);
+        if b_bytes.len() < b_s_count as usize {
+            eprintln!("ByteSet {b_idx} truncated");
+            break;
+        }
+        if b_type == 1 {
+            let mut h_set = HashSet::with_capacity(b_s_count as usize);
+            for _i in 0..b_s_count {
+                h_set.insert(b_bytes.remove(0));
+            }
+            b_set = ByteSet::new(h_set);
+        } else if b_type == 2 {
+            b_set = if let Some(b_s) = byteset_reg.remove(&b_idx) {
+                b_s
+            } else {
+                ByteSet::empty()
+            };
+            let mut val: [u8; 2] = [0, 0];
+            for _i in 0..b_s_count >> 1 {
+                val[0] = b_bytes.remove(0);
+                val[1] = b_bytes.remove(0);
+                b_set.add_pair(u16::from_be_bytes(val))
+            }
+        } else if b_type == 0 {
+            //todo
+        } else {
+            eprintln!("unexpected ByteSet type: {b_type}");
+        }
+        let curr_bset = std::mem::replace(&mut b_set, ByteSet::empty());
+        byteset_reg.insert(b_idx, curr_bset);
+        if !b_bytes.is_empty() {
+            b_idx = b_bytes.remove(0);
+        }
+    }
+    byteset_reg
+}
+
+fn read_schemas(bytes: Vec<u8>) -> HashMap<u8, DataSchema> {
+    let mut schemas = HashMap::new();
+    let mut s_iter = bytes.into_iter().peekable();
+    while s_iter.peek().is_some() {
+        if let Some((d_id, schema)) = DataSchema::from(&mut s_iter) {
+            schemas.insert(d_id, schema);
+        } else {
+            eprintln!("Unable to read DataType schema");
+            break;
+        }
+    }
+    schemas
+}
+
+fn read_locales(bytes: Vec<u8>) -> HashMap<String, Localization> {
+    let mut locales = HashMap::new();
+    let mut l_iter = bytes.into_iter().peekable();
+    while l_iter.peek().is_some() {
+        if let Some((code, loc)) = Localization::from(&mut l_iter) {
+            locales.insert(code, loc);
+        } else {
+            eprintln!("Unable to read Localization");
+            break;
+        }
+    }
+    locales
+}
+
+fn read_messages(bytes: Vec<u8>) -> HashMap<u8, MessageDecl> {
+    let mut messages = HashMap::new();
+    let mut m_iter = bytes.into_iter().peekable();
+    while m_iter.peek().is_some() {
+        if let Some((m_type, decl)) = MessageDecl::from(&mut m_iter) {
+            messages.insert(m_type, decl);
+        } else {
+            eprintln!("Unable to read Message declaration");
+            break;
+        }
+    }
+    messages
+}
+
+// First byte in UTF-8 encoded String can not start with a 1 value,
+// so we are good
+// Read one byte and check its value:
+// – if it's higher than 251 use old logic
+// – otherwise it indicates how many following bytes
+//   need to be read in order to retrieve pub_ips.
+// Read that many and update pub_ips
+fn read_pub_ips(iter: &mut std::vec::IntoIter<u8>) -> CombinedNetworkSettings {
+    let mut pub_ips = CombinedNetworkSettings::new();
+    if let Some(next_byte) = iter.clone().peekable().next() {
+        eprintln!("Next_byte: {}", next_byte);
+        if next_byte == 255 || next_byte == 254 || next_byte == 253 || next_byte == 252 {
+            match iter.next().unwrap() {
+                255 => {
+                    eprintln!("255 we have IPv4 & IPv6");
+                    // first read IPv4 address, port,Nat,PortAllocationRule,step
+                    let (pub_ip, pub_port, nat_type, port_allocation) = read_ipv4(iter);
+                    let ns = NetworkSettings {
+                        pub_ip,
+                        pub_port,
+                        nat_type,
+                        port_allocation,
+                        transport: gnome::prelude::Transport::UDPoverIP4,
+                    };
+                    // pub_ips.push(read_ipv4(iter));
+                    pub_ips.update(vec![ns]);
+                    // second read IPv6 address and port, we assume all IPv6 to be public
+                    // pub_ips.push(read_ipv6(iter));
+                    let (pub_ip, pub_port, nat_type, port_allocation) = read_ipv6(iter);
+                    let ns = NetworkSettings {
+                        pub_ip,
+                        pub_port,
+                        nat_type,
+                        port_allocation,
+                        transport: gnome::prelude::Transport::UDPoverIP4,
+                    };
+                    pub_ips.update(vec![ns]);
+                }
+                254 => {
+                    eprintln!("254 IPv6 only");
+                    //     254 IPv6 only
+                    // pub_ips.push(read_ipv6(iter));
+                    let (pub_ip, pub_port, nat_type, port_allocation) = read_ipv6(iter);
+                    let ns = NetworkSettings {
+                        pub_ip,
+                        pub_port,
+                        nat_type,
+                        port_allocation,
+                        transport: gnome::prelude::Transport::UDPoverIP4,
+                    };
+                    pub_ips.update(vec![ns]);
+                }
+                253 => {
+                    eprintln!("253 IPv4 only");
+                    //     253 IPv4 only
+                    let (pub_ip, pub_port, nat_type, port_allocation) = read_ipv4(iter);
+                    let ns = NetworkSettings {
+                        pub_ip,
+                        pub_port,
+                        nat_type,
+                        port_allocation,
+                        transport: gnome::prelude::Transport::UDPoverIP4,
+                    };
+                    // pub_ips.push(read_ipv4(iter));
+                    pub_ips.update(vec![ns]);
+                }
+                _ => {
+                    eprintln!("252 No Public IPs defined");
+                    //     252 No Public IPs defined
+                }
+            }
+        } else if next_byte == 0 {
+            let _byte = iter.next().unwrap();
+            let ns_bytesize = iter.next().unwrap();
+            let mut ns_bytes = Vec::with_capacity(ns_bytesize as usize);
+            eprintln!(
+                "We are using new method to load pub_ips from {} bytes",
+                ns_bytesize
+            );
+            for _i in 0..ns_bytesize {
+                ns_bytes.push(iter.next().unwrap());
+            }
+            let nss = NetworkSettings::from(&ns_bytes);
+            pub_ips.update(nss);
+        } else {
+            eprintln!("Uncecognized next_byte: {next_byte}");
+        }
+    }
+    pub_ips
+}
+
 fn read_ipv4<T>(iter: &mut T) -> (IpAddr, u16, Nat, (PortAllocationRule, i8))
 where
     T: Iterator<Item = u8>,
@@ -1306,3 +1811,130 @@ where
         (PortAllocationRule::from(port_alloc), port_step),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Encoding;
+    use crate::schema::FieldSize;
+    use crate::schema::SchemaField;
+
+    fn tag(name: &str) -> Tag {
+        Tag::new(name.to_string()).unwrap()
+    }
+
+    fn sample() -> Manifest {
+        let tags = (0..40)
+            .map(|id| (id, tag(&format!("tag-{}", id))))
+            .collect();
+        let mut manifest = Manifest::new(AppType::Forum, tags);
+        assert!(manifest.set_description("A forum".to_string()));
+        manifest.tags.remove(&3);
+        manifest.tag_tombstones.insert(3);
+        assert!(manifest.add_data_type(tag("post")));
+        let schema = DataSchema {
+            standard_first_page: false,
+            last_repeats: true,
+            fields: vec![SchemaField {
+                name: "votes".to_string(),
+                encoding: Encoding::Unsigned,
+                size: FieldSize::Fixed(2),
+            }],
+        };
+        assert!(manifest.set_schema(0, Some(schema)));
+        assert!(manifest.declare_message(
+            7,
+            Some(MessageDecl {
+                name: "vote".to_string(),
+                sender: MessageSender::Anyone,
+                payload: None,
+            })
+        ));
+        let localization = Localization {
+            description: "Un forum".to_string(),
+            tags: HashMap::from([(1, tag("etiquette"))]),
+        };
+        assert!(manifest.set_localization("fr".to_string(), localization));
+        manifest
+    }
+
+    #[test]
+    fn manifest_survives_directory_round_trip() {
+        let mut manifest = sample();
+        assert!(!manifest.set_extension_pages(SECTION_LOCALES, vec![]));
+        assert!(!manifest.set_extension_pages(DIRECTORY_MARKER, vec![]));
+        let extension = vec![Data::new(vec![1, 2, 3]).unwrap()];
+        assert!(manifest.set_extension_pages(100, extension));
+
+        let pages = manifest.to_data().unwrap();
+        // 40 Tags do not fit on a single page
+        assert!(pages.len() > 2);
+        let read = Manifest::from(pages);
+        assert!(manifest.diff(&read).is_empty());
+        assert_eq!(read.tags.len(), 39);
+        assert!(read.tag_tombstones.contains(&3));
+        assert_eq!(read.message_decl(7).unwrap().name, "vote");
+        assert_eq!(read.extension_pages()[0].1[0].ref_bytes(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn diff_lists_changed_sections() {
+        let manifest = sample();
+        let mut changed = sample();
+        assert!(manifest.diff(&changed).is_empty());
+        changed.description = "Another forum".to_string();
+        changed.tags.insert(3, tag("revived"));
+        changed.tag_tombstones.clear();
+        changed.remove_localization("fr");
+        let changes: Vec<String> = manifest
+            .diff(&changed)
+            .iter()
+            .map(|change| format!("{:?}", change))
+            .collect();
+        assert_eq!(changes.len(), 4);
+        assert!(changes[0].starts_with("Description"));
+        assert!(changes[1].starts_with("Tag(3"));
+        assert_eq!(changes[2], "TagTombstones");
+        assert_eq!(changes[3], "Locales");
+    }
+
+    #[test]
+    fn translations_fall_back_to_defaults() {
+        let manifest = sample();
+        assert_eq!(manifest.description_for(&["de", "fr-CA"]), "Un forum");
+        assert_eq!(manifest.description_for(&["de"]), "A forum");
+        assert_eq!(manifest.tag_name_for(1, &["fr"]), Some("etiquette"));
+        assert_eq!(manifest.tag_name_for(2, &["fr"]), Some("tag-2"));
+        assert_eq!(manifest.tag_name_for(3, &["fr"]), None);
+        assert_eq!(manifest.all_tag_names(1), vec!["tag-1", "etiquette"]);
+
+        let mut manifest = manifest;
+        let undefined = Localization {
+            description: String::new(),
+            tags: HashMap::from([(3, tag("absent"))]),
+        };
+        assert!(!manifest.set_localization("de".to_string(), undefined));
+        assert!(!manifest.set_localization(String::new(), Localization::default()));
+    }
+
+    #[test]
+    fn tag_ids_stay_reserved_until_unreferenced() {
+        let mut manifest = sample();
+        assert_eq!(manifest.del_tags(vec![tag("tag-5")]), vec![5]);
+        assert!(manifest.locales["fr"].tags.contains_key(&1));
+        let merged = manifest.rename_tags(HashMap::from([(tag("tag-6"), tag("tag-7"))]));
+        assert_eq!(merged, HashMap::from([(6, 7)]));
+        assert_eq!(manifest.tag_tombstones, HashSet::from([3, 5, 6]));
+
+        assert!(manifest.add_tags(vec![tag("fresh")]));
+        assert_eq!(manifest.tags.get(&40), Some(&tag("fresh")));
+
+        assert!(manifest.release_tag_tombstones(&HashSet::from([5])));
+        assert_eq!(manifest.tag_tombstones, HashSet::from([5]));
+        assert!(!manifest.release_tag_tombstones(&HashSet::from([5])));
+
+        assert!(manifest.add_tags(vec![tag("reused")]));
+        assert_eq!(manifest.tags.get(&3), Some(&tag("reused")));
+        assert!(!manifest.tags.contains_key(&5));
+    }
+}
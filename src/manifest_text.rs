@@ -0,0 +1,853 @@
+use crate::app_type::AppType;
+use crate::catalogue::MessageDecl;
+use crate::catalogue::MessageSender;
+use crate::manifest::Localization;
+use crate::manifest::Manifest;
+use crate::manifest::Tag;
+use crate::schema::DataSchema;
+use crate::schema::Encoding;
+use crate::schema::FieldSize;
+use crate::schema::SchemaField;
+use crate::Data;
+use gnome::prelude::ByteSet;
+use gnome::prelude::CapabiLeaf;
+use gnome::prelude::Capabilities;
+use gnome::prelude::GnomeId;
+use gnome::prelude::Nat;
+use gnome::prelude::NetworkSettings;
+use gnome::prelude::Policy;
+use gnome::prelude::PortAllocationRule;
+use gnome::prelude::Requirement;
+use gnome::prelude::Transport;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+// Manifest can be exported to, and imported from a text file,
+// so that a Founder can edit it by hand instead of writing code.
+// We use a small subset of TOML:
+// - top level keys: app_type, description, tag_tombstones
+// - [tags] and [data_types] tables: id = "name"
+// - [[policy]], [[capability]], [[byte_set]], [[network]],
+//   [[schema]], [[message]], [[locale]] and [[extension]] tables
+// - values are strings, integers, booleans or single line arrays of those
+//
+// Policies with their Requirements are stored as hex encoded bytes,
+// the same bytes that Manifest holds, with decoded form in a comment.
+// Schema fields are written as "name:encoding:size", where size is one of:
+// fixedN, prefixed8, prefixed16, rest.
+// Unknown keys and tables are rejected, so that typos are not silently dropped.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportError {
+    // 0 when error is not bound to a line
+    pub line: usize,
+    pub reason: String,
+}
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.reason)
+        } else {
+            write!(f, "line {}: {}", self.line, self.reason)
+        }
+    }
+}
+
+impl Manifest {
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# {:?}", self.app_type);
+        let _ = writeln!(out, "app_type = {}", self.app_type.byte());
+        let _ = writeln!(out, "description = {}", quote(&self.description));
+        let mut tombstones: Vec<&u8> = self.tag_tombstones.iter().collect();
+        tombstones.sort();
+        let _ = writeln!(out, "tag_tombstones = {}", int_array(tombstones));
+
+        let _ = writeln!(out, "\n[tags]");
+        write_tags(&mut out, &self.tags);
+        let _ = writeln!(out, "\n[data_types]");
+        write_tags(&mut out, &self.d_types);
+
+        let mut policies: Vec<(String, String)> = self
+            .policy_reg
+            .iter()
+            .map(|(pol, req)| {
+                let mut bytes = vec![];
+                pol.append_bytes_to(&mut bytes);
+                req.append_bytes_to(&mut bytes);
+                (hex(&bytes), format!("{:?}: {:?}", pol, req))
+            })
+            .collect();
+        policies.sort();
+        for (bytes, decoded) in policies {
+            let _ = writeln!(out, "\n[[policy]]\n# {}", decoded);
+            let _ = writeln!(out, "bytes = {}", quote(&bytes));
+        }
+
+        let mut caps: Vec<(&Capabilities, &CapabiLeaf)> = self.capability_reg.iter().collect();
+        caps.sort_by_key(|(cap, _c_tree)| cap.byte());
+        for (cap, c_tree) in caps {
+            let mut gnomes: Vec<String> = c_tree
+                .get_all_members()
+                .iter()
+                .map(|g_id| quote(&hex(&g_id.bytes())))
+                .collect();
+            gnomes.sort();
+            let _ = writeln!(out, "\n[[capability]]\n# {:?}", cap);
+            let _ = writeln!(out, "capability = {}", cap.byte());
+            let _ = writeln!(out, "gnomes = [{}]", gnomes.join(", "));
+        }
+
+        for b_id in 0..=255 {
+            let Some(bset) = self.byteset_reg.get(&b_id) else {
+                continue;
+            };
+            let _ = writeln!(out, "\n[[byte_set]]\nid = {}", b_id);
+            if bset.is_none() {
+                let _ = writeln!(out, "kind = \"none\"");
+            } else if bset.is_pair() {
+                let pairs: Vec<u16> = bset
+                    .bytes()
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect();
+                let _ = writeln!(out, "kind = \"pairs\"\nvalues = {}", int_array(pairs));
+            } else {
+                let mut values = bset.bytes();
+                values.sort();
+                let _ = writeln!(out, "kind = \"set\"\nvalues = {}", int_array(values));
+            }
+        }
+
+        for ns in self.get_pub_ips() {
+            let _ = writeln!(out, "\n[[network]]");
+            let _ = writeln!(out, "ip = {}", quote(&ns.pub_ip.to_string()));
+            let _ = writeln!(out, "port = {}", ns.pub_port);
+            let _ = writeln!(out, "nat = {}", quote(&format!("{:?}", ns.nat_type)));
+            let (rule, step) = ns.port_allocation;
+            let _ = writeln!(out, "port_rule = {}", quote(&format!("{:?}", rule)));
+            let _ = writeln!(out, "port_step = {}", step);
+            let _ = writeln!(out, "transport = {}", quote(&format!("{:?}", ns.transport)));
+        }
+
+        for d_id in 0..=255 {
+            if let Some(schema) = self.schemas.get(&d_id) {
+                let _ = writeln!(out, "\n[[schema]]\ndata_type = {}", d_id);
+                write_schema(&mut out, schema, "fields");
+            }
+        }
+
+        for m_type in 0..=255 {
+            let Some(decl) = self.messages.get(&m_type) else {
+                continue;
+            };
+            let _ = writeln!(out, "\n[[message]]\nid = {}", m_type);
+            let _ = writeln!(out, "name = {}", quote(&decl.name));
+            let sender = match &decl.sender {
+                MessageSender::Anyone => "anyone".to_string(),
+                MessageSender::Capability(cap) => {
+                    let _ = writeln!(out, "# {:?}", cap);
+                    format!("capability:{}", cap.byte())
+                }
+                MessageSender::Requirement(req) => {
+                    let _ = writeln!(out, "# {:?}", req);
+                    let mut bytes = vec![];
+                    req.append_bytes_to(&mut bytes);
+                    format!("requirement:{}", hex(&bytes))
+                }
+            };
+            let _ = writeln!(out, "sender = {}", quote(&sender));
+            if let Some(payload) = &decl.payload {
+                write_schema(&mut out, payload, "payload");
+            }
+        }
+
+        let mut codes: Vec<&String> = self.locales.keys().collect();
+        codes.sort();
+        for code in codes {
+            let loc = &self.locales[code];
+            let _ = writeln!(out, "\n[[locale]]\ncode = {}", quote(code));
+            let _ = writeln!(out, "description = {}", quote(&loc.description));
+            for t_id in 0..=255 {
+                if let Some(tag) = loc.tags.get(&t_id) {
+                    let _ = writeln!(out, "tag.{} = {}", t_id, quote(&tag.0));
+                }
+            }
+        }
+
+        for (s_id, pages) in self.extension_pages() {
+            let pages: Vec<String> = pages.iter().map(|d| quote(&hex(d.ref_bytes()))).collect();
+            let _ = writeln!(out, "\n[[extension]]\nid = {}", s_id);
+            let _ = writeln!(out, "pages = [{}]", pages.join(", "));
+        }
+        out
+    }
+
+    /// Parse a Manifest from text and make sure it can be stored.
+    pub fn from_text(text: &str) -> Result<Manifest, ImportError> {
+        let mut manifest = Manifest::new(AppType::Other(0), HashMap::new());
+        let mut table = Table::Root;
+        let mut entries: Vec<(usize, Table, HashMap<String, Value>)> = vec![];
+        for (idx, raw_line) in text.lines().enumerate() {
+            let line_no = idx + 1;
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |reason: String| ImportError {
+                line: line_no,
+                reason,
+            };
+            if let Some(name) = line.strip_prefix("[[").and_then(|l| l.strip_suffix("]]")) {
+                table = Table::from(name.trim()).ok_or(err(format!("Unknown table {}", name)))?;
+                entries.push((line_no, table, HashMap::new()));
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                table = match name.trim() {
+                    "tags" => Table::Tags,
+                    "data_types" => Table::DataTypes,
+                    other => return Err(err(format!("Unknown table {}", other))),
+                };
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(err("Expected key = value".to_string()));
+            };
+            let key = key.trim();
+            let value = parse_value(value.trim()).map_err(err)?;
+            match table {
+                Table::Root => match key {
+                    "app_type" => manifest.app_type = AppType::from(value.byte().map_err(err)?),
+                    "description" => manifest.description = value.string().map_err(err)?,
+                    "tag_tombstones" => {
+                        for v in value.array().map_err(err)? {
+                            manifest.tag_tombstones.insert(v.byte().map_err(err)?);
+                        }
+                    }
+                    other => return Err(err(format!("Unknown key {}", other))),
+                },
+                Table::Tags | Table::DataTypes => {
+                    let id = u8::from_str(key).map_err(|_e| err(format!("Invalid id {}", key)))?;
+                    let tag = Tag::new(value.string().map_err(err)?)
+                        .map_err(|_e| err("Name longer than 32 bytes".to_string()))?;
+                    if tag.is_empty() {
+                        return Err(err("Empty name".to_string()));
+                    }
+                    if table == Table::Tags {
+                        manifest.tags.insert(id, tag);
+                    } else {
+                        manifest.d_types.insert(id, tag);
+                    }
+                }
+                _other => {
+                    let (_line, _table, keys) = entries.last_mut().unwrap();
+                    if keys.insert(key.to_string(), value).is_some() {
+                        return Err(err(format!("Duplicate key {}", key)));
+                    }
+                }
+            }
+        }
+        for (line, table, keys) in entries {
+            read_entry(&mut manifest, table, keys)
+                .map_err(|reason| ImportError { line, reason })?;
+        }
+        validate(&manifest)?;
+        Ok(manifest)
+    }
+}
+
+fn validate(manifest: &Manifest) -> Result<(), ImportError> {
+    let err = |reason: String| ImportError { line: 0, reason };
+    let mut names = HashSet::new();
+    for tag in manifest.tags.values() {
+        if !names.insert(&tag.0) {
+            return Err(err(format!("Tag {} defined twice", tag.0)));
+        }
+    }
+    if let Some(t_id) = manifest
+        .tag_tombstones
+        .iter()
+        .find(|t_id| manifest.tags.contains_key(t_id))
+    {
+        return Err(err(format!("Tag {} is both defined and tombstoned", t_id)));
+    }
+    if let Some(d_id) = manifest
+        .schemas
+        .keys()
+        .find(|d_id| !manifest.d_types.contains_key(d_id))
+    {
+        return Err(err(format!("Schema for undefined data type {}", d_id)));
+    }
+    for (code, loc) in &manifest.locales {
+        if let Some(t_id) = loc
+            .tags
+            .keys()
+            .find(|t_id| !manifest.tags.contains_key(t_id))
+        {
+            return Err(err(format!(
+                "Locale {} translates undefined Tag {}",
+                code, t_id
+            )));
+        }
+    }
+    if manifest.description.len() > manifest.description_budget() {
+        return Err(err(format!(
+            "Description has {} bytes, max is {}",
+            manifest.description.len(),
+            manifest.description_budget()
+        )));
+    }
+    manifest
+        .to_data()
+        .map(|_data_vec| ())
+        .map_err(|e| err(e.to_string()))
+}
+
+fn read_entry(
+    manifest: &mut Manifest,
+    table: Table,
+    mut keys: HashMap<String, Value>,
+) -> Result<(), String> {
+    let mut take = |key: &str| keys.remove(key).ok_or(format!("Missing {}", key));
+    match table {
+        Table::Policy => {
+            let mut bytes = unhex(&take("bytes")?.string()?)?;
+            if bytes.is_empty() {
+                return Err("Empty policy".to_string());
+            }
+            let pol = Policy::from(&mut bytes);
+            let req = Requirement::from(&mut bytes);
+            if !bytes.is_empty() {
+                return Err(format!("{} bytes left after policy", bytes.len()));
+            }
+            manifest.policy_reg.insert(pol, req);
+        }
+        Table::Capability => {
+            let cap = Capabilities::from(take("capability")?.byte()?);
+            let mut c_tree = CapabiLeaf::create();
+            for g_id in take("gnomes")?.array()? {
+                let bytes = unhex(&g_id.string()?)?;
+                let arr: [u8; 8] = bytes
+                    .try_into()
+                    .map_err(|_b| "GnomeId has to be 8 bytes".to_string())?;
+                c_tree.insert(GnomeId::from(arr));
+            }
+            manifest.capability_reg.insert(cap, c_tree);
+        }
+        Table::ByteSet => {
+            let b_id = take("id")?.byte()?;
+            let bset = match &take("kind")?.string()?[..] {
+                "none" => ByteSet::empty(),
+                "set" => {
+                    let mut h_set = HashSet::new();
+                    for v in take("values")?.array()? {
+                        h_set.insert(v.byte()?);
+                    }
+                    ByteSet::new(h_set)
+                }
+                "pairs" => {
+                    let mut bset = ByteSet::empty();
+                    for v in take("values")?.array()? {
+                        let pair = u16::try_from(v.int()?).map_err(|_e| "Invalid pair")?;
+                        bset.add_pair(pair);
+                    }
+                    bset
+                }
+                other => return Err(format!("Unknown ByteSet kind {}", other)),
+            };
+            manifest.byteset_reg.insert(b_id, bset);
+        }
+        Table::Network => {
+            let ip = take("ip")?.string()?;
+            let pub_ip = IpAddr::from_str(&ip).map_err(|_e| format!("Invalid ip {}", ip))?;
+            let pub_port = u16::try_from(take("port")?.int()?).map_err(|_e| "Invalid port")?;
+            let nat = take("nat")?.string()?;
+            let nat_type = by_debug_name(&nat, |b| Some(Nat::from(b)))
+                .ok_or(format!("Unknown nat {}", nat))?;
+            let rule = take("port_rule")?.string()?;
+            let rule = by_debug_name(&rule, |b| Some(PortAllocationRule::from(b)))
+                .ok_or(format!("Unknown port_rule {}", rule))?;
+            let step = i8::try_from(take("port_step")?.int()?).map_err(|_e| "Invalid port_step")?;
+            let transport = take("transport")?.string()?;
+            let transport = by_debug_name(&transport, |b| Transport::from(b).ok())
+                .ok_or(format!("Unknown transport {}", transport))?;
+            manifest.update_pub_ips(vec![NetworkSettings {
+                pub_ip,
+                pub_port,
+                nat_type,
+                port_allocation: (rule, step),
+                transport,
+            }]);
+        }
+        Table::Schema => {
+            let d_id = take("data_type")?.byte()?;
+            let schema = read_schema(&mut keys, "fields")?.ok_or("Missing fields")?;
+            manifest.schemas.insert(d_id, schema);
+        }
+        Table::Message => {
+            let m_type = take("id")?.byte()?;
+            let name = take("name")?.string()?;
+            let sender = take("sender")?.string()?;
+            let sender = if sender == "anyone" {
+                MessageSender::Anyone
+            } else if let Some(cap) = sender.strip_prefix("capability:") {
+                let cap = u8::from_str(cap).map_err(|_e| format!("Invalid capability {}", cap))?;
+                MessageSender::Capability(Capabilities::from(cap))
+            } else if sender.starts_with("requirement:") {
+                return Err("Requirement senders can not be enforced, use a capability".to_string());
+            } else {
+                return Err(format!("Unknown sender {}", sender));
+            };
+            let payload = read_schema(&mut keys, "payload")?;
+            if !manifest.declare_message(
+                m_type,
+                Some(MessageDecl {
+                    name,
+                    sender,
+                    payload,
+                }),
+            ) {
+                return Err(format!("Message id {} is out of range", m_type));
+            }
+        }
+        Table::Locale => {
+            let code = take("code")?.string()?;
+            let description = take("description")?.string()?;
+            let mut tags = HashMap::new();
+            for (key, value) in keys.drain() {
+                let Some(t_id) = key.strip_prefix("tag.") else {
+                    return Err(format!("Unknown key {}", key));
+                };
+                let t_id = u8::from_str(t_id).map_err(|_e| format!("Invalid id {}", t_id))?;
+                let tag = Tag::new(value.string()?).map_err(|_e| "Name longer than 32 bytes")?;
+                tags.insert(t_id, tag);
+            }
+            if code.is_empty() || code.len() > 35 || !code.is_ascii() {
+                return Err(format!("Invalid language code {}", code));
+            }
+            manifest
+                .locales
+                .insert(code, Localization { description, tags });
+        }
+        Table::Extension => {
+            let s_id = take("id")?.byte()?;
+            let mut pages = vec![];
+            for page in take("pages")?.array()? {
+                let data = Data::new(unhex(&page.string()?)?)
+                    .map_err(|_b| "Page longer than 1024 bytes".to_string())?;
+                pages.push(data);
+            }
+            if !manifest.set_extension_pages(s_id, pages) {
+                return Err(format!("Section id {} is reserved", s_id));
+            }
+        }
+        Table::Root | Table::Tags | Table::DataTypes => {}
+    }
+    if let Some(key) = keys.keys().next() {
+        return Err(format!("Unknown key {}", key));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Table {
+    Root,
+    Tags,
+    DataTypes,
+    Policy,
+    Capability,
+    ByteSet,
+    Network,
+    Schema,
+    Message,
+    Locale,
+    Extension,
+}
+impl Table {
+    fn from(name: &str) -> Option<Self> {
+        match name {
+            "policy" => Some(Self::Policy),
+            "capability" => Some(Self::Capability),
+            "byte_set" => Some(Self::ByteSet),
+            "network" => Some(Self::Network),
+            "schema" => Some(Self::Schema),
+            "message" => Some(Self::Message),
+            "locale" => Some(Self::Locale),
+            "extension" => Some(Self::Extension),
+            _other => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+impl Value {
+    fn string(self) -> Result<String, String> {
+        match self {
+            Self::Str(s) => Ok(s),
+            other => Err(format!("Expected a string, got {:?}", other)),
+        }
+    }
+    fn int(self) -> Result<i64, String> {
+        match self {
+            Self::Int(i) => Ok(i),
+            other => Err(format!("Expected an integer, got {:?}", other)),
+        }
+    }
+    fn byte(self) -> Result<u8, String> {
+        let i = self.int()?;
+        u8::try_from(i).map_err(|_e| format!("{} is not in 0..=255", i))
+    }
+    fn bool(self) -> Result<bool, String> {
+        match self {
+            Self::Bool(b) => Ok(b),
+            other => Err(format!("Expected a boolean, got {:?}", other)),
+        }
+    }
+    fn array(self) -> Result<Vec<Value>, String> {
+        match self {
+            Self::Array(a) => Ok(a),
+            other => Err(format!("Expected an array, got {:?}", other)),
+        }
+    }
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let mut chars = text.chars().peekable();
+    let value = parse_one(&mut chars)?;
+    let rest: String = chars.collect();
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(value)
+    } else {
+        Err(format!("Unexpected {}", rest))
+    }
+}
+
+fn parse_one(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Value, String> {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+    match chars.peek() {
+        Some('"') => {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => return Ok(Value::Str(s)),
+                    Some('\\') => match chars.next() {
+                        Some('n') => s.push('\n'),
+                        Some('t') => s.push('\t'),
+                        Some('r') => s.push('\r'),
+                        Some('"') => s.push('"'),
+                        Some('\\') => s.push('\\'),
+                        other => return Err(format!("Unsupported escape {:?}", other)),
+                    },
+                    Some(c) => s.push(c),
+                    None => return Err("Unterminated string".to_string()),
+                }
+            }
+        }
+        Some('[') => {
+            chars.next();
+            let mut values = vec![];
+            loop {
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                    return Ok(Value::Array(values));
+                }
+                values.push(parse_one(chars)?);
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => return Ok(Value::Array(values)),
+                    other => return Err(format!("Expected , or ] got {:?}", other)),
+                }
+            }
+        }
+        Some(_c) => {
+            let mut word = String::new();
+            while let Some(c) = chars.peek() {
+                if c.is_alphanumeric() || *c == '-' || *c == '_' {
+                    word.push(*c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            match &word[..] {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                other => i64::from_str(&other.replace('_', ""))
+                    .map(Value::Int)
+                    .map_err(|_e| format!("Invalid value {}", other)),
+            }
+        }
+        None => Err("Missing value".to_string()),
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            other => quoted.push(other),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn int_array<T: fmt::Display>(values: impl IntoIterator<Item = T>) -> String {
+    let values: Vec<String> = values.into_iter().map(|v| v.to_string()).collect();
+    format!("[{}]", values.join(", "))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(out, "{:02x}", byte);
+    }
+    out
+}
+
+fn unhex(text: &str) -> Result<Vec<u8>, String> {
+    if text.len() % 2 > 0 || !text.is_ascii() {
+        return Err(format!("Invalid hex {}", text));
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&text[i..i + 2], 16).map_err(|_e| format!("Invalid hex {}", text))
+        })
+        .collect()
+}
+
+// Gnome's network enums are built from a byte, so we look for
+// a byte that gives a variant with given name.
+fn by_debug_name<T: fmt::Debug>(name: &str, from: impl Fn(u8) -> Option<T>) -> Option<T> {
+    (0..=255)
+        .filter_map(from)
+        .find(|variant| format!("{:?}", variant) == name)
+}
+
+fn write_tags(out: &mut String, tags: &HashMap<u8, Tag>) {
+    for id in 0..=255 {
+        if let Some(tag) = tags.get(&id) {
+            let _ = writeln!(out, "{} = {}", id, quote(&tag.0));
+        }
+    }
+}
+
+fn write_schema(out: &mut String, schema: &DataSchema, key: &str) {
+    if schema.standard_first_page {
+        let _ = writeln!(out, "standard_first_page = true");
+    }
+    if schema.last_repeats {
+        let _ = writeln!(out, "last_repeats = true");
+    }
+    let fields: Vec<String> = schema
+        .fields
+        .iter()
+        .map(|field| {
+            let encoding = match field.encoding {
+                Encoding::Bytes => "bytes",
+                Encoding::Utf8 => "utf8",
+                Encoding::Unsigned => "unsigned",
+                Encoding::Signed => "signed",
+                Encoding::Float => "float",
+                Encoding::Bool => "bool",
+            };
+            let size = match field.size {
+                FieldSize::Fixed(size) => format!("fixed{}", size),
+                FieldSize::Prefixed8 => "prefixed8".to_string(),
+                FieldSize::Prefixed16 => "prefixed16".to_string(),
+                FieldSize::Rest => "rest".to_string(),
+            };
+            quote(&format!("{}:{}:{}", field.name, encoding, size))
+        })
+        .collect();
+    let _ = writeln!(out, "{} = [{}]", key, fields.join(", "));
+}
+
+// Returns None when there is no fields key
+fn read_schema(keys: &mut HashMap<String, Value>, key: &str) -> Result<Option<DataSchema>, String> {
+    let Some(fields_value) = keys.remove(key) else {
+        return Ok(None);
+    };
+    let standard_first_page = match keys.remove("standard_first_page") {
+        Some(value) => value.bool()?,
+        None => false,
+    };
+    let last_repeats = match keys.remove("last_repeats") {
+        Some(value) => value.bool()?,
+        None => false,
+    };
+    let mut fields = vec![];
+    for field in fields_value.array()? {
+        let field = field.string()?;
+        // name can contain ':', so we split from the end
+        let mut parts = field.rsplitn(3, ':');
+        let (Some(size), Some(encoding), Some(name)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(format!("Expected name:encoding:size, got {}", field));
+        };
+        let encoding = match encoding {
+            "bytes" => Encoding::Bytes,
+            "utf8" => Encoding::Utf8,
+            "unsigned" => Encoding::Unsigned,
+            "signed" => Encoding::Signed,
+            "float" => Encoding::Float,
+            "bool" => Encoding::Bool,
+            other => return Err(format!("Unknown encoding {}", other)),
+        };
+        let size = match size {
+            "prefixed8" => FieldSize::Prefixed8,
+            "prefixed16" => FieldSize::Prefixed16,
+            "rest" => FieldSize::Rest,
+            other => {
+                let fixed = other
+                    .strip_prefix("fixed")
+                    .and_then(|n| u16::from_str(n).ok())
+                    .ok_or(format!("Unknown size {}", other))?;
+                FieldSize::Fixed(fixed)
+            }
+        };
+        fields.push(SchemaField {
+            name: name.to_string(),
+            encoding,
+            size,
+        });
+    }
+    Ok(Some(DataSchema {
+        standard_first_page,
+        last_repeats,
+        fields,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Manifest {
+        let tags = HashMap::from([
+            (0, Tag::new("news".to_string()).unwrap()),
+            (2, Tag::new("say \"hi\"".to_string()).unwrap()),
+        ]);
+        let mut manifest = Manifest::new(AppType::Catalog, tags);
+        manifest.description = "Line one\nline\ttwo \\ end".to_string();
+        manifest.tag_tombstones.insert(1);
+        manifest
+            .d_types
+            .insert(4, Tag::new("item".to_string()).unwrap());
+        let schema = DataSchema {
+            standard_first_page: true,
+            last_repeats: false,
+            fields: vec![
+                SchemaField {
+                    name: "a:b".to_string(),
+                    encoding: Encoding::Utf8,
+                    size: FieldSize::Prefixed8,
+                },
+                SchemaField {
+                    name: "price".to_string(),
+                    encoding: Encoding::Float,
+                    size: FieldSize::Fixed(8),
+                },
+            ],
+        };
+        manifest.schemas.insert(4, schema.clone());
+        manifest.messages.insert(
+            9,
+            MessageDecl {
+                name: "bid".to_string(),
+                sender: MessageSender::Anyone,
+                payload: Some(schema),
+            },
+        );
+        manifest.locales.insert(
+            "pl".to_string(),
+            Localization {
+                description: "Opis".to_string(),
+                tags: HashMap::from([(0, Tag::new("wiadomości".to_string()).unwrap())]),
+            },
+        );
+        manifest.set_extension_pages(120, vec![Data::new(vec![0, 255, 16]).unwrap()]);
+        manifest
+    }
+
+    #[test]
+    fn manifest_survives_text_round_trip() {
+        let manifest = sample();
+        let text = manifest.to_text();
+        let read = Manifest::from_text(&text).unwrap();
+        assert!(manifest.diff(&read).is_empty());
+        assert_eq!(read.description, manifest.description);
+        assert_eq!(read.schemas[&4], manifest.schemas[&4]);
+        assert_eq!(
+            read.message_decl(9).unwrap().payload,
+            manifest.schemas.get(&4).cloned()
+        );
+        assert_eq!(read.to_text(), text);
+    }
+
+    #[test]
+    fn errors_point_at_lines() {
+        let err = Manifest::from_text("app_type = 1\ncolour = \"red\"").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.reason, "Unknown key colour");
+
+        let err = Manifest::from_text("[tags]\n0 = \"a\"\n[[unknown]]").unwrap_err();
+        assert_eq!(err.line, 3);
+
+        let text = "[[message]]\nid = 1\nname = \"m\"\nsender = \"requirement:00\"";
+        let err = Manifest::from_text(text).unwrap_err();
+        assert_eq!(err.line, 1);
+        assert!(err.reason.starts_with("Requirement senders"));
+
+        let text = "[[extension]]\nid = 8\npages = []";
+        assert_eq!(Manifest::from_text(text).unwrap_err().line, 1);
+
+        let text = "[[schema]]\ndata_type = 1\nfields = [\"x:utf8:rest\"]\nfields = []";
+        assert_eq!(Manifest::from_text(text).unwrap_err().line, 4);
+    }
+
+    #[test]
+    fn inconsistent_manifests_are_rejected() {
+        let err = Manifest::from_text("tag_tombstones = [0]\n[tags]\n0 = \"a\"").unwrap_err();
+        assert_eq!(err.line, 0);
+        assert_eq!(err.reason, "Tag 0 is both defined and tombstoned");
+
+        let text = "[tags]\n0 = \"a\"\n1 = \"a\"";
+        assert!(Manifest::from_text(text).is_err());
+
+        let text = "[[schema]]\ndata_type = 1\nfields = [\"x:utf8:rest\"]";
+        let err = Manifest::from_text(text).unwrap_err();
+        assert_eq!(err.reason, "Schema for undefined data type 1");
+
+        let text = "[[locale]]\ncode = \"de\"\ndescription = \"\"\ntag.5 = \"x\"";
+        assert!(Manifest::from_text(text).is_err());
+    }
+}
@@ -1,14 +1,31 @@
-pub const MAX_AVAIL_APP_MSG_ID: u8 = 247;
+// 247 is taken by Transaction, 246 carries parts of Proofs.
+// Every byte above this value is a built-in SyncMessageType, so there is
+// no free code outside of App's range.
+// This is a breaking change: Apps that used 246 or 247 for their own
+// messages have to move them below this value, and every Gnome in a Swarm
+// has to be upgraded, since older ones decode those as AppDefined.
+// Manifests declaring such ids are refused by Manifest::declare_message.
+pub const MAX_AVAIL_APP_MSG_ID: u8 = 245;
+// Payload bytes that fit into a single part message: 900 - 5 - 2
+const SINGLE_PART_CHUNK: usize = 893;
+// Payload bytes in header of a multi part message, before part hashes
+const HEADER_CHUNK: usize = 885;
+// Payload bytes in every following part: 1024 - 5 - 2 - 8
+const PART_CHUNK: usize = 1009;
+use crate::assembly::message_hash;
 use crate::prelude::DataType;
 use crate::SyncData;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
 
 use crate::Data;
 
 use crate::content::ContentID;
 use crate::ApplicationData;
+use gnome::prelude::sha_hash;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct SyncRequirements {
     pub pre: Vec<(ContentID, u64)>,
     pub post: Vec<(ContentID, u64)>,
@@ -39,9 +56,54 @@ impl SyncRequirements {
         bytes
     }
 
-    // TODO: we need to add a read: Vec<Content_ID> argument
-    // to verify that only specified contents were read
-    // and that all specified contents were read
+    // Requirements are defined in following way:
+    // - first byte indicates number of pre requirements
+    // - then there is a list of two byte CID followed by eight byte hash pairs
+    // - after that there is again above procedure but for post requirements
+    pub(crate) fn from(bytes: &mut impl Iterator<Item = u8>) -> Option<Self> {
+        let mut read_pairs = || -> Option<Vec<(ContentID, u64)>> {
+            let count = bytes.next()?;
+            let mut pairs = Vec::with_capacity(count as usize);
+            for _i in 0..count {
+                let c_id = u16::from_be_bytes([bytes.next()?, bytes.next()?]);
+                let mut hash = [0; 8];
+                for byte in hash.iter_mut() {
+                    *byte = bytes.next()?;
+                }
+                pairs.push((c_id, u64::from_be_bytes(hash)));
+            }
+            Some(pairs)
+        };
+        let pre = read_pairs()?;
+        let post = read_pairs()?;
+        Some(SyncRequirements { pre, post })
+    }
+
+    /// Check that post requirements list exactly those Contents
+    /// that are changed, and that prior state of every changed Content
+    /// is listed in pre requirements.
+    /// Pre requirements can also list Contents that are only read.
+    pub fn check_sets(&self, changed: &HashSet<ContentID>) -> Result<(), RequirementsViolation> {
+        let pre: HashSet<ContentID> = self.pre.iter().map(|(c_id, _hash)| *c_id).collect();
+        let post: HashSet<ContentID> = self.post.iter().map(|(c_id, _hash)| *c_id).collect();
+        let mut undeclared: Vec<ContentID> = changed.difference(&post).copied().collect();
+        let mut unchanged: Vec<ContentID> = post.difference(changed).copied().collect();
+        let mut unread: Vec<ContentID> = changed.difference(&pre).copied().collect();
+        if undeclared.is_empty() && unchanged.is_empty() && unread.is_empty() {
+            return Ok(());
+        }
+        undeclared.sort();
+        unchanged.sort();
+        unread.sort();
+        Err(RequirementsViolation {
+            undeclared,
+            unchanged,
+            unread,
+        })
+    }
+
+    // Only compares hashes, which Contents should be listed
+    // is verified by check_sets
     pub fn pre_validate(&self, _c_id: ContentID, app: &ApplicationData) -> bool {
         for (c_id, hash) in self.pre.iter() {
             if let Ok((_d_type, d_hash)) = app.content_root_hash(*c_id) {
@@ -60,9 +122,6 @@ impl SyncRequirements {
         true
     }
 
-    // TODO: we need to add a changed: Vec<Content_ID> argument
-    // to verify that only specified contents were changed
-    // and that all specified contents were changed
     pub fn post_validate(&self, _c_id: ContentID, app: &ApplicationData) -> bool {
         for (c_id, hash) in self.post.iter() {
             if let Ok((_d_type, d_hash)) = app.content_root_hash(*c_id) {
@@ -85,6 +144,27 @@ impl SyncRequirements {
         true
     }
 }
+
+// Contents a message changed or was going to change,
+// that do not match it's SyncRequirements
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequirementsViolation {
+    // Changed, but not listed in post requirements
+    pub undeclared: Vec<ContentID>,
+    // Listed in post requirements, but not changed
+    pub unchanged: Vec<ContentID>,
+    // Changed, but prior state not listed in pre requirements
+    pub unread: Vec<ContentID>,
+}
+impl fmt::Display for RequirementsViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "undeclared: {:?}, unchanged: {:?}, unread: {:?}",
+            self.undeclared, self.unchanged, self.unread
+        )
+    }
+}
 // We need a high level way to manipulate Datastore elements.
 // Manipulation can be done at two levels: Content, and specific Data within Content
 // On Content level we can:
@@ -147,6 +227,16 @@ impl ChangeContentOperation {
             }
         }
     }
+    // Same as from, but for bytes we can not trust
+    fn try_from(bytes: &mut impl Iterator<Item = u8>) -> Option<Self> {
+        match bytes.next()? {
+            1 => Some(Self::DirectCTreeRebuild),
+            2 => Some(Self::DropAndAppend(read_u16(bytes)?)),
+            4 => Some(Self::PopAndAppendConverted(read_u16(bytes)?)),
+            8 => Some(Self::PopAndCTreeRebuild(read_u16(bytes)?)),
+            _other => None,
+        }
+    }
     pub fn bytes(&self) -> Vec<u8> {
         match self {
             Self::DirectCTreeRebuild => {
@@ -183,8 +273,9 @@ pub enum SyncMessageType {
     UpdateData(ContentID, u16),
     InsertData(ContentID, u16),
     ExtendData(ContentID, u16),
+    Transaction(ContentID), // first CID changed, for Policy check
     AppDefined(u8, u16, u16), // req_id, CID (2bytes!), DID
-                              // Policy check will only recognize two bytes of CID, none of DID!
+                            // Policy check will only recognize two bytes of CID, none of DID!
 }
 impl SyncMessageType {
     pub fn new(bytes: &mut Vec<u8>) -> Self {
@@ -256,6 +347,13 @@ impl SyncMessageType {
                 SyncMessageType::ExtendData(c_id, d_id)
             }
 
+            247 => {
+                let b1 = bytes.drain(0..1).next().unwrap();
+                let b2 = bytes.drain(0..1).next().unwrap();
+                let c_id = u16::from_be_bytes([b1, b2]);
+                SyncMessageType::Transaction(c_id)
+            }
+
             other => {
                 let b1 = bytes.drain(0..1).next().unwrap();
                 let b2 = bytes.drain(0..1).next().unwrap();
@@ -269,6 +367,29 @@ impl SyncMessageType {
         }
     }
 
+    /// Read a message type from bytes we can not trust,
+    /// returns None instead of panicking when those are malformed.
+    pub fn try_from(bytes: &mut impl Iterator<Item = u8>) -> Option<Self> {
+        let m_type = match bytes.next()? {
+            255 => SyncMessageType::AppendShelledDatas(read_u16(bytes)?),
+            254 => SyncMessageType::AppendContent(DataType::from(bytes.next()?)),
+            253 => {
+                let c_id = read_u16(bytes)?;
+                let d_type = DataType::from(bytes.next()?);
+                let operation = ChangeContentOperation::try_from(bytes)?;
+                SyncMessageType::ChangeContent(c_id, d_type, operation)
+            }
+            252 => SyncMessageType::AppendData(read_u16(bytes)?),
+            251 => SyncMessageType::RemoveData(read_u16(bytes)?, read_u16(bytes)?),
+            250 => SyncMessageType::UpdateData(read_u16(bytes)?, read_u16(bytes)?),
+            249 => SyncMessageType::InsertData(read_u16(bytes)?, read_u16(bytes)?),
+            248 => SyncMessageType::ExtendData(read_u16(bytes)?, read_u16(bytes)?),
+            247 => SyncMessageType::Transaction(read_u16(bytes)?),
+            other => SyncMessageType::AppDefined(other, read_u16(bytes)?, read_u16(bytes)?),
+        };
+        Some(m_type)
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         match self {
             SyncMessageType::AppendShelledDatas(c_id) => {
@@ -310,6 +431,11 @@ impl SyncMessageType {
                 vec![248, b1, b2, b3, b4]
             }
 
+            SyncMessageType::Transaction(c_id) => {
+                let [b1, b2] = c_id.to_be_bytes();
+                vec![247, b1, b2]
+            }
+
             SyncMessageType::AppDefined(other, c_id, d_id) => {
                 // eprintln!("UserDefined bytes: {other},{c_id},{d_id}");
                 let [b1, b2] = c_id.to_be_bytes();
@@ -320,6 +446,102 @@ impl SyncMessageType {
     }
 }
 
+// A Transaction carries a list of Data level operations that can span
+// multiple Contents. It's SyncRequirements have to list pre and post
+// hashes of every Content touched, and it is applied all or nothing:
+// if any operation fails or any post hash does not match,
+// all touched Contents are restored.
+// All operations with their Data have to fit into a single Data.
+//
+// Serialized operation layout:
+// 1 byte  - kind, same as SyncMessageType: 252 - AppendData,
+//           251 - RemoveData, 250 - UpdateData, 249 - InsertData
+// 2 bytes - ContentID
+// 2 bytes - DataID, not present for AppendData
+// 2 bytes - Data len + Data bytes, not present for RemoveData
+#[derive(Clone, Debug)]
+pub enum TxOperation {
+    AppendData(ContentID, Data),
+    RemoveData(ContentID, u16),
+    UpdateData(ContentID, u16, Data),
+    InsertData(ContentID, u16, Data),
+}
+
+impl TxOperation {
+    pub fn c_id(&self) -> ContentID {
+        match self {
+            Self::AppendData(c_id, _)
+            | Self::RemoveData(c_id, _)
+            | Self::UpdateData(c_id, _, _)
+            | Self::InsertData(c_id, _, _) => *c_id,
+        }
+    }
+
+    fn append_bytes_to(&self, bytes: &mut Vec<u8>) {
+        let (kind, d_id, data) = match self {
+            Self::AppendData(_c_id, data) => (252, None, Some(data)),
+            Self::RemoveData(_c_id, d_id) => (251, Some(d_id), None),
+            Self::UpdateData(_c_id, d_id, data) => (250, Some(d_id), Some(data)),
+            Self::InsertData(_c_id, d_id, data) => (249, Some(d_id), Some(data)),
+        };
+        bytes.push(kind);
+        bytes.extend_from_slice(&self.c_id().to_be_bytes());
+        if let Some(d_id) = d_id {
+            bytes.extend_from_slice(&d_id.to_be_bytes());
+        }
+        if let Some(data) = data {
+            bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(data.ref_bytes());
+        }
+    }
+
+    fn from(bytes: &mut impl Iterator<Item = u8>) -> Option<Self> {
+        let kind = bytes.next()?;
+        let c_id = read_u16(bytes)?;
+        let d_id = if kind == 252 { 0 } else { read_u16(bytes)? };
+        if kind == 251 {
+            return Some(Self::RemoveData(c_id, d_id));
+        }
+        let d_len = read_u16(bytes)?;
+        let mut d_bytes = Vec::with_capacity(d_len as usize);
+        for _i in 0..d_len {
+            d_bytes.push(bytes.next()?);
+        }
+        let data = Data::new(d_bytes).ok()?;
+        match kind {
+            252 => Some(Self::AppendData(c_id, data)),
+            250 => Some(Self::UpdateData(c_id, d_id, data)),
+            249 => Some(Self::InsertData(c_id, d_id, data)),
+            _other => None,
+        }
+    }
+
+    /// Serialize operations into a single Data,
+    /// returns None when those do not fit.
+    pub fn to_data(ops: &[TxOperation]) -> Option<Data> {
+        let mut bytes = Vec::with_capacity(1024);
+        for op in ops {
+            op.append_bytes_to(&mut bytes);
+        }
+        Data::new(bytes).ok()
+    }
+
+    /// Deserialize operations, returns None when any of them is malformed.
+    pub fn from_data(data: &Data) -> Option<Vec<TxOperation>> {
+        let mut ops = vec![];
+        let mut bytes = data.ref_bytes().iter().copied().peekable();
+        while bytes.peek().is_some() {
+            ops.push(TxOperation::from(&mut bytes)?);
+        }
+        Some(ops)
+    }
+}
+
+fn read_u16(bytes: &mut impl Iterator<Item = u8>) -> Option<u16> {
+    Some(u16::from_be_bytes([bytes.next()?, bytes.next()?]))
+}
+
+#[derive(Clone, Debug)]
 pub struct SyncMessage {
     pub m_type: SyncMessageType,
     pub requirements: SyncRequirements,
@@ -336,233 +558,116 @@ impl SyncMessage {
     }
 
     pub fn into_parts(self) -> Vec<SyncData> {
-        //TODO: here we need to determine how many parts will given message consist of
-        // based on Data.len() and requirements size.
-        // We also need to count 10 byte pairs of (c_id, hash) for each
-        // subsequent part in part 0.
-        // First partial message should not exceed 900 bytes total, in order to
-        // carry PUBKEY DER.
         // Every partial message will consist of
-        // - 1 byte of message type
+        // - 1 to 5 bytes of message type
         // - 2 bytes of part_no and total_parts
-        let data_size = self.data.len();
-        let req_size = 2 + 10 * (self.requirements.pre.len() + self.requirements.post.len());
-        let netto_size = data_size + req_size;
+        // - for multi part messages 8 bytes of message hash
+        // First partial message should not exceed 900 bytes total, in order to
+        // carry PUBKEY DER, and it also holds 8 byte hashes of following parts.
+        // Following parts can take up to 1024 bytes.
+        // Maximum size of Requirements and Data is
+        // 1 + 2550 + 1 + 2550 + 1024 = 6126 bytes,
+        // so there should never be more than 7 following parts.
+        let mut payload = self.requirements.bytes();
+        payload.append(&mut self.data.bytes());
+        let type_bytes = self.m_type.as_bytes();
         let mut partials = vec![];
-        // eprintln!("data size: {}", data_size);
-        // eprintln!("req size: {}", req_size);
-        // eprintln!("netto size: {}", netto_size);
-
-        // TODO/DONE? (was 897): recalculate how many bytes to drain, since now
-        // message type can take up to 5 bytes, not 1 as previously
-        // println!("Netto: {}", netto_size);
-        if netto_size < 893 {
-            // eprintln!("netto_size < 893 ");
-            let mut bytes = Vec::with_capacity(netto_size + 3);
-            for byte in self.m_type.as_bytes() {
-                bytes.push(byte);
-            }
+        if payload.len() < SINGLE_PART_CHUNK {
+            let mut bytes = Vec::with_capacity(payload.len() + 7);
+            bytes.extend_from_slice(&type_bytes);
             bytes.push(0);
             bytes.push(0);
-            bytes.append(&mut self.requirements.bytes());
-            bytes.append(&mut self.data.bytes());
-            let data = SyncData::new(bytes).unwrap();
-            partials.push(data);
-            // partials.push(PartialMessage { m_type, part_no: 0, total_parts:0, data })
-        } else {
-            // eprintln!("netto_size ({}) >= 893 ", netto_size);
-            let mut non_header_parts = 1;
-            let mut done = false;
-            while !done {
-                let non_header_bytes_consumed = non_header_parts * 1021;
-                // eprintln!("netto_size: {}", netto_size);
-                // eprintln!("non_header_bytes_consumed : {}", non_header_bytes_consumed);
-                // eprintln!("(893-) non_header_parts*8: {}", non_header_parts * 8);
-                if netto_size < non_header_bytes_consumed
-                    || netto_size - non_header_bytes_consumed <= 893 - (8 * non_header_parts)
-                {
-                    done = true;
-                } else {
-                    non_header_parts += 1;
-                }
-            }
-            // eprintln!("Non header parts: {}", non_header_parts);
-            // First we assume worst case scenario - 7 parts
-            // Maximum size of SyncMessage is:
-            // 1 byte for m_type
-            // + 1 byte for pre req size
-            // + 2560 bytes for pre reqs
-            // + 1 byte for post req size
-            // + 2560 bytes for postreqs
-            // + 1024 bytes for Data
-            // = 6147 bytes
-            // (and we can add those 18 bytes 6165)
-            // (and those 60 bytes 6225)
-            // So there should never be more than 7 parts
-            // We can reserve six 8-byte slots in first part for following parts' hashes
-            // Partial message should only contain type, Data, part_no and total_parts
-            // no other logical structures.
-            // We only assemble SyncMessage from parts and then discover
-            // hashes, Requirements and everything else.
-            let hashes_size = 8 * non_header_parts;
-            let mut header_bytes: Vec<u8> = Vec::with_capacity(900);
-            // TODO/DONE? (was 897): recalculate how many bytes to drain, since now
-            // message type can take up to 5 bytes, not 1 as previously
-            for byte in self.m_type.as_bytes() {
-                header_bytes.push(byte);
-            }
-            header_bytes.push(0);
-            header_bytes.push(non_header_parts as u8);
-            let first_chunk_size = 893 - hashes_size;
-            // eprintln!("first_chunk_size: {}", first_chunk_size);
-            // later chunks have up to 1021 bytes (3 bytes for type, part_no, total_parts)
-            // First we put hashes (after we calculate them...)
-            // Then we put requirements
-            let mut req_and_data_bytes = self.requirements.bytes();
-            // eprintln!("req_bytes: {}", req_and_data_bytes.len());
-            // And at the end we put data_bytes
-            let mut data_bytes = self.data.bytes();
-            req_and_data_bytes.append(&mut data_bytes);
-            // eprintln!("req_and_data_bytes: {}", req_and_data_bytes.len());
-            let mut first_chunk_bytes: Vec<u8> =
-                req_and_data_bytes.drain(0..first_chunk_size).collect();
-            let mut subsequent_chunks: Vec<SyncData> = vec![];
-            // TODO/DONE?(was 1021): recalculate how many bytes to drain, since now
-            // message type can take up to 5 bytes, not 1 as previously
-            for i in 0..non_header_parts {
-                let mut vec = self.m_type.as_bytes();
-                vec.push((i + 1) as u8);
-                vec.push(non_header_parts as u8);
-                let bytes_count = req_and_data_bytes.len();
-                let drain_count = if bytes_count >= 1017 {
-                    1017
-                } else {
-                    bytes_count
-                };
-                // eprintln!(
-                //     "before drain, rem {} bytes from {} remaining",
-                //     drain_count,
-                //     req_and_data_bytes.len()
-                // );
-                vec.append(&mut req_and_data_bytes.drain(0..drain_count).collect());
-                let data = SyncData::new(vec).unwrap();
-                let hash = data.hash();
-                eprintln!("into part hash: {}(len: {},\n{})", hash, data.len(), &data);
-                for byte in hash.to_be_bytes() {
-                    header_bytes.push(byte);
-                }
-                // eprintln!("Pushing {} bytes", data.len());
-                subsequent_chunks.push(data);
-            }
-            header_bytes.append(&mut first_chunk_bytes);
-            let header_data = SyncData::new(header_bytes).unwrap();
-            // eprintln!("Pushing {} header bytes", header_data.len());
-            partials.push(header_data);
-            partials.append(&mut subsequent_chunks);
+            bytes.append(&mut payload);
+            partials.push(SyncData::new(bytes).unwrap());
+            return partials;
         }
-
+        let mut non_header_parts = 1;
+        while payload.len() > HEADER_CHUNK + (PART_CHUNK - 8) * non_header_parts {
+            non_header_parts += 1;
+        }
+        let msg_hash = message_hash(&self.m_type, &payload);
+        let first_chunk_size = (HEADER_CHUNK - 8 * non_header_parts).min(payload.len());
+        let first_chunk: Vec<u8> = payload.drain(0..first_chunk_size).collect();
+        let mut header_bytes: Vec<u8> = Vec::with_capacity(900);
+        header_bytes.extend_from_slice(&type_bytes);
+        header_bytes.push(0);
+        header_bytes.push(non_header_parts as u8);
+        header_bytes.extend_from_slice(&msg_hash.to_be_bytes());
+        let mut subsequent_chunks: Vec<SyncData> = Vec::with_capacity(non_header_parts);
+        for i in 0..non_header_parts {
+            let mut bytes = Vec::with_capacity(1024);
+            bytes.extend_from_slice(&type_bytes);
+            bytes.push((i + 1) as u8);
+            bytes.push(non_header_parts as u8);
+            bytes.extend_from_slice(&msg_hash.to_be_bytes());
+            let drain_count = payload.len().min(PART_CHUNK);
+            bytes.extend(payload.drain(0..drain_count));
+            // Same hash as Data::get_hash, so receivers can verify parts
+            header_bytes.extend_from_slice(&sha_hash(&bytes).to_be_bytes());
+            subsequent_chunks.push(SyncData::new(bytes).unwrap());
+        }
+        header_bytes.extend(first_chunk);
+        partials.push(SyncData::new(header_bytes).unwrap());
+        partials.append(&mut subsequent_chunks);
         partials
     }
 
-    // TODO: this needs rework
+    /// Assemble a SyncMessage from it's parts.
+    /// idx holds hashes of all parts in order, with header part stored under 0.
     pub fn from_data(idx: Vec<u64>, mut vec_data: HashMap<u64, Data>) -> Result<Self, ()> {
-        // for (idx, data) in &vec_data {
-        //     eprintln!("{} size {} bytes", idx, data.len());
-        // }
-        let idx_len = idx.len();
-        if idx.len() != vec_data.len() {
+        if idx.is_empty() || idx.len() != vec_data.len() {
             return Err(());
         }
-        let mut total_bytes = Vec::with_capacity(idx_len * 1021);
+        let idx_len = idx.len();
         let mut idx_iter = idx.into_iter();
         let key = idx_iter.next().unwrap();
-        let p_data = vec_data.remove(&key).unwrap();
-        let mut header_bytes = p_data.bytes();
-        // eprintln!("after remove header len: {}", header_bytes.len());
+        let mut header_bytes = vec_data.remove(&key).ok_or(())?.bytes();
+        if header_bytes.is_empty() {
+            return Err(());
+        }
         let m_type = SyncMessageType::new(&mut header_bytes);
-        // drop part_no & total_parts
-        let _ = header_bytes.drain(0..2);
-        // eprintln!("after remove part/total len: {}", header_bytes.len());
-
-        let mut non_header_bytes = Vec::with_capacity((idx_len - 1) * 1021);
+        if header_bytes.len() < 2 {
+            return Err(());
+        }
+        // drop part_no
+        header_bytes.remove(0);
+        let total_parts = header_bytes.remove(0) as usize;
+        if idx_len != total_parts + 1 {
+            return Err(());
+        }
+        let msg_hash = if total_parts > 0 {
+            // message hash followed by hashes of following parts
+            let hashes_len = 8 * (total_parts + 1);
+            if header_bytes.len() < hashes_len {
+                return Err(());
+            }
+            let hashes: Vec<u8> = header_bytes.drain(0..hashes_len).collect();
+            Some(u64::from_be_bytes(hashes[0..8].try_into().unwrap()))
+        } else {
+            None
+        };
+        let mut payload = header_bytes;
         for hash in idx_iter {
-            let p_data = vec_data.remove(&hash).unwrap();
-            let mut p_bytes = p_data.bytes();
+            let mut p_bytes = vec_data.remove(&hash).ok_or(())?.bytes();
+            if p_bytes.is_empty() {
+                return Err(());
+            }
             let _m_type = SyncMessageType::new(&mut p_bytes);
-            let _ = p_bytes.drain(0..2);
-            let _ = header_bytes.drain(0..8);
-            non_header_bytes.append(&mut p_bytes);
+            // part_no, total_parts and message hash
+            if p_bytes.len() < 10 {
+                return Err(());
+            }
+            payload.extend_from_slice(&p_bytes[10..]);
         }
-        total_bytes.append(&mut header_bytes);
-        total_bytes.append(&mut non_header_bytes);
-        let mut bytes_iter = total_bytes.into_iter();
-        // let part_no = bytes_iter.next().unwrap();
-        // let total_parts = bytes_iter.next().unwrap();
-        // let mut part_hashes = None;
-        let requirements = {
-            // Requirements are defined in following way:
-            // - first byte indicates number of pre requirements
-            // - then there is a list of two byte CID followed by eight byte hash pairs
-            // - after that there is again above procedure but for post requirements
-            let pre_count = bytes_iter.next().unwrap();
-            let mut pre = Vec::with_capacity(pre_count as usize);
-            for _i in 0..pre_count {
-                let b1 = bytes_iter.next().unwrap();
-                let b2 = bytes_iter.next().unwrap();
-                let c_id = u16::from_be_bytes([b1, b2]);
-                let b1 = bytes_iter.next().unwrap();
-                let b2 = bytes_iter.next().unwrap();
-                let b3 = bytes_iter.next().unwrap();
-                let b4 = bytes_iter.next().unwrap();
-                let b5 = bytes_iter.next().unwrap();
-                let b6 = bytes_iter.next().unwrap();
-                let b7 = bytes_iter.next().unwrap();
-                let b8 = bytes_iter.next().unwrap();
-                let hash = u64::from_be_bytes([b1, b2, b3, b4, b5, b6, b7, b8]);
-                pre.push((c_id, hash));
-            }
-            let post_count = bytes_iter.next().unwrap();
-            let mut post = Vec::with_capacity(post_count as usize);
-            for _i in 0..post_count {
-                let b1 = bytes_iter.next().unwrap();
-                let b2 = bytes_iter.next().unwrap();
-                let c_id = u16::from_be_bytes([b1, b2]);
-                let b1 = bytes_iter.next().unwrap();
-                let b2 = bytes_iter.next().unwrap();
-                let b3 = bytes_iter.next().unwrap();
-                let b4 = bytes_iter.next().unwrap();
-                let b5 = bytes_iter.next().unwrap();
-                let b6 = bytes_iter.next().unwrap();
-                let b7 = bytes_iter.next().unwrap();
-                let b8 = bytes_iter.next().unwrap();
-                let hash = u64::from_be_bytes([b1, b2, b3, b4, b5, b6, b7, b8]);
-                post.push((c_id, hash));
-            }
-            SyncRequirements { pre, post }
-        };
-        // if part_no == 0 && total_parts>0 {
-        //     let mut hashes_vec = Vec::with_capacity(total_parts as usize);
-        //     for _i in 0..total_parts{
-        //         let b1 = bytes_iter.next().unwrap();
-        //         let b2 = bytes_iter.next().unwrap();
-        //         let b3 = bytes_iter.next().unwrap();
-        //         let b4 = bytes_iter.next().unwrap();
-        //         let b5 = bytes_iter.next().unwrap();
-        //         let b6 = bytes_iter.next().unwrap();
-        //         let b7 = bytes_iter.next().unwrap();
-        //         let b8 = bytes_iter.next().unwrap();
-        //         let hash = u64::from_be_bytes([b1,b2,b3,b4,b5,b6,b7,b8]);
-        //         hashes_vec.push(hash);
-        //     }
-        //     part_hashes = Some(hashes_vec);
-
-        // }
-        let bytes: Vec<u8> = bytes_iter.collect();
-        // eprintln!("m_type: {:?}", m_type);
-        // eprintln!("pre_req: {:?}", requirements.pre);
-        // eprintln!("post_req: {:?}", requirements.post);
-        // eprintln!("Bytes size: {}", bytes.len());
-        let data = Data::new(bytes).unwrap();
+        if let Some(msg_hash) = msg_hash {
+            if message_hash(&m_type, &payload) != msg_hash {
+                eprintln!("Assembled message does not match it's hash");
+                return Err(());
+            }
+        }
+        let mut bytes_iter = payload.into_iter();
+        let requirements = SyncRequirements::from(&mut bytes_iter).ok_or(())?;
+        let data = Data::new(bytes_iter.collect()).map_err(|_b| ())?;
         Ok(SyncMessage {
             m_type,
             requirements,
@@ -570,3 +675,124 @@ impl SyncMessage {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(bytes: &[u8]) -> Data {
+        Data::new(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn tx_operations_round_trip() {
+        let ops = vec![
+            TxOperation::AppendData(1, data(b"appended")),
+            TxOperation::RemoveData(2, 7),
+            TxOperation::UpdateData(3, 0, data(b"updated")),
+            TxOperation::InsertData(65535, 4, data(b"inserted")),
+        ];
+        let read = TxOperation::from_data(&TxOperation::to_data(&ops).unwrap()).unwrap();
+        assert_eq!(read.len(), ops.len());
+        assert!(matches!(&read[0], TxOperation::AppendData(1, d) if d.ref_bytes() == b"appended"));
+        assert!(matches!(read[1], TxOperation::RemoveData(2, 7)));
+        assert!(
+            matches!(&read[2], TxOperation::UpdateData(3, 0, d) if d.ref_bytes() == b"updated")
+        );
+        assert!(
+            matches!(&read[3], TxOperation::InsertData(65535, 4, d) if d.ref_bytes() == b"inserted")
+        );
+    }
+
+    #[test]
+    fn malformed_tx_operations_are_rejected() {
+        let ops = vec![TxOperation::UpdateData(3, 0, data(b"updated"))];
+        let mut bytes = TxOperation::to_data(&ops).unwrap().bytes();
+        bytes.pop();
+        assert!(TxOperation::from_data(&data(&bytes)).is_none());
+        // Unknown kind
+        assert!(TxOperation::from_data(&data(&[7, 0, 1, 0, 0, 0, 1, 9])).is_none());
+    }
+
+    #[test]
+    fn message_types_are_read_back() {
+        let types = [
+            SyncMessageType::AppendContent(DataType::from(3)),
+            SyncMessageType::ChangeContent(
+                5,
+                DataType::from(1),
+                ChangeContentOperation::DropAndAppend(2),
+            ),
+            SyncMessageType::AppendData(5),
+            SyncMessageType::AppendShelledDatas(5),
+            SyncMessageType::RemoveData(5, 6),
+            SyncMessageType::UpdateData(5, 6),
+            SyncMessageType::InsertData(5, 6),
+            SyncMessageType::ExtendData(5, 6),
+            SyncMessageType::Transaction(5),
+            SyncMessageType::AppDefined(MAX_AVAIL_APP_MSG_ID, 5, 6),
+        ];
+        for m_type in types {
+            let bytes = m_type.as_bytes();
+            let read = SyncMessageType::try_from(&mut bytes.iter().copied()).unwrap();
+            assert_eq!(read.as_bytes(), bytes);
+            assert_eq!(SyncMessageType::new(&mut bytes.clone()).as_bytes(), bytes);
+            // Truncated bytes never panic
+            let short = &bytes[..bytes.len() - 1];
+            assert!(SyncMessageType::try_from(&mut short.iter().copied()).is_none());
+        }
+        assert!(SyncMessageType::try_from(&mut [253, 0, 1, 0, 3].into_iter()).is_none());
+    }
+
+    #[test]
+    fn single_part_message_round_trip() {
+        let requirements = SyncRequirements {
+            pre: vec![(1, 11)],
+            post: vec![(1, 12)],
+        };
+        let msg = SyncMessage::new(SyncMessageType::UpdateData(1, 0), requirements, data(b"x"));
+        let parts = msg.into_parts();
+        assert_eq!(parts.len(), 1);
+        let mut hm = HashMap::new();
+        hm.insert(0, data(parts[0].ref_bytes()));
+        let read = SyncMessage::from_data(vec![0], hm).unwrap();
+        assert_eq!(read.m_type.as_bytes(), vec![250, 0, 1, 0, 0]);
+        assert_eq!(read.requirements.pre, vec![(1, 11)]);
+        assert_eq!(read.requirements.post, vec![(1, 12)]);
+        assert_eq!(read.data.ref_bytes(), b"x");
+    }
+
+    #[test]
+    fn requirement_sets_have_to_match_changed_contents() {
+        let requirements = SyncRequirements {
+            pre: vec![(1, 0), (2, 0), (4, 0)],
+            post: vec![(1, 0), (3, 0)],
+        };
+        assert!(requirements.check_sets(&HashSet::from([1])).is_err());
+        assert_eq!(
+            requirements.check_sets(&HashSet::from([1, 2])),
+            Err(RequirementsViolation {
+                undeclared: vec![2],
+                unchanged: vec![3],
+                unread: vec![],
+            })
+        );
+        let requirements = SyncRequirements {
+            pre: vec![(1, 0), (4, 0)],
+            post: vec![(1, 0), (3, 0)],
+        };
+        assert_eq!(
+            requirements.check_sets(&HashSet::from([1, 3])),
+            Err(RequirementsViolation {
+                undeclared: vec![],
+                unchanged: vec![],
+                unread: vec![3],
+            })
+        );
+        let requirements = SyncRequirements {
+            pre: vec![(1, 0), (3, 0), (4, 0)],
+            post: vec![(1, 0), (3, 0)],
+        };
+        assert!(requirements.check_sets(&HashSet::from([1, 3])).is_ok());
+    }
+}
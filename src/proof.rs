@@ -0,0 +1,674 @@
+use crate::assembly::message_hash;
+use crate::catalogue::CatalogueError;
+use crate::changed_c_ids;
+use crate::content::double_hash;
+use crate::content::ContentID;
+use crate::message::RequirementsViolation;
+use crate::message::SyncMessage;
+use crate::message::SyncMessageType;
+use crate::message::SyncRequirements;
+use crate::message::TxOperation;
+use crate::AppDefinedMsg;
+use crate::Data;
+use gnome::prelude::sha_hash;
+use gnome::prelude::GnomeId;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::time::Duration;
+use std::time::Instant;
+
+// A Proof of wrongdoing (see notes at the bottom of lib.rs) is built by a Gnome
+// that holds Data pages required to show that a message should not have been
+// accepted. It consists of offending message, it's signer and those pages,
+// each with sibling hashes leading from it up to Content's root hash.
+// Every page has to be included in a Content root hash declared in message's
+// pre requirements, so any Gnome that only stores root hashes can verify it.
+//
+// Some offenses can be verified by this library:
+// - message's requirements do not list exactly the Contents it changes,
+// - post requirements do not match what we get after applying UpdateData
+//   to included page,
+// - AppDefined message does not match Manifest's catalogue.
+// If none of above is found, but all pages are included, application has
+// to run it's own validation.
+//
+// Offender is only claimed by Proof's builder. Until Proofs include
+// offender's original signed part or header, so that it can be checked
+// against offender's pubkey, anyone could blame any Gnome.
+// Gnome does not expose signatures of received messages, so for now
+// Offenses we find are only logged and not reported to App.
+//
+// Every Gnome that applies an offending message can build the same Proof.
+// In order not to flood Swarm, Proofs we build are held for a random backoff
+// and dropped if a Proof of the same message was received in the meantime.
+//
+// Proofs are broadcast through Swarm as AppDefined messages of PROOF_MSG_ID type,
+// so they do not land on the heap but get verified by every Gnome.
+// Serialized Proof is split into parts, each part's Data starts
+// with eight bytes of Proof's hash, AppDefined CID holds part_no and total_parts,
+// counting from 1.
+pub const PROOF_MSG_ID: u8 = 246;
+const PROOF_PART_CHUNK: usize = 1016;
+const PROOF_PARTS_TTL: Duration = Duration::from_secs(300);
+const MAX_PROOF_PARTS_BYTES: usize = 256 * 1024;
+const MAX_RECEIVED_PROOFS: usize = 64;
+const PROOF_BACKOFF_MS: u64 = 30_000;
+
+#[derive(Clone, Debug)]
+pub struct PageProof {
+    pub c_id: ContentID,
+    pub d_id: u16,
+    // Can be shelled, when only it's hash is required
+    pub data: Data,
+    // Sibling hashes bottom first, true if sibling is on the left
+    pub path: Vec<(bool, u64)>,
+}
+
+impl PageProof {
+    pub fn root_hash(&self) -> u64 {
+        self.root_hash_with(self.data.get_hash())
+    }
+
+    // Root hash after page's Data was replaced with one of given hash
+    fn root_hash_with(&self, leaf_hash: u64) -> u64 {
+        self.path
+            .iter()
+            .fold(leaf_hash, |hash, (sibling_left, sibling)| {
+                if *sibling_left {
+                    double_hash(*sibling, hash)
+                } else {
+                    double_hash(hash, *sibling)
+                }
+            })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Proof {
+    // As claimed by Proof's builder, not verified
+    pub offender: GnomeId,
+    pub message: SyncMessage,
+    pub pages: Vec<PageProof>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Offense {
+    Requirements(RequirementsViolation),
+    PostMismatch(ContentID, u64, u64), // declared, computed
+    AppMsgRejected(CatalogueError),
+}
+impl fmt::Display for Offense {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Requirements(violation) => write!(f, "Requirements violated: {}", violation),
+            Self::PostMismatch(c_id, declared, computed) => write!(
+                f,
+                "CID-{} declared post hash {}, computed {}",
+                c_id, declared, computed
+            ),
+            Self::AppMsgRejected(e) => write!(f, "AppDefined message rejected: {}", e),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProofError {
+    Malformed,
+    TooLarge,
+    PageNotDeclared(ContentID),
+    PageNotIncluded(ContentID, u16),
+}
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "Proof is malformed"),
+            Self::TooLarge => write!(f, "Proof does not fit into 255 parts"),
+            Self::PageNotDeclared(c_id) => {
+                write!(f, "CID-{} is not in message's pre requirements", c_id)
+            }
+            Self::PageNotIncluded(c_id, d_id) => {
+                write!(f, "Page {}-{} is not included in declared hash", c_id, d_id)
+            }
+        }
+    }
+}
+
+impl Proof {
+    pub fn new(offender: GnomeId, message: SyncMessage, pages: Vec<PageProof>) -> Self {
+        Proof {
+            offender,
+            message,
+            pages,
+        }
+    }
+
+    /// Hash of offending message, same as used for multi part messages.
+    pub fn message_hash(&self) -> u64 {
+        let mut payload = self.message.requirements.clone().bytes();
+        payload.extend_from_slice(self.message.data.ref_bytes());
+        message_hash(&self.message.m_type, &payload)
+    }
+
+    /// Every page has to be included in Content root hash
+    /// declared in message's pre requirements.
+    pub fn check_pages(&self) -> Result<(), ProofError> {
+        for page in &self.pages {
+            let Some((_c_id, pre_hash)) = self
+                .message
+                .requirements
+                .pre
+                .iter()
+                .find(|(c_id, _hash)| *c_id == page.c_id)
+            else {
+                return Err(ProofError::PageNotDeclared(page.c_id));
+            };
+            if page.root_hash() != *pre_hash {
+                return Err(ProofError::PageNotIncluded(page.c_id, page.d_id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Offenses that can be shown with message and included pages only.
+    /// Pages should be checked first.
+    pub fn offense(&self) -> Option<Offense> {
+        let SyncMessage {
+            m_type,
+            requirements,
+            data,
+        } = &self.message;
+        if let Some(changed) = changed_c_ids(m_type, data, None) {
+            if let Err(violation) = requirements.check_sets(&changed) {
+                return Some(Offense::Requirements(violation));
+            }
+        }
+        let updates = match m_type {
+            SyncMessageType::UpdateData(c_id, d_id) => vec![(*c_id, *d_id, data.get_hash())],
+            SyncMessageType::Transaction(_c_id) => {
+                let ops = TxOperation::from_data(data)?;
+                // Only Contents changed by a single UpdateData
+                ops.iter()
+                    .filter_map(|op| match op {
+                        TxOperation::UpdateData(c_id, d_id, data)
+                            if ops.iter().filter(|other| other.c_id() == *c_id).count() == 1 =>
+                        {
+                            Some((*c_id, *d_id, data.get_hash()))
+                        }
+                        _other => None,
+                    })
+                    .collect()
+            }
+            _other => vec![],
+        };
+        for (c_id, d_id, new_hash) in updates {
+            let Some(page) = self
+                .pages
+                .iter()
+                .find(|page| page.c_id == c_id && page.d_id == d_id)
+            else {
+                continue;
+            };
+            let Some((_c_id, declared)) = requirements
+                .post
+                .iter()
+                .find(|(post_c_id, _hash)| *post_c_id == c_id)
+            else {
+                continue;
+            };
+            let computed = page.root_hash_with(new_hash);
+            if computed != *declared {
+                return Some(Offense::PostMismatch(c_id, *declared, computed));
+            }
+        }
+        None
+    }
+
+    // Proof is serialized as follows:
+    // - 8 bytes of offender's GnomeId,
+    // - message type, requirements, two bytes of Data length and Data,
+    // - one byte of pages count, then for every page:
+    //   CID, DID, 8 byte leaf hash, Data length, Data,
+    //   path length and a flag byte with hash for every path element.
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(2048);
+        bytes.extend_from_slice(&self.offender.bytes());
+        bytes.append(&mut self.message.m_type.as_bytes());
+        bytes.append(&mut self.message.requirements.clone().bytes());
+        push_data(&mut bytes, &self.message.data);
+        bytes.push(self.pages.len() as u8);
+        for page in &self.pages {
+            bytes.extend_from_slice(&page.c_id.to_be_bytes());
+            bytes.extend_from_slice(&page.d_id.to_be_bytes());
+            bytes.extend_from_slice(&page.data.get_hash().to_be_bytes());
+            push_data(&mut bytes, &page.data);
+            bytes.push(page.path.len() as u8);
+            for (sibling_left, hash) in &page.path {
+                bytes.push(*sibling_left as u8);
+                bytes.extend_from_slice(&hash.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(mut bytes: Vec<u8>) -> Result<Self, ProofError> {
+        // GnomeId and at least one byte of message type
+        if bytes.len() < 9 {
+            return Err(ProofError::Malformed);
+        }
+        let g_bytes: [u8; 8] = bytes.drain(0..8).collect::<Vec<u8>>().try_into().unwrap();
+        let offender = GnomeId::from(g_bytes);
+        let mut iter = bytes.into_iter();
+        let m_type = SyncMessageType::try_from(&mut iter).ok_or(ProofError::Malformed)?;
+        let requirements = SyncRequirements::from(&mut iter).ok_or(ProofError::Malformed)?;
+        let data = read_data(&mut iter, None).ok_or(ProofError::Malformed)?;
+        let page_count = iter.next().ok_or(ProofError::Malformed)?;
+        let mut pages = Vec::with_capacity(page_count as usize);
+        for _i in 0..page_count {
+            pages.push(read_page(&mut iter).ok_or(ProofError::Malformed)?);
+        }
+        if iter.next().is_some() {
+            return Err(ProofError::Malformed);
+        }
+        Ok(Proof::new(
+            offender,
+            SyncMessage::new(m_type, requirements, data),
+            pages,
+        ))
+    }
+
+    /// Split into AppDefined messages, to be sent to Swarm.
+    pub fn into_app_msgs(self) -> Result<Vec<AppDefinedMsg>, ProofError> {
+        let bytes = self.bytes();
+        let hash = sha_hash(&bytes);
+        let chunks: Vec<&[u8]> = bytes.chunks(PROOF_PART_CHUNK).collect();
+        if chunks.len() > u8::MAX as usize {
+            return Err(ProofError::TooLarge);
+        }
+        let total = chunks.len() as u8;
+        let mut msgs = Vec::with_capacity(chunks.len());
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let mut part = Vec::with_capacity(8 + chunk.len());
+            part.extend_from_slice(&hash.to_be_bytes());
+            part.extend_from_slice(chunk);
+            msgs.push(AppDefinedMsg {
+                m_type: PROOF_MSG_ID,
+                c_id: u16::from_be_bytes([i as u8 + 1, total]),
+                d_id: 0,
+                data: Data::new(part).unwrap(),
+            });
+        }
+        Ok(msgs)
+    }
+}
+
+fn push_data(bytes: &mut Vec<u8>, data: &Data) {
+    bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(data.ref_bytes());
+}
+
+// Empty Data is replaced with a shell of given hash
+fn read_data(iter: &mut impl Iterator<Item = u8>, hash: Option<u64>) -> Option<Data> {
+    let len = read_u16(iter)? as usize;
+    let bytes: Vec<u8> = iter.by_ref().take(len).collect();
+    if bytes.len() < len {
+        return None;
+    }
+    if bytes.is_empty() {
+        return Some(Data::empty(hash.unwrap_or(0)));
+    }
+    let data = Data::new(bytes).ok()?;
+    if hash.is_some_and(|hash| hash != data.get_hash()) {
+        return None;
+    }
+    Some(data)
+}
+
+fn read_page(iter: &mut impl Iterator<Item = u8>) -> Option<PageProof> {
+    let c_id = read_u16(iter)?;
+    let d_id = read_u16(iter)?;
+    let hash = read_u64(iter)?;
+    let data = read_data(iter, Some(hash))?;
+    let path_len = iter.next()?;
+    let mut path = Vec::with_capacity(path_len as usize);
+    for _i in 0..path_len {
+        let sibling_left = iter.next()? > 0;
+        path.push((sibling_left, read_u64(iter)?));
+    }
+    Some(PageProof {
+        c_id,
+        d_id,
+        data,
+        path,
+    })
+}
+
+fn read_u16(iter: &mut impl Iterator<Item = u8>) -> Option<u16> {
+    Some(u16::from_be_bytes([iter.next()?, iter.next()?]))
+}
+
+fn read_u64(iter: &mut impl Iterator<Item = u8>) -> Option<u64> {
+    let mut bytes = [0; 8];
+    for byte in bytes.iter_mut() {
+        *byte = iter.next()?;
+    }
+    Some(u64::from_be_bytes(bytes))
+}
+
+/// How long to hold a Proof we have built before broadcasting it.
+pub fn proof_backoff() -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(random % PROOF_BACKOFF_MS)
+}
+
+struct PartialProof {
+    total: u8,
+    parts: HashMap<u8, Vec<u8>>,
+    bytes: usize,
+    started: Instant,
+}
+
+// Collects parts of Proofs received from Swarm
+// and holds Proofs we have built until their backoff passes.
+pub struct ProofParts {
+    partials: HashMap<u64, PartialProof>,
+    bytes: usize,
+    // Offending message hashes of recently received Proofs
+    received: VecDeque<u64>,
+    held: HashMap<u64, Proof>,
+}
+
+impl ProofParts {
+    pub fn new() -> Self {
+        ProofParts {
+            partials: HashMap::new(),
+            bytes: 0,
+            received: VecDeque::with_capacity(MAX_RECEIVED_PROOFS),
+            held: HashMap::new(),
+        }
+    }
+
+    /// Returns Proof once all it's parts are collected.
+    pub fn insert(&mut self, app_msg: &AppDefinedMsg) -> Option<Result<Proof, ProofError>> {
+        let now = Instant::now();
+        let [part_no, total] = app_msg.c_id.to_be_bytes();
+        let bytes = app_msg.data.ref_bytes();
+        if part_no == 0 || part_no > total || bytes.len() < 8 {
+            return Some(Err(ProofError::Malformed));
+        }
+        let hash = u64::from_be_bytes(bytes[..8].try_into().unwrap());
+        let partial = self.partials.entry(hash).or_insert_with(|| PartialProof {
+            total,
+            parts: HashMap::with_capacity(total as usize),
+            bytes: 0,
+            started: now,
+        });
+        if partial.total != total {
+            return Some(Err(ProofError::Malformed));
+        }
+        if partial.parts.contains_key(&part_no) {
+            return None;
+        }
+        partial.parts.insert(part_no, bytes[8..].to_vec());
+        partial.bytes += bytes.len() - 8;
+        self.bytes += bytes.len() - 8;
+        if partial.parts.len() < total as usize {
+            self.evict(now);
+            return None;
+        }
+        let mut partial = self.partials.remove(&hash).unwrap();
+        self.bytes -= partial.bytes;
+        let mut proof_bytes = Vec::with_capacity(partial.bytes);
+        for part_no in 1..=total {
+            proof_bytes.append(&mut partial.parts.remove(&part_no)?);
+        }
+        if sha_hash(&proof_bytes) != hash {
+            return Some(Err(ProofError::Malformed));
+        }
+        let result = Proof::from_bytes(proof_bytes);
+        if let Ok(proof) = &result {
+            let msg_hash = proof.message_hash();
+            self.held.remove(&msg_hash);
+            if self.received.len() >= MAX_RECEIVED_PROOFS {
+                self.received.pop_front();
+            }
+            self.received.push_back(msg_hash);
+        }
+        Some(result)
+    }
+
+    // Drop partial Proofs that are too old, then oldest ones
+    // until we fit into memory budget.
+    fn evict(&mut self, now: Instant) {
+        let mut freed = 0;
+        self.partials.retain(|_hash, partial| {
+            let keep = now.duration_since(partial.started) < PROOF_PARTS_TTL;
+            if !keep {
+                freed += partial.bytes;
+            }
+            keep
+        });
+        self.bytes -= freed;
+        while self.bytes > MAX_PROOF_PARTS_BYTES {
+            let Some(oldest) = self
+                .partials
+                .iter()
+                .min_by_key(|(_hash, partial)| partial.started)
+                .map(|(hash, _partial)| *hash)
+            else {
+                break;
+            };
+            eprintln!("Evicting partial Proof {} over memory budget", oldest);
+            let partial = self.partials.remove(&oldest).unwrap();
+            self.bytes -= partial.bytes;
+        }
+    }
+
+    /// Hold a Proof we have built, returns offending message hash
+    /// unless a Proof of that message was already received or held.
+    pub fn hold(&mut self, proof: Proof) -> Option<u64> {
+        let msg_hash = proof.message_hash();
+        if self.received.contains(&msg_hash) || self.held.contains_key(&msg_hash) {
+            return None;
+        }
+        self.held.insert(msg_hash, proof);
+        Some(msg_hash)
+    }
+
+    /// Proof to broadcast once it's backoff passed,
+    /// None if someone else has broadcast it already.
+    pub fn release(&mut self, msg_hash: u64) -> Option<Proof> {
+        self.held.remove(&msg_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(bytes: &[u8]) -> Data {
+        Data::new(bytes.to_vec()).unwrap()
+    }
+
+    // Proof of an UpdateData of first page in a two page Content
+    fn update_proof(new_page: &[u8], post_page: &[u8]) -> Proof {
+        let old_page = data(b"old page");
+        let sibling = data(b"second page").get_hash();
+        let pre = double_hash(old_page.get_hash(), sibling);
+        let post = double_hash(data(post_page).get_hash(), sibling);
+        let message = SyncMessage::new(
+            SyncMessageType::UpdateData(1, 0),
+            SyncRequirements {
+                pre: vec![(1, pre)],
+                post: vec![(1, post)],
+            },
+            data(new_page),
+        );
+        let page = PageProof {
+            c_id: 1,
+            d_id: 0,
+            data: old_page,
+            path: vec![(false, sibling)],
+        };
+        Proof::new(GnomeId(7), message, vec![page])
+    }
+
+    #[test]
+    fn post_mismatch_is_found() {
+        let honest = update_proof(b"new page", b"new page");
+        assert_eq!(honest.check_pages(), Ok(()));
+        assert!(honest.offense().is_none());
+
+        let lying = update_proof(b"new page", b"other page");
+        assert_eq!(lying.check_pages(), Ok(()));
+        assert!(matches!(
+            lying.offense(),
+            Some(Offense::PostMismatch(1, _, _))
+        ));
+    }
+
+    #[test]
+    fn pages_have_to_be_declared_and_included() {
+        let mut proof = update_proof(b"new page", b"new page");
+        proof.pages[0].data = data(b"forged page");
+        assert_eq!(proof.check_pages(), Err(ProofError::PageNotIncluded(1, 0)));
+        proof.pages[0].c_id = 2;
+        assert_eq!(proof.check_pages(), Err(ProofError::PageNotDeclared(2)));
+    }
+
+    #[test]
+    fn undeclared_contents_are_an_offense() {
+        let mut proof = update_proof(b"new page", b"new page");
+        proof.message.requirements.pre.clear();
+        assert!(matches!(proof.offense(), Some(Offense::Requirements(_))));
+    }
+
+    #[test]
+    fn proof_bytes_round_trip() {
+        let mut proof = update_proof(b"new page", b"other page");
+        proof.pages.push(PageProof {
+            c_id: 1,
+            d_id: 1,
+            data: Data::empty(42),
+            path: vec![],
+        });
+        let read = Proof::from_bytes(proof.bytes()).unwrap();
+        assert_eq!(read.offender, GnomeId(7));
+        assert_eq!(
+            read.message.m_type.as_bytes(),
+            proof.message.m_type.as_bytes()
+        );
+        assert_eq!(
+            read.message.requirements.pre,
+            proof.message.requirements.pre
+        );
+        assert_eq!(
+            read.message.requirements.post,
+            proof.message.requirements.post
+        );
+        assert_eq!(read.message.data, proof.message.data);
+        assert_eq!(read.pages.len(), 2);
+        assert_eq!(read.pages[0].data, proof.pages[0].data);
+        assert_eq!(read.pages[0].path, proof.pages[0].path);
+        assert_eq!(read.pages[1].data.get_hash(), 42);
+        assert!(matches!(
+            read.offense(),
+            Some(Offense::PostMismatch(1, _, _))
+        ));
+    }
+
+    #[test]
+    fn malformed_proofs_are_rejected() {
+        let bytes = update_proof(b"new page", b"new page").bytes();
+        for len in [0, 8, 9, bytes.len() - 1] {
+            assert!(matches!(
+                Proof::from_bytes(bytes[..len].to_vec()),
+                Err(ProofError::Malformed)
+            ));
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(Proof::from_bytes(trailing).is_err());
+        // Unknown message type
+        let mut unknown = bytes;
+        unknown[8] = 253;
+        assert!(Proof::from_bytes(unknown).is_err());
+    }
+
+    #[test]
+    fn proof_is_reassembled_from_app_msgs() {
+        let mut proof = update_proof(b"new page", b"new page");
+        for d_id in 1..4 {
+            proof.pages.push(PageProof {
+                c_id: 1,
+                d_id,
+                data: data(&[d_id as u8; 1000]),
+                path: vec![],
+            });
+        }
+        let msgs = proof.into_app_msgs().unwrap();
+        assert!(msgs.len() > 1);
+        let mut parts = ProofParts::new();
+        for msg in msgs.iter().skip(1).rev() {
+            assert!(parts.insert(msg).is_none());
+        }
+        let read = parts.insert(&msgs[0]).unwrap().unwrap();
+        assert_eq!(read.pages.len(), 4);
+        assert_eq!(read.pages[3].data, data(&[3; 1000]));
+
+        let mut broken = msgs[0].clone();
+        broken.c_id = u16::from_be_bytes([0, 2]);
+        assert!(matches!(
+            parts.insert(&broken),
+            Some(Err(ProofError::Malformed))
+        ));
+    }
+
+    #[test]
+    fn partial_proofs_fit_into_memory_budget() {
+        let mut parts = ProofParts::new();
+        let chunk = Data::new([[0; 8].as_slice(), &[1; 1000]].concat()).unwrap();
+        let per_proof = chunk.len() - 8;
+        let fitting = MAX_PROOF_PARTS_BYTES / per_proof;
+        for hash in 0..fitting as u64 + 2 {
+            let mut bytes = chunk.clone().bytes();
+            bytes[..8].copy_from_slice(&hash.to_be_bytes());
+            let msg = AppDefinedMsg {
+                m_type: PROOF_MSG_ID,
+                c_id: u16::from_be_bytes([1, 2]),
+                d_id: 0,
+                data: Data::new(bytes).unwrap(),
+            };
+            assert!(parts.insert(&msg).is_none());
+            // Same part again is ignored
+            assert!(parts.insert(&msg).is_none());
+        }
+        assert!(parts.bytes <= MAX_PROOF_PARTS_BYTES);
+        assert_eq!(parts.partials.len(), fitting);
+        // Oldest were evicted first
+        assert!(!parts.partials.contains_key(&0));
+        assert!(parts.partials.contains_key(&(fitting as u64 + 1)));
+    }
+
+    #[test]
+    fn held_proof_is_dropped_once_received() {
+        let proof = update_proof(b"new page", b"other page");
+        let msg_hash = proof.message_hash();
+        let mut parts = ProofParts::new();
+        assert_eq!(parts.hold(proof.clone()), Some(msg_hash));
+        assert_eq!(parts.hold(proof.clone()), None);
+        assert!(parts.release(msg_hash).is_some());
+        assert!(parts.release(msg_hash).is_none());
+
+        assert_eq!(parts.hold(proof.clone()), Some(msg_hash));
+        for msg in proof.clone().into_app_msgs().unwrap() {
+            parts.insert(&msg);
+        }
+        assert!(parts.release(msg_hash).is_none());
+        assert_eq!(parts.hold(proof), None);
+        assert!(proof_backoff() < Duration::from_millis(PROOF_BACKOFF_MS));
+    }
+}
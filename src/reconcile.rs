@@ -0,0 +1,465 @@
+use crate::error::AppError;
+use crate::manifest::Manifest;
+use gnome::prelude::ByteSet;
+use gnome::prelude::Capabilities;
+use gnome::prelude::GnomeId;
+use gnome::prelude::Policy;
+use gnome::prelude::Requirement;
+use gnome::prelude::SwarmID;
+use gnome::prelude::SwarmName;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+// Manifest holds Policies, Capabilities and ByteSets a Swarm should run with.
+// Swarm's running settings can diverge from those, for example when Manifest
+// was updated, but nobody issued Reconfigure messages afterwards.
+//
+// Reconciliation collects Manifest and all three running settings
+// of a single Swarm, then compares them.
+// Only entries defined in Manifest are compared, running settings
+// not mentioned in Manifest are left as they are.
+// Every difference becomes a SettingChange, those that our Capabilities
+// allow are sent to Gnome as SetRunning* reconfigurations, unless
+// we were asked for a dry run, in which case we only report them.
+//
+// Running settings can be changed by Swarm's Founder and by Owners,
+// but only Founder can change who is an Owner or a Founder.
+//
+// Gnome does not tell which Swarm running settings it sends belong to,
+// so Reconciler asks for running settings of only one Swarm at a time.
+// Requests for other Swarms are queued, at most one per SwarmName.
+// Active Reconciliation is dropped when it's Swarm disconnects,
+// or when it did not receive everything within RECONCILE_TIMEOUT,
+// so that a lost response does not block following ones.
+pub const RECONCILE_TIMEOUT: Duration = Duration::from_secs(10);
+#[derive(Clone, Debug)]
+pub enum SettingChange {
+    Policy(Policy, Requirement),
+    Capability(Capabilities, Vec<GnomeId>),
+    ByteSet(u8, ByteSet),
+}
+
+#[derive(Debug)]
+pub struct ReconcileReport {
+    pub s_name: SwarmName,
+    pub dry_run: bool,
+    /// Changes required to match Manifest that we are allowed to make
+    pub allowed: Vec<SettingChange>,
+    /// Changes required to match Manifest that our Capabilities do not allow
+    pub denied: Vec<SettingChange>,
+}
+
+pub struct Reconciliation {
+    pub s_id: SwarmID,
+    pub s_name: SwarmName,
+    pub dry_run: bool,
+    // Outer Option is None until App data responded
+    manifest: Option<Option<Manifest>>,
+    policies: Option<Vec<(Policy, Requirement)>>,
+    capabilities: Option<Vec<(Capabilities, Vec<GnomeId>)>>,
+    byte_sets: Option<Vec<(u8, ByteSet)>>,
+}
+
+impl Reconciliation {
+    pub fn new(s_id: SwarmID, s_name: SwarmName, dry_run: bool) -> Self {
+        Reconciliation {
+            s_id,
+            s_name,
+            dry_run,
+            manifest: None,
+            policies: None,
+            capabilities: None,
+            byte_sets: None,
+        }
+    }
+
+    pub fn set_manifest(&mut self, manifest: Option<Manifest>) {
+        self.manifest = Some(manifest);
+    }
+
+    /// Returns false if running Policies were already received,
+    /// so those should go to the App instead.
+    pub fn set_policies(&mut self, policies: &[(Policy, Requirement)]) -> bool {
+        if self.policies.is_some() {
+            return false;
+        }
+        self.policies = Some(policies.to_vec());
+        true
+    }
+
+    pub fn set_capabilities(&mut self, capabilities: &[(Capabilities, Vec<GnomeId>)]) -> bool {
+        if self.capabilities.is_some() {
+            return false;
+        }
+        self.capabilities = Some(capabilities.to_vec());
+        true
+    }
+
+    pub fn set_byte_sets(&mut self, byte_sets: &[(u8, ByteSet)]) -> bool {
+        if self.byte_sets.is_some() {
+            return false;
+        }
+        self.byte_sets = Some(byte_sets.to_vec());
+        true
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.manifest.is_some()
+            && self.policies.is_some()
+            && self.capabilities.is_some()
+            && self.byte_sets.is_some()
+    }
+
+    /// Compare Manifest with running settings and decide which
+    /// of required changes given Gnome is allowed to make.
+    pub fn report(self, my_id: GnomeId) -> Result<ReconcileReport, AppError> {
+        let Some(Some(manifest)) = self.manifest else {
+            return Err(AppError::AppDataNotSynced);
+        };
+        let policies = self.policies.unwrap_or_default();
+        let capabilities = self.capabilities.unwrap_or_default();
+        let byte_sets = self.byte_sets.unwrap_or_default();
+        let is_founder = self.s_name.founder.0 == my_id.0;
+        let is_owner = capabilities.iter().any(|(cap, g_ids)| {
+            *cap == Capabilities::Owner && g_ids.iter().any(|g_id| g_id.0 == my_id.0)
+        });
+        let mut allowed = vec![];
+        let mut denied = vec![];
+        for change in diff(&manifest, &policies, &capabilities, &byte_sets) {
+            let is_allowed = match &change {
+                SettingChange::Capability(Capabilities::Founder, _g_ids)
+                | SettingChange::Capability(Capabilities::Owner, _g_ids) => is_founder,
+                _other => is_founder || is_owner,
+            };
+            if is_allowed {
+                allowed.push(change);
+            } else {
+                denied.push(change);
+            }
+        }
+        Ok(ReconcileReport {
+            s_name: self.s_name,
+            dry_run: self.dry_run,
+            allowed,
+            denied,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Reconciler {
+    active: Option<(Reconciliation, Instant)>,
+    queued: VecDeque<(SwarmName, bool)>,
+}
+
+impl Reconciler {
+    /// Queue a Reconciliation of given Swarm,
+    /// returns false if that Swarm is already being reconciled or queued.
+    pub fn request(&mut self, s_name: SwarmName, dry_run: bool) -> bool {
+        let is_active = self
+            .active
+            .as_ref()
+            .is_some_and(|(rec, _started)| rec.s_name == s_name);
+        if is_active || self.queued.iter().any(|(q_name, _d)| *q_name == s_name) {
+            return false;
+        }
+        self.queued.push_back((s_name, dry_run));
+        true
+    }
+
+    /// Next queued Swarm to reconcile, if none is active.
+    pub fn next(&mut self) -> Option<(SwarmName, bool)> {
+        if self.active.is_some() {
+            return None;
+        }
+        self.queued.pop_front()
+    }
+
+    pub fn start(&mut self, rec: Reconciliation) {
+        self.active = Some((rec, Instant::now()));
+    }
+
+    pub fn set_manifest(&mut self, s_id: SwarmID, manifest: Option<Manifest>) -> bool {
+        match self.active.as_mut() {
+            Some((rec, _started)) if rec.s_id == s_id => {
+                rec.set_manifest(manifest);
+                true
+            }
+            _other => false,
+        }
+    }
+
+    pub fn set_policies(&mut self, policies: &[(Policy, Requirement)]) -> bool {
+        self.active
+            .as_mut()
+            .is_some_and(|(rec, _started)| rec.set_policies(policies))
+    }
+
+    pub fn set_capabilities(&mut self, capabilities: &[(Capabilities, Vec<GnomeId>)]) -> bool {
+        self.active
+            .as_mut()
+            .is_some_and(|(rec, _started)| rec.set_capabilities(capabilities))
+    }
+
+    pub fn set_byte_sets(&mut self, byte_sets: &[(u8, ByteSet)]) -> bool {
+        self.active
+            .as_mut()
+            .is_some_and(|(rec, _started)| rec.set_byte_sets(byte_sets))
+    }
+
+    /// Active Reconciliation, once it has everything it needs.
+    pub fn take_ready(&mut self) -> Option<Reconciliation> {
+        if !self
+            .active
+            .as_ref()
+            .is_some_and(|(rec, _started)| rec.is_ready())
+        {
+            return None;
+        }
+        self.active.take().map(|(rec, _started)| rec)
+    }
+
+    /// Active Reconciliation of given Swarm, if it is running
+    /// for at least RECONCILE_TIMEOUT.
+    pub fn take_expired(&mut self, s_name: &SwarmName) -> Option<SwarmName> {
+        let expired = self.active.as_ref().is_some_and(|(rec, started)| {
+            rec.s_name == *s_name && started.elapsed() >= RECONCILE_TIMEOUT
+        });
+        if !expired {
+            return None;
+        }
+        self.active.take().map(|(rec, _started)| rec.s_name)
+    }
+
+    /// Given Swarm is gone, returns names of dropped Reconciliations.
+    pub fn swarm_gone(&mut self, s_name: &SwarmName) -> Vec<SwarmName> {
+        let mut dropped = vec![];
+        self.queued.retain(|(q_name, _d)| {
+            if q_name == s_name {
+                dropped.push(q_name.clone());
+                false
+            } else {
+                true
+            }
+        });
+        if self
+            .active
+            .as_ref()
+            .is_some_and(|(rec, _started)| rec.s_name == *s_name)
+        {
+            if let Some((rec, _started)) = self.active.take() {
+                dropped.push(rec.s_name);
+            }
+        }
+        dropped
+    }
+}
+
+// Requirements and ByteSets are compared by their serialized form,
+// the same one that is stored in Manifest.
+fn diff(
+    manifest: &Manifest,
+    policies: &[(Policy, Requirement)],
+    capabilities: &[(Capabilities, Vec<GnomeId>)],
+    byte_sets: &[(u8, ByteSet)],
+) -> Vec<SettingChange> {
+    let mut changes = vec![];
+    for (pol, req) in &manifest.policy_reg {
+        let running = policies.iter().find(|(r_pol, _r_req)| r_pol == pol);
+        let up_to_date = running.is_some_and(|(_r_pol, r_req)| {
+            let mut r_bytes = vec![];
+            r_req.append_bytes_to(&mut r_bytes);
+            let mut m_bytes = vec![];
+            req.append_bytes_to(&mut m_bytes);
+            r_bytes == m_bytes
+        });
+        if !up_to_date {
+            changes.push(SettingChange::Policy(pol.clone(), req.clone()));
+        }
+    }
+    for (cap, c_tree) in &manifest.capability_reg {
+        let mut m_gnomes = c_tree.get_all_members();
+        m_gnomes.sort_by_key(|g_id| g_id.0);
+        let running = capabilities.iter().find(|(r_cap, _g_ids)| r_cap == cap);
+        let up_to_date = running.is_some_and(|(_r_cap, r_gnomes)| {
+            let mut r_ids: Vec<u64> = r_gnomes.iter().map(|g_id| g_id.0).collect();
+            r_ids.sort();
+            r_ids == m_gnomes.iter().map(|g_id| g_id.0).collect::<Vec<u64>>()
+        });
+        if !up_to_date {
+            changes.push(SettingChange::Capability(cap.clone(), m_gnomes));
+        }
+    }
+    for (b_id, bset) in &manifest.byteset_reg {
+        let running = byte_sets.iter().find(|(r_id, _r_set)| r_id == b_id);
+        let up_to_date = running.is_some_and(|(_r_id, r_set)| {
+            r_set.is_pair() == bset.is_pair() && r_set.bytes() == bset.bytes()
+        });
+        if !up_to_date {
+            changes.push(SettingChange::ByteSet(*b_id, bset.clone()));
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_type::AppType;
+    use gnome::prelude::CapabiLeaf;
+    use std::collections::HashMap;
+    use std::collections::HashSet;
+
+    fn swarm_name(founder: u64) -> SwarmName {
+        SwarmName::new(GnomeId(founder), "/reconcile".to_string()).unwrap()
+    }
+
+    fn c_tree(g_ids: &[u64]) -> CapabiLeaf {
+        let mut c_tree = CapabiLeaf::create();
+        for g_id in g_ids {
+            c_tree.insert(GnomeId(*g_id));
+        }
+        c_tree
+    }
+
+    fn manifest() -> Manifest {
+        let mut manifest = Manifest::new(AppType::Forum, HashMap::new());
+        manifest
+            .capability_reg
+            .insert(Capabilities::Owner, c_tree(&[2, 1]));
+        manifest
+            .capability_reg
+            .insert(Capabilities::Admin, c_tree(&[3]));
+        manifest
+            .byteset_reg
+            .insert(1, ByteSet::new(HashSet::from([1, 2])));
+        manifest
+            .byteset_reg
+            .insert(2, ByteSet::new(HashSet::from([3])));
+        manifest
+    }
+
+    fn running_capabilities() -> Vec<(Capabilities, Vec<GnomeId>)> {
+        vec![
+            (Capabilities::Owner, vec![GnomeId(1), GnomeId(2)]),
+            (Capabilities::Admin, vec![GnomeId(4)]),
+            // Not mentioned in Manifest, left as it is
+            (Capabilities::Moderator, vec![GnomeId(5)]),
+        ]
+    }
+
+    fn reconciliation(founder: u64, manifest: Option<Manifest>) -> Reconciliation {
+        let mut rec = Reconciliation::new(SwarmID(1), swarm_name(founder), false);
+        rec.set_manifest(manifest);
+        assert!(!rec.is_ready());
+        assert!(rec.set_policies(&[]));
+        assert!(rec.set_capabilities(&running_capabilities()));
+        assert!(rec.set_byte_sets(&[(1, ByteSet::new(HashSet::from([2, 1])))]));
+        assert!(rec.is_ready());
+        rec
+    }
+
+    #[test]
+    fn diff_lists_only_settings_that_differ() {
+        let byte_sets = vec![(1, ByteSet::new(HashSet::from([2, 1])))];
+        let changes = diff(&manifest(), &[], &running_capabilities(), &byte_sets);
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().any(|change| matches!(
+            change,
+            SettingChange::Capability(Capabilities::Admin, g_ids) if g_ids == &vec![GnomeId(3)]
+        )));
+        assert!(changes
+            .iter()
+            .any(|change| matches!(change, SettingChange::ByteSet(2, _bset))));
+
+        let mut with_policy = manifest();
+        with_policy
+            .policy_reg
+            .insert(Policy::Data, Requirement::None);
+        let changes = diff(&with_policy, &[], &running_capabilities(), &byte_sets);
+        assert!(changes
+            .iter()
+            .any(|change| matches!(change, SettingChange::Policy(Policy::Data, _req))));
+    }
+
+    #[test]
+    fn only_founder_changes_owners() {
+        let mut manifest = manifest();
+        manifest
+            .capability_reg
+            .insert(Capabilities::Owner, c_tree(&[2, 6]));
+
+        let report = reconciliation(9, Some(manifest.clone()))
+            .report(GnomeId(9))
+            .unwrap();
+        assert_eq!((report.allowed.len(), report.denied.len()), (3, 0));
+
+        // An Owner can change Admins and ByteSets, but not Owners
+        let report = reconciliation(9, Some(manifest.clone()))
+            .report(GnomeId(2))
+            .unwrap();
+        assert_eq!((report.allowed.len(), report.denied.len()), (2, 1));
+        assert!(matches!(
+            report.denied[0],
+            SettingChange::Capability(Capabilities::Owner, _)
+        ));
+
+        let report = reconciliation(9, Some(manifest))
+            .report(GnomeId(7))
+            .unwrap();
+        assert_eq!((report.allowed.len(), report.denied.len()), (0, 3));
+
+        assert!(reconciliation(9, None).report(GnomeId(9)).is_err());
+    }
+
+    #[test]
+    fn reconciliations_are_queued_one_per_swarm() {
+        let mut reconciler = Reconciler::default();
+        assert!(reconciler.request(swarm_name(1), false));
+        assert!(!reconciler.request(swarm_name(1), true));
+        assert!(reconciler.request(swarm_name(2), true));
+        let (s_name, dry_run) = reconciler.next().unwrap();
+        assert!(!dry_run);
+        reconciler.start(Reconciliation::new(SwarmID(1), s_name, dry_run));
+        assert!(reconciler.next().is_none());
+        // Active one can not be queued again
+        assert!(!reconciler.request(swarm_name(1), false));
+
+        assert!(!reconciler.set_manifest(SwarmID(2), None));
+        assert!(reconciler.set_manifest(SwarmID(1), Some(manifest())));
+        assert!(reconciler.set_policies(&[]));
+        // Second listing is not ours, it goes to the App
+        assert!(!reconciler.set_policies(&[]));
+        assert!(reconciler.set_capabilities(&[]));
+        assert!(reconciler.take_ready().is_none());
+        assert!(reconciler.set_byte_sets(&[]));
+        let rec = reconciler.take_ready().unwrap();
+        assert_eq!(rec.s_name, swarm_name(1));
+
+        assert_eq!(reconciler.next().unwrap().0, swarm_name(2));
+        assert!(reconciler.next().is_none());
+        assert!(!reconciler.set_policies(&[]));
+    }
+
+    #[test]
+    fn stale_reconciliations_are_dropped() {
+        let mut reconciler = Reconciler::default();
+        reconciler.start(Reconciliation::new(SwarmID(1), swarm_name(1), false));
+        assert!(reconciler.take_expired(&swarm_name(1)).is_none());
+        if let Some((_rec, started)) = reconciler.active.as_mut() {
+            *started = Instant::now() - RECONCILE_TIMEOUT;
+        }
+        assert!(reconciler.take_expired(&swarm_name(2)).is_none());
+        assert_eq!(reconciler.take_expired(&swarm_name(1)), Some(swarm_name(1)));
+        assert!(reconciler.next().is_none());
+
+        assert!(reconciler.request(swarm_name(2), false));
+        assert!(reconciler.request(swarm_name(3), false));
+        let (s_name, dry_run) = reconciler.next().unwrap();
+        reconciler.start(Reconciliation::new(SwarmID(2), s_name, dry_run));
+        assert_eq!(reconciler.swarm_gone(&swarm_name(2)), vec![swarm_name(2)]);
+        assert_eq!(reconciler.swarm_gone(&swarm_name(3)), vec![swarm_name(3)]);
+        assert!(reconciler.next().is_none());
+    }
+}
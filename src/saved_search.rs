@@ -0,0 +1,312 @@
+use crate::prelude::DataType;
+use crate::search::Score;
+use crate::util::now;
+use crate::ContentID;
+use gnome::prelude::sha_hash;
+use gnome::prelude::SwarmName;
+
+// Permanent searches are stored one file per Query,
+// named by decimal sha_hash of Query's text.
+//
+// Originally such a file contained only raw Query text,
+// we call it version 0 and still read it.
+// Since Query was appended to that file every time it was made permanent,
+// it can contain the same text repeated multiple times.
+//
+// Version 1 layout:
+// 3 bytes - "DSQ"
+// 1 byte  - version
+// 1 byte  - options: bit 0 - is permanent, bit 1 - terms were stemmed
+// 8 bytes - created, seconds since UNIX epoch
+// 8 bytes - last run, seconds since UNIX epoch
+// 2 bytes - text len
+// this many bytes with Query text
+// 4 bytes - hits count
+// for every hit:
+// 2 bytes - SwarmName bytes len
+// this many bytes with SwarmName
+// 2 bytes - ContentID
+// 1 byte  - 1 if Content hash is known, 0 otherwise
+// 1 byte  - DataType
+// 8 bytes - Content root hash
+// 4 bytes - Score value
+// 1 byte  - matched terms
+// 1 byte  - total terms
+// 1 byte  - 1 if entire Query was found as a phrase
+//
+// Filters are not stored separately, they are parsed again from Query text.
+const MAGIC: &[u8; 3] = b"DSQ";
+const VERSION: u8 = 1;
+const PERMANENT: u8 = 0b01;
+const STEMMED: u8 = 0b10;
+
+pub struct SavedSearch {
+    pub version: u8,
+    pub text: String,
+    pub is_permanent: bool,
+    pub stemming: bool,
+    pub created: u64,
+    pub last_run: u64,
+    pub hits: Vec<SavedHit>,
+}
+
+pub struct SavedHit {
+    pub s_name: SwarmName,
+    pub c_id: ContentID,
+    // Content hash at the time Hit was found
+    pub c_hash: Option<(DataType, u64)>,
+    pub score: Score,
+}
+
+impl SavedSearch {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(64 + self.hits.len() * 64);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        let mut options = 0;
+        if self.is_permanent {
+            options |= PERMANENT;
+        }
+        if self.stemming {
+            options |= STEMMED;
+        }
+        bytes.push(options);
+        bytes.extend_from_slice(&self.created.to_be_bytes());
+        bytes.extend_from_slice(&self.last_run.to_be_bytes());
+        // Query::parse rejects longer texts,
+        // we still make sure not to split a UTF-8 sequence
+        let mut text_len = self.text.len().min(u16::MAX as usize);
+        while !self.text.is_char_boundary(text_len) {
+            text_len -= 1;
+        }
+        bytes.extend_from_slice(&(text_len as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.text.as_bytes()[..text_len]);
+        bytes.extend_from_slice(&(self.hits.len() as u32).to_be_bytes());
+        for hit in &self.hits {
+            let name_bytes = hit.s_name.as_bytes();
+            bytes.extend_from_slice(&(name_bytes.len() as u16).to_be_bytes());
+            bytes.extend(name_bytes);
+            bytes.extend_from_slice(&hit.c_id.to_be_bytes());
+            if let Some((d_type, c_hash)) = hit.c_hash {
+                bytes.push(1);
+                bytes.push(d_type.byte());
+                bytes.extend_from_slice(&c_hash.to_be_bytes());
+            } else {
+                bytes.push(0);
+                bytes.push(0);
+                bytes.extend_from_slice(&0u64.to_be_bytes());
+            }
+            bytes.extend_from_slice(&hit.score.value.to_be_bytes());
+            bytes.push(hit.score.matched);
+            bytes.push(hit.score.total);
+            bytes.push(if hit.score.phrase { 1 } else { 0 });
+        }
+        bytes
+    }
+
+    /// File name is required to read a version 0 file.
+    pub fn from_bytes(bytes: Vec<u8>, f_name: &str) -> Option<Self> {
+        if !bytes.starts_with(MAGIC) {
+            return Self::from_legacy(bytes, f_name);
+        }
+        let mut iter = bytes.into_iter().skip(MAGIC.len());
+        let version = iter.next()?;
+        if version != VERSION {
+            eprintln!("Unsupported saved search version: {}", version);
+            return None;
+        }
+        let options = iter.next()?;
+        let created = read_u64(&mut iter)?;
+        let last_run = read_u64(&mut iter)?;
+        let text_len = read_u16(&mut iter)?;
+        let text = read_string(&mut iter, text_len as usize)?;
+        let count = u32::from_be_bytes([iter.next()?, iter.next()?, iter.next()?, iter.next()?]);
+        let mut hits = Vec::with_capacity(count as usize);
+        for _i in 0..count {
+            let name_len = read_u16(&mut iter)?;
+            let mut name_bytes = Vec::with_capacity(name_len as usize);
+            for _j in 0..name_len {
+                name_bytes.push(iter.next()?);
+            }
+            let s_name = SwarmName::from(&name_bytes).ok()?;
+            let c_id = read_u16(&mut iter)?;
+            let has_hash = iter.next()? == 1;
+            let d_type = DataType::from(iter.next()?);
+            let c_hash = read_u64(&mut iter)?;
+            let value =
+                f32::from_be_bytes([iter.next()?, iter.next()?, iter.next()?, iter.next()?]);
+            let score = Score {
+                value,
+                matched: iter.next()?,
+                total: iter.next()?,
+                phrase: iter.next()? == 1,
+            };
+            hits.push(SavedHit {
+                s_name,
+                c_id,
+                c_hash: if has_hash {
+                    Some((d_type, c_hash))
+                } else {
+                    None
+                },
+                score,
+            });
+        }
+        Some(SavedSearch {
+            version,
+            text,
+            is_permanent: options & PERMANENT > 0,
+            stemming: options & STEMMED > 0,
+            created,
+            last_run,
+            hits,
+        })
+    }
+
+    // Version 0 file only contains Query text, possibly repeated,
+    // we find which repetition has the same hash as file's name.
+    fn from_legacy(bytes: Vec<u8>, f_name: &str) -> Option<Self> {
+        let text = String::from_utf8(bytes).ok()?;
+        let len = text.len();
+        let mut phrase = text.as_str();
+        if let Ok(f_hash) = f_name.parse::<u64>() {
+            for period in 1..=len / 2 {
+                if len % period == 0
+                    && text.is_char_boundary(period)
+                    && text[..period].repeat(len / period) == text
+                    && sha_hash(text[..period].trim().as_bytes()) == f_hash
+                {
+                    phrase = &text[..period];
+                    break;
+                }
+            }
+        }
+        let phrase = phrase.trim().to_string();
+        if phrase.is_empty() {
+            return None;
+        }
+        Some(SavedSearch {
+            version: 0,
+            text: phrase,
+            is_permanent: true,
+            stemming: false,
+            created: now(),
+            last_run: 0,
+            hits: vec![],
+        })
+    }
+}
+
+fn read_u16(iter: &mut impl Iterator<Item = u8>) -> Option<u16> {
+    Some(u16::from_be_bytes([iter.next()?, iter.next()?]))
+}
+
+fn read_u64(iter: &mut impl Iterator<Item = u8>) -> Option<u64> {
+    let mut bytes = [0u8; 8];
+    for byte in bytes.iter_mut() {
+        *byte = iter.next()?;
+    }
+    Some(u64::from_be_bytes(bytes))
+}
+
+fn read_string(iter: &mut impl Iterator<Item = u8>, len: usize) -> Option<String> {
+    let mut bytes = Vec::with_capacity(len);
+    for _i in 0..len {
+        bytes.push(iter.next()?);
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gnome::prelude::GnomeId;
+
+    fn hit(c_id: ContentID, c_hash: Option<(DataType, u64)>) -> SavedHit {
+        SavedHit {
+            s_name: SwarmName::new(GnomeId(7), "/music".to_string()).unwrap(),
+            c_id,
+            c_hash,
+            score: Score {
+                value: 1.5,
+                matched: 2,
+                total: 3,
+                phrase: c_hash.is_some(),
+            },
+        }
+    }
+
+    #[test]
+    fn saved_search_round_trip() {
+        let saved = SavedSearch {
+            version: VERSION,
+            text: "żółw \"slow animals\" tag:nature".to_string(),
+            is_permanent: true,
+            stemming: true,
+            created: 1_700_000_000,
+            last_run: 1_700_000_100,
+            hits: vec![hit(3, Some((DataType::from(2), 99))), hit(5, None)],
+        };
+        let read = SavedSearch::from_bytes(saved.to_bytes(), "any").unwrap();
+        assert_eq!(read.version, VERSION);
+        assert_eq!(read.text, saved.text);
+        assert!(read.is_permanent && read.stemming);
+        assert_eq!(
+            (read.created, read.last_run),
+            (saved.created, saved.last_run)
+        );
+        assert_eq!(read.hits.len(), 2);
+        for (read_hit, hit) in read.hits.iter().zip(&saved.hits) {
+            assert_eq!(read_hit.s_name, hit.s_name);
+            assert_eq!(read_hit.c_id, hit.c_id);
+            assert_eq!(read_hit.c_hash, hit.c_hash);
+            assert_eq!(read_hit.score, hit.score);
+        }
+    }
+
+    #[test]
+    fn long_text_is_cut_on_char_boundary() {
+        let saved = SavedSearch {
+            version: VERSION,
+            text: "ż".repeat(u16::MAX as usize),
+            is_permanent: false,
+            stemming: false,
+            created: 0,
+            last_run: 0,
+            hits: vec![],
+        };
+        let read = SavedSearch::from_bytes(saved.to_bytes(), "any").unwrap();
+        assert_eq!(read.text.len(), u16::MAX as usize - 1);
+        assert!(read.text.chars().all(|c| c == 'ż'));
+    }
+
+    #[test]
+    fn legacy_file_with_repeated_text_is_read() {
+        let text = "rust async ";
+        let f_name = sha_hash(text.trim().as_bytes()).to_string();
+        let read = SavedSearch::from_bytes(text.repeat(3).into_bytes(), &f_name).unwrap();
+        assert_eq!(read.version, 0);
+        assert_eq!(read.text, "rust async");
+        assert!(read.is_permanent);
+        assert!(SavedSearch::from_bytes(b"  ".to_vec(), "1").is_none());
+    }
+
+    #[test]
+    fn unknown_version_and_truncated_files_are_rejected() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION + 1);
+        assert!(SavedSearch::from_bytes(bytes, "any").is_none());
+        let saved = SavedSearch {
+            version: VERSION,
+            text: "text".to_string(),
+            is_permanent: true,
+            stemming: false,
+            created: 0,
+            last_run: 0,
+            hits: vec![hit(1, None)],
+        };
+        let mut bytes = saved.to_bytes();
+        bytes.pop();
+        assert!(SavedSearch::from_bytes(bytes, "any").is_none());
+    }
+}
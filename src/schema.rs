@@ -0,0 +1,513 @@
+use std::fmt;
+
+// A Manifest can declare a schema for every DataType::Data(n) it defines,
+// so that any tool can tell what given Content's pages contain
+// and so that we can reject Data that does not follow it.
+//
+// Every page is a sequence of fields, read in order.
+// When standard_first_page is set, first page of a Content starts with
+// standard FirstPage layout: TagLen|Tags|HeaderLen|Header
+// and schema fields describe what follows it.
+// When last_repeats is set, last field is read repeatedly until page ends,
+// otherwise there can be no bytes left after last field.
+//
+// Serialized schema layout:
+// 1 byte  - DataType id
+// 1 byte  - flags: bit 0 - standard first page, bit 1 - last field repeats
+// 1 byte  - fields count
+// for every field:
+// 1 byte  - name len + this many bytes of name
+// 1 byte  - Encoding
+// 1 byte  - size kind: 0 - fixed, 1 - 1 byte len prefix,
+//           2 - 2 bytes len prefix, 3 - rest of page
+// 2 bytes - size, only meaningful for fixed size
+const STANDARD_FIRST_PAGE: u8 = 0b01;
+const LAST_REPEATS: u8 = 0b10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Bytes,
+    Utf8,
+    Unsigned, // big endian, up to 8 bytes
+    Signed,   // big endian, up to 8 bytes
+    Float,    // big endian, 4 or 8 bytes
+    Bool,     // 1 byte, 0 or 1
+}
+impl Encoding {
+    pub fn from(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Encoding::Bytes),
+            1 => Some(Encoding::Utf8),
+            2 => Some(Encoding::Unsigned),
+            3 => Some(Encoding::Signed),
+            4 => Some(Encoding::Float),
+            5 => Some(Encoding::Bool),
+            _other => None,
+        }
+    }
+    pub fn byte(&self) -> u8 {
+        match self {
+            Encoding::Bytes => 0,
+            Encoding::Utf8 => 1,
+            Encoding::Unsigned => 2,
+            Encoding::Signed => 3,
+            Encoding::Float => 4,
+            Encoding::Bool => 5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FieldSize {
+    Fixed(u16),
+    Prefixed8,
+    Prefixed16,
+    Rest,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchemaField {
+    pub name: String,
+    pub encoding: Encoding,
+    pub size: FieldSize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataSchema {
+    pub standard_first_page: bool,
+    pub last_repeats: bool,
+    pub fields: Vec<SchemaField>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bytes(Vec<u8>),
+    Text(String),
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    Bool(bool),
+    Tags(Vec<u8>),
+}
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bytes(bytes) => {
+                for byte in bytes {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            Value::Text(text) => write!(f, "{}", text),
+            Value::Unsigned(val) => write!(f, "{}", val),
+            Value::Signed(val) => write!(f, "{}", val),
+            Value::Float(val) => write!(f, "{}", val),
+            Value::Bool(val) => write!(f, "{}", val),
+            Value::Tags(t_ids) => write!(f, "{:?}", t_ids),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SchemaError {
+    // Field name, expected bytes, bytes left
+    TooShort(String, usize, usize),
+    InvalidUtf8(String),
+    InvalidSize(String, usize),
+    InvalidBool(String, u8),
+    TrailingBytes(usize),
+    InvalidFirstPage,
+}
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort(name, need, left) => {
+                write!(f, "{}: need {} bytes, {} left", name, need, left)
+            }
+            Self::InvalidUtf8(name) => write!(f, "{}: invalid UTF-8", name),
+            Self::InvalidSize(name, size) => write!(f, "{}: invalid size {}", name, size),
+            Self::InvalidBool(name, byte) => write!(f, "{}: invalid bool {}", name, byte),
+            Self::TrailingBytes(left) => write!(f, "{} bytes left after last field", left),
+            Self::InvalidFirstPage => write!(f, "Invalid FirstPage layout"),
+        }
+    }
+}
+
+impl DataSchema {
+    /// Check if given page follows this schema.
+    pub fn validate(&self, page_no: u16, bytes: &[u8]) -> Result<(), SchemaError> {
+        self.decode(page_no, bytes).map(|_fields| ())
+    }
+
+    /// Read all fields of given page, with standard FirstPage
+    /// tags and header returned as "tags" and "header" fields.
+    pub fn decode(&self, page_no: u16, bytes: &[u8]) -> Result<Vec<(String, Value)>, SchemaError> {
+        let mut decoded = vec![];
+        let mut rest = bytes;
+        if page_no == 0 && self.standard_first_page {
+            let (tags, header, remainder) =
+                split_first_page(rest).ok_or(SchemaError::InvalidFirstPage)?;
+            decoded.push(("tags".to_string(), Value::Tags(tags)));
+            decoded.push(("header".to_string(), Value::Text(header)));
+            rest = remainder;
+        }
+        let Some(last) = self.fields.last() else {
+            if !rest.is_empty() {
+                return Err(SchemaError::TrailingBytes(rest.len()));
+            }
+            return Ok(decoded);
+        };
+        for field in &self.fields[..self.fields.len() - 1] {
+            let (value, remainder) = field.read(rest)?;
+            decoded.push((field.name.clone(), value));
+            rest = remainder;
+        }
+        loop {
+            let (value, remainder) = last.read(rest)?;
+            decoded.push((last.name.clone(), value));
+            // A zero sized field would repeat forever
+            let consumed = rest.len() - remainder.len();
+            rest = remainder;
+            if !self.last_repeats || rest.is_empty() || consumed == 0 {
+                break;
+            }
+        }
+        if !rest.is_empty() {
+            return Err(SchemaError::TrailingBytes(rest.len()));
+        }
+        Ok(decoded)
+    }
+
+    pub fn append_bytes_to(&self, d_id: u8, bytes: &mut Vec<u8>) {
+        bytes.push(d_id);
+        let mut flags = 0;
+        if self.standard_first_page {
+            flags |= STANDARD_FIRST_PAGE;
+        }
+        if self.last_repeats {
+            flags |= LAST_REPEATS;
+        }
+        bytes.push(flags);
+        let fields: Vec<&SchemaField> = self
+            .fields
+            .iter()
+            .filter(|field| field.name.len() <= 255)
+            .take(255)
+            .collect();
+        bytes.push(fields.len() as u8);
+        for field in fields {
+            bytes.push(field.name.len() as u8);
+            bytes.extend_from_slice(field.name.as_bytes());
+            bytes.push(field.encoding.byte());
+            let (kind, size) = match field.size {
+                FieldSize::Fixed(size) => (0, size),
+                FieldSize::Prefixed8 => (1, 0),
+                FieldSize::Prefixed16 => (2, 0),
+                FieldSize::Rest => (3, 0),
+            };
+            bytes.push(kind);
+            bytes.extend_from_slice(&size.to_be_bytes());
+        }
+    }
+
+    /// Returns DataType id with it's schema.
+    pub fn from(bytes: &mut impl Iterator<Item = u8>) -> Option<(u8, Self)> {
+        let d_id = bytes.next()?;
+        let flags = bytes.next()?;
+        let count = bytes.next()?;
+        let mut fields = Vec::with_capacity(count as usize);
+        for _i in 0..count {
+            let name_len = bytes.next()?;
+            let mut name_bytes = Vec::with_capacity(name_len as usize);
+            for _j in 0..name_len {
+                name_bytes.push(bytes.next()?);
+            }
+            let name = String::from_utf8(name_bytes).ok()?;
+            let encoding = Encoding::from(bytes.next()?)?;
+            let kind = bytes.next()?;
+            let size = u16::from_be_bytes([bytes.next()?, bytes.next()?]);
+            let size = match kind {
+                0 => FieldSize::Fixed(size),
+                1 => FieldSize::Prefixed8,
+                2 => FieldSize::Prefixed16,
+                3 => FieldSize::Rest,
+                _other => return None,
+            };
+            fields.push(SchemaField {
+                name,
+                encoding,
+                size,
+            });
+        }
+        Some((
+            d_id,
+            DataSchema {
+                standard_first_page: flags & STANDARD_FIRST_PAGE > 0,
+                last_repeats: flags & LAST_REPEATS > 0,
+                fields,
+            },
+        ))
+    }
+}
+
+impl SchemaField {
+    // Returns decoded value and remaining bytes
+    fn read<'a>(&self, bytes: &'a [u8]) -> Result<(Value, &'a [u8]), SchemaError> {
+        let (len, skip) = match self.size {
+            FieldSize::Fixed(size) => (size as usize, 0),
+            FieldSize::Prefixed8 => {
+                let len = *bytes
+                    .first()
+                    .ok_or(SchemaError::TooShort(self.name.clone(), 1, 0))?;
+                (len as usize, 1)
+            }
+            FieldSize::Prefixed16 => {
+                if bytes.len() < 2 {
+                    return Err(SchemaError::TooShort(self.name.clone(), 2, bytes.len()));
+                }
+                (u16::from_be_bytes([bytes[0], bytes[1]]) as usize, 2)
+            }
+            FieldSize::Rest => (bytes.len(), 0),
+        };
+        let bytes = &bytes[skip..];
+        if bytes.len() < len {
+            return Err(SchemaError::TooShort(self.name.clone(), len, bytes.len()));
+        }
+        let (field_bytes, rest) = bytes.split_at(len);
+        Ok((self.value(field_bytes)?, rest))
+    }
+
+    fn value(&self, bytes: &[u8]) -> Result<Value, SchemaError> {
+        let invalid_size = || SchemaError::InvalidSize(self.name.clone(), bytes.len());
+        match self.encoding {
+            Encoding::Bytes => Ok(Value::Bytes(bytes.to_vec())),
+            Encoding::Utf8 => String::from_utf8(bytes.to_vec())
+                .map(Value::Text)
+                .map_err(|_e| SchemaError::InvalidUtf8(self.name.clone())),
+            Encoding::Unsigned => {
+                if bytes.is_empty() || bytes.len() > 8 {
+                    return Err(invalid_size());
+                }
+                let mut val = 0u64;
+                for byte in bytes {
+                    val = (val << 8) | *byte as u64;
+                }
+                Ok(Value::Unsigned(val))
+            }
+            Encoding::Signed => {
+                if bytes.is_empty() || bytes.len() > 8 {
+                    return Err(invalid_size());
+                }
+                // sign extend from most significant byte
+                let mut val: i64 = if bytes[0] & 0x80 > 0 { -1 } else { 0 };
+                for byte in bytes {
+                    val = (val << 8) | *byte as i64;
+                }
+                Ok(Value::Signed(val))
+            }
+            Encoding::Float => match bytes.len() {
+                4 => Ok(Value::Float(
+                    f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                )),
+                8 => Ok(Value::Float(f64::from_be_bytes([
+                    bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+                ]))),
+                _other => Err(invalid_size()),
+            },
+            Encoding::Bool => match bytes {
+                [0] => Ok(Value::Bool(false)),
+                [1] => Ok(Value::Bool(true)),
+                [other] => Err(SchemaError::InvalidBool(self.name.clone(), *other)),
+                _other => Err(invalid_size()),
+            },
+        }
+    }
+}
+
+// Standard FirstPage layout: TagLen|Tags|HeaderLen|Header|Remainder
+fn split_first_page(bytes: &[u8]) -> Option<(Vec<u8>, String, &[u8])> {
+    let (t_len, rest) = bytes.split_first()?;
+    if rest.len() < *t_len as usize + 2 {
+        return None;
+    }
+    let (tags, rest) = rest.split_at(*t_len as usize);
+    let h_len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+    let rest = &rest[2..];
+    if rest.len() < h_len {
+        return None;
+    }
+    let (header, rest) = rest.split_at(h_len);
+    let header = String::from_utf8(header.to_vec()).ok()?;
+    Some((tags.to_vec(), header, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, encoding: Encoding, size: FieldSize) -> SchemaField {
+        SchemaField {
+            name: name.to_string(),
+            encoding,
+            size,
+        }
+    }
+
+    fn schema(
+        standard_first_page: bool,
+        last_repeats: bool,
+        fields: Vec<SchemaField>,
+    ) -> DataSchema {
+        DataSchema {
+            standard_first_page,
+            last_repeats,
+            fields,
+        }
+    }
+
+    #[test]
+    fn fixed_prefixed_and_rest_fields_are_decoded() {
+        let schema = schema(
+            false,
+            false,
+            vec![
+                field("id", Encoding::Unsigned, FieldSize::Fixed(2)),
+                field("delta", Encoding::Signed, FieldSize::Fixed(1)),
+                field("name", Encoding::Utf8, FieldSize::Prefixed8),
+                field("blob", Encoding::Bytes, FieldSize::Prefixed16),
+                field("ratio", Encoding::Float, FieldSize::Fixed(4)),
+                field("flag", Encoding::Bool, FieldSize::Fixed(1)),
+                field("tail", Encoding::Bytes, FieldSize::Rest),
+            ],
+        );
+        let mut bytes = vec![1, 2, 0xff, 3];
+        bytes.extend_from_slice(b"abc");
+        bytes.extend_from_slice(&[0, 2, 9, 8]);
+        bytes.extend_from_slice(&1.5f32.to_be_bytes());
+        bytes.push(1);
+        bytes.extend_from_slice(&[7, 7, 7]);
+        assert_eq!(
+            schema.decode(1, &bytes).unwrap(),
+            vec![
+                ("id".to_string(), Value::Unsigned(0x0102)),
+                ("delta".to_string(), Value::Signed(-1)),
+                ("name".to_string(), Value::Text("abc".to_string())),
+                ("blob".to_string(), Value::Bytes(vec![9, 8])),
+                ("ratio".to_string(), Value::Float(1.5)),
+                ("flag".to_string(), Value::Bool(true)),
+                ("tail".to_string(), Value::Bytes(vec![7, 7, 7])),
+            ]
+        );
+        assert_eq!(
+            schema.decode(1, &bytes[..4]),
+            Err(SchemaError::TooShort("name".to_string(), 3, 0))
+        );
+    }
+
+    #[test]
+    fn last_field_repeats_until_page_ends() {
+        let field = field("n", Encoding::Unsigned, FieldSize::Fixed(2));
+        let repeating = schema(false, true, vec![field.clone()]);
+        let decoded = repeating.decode(1, &[0, 1, 0, 2, 0, 3]).unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[2], ("n".to_string(), Value::Unsigned(3)));
+        assert_eq!(
+            repeating.decode(1, &[0, 1, 0]),
+            Err(SchemaError::TooShort("n".to_string(), 2, 1))
+        );
+
+        let single = schema(false, false, vec![field]);
+        assert_eq!(
+            single.decode(1, &[0, 1, 0, 2]),
+            Err(SchemaError::TrailingBytes(2))
+        );
+    }
+
+    #[test]
+    fn standard_first_page_is_split_only_on_page_zero() {
+        let schema = schema(
+            true,
+            false,
+            vec![field("body", Encoding::Utf8, FieldSize::Rest)],
+        );
+        let mut bytes = vec![2, 4, 5, 0, 5];
+        bytes.extend_from_slice(b"titlebody");
+        assert_eq!(
+            schema.decode(0, &bytes).unwrap(),
+            vec![
+                ("tags".to_string(), Value::Tags(vec![4, 5])),
+                ("header".to_string(), Value::Text("title".to_string())),
+                ("body".to_string(), Value::Text("body".to_string())),
+            ]
+        );
+        assert_eq!(
+            schema.decode(0, &[2, 4, 5, 0, 9]),
+            Err(SchemaError::InvalidFirstPage)
+        );
+        assert_eq!(
+            schema.decode(1, b"body").unwrap(),
+            vec![("body".to_string(), Value::Text("body".to_string()))]
+        );
+    }
+
+    #[test]
+    fn empty_schema_rejects_trailing_bytes() {
+        let schema = schema(false, false, vec![]);
+        assert!(schema.validate(1, &[]).is_ok());
+        assert_eq!(schema.validate(1, &[0]), Err(SchemaError::TrailingBytes(1)));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let text = schema(
+            false,
+            false,
+            vec![field("t", Encoding::Utf8, FieldSize::Rest)],
+        );
+        assert_eq!(
+            text.validate(1, &[0xff, 0xfe]),
+            Err(SchemaError::InvalidUtf8("t".to_string()))
+        );
+        let flag = schema(
+            false,
+            false,
+            vec![field("b", Encoding::Bool, FieldSize::Fixed(1))],
+        );
+        assert_eq!(
+            flag.validate(1, &[2]),
+            Err(SchemaError::InvalidBool("b".to_string(), 2))
+        );
+        let float = schema(
+            false,
+            false,
+            vec![field("f", Encoding::Float, FieldSize::Fixed(3))],
+        );
+        assert_eq!(
+            float.validate(1, &[0, 0, 0]),
+            Err(SchemaError::InvalidSize("f".to_string(), 3))
+        );
+    }
+
+    #[test]
+    fn schema_bytes_round_trip() {
+        let schema = schema(
+            true,
+            true,
+            vec![
+                field("id", Encoding::Unsigned, FieldSize::Fixed(8)),
+                field("name", Encoding::Utf8, FieldSize::Prefixed8),
+                field("blob", Encoding::Bytes, FieldSize::Prefixed16),
+                field("tail", Encoding::Bool, FieldSize::Rest),
+            ],
+        );
+        let mut bytes = vec![];
+        schema.append_bytes_to(3, &mut bytes);
+        let mut iter = bytes.into_iter();
+        assert_eq!(DataSchema::from(&mut iter), Some((3, schema)));
+        assert!(iter.next().is_none());
+
+        // Unknown size kind
+        let bytes = vec![3, 0, 1, 1, b'x', 0, 4, 0, 0];
+        assert_eq!(DataSchema::from(&mut bytes.into_iter()), None);
+    }
+}
@@ -1,10 +1,26 @@
 // use crate::manifest;
+use crate::crawler::Crawler;
+use crate::federated::decode_request;
+use crate::federated::decode_response;
+use crate::federated::encode_request;
+use crate::federated::encode_response;
+use crate::federated::RateLimiter;
+use crate::federated::RemoteResult;
+use crate::federated::MAX_REMOTE_RESULTS;
+use crate::index::ContentMeta;
+use crate::index::Field;
+use crate::index::SearchIndex;
+use crate::index::SwarmMeta;
+use crate::index::Tokenizer;
 use crate::manifest::Manifest;
 use crate::manifest::Tag;
 use crate::prelude::read_tags_and_header;
 use crate::prelude::AppError;
 use crate::prelude::AppType;
 use crate::prelude::DataType;
+use crate::saved_search::SavedHit;
+use crate::saved_search::SavedSearch;
+use crate::util::now;
 use crate::ContentID;
 use crate::Data;
 use crate::SwarmName;
@@ -12,6 +28,11 @@ use crate::ToAppData;
 use crate::ToAppMgr;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::time::Duration;
+use std::time::Instant;
 // TODO: make everything FS-related async
 use std::fs;
 use std::fs::File;
@@ -26,16 +47,17 @@ pub use crate::ToApp;
 // pub use crate::ToAppMgr;
 // use async_std::channel::Receiver;
 use smol::channel::Receiver;
+use smol::channel::RecvError;
 // use async_std::channel::Sender;
 use smol::channel::Sender;
-use smol::fs::OpenOptions;
 // use async_std::fs::OpenOptions;
 // use async_std::io::BufWriter;
 // use async_std::io::WriteExt;
 use gnome::prelude::sha_hash;
+use gnome::prelude::GnomeId;
 use gnome::prelude::SwarmID;
-use smol::io::AsyncWriteExt as WriteExt;
-use smol::io::BufWriter;
+use smol::future::FutureExt;
+use smol::Timer;
 
 // TODO: build a search engine.
 // It should be run against every Catalog Swarm,
@@ -66,6 +88,13 @@ use smol::io::BufWriter;
 // - count how namy words from Query string are found within given text;
 // - later some more sophisticated algorithms can be deployed.
 
+// Index changes made outside of crawled ranges are written to disk
+// at most this long after they were made
+const FLUSH_DELAY: Duration = Duration::from_secs(5);
+
+// Longest Query text we accept, so that it can be stored with a 2 byte length
+const MAX_QUERY_LEN: usize = u16::MAX as usize;
+
 // Hit should contain a set of (SwarmName,CID) pairs, no less, no more.
 #[derive(Debug)]
 pub struct SwarmLink {
@@ -74,6 +103,9 @@ pub struct SwarmLink {
     pub max_cid: ContentID,
     pub sender: Sender<ToAppData>,
     pub root_hash: u64,
+    // Swarm is stored under a StoragePolicy that keeps all pages on disk
+    pub full_pages_stored: bool,
+    pub c_hashes: Vec<(DataType, u64)>,
     pub s_descr: String,
     pub s_tags: HashMap<u8, Tag>,
 }
@@ -87,6 +119,13 @@ impl EngineState {
     pub fn is_idling(&self) -> bool {
         matches!(&self, Self::Idling)
     }
+    pub fn swarm_id(&self) -> Option<SwarmID> {
+        if let Self::Processing(s_id, _sender, _processing, _to_process) = self {
+            Some(*s_id)
+        } else {
+            None
+        }
+    }
     pub fn enqueue_swarm(&mut self, add_swarm_id: SwarmID) {
         // if self.is_idling() {
         //     return;
@@ -121,109 +160,208 @@ impl EngineState {
         false
     }
 }
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
-pub struct Hit(pub SwarmName, pub ContentID, pub u8);
+/// Relevance of a Hit, Hits with higher value should be presented first
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub struct Score {
+    /// BM25 score
+    pub value: f32,
+    /// how many distinct Query terms were found
+    pub matched: u8,
+    /// how many distinct terms given Query has
+    pub total: u8,
+    /// entire Query was found as a phrase
+    pub phrase: bool,
+}
+/// Which part of a Content, or of a Swarm's Manifest, given Query matched
+#[derive(Clone, PartialEq, Debug)]
+pub enum MatchedOn {
+    Description,
+    Header,
+    ManifestTag(String),
+    ContentTag(String),
+    Body(Snippet),
+}
+/// Part of a Content's body that matched a Query
+#[derive(Clone, PartialEq, Debug)]
+pub struct Snippet {
+    /// index of page containing matched text
+    pub page: u16,
+    /// byte offset of matched word within that page
+    pub offset: u32,
+    /// normalized terms surrounding matched one
+    pub text: String,
+}
+/// Whether a Hit was confirmed against our own index
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HitState {
+    Fresh,
+    /// restored from disk, not yet revalidated against it's Swarm
+    Stale,
+    /// reported by given Neighbor, we did not verify it's first page yet
+    Remote(GnomeId),
+}
+#[derive(Clone, PartialEq, Debug)]
+pub struct Hit(
+    pub SwarmName,
+    pub ContentID,
+    pub Score,
+    pub Vec<MatchedOn>,
+    pub HitState,
+);
+// Current Hits of a Query, with their Scores and what they matched on
+type QueryHits = HashMap<(SwarmName, ContentID), (Score, Vec<MatchedOn>)>;
+/// Search related parts of Configuration
+pub struct SearchSettings {
+    pub stemming: bool,
+    pub crawl_bandwidth: u64,
+    pub full_text: Vec<String>,
+    pub federated: bool,
+}
 struct Engine {
     search_path: PathBuf,
-    queries: HashMap<u64, (Query, HashSet<Hit>)>,
+    queries: HashMap<u64, (Query, QueryHits)>,
     swarm_links: HashMap<SwarmID, SwarmLink>,
-    tags: HashMap<SwarmID, HashMap<u8, Tag>>,
+    // Contents that changed since given Swarm was last indexed
+    pending: HashMap<SwarmID, Vec<ContentID>>,
+    // Pages of Contents read in multiple chunks
+    partial_reads: HashMap<(SwarmID, ContentID), Vec<Data>>,
+    // Names of DataTypes whose entire bodies get indexed
+    full_text: HashSet<String>,
+    // Ask Neighbors to search their indexes and answer their Queries
+    federated: bool,
+    rate_limiter: RateLimiter,
     state: EngineState,
+    crawler: Crawler,
+    index: SearchIndex,
+    // Permanent Queries whose Hits changed since last flush
+    dirty_queries: HashSet<u64>,
+    // Swarms with Contents that started or stopped matching
+    // some Query since last flush
+    changed_swarms: HashSet<SwarmName>,
+    // When pending index and Query changes should be written to disk
+    flush_at: Option<Instant>,
+    to_user: Sender<ToApp>,
     to_app_mgr: Sender<ToAppMgr>,
-    //TODO: some structure tracking SwarmName to root hash,
-    //      so that we do not query non-changed given swarm multiple times
 }
 impl Engine {
-    pub async fn new(search_path: PathBuf, to_app_mgr: Sender<ToAppMgr>) -> Self {
+    pub async fn new(
+        search_path: PathBuf,
+        settings: SearchSettings,
+        to_user: Sender<ToApp>,
+        to_app_mgr: Sender<ToAppMgr>,
+    ) -> Self {
         // Load permanent searches from search path
         eprintln!("Should load searches from {search_path:?}");
         if !fs::exists(search_path.clone()).unwrap() {
             let _ = fs::create_dir(search_path.clone());
         }
+        let index = SearchIndex::new(&search_path, Tokenizer::new(settings.stemming));
         let mut engine = Engine {
             search_path: search_path.clone(),
             queries: HashMap::new(),
             swarm_links: HashMap::new(),
-            tags: HashMap::new(),
+            pending: HashMap::new(),
+            partial_reads: HashMap::new(),
+            full_text: settings.full_text.into_iter().collect(),
+            federated: settings.federated,
+            rate_limiter: RateLimiter::default(),
             state: EngineState::Idling,
+            crawler: Crawler::new(settings.crawl_bandwidth),
+            index,
+            dirty_queries: HashSet::new(),
+            changed_swarms: HashSet::new(),
+            flush_at: None,
+            to_user,
             to_app_mgr,
         };
         for f_name in fs::read_dir(search_path.clone()).unwrap().into_iter() {
             // eprintln!("we have: {f_name:?}");
             if let Ok(f) = f_name {
-                let mut str = String::new();
+                if f.path().is_dir() {
+                    // this is where index is stored
+                    continue;
+                }
+                let mut bytes = vec![];
                 let mut fl = File::open(f.path()).unwrap();
-                if let Ok(count) = fl.read_to_string(&mut str) {
-                    if count > 0 {
-                        engine.add_query(str, true).await;
+                if fl.read_to_end(&mut bytes).is_ok() && !bytes.is_empty() {
+                    let f_name = f.file_name().to_string_lossy().to_string();
+                    let Some(saved) = SavedSearch::from_bytes(bytes, &f_name) else {
+                        eprintln!("Unable to read query from {:?}", f.path());
+                        continue;
+                    };
+                    if let Err(e) = engine.restore_query(saved).await {
+                        eprintln!("Failed to load query from {:?}: {}", f.path(), e);
                     }
                 }
             }
         }
         engine
     }
-    pub async fn add_query(&mut self, phrase: String, is_permanent: bool) {
+    pub async fn add_query(
+        &mut self,
+        phrase: String,
+        is_permanent: bool,
+    ) -> Result<(), QueryError> {
         // eprintln!("add_query: {phrase}, state: {:?}", self.state);
         let phrase = phrase.trim().to_string();
         let q_hash = sha_hash(phrase.as_bytes());
-        let query = Query::new(phrase, is_permanent);
-        self.queries.insert(q_hash, (query, HashSet::new()));
-        if self.swarm_links.is_empty() {
-            self.state = EngineState::Idling;
-            return;
-        }
-        if self.state.is_idling() {
-            let mut s_ids = Vec::with_capacity(self.swarm_links.len());
-            for key in self.swarm_links.keys() {
-                s_ids.push(*key);
-            }
-            while let Some(s_id) = s_ids.pop() {
-                if let Some(s_link) = self.swarm_links.get(&s_id) {
-                    let mut queried_cids = vec![];
-                    for c_id in 1..=s_link.max_cid {
-                        queried_cids.push(c_id);
-                    }
-                    self.state =
-                        EngineState::Processing(s_id, s_link.sender.clone(), queried_cids, s_ids);
-                    // TODO: only query for some chunk of contents, not everything at once
-                    eprintln!("Requesting all first pages for: {}", s_id);
-                    let _ = s_link
-                        .sender
-                        .send(ToAppData::ReadAllFirstPages(Requestor::Search, None))
-                        .await;
-
-                    break;
-                }
-            }
-        } else {
-            // we need to go through all swarms once again
-            let cur_state = std::mem::replace(&mut self.state, EngineState::Idling);
-            if let EngineState::Processing(s_id, sender, processing, mut queued_swarms) = cur_state
-            {
-                for key in self.swarm_links.keys() {
-                    if !queued_swarms.contains(key) {
-                        queued_swarms.push(*key);
-                    }
-                }
-                self.state = EngineState::Processing(s_id, sender, processing, queued_swarms);
+        let mut query = Query::parse(phrase, is_permanent, self.index.tokenizer())?;
+        query.last_run = now();
+        // We answer from index, Swarms get (re)indexed when they are synced
+        // or when their Contents change, so no need to read them again
+        let mut hits = HashMap::new();
+        let mut changed_swarms = HashSet::new();
+        for (s_name, c_id) in self.index.candidates(&query.terms) {
+            if let Some(hit) = query.evaluate(&self.index, &s_name, c_id) {
+                changed_swarms.insert(s_name.clone());
+                hits.insert((s_name, c_id), hit);
             }
         }
+        self.queries.insert(q_hash, (query, hits));
+        self.notify_manager_about_swarms(changed_swarms).await;
+        Ok(())
     }
-    pub fn del_query(&mut self, phrase: String) {
+    pub async fn del_query(&mut self, phrase: String) {
         let sha = sha_hash(phrase.as_bytes());
-        if let Some((q, _hm)) = self.queries.remove(&sha) {
+        if let Some((q, hits)) = self.queries.remove(&sha) {
             if q.is_permanent {
                 // TODO: remove file from disk
                 let mut f_path = self.search_path.clone();
                 f_path.push(format!("{}", sha));
                 let _ = fs::remove_file(f_path);
             }
+            let mut changed_swarms = HashSet::new();
+            for (s_name, _c_id) in hits.into_keys() {
+                changed_swarms.insert(s_name);
+            }
+            self.notify_manager_about_swarms(changed_swarms).await;
         }
     }
     pub fn get_query(&self, phrase: String) -> (String, bool, Vec<Hit>) {
         let q_hash = sha_hash(phrase.as_bytes());
-        if let Some((_q, hset)) = self.queries.get(&q_hash) {
-            (phrase, _q.is_permanent, hset.clone().into_iter().collect())
+        if let Some((_q, hits)) = self.queries.get(&q_hash) {
+            let mut results = Vec::with_capacity(hits.len());
+            for ((s_name, c_id), (score, matched_on)) in hits {
+                let key = (s_name.clone(), *c_id);
+                let h_state = if _q.stale.contains(&key) {
+                    HitState::Stale
+                } else {
+                    HitState::Fresh
+                };
+                results.push(Hit(key.0, key.1, *score, matched_on.clone(), h_state));
+            }
+            for ((s_name, c_id), (g_id, _page_hash, score)) in &_q.remote {
+                results.push(Hit(
+                    s_name.clone(),
+                    *c_id,
+                    *score,
+                    vec![],
+                    HitState::Remote(*g_id),
+                ));
+            }
+            // Most relevant first
+            results.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal));
+            (phrase, _q.is_permanent, results)
         } else {
             (phrase, false, vec![])
         }
@@ -234,20 +372,7 @@ impl Engine {
         if let Some((query, _hset)) = self.queries.get_mut(&q_hash) {
             query.is_permanent = flag;
             if flag {
-                // Create query file from disk location
-                let mut f_path = self.search_path.clone();
-                f_path.push(format!("{}", q_hash));
-                let header_file = OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .append(true)
-                    .open(f_path)
-                    .await
-                    .unwrap();
-                let mut header_file = BufWriter::new(header_file);
-                let _ = header_file.write(&phrase.clone().into_bytes()).await;
-                let _ = header_file.flush().await;
-                drop(header_file);
+                self.store_query(q_hash).await;
             } else {
                 // Remove file from disk
                 let mut f_path = self.search_path.clone();
@@ -257,10 +382,6 @@ impl Engine {
         }
     }
 
-    pub fn has_queries(&self) -> bool {
-        !self.queries.is_empty()
-    }
-
     pub fn summary(&self) -> Vec<(String, usize)> {
         let mut all_queries = Vec::with_capacity(self.queries.len());
         for (q, hset) in self.queries.values() {
@@ -268,6 +389,31 @@ impl Engine {
         }
         all_queries
     }
+    // Contents longer than 64 pages are sent in chunks,
+    // we wait until we have all of them
+    fn collect_pages(
+        &mut self,
+        s_id: SwarmID,
+        c_id: ContentID,
+        starting_page: u16,
+        is_last: bool,
+        mut data_vec: Vec<Data>,
+    ) -> Option<Vec<Data>> {
+        let key = (s_id, c_id);
+        let mut pages = if starting_page == 0 {
+            vec![]
+        } else {
+            self.partial_reads.remove(&key).unwrap_or_default()
+        };
+        pages.append(&mut data_vec);
+        if is_last {
+            Some(pages)
+        } else {
+            self.partial_reads.insert(key, pages);
+            None
+        }
+    }
+
     pub async fn parse_content(
         &mut self,
         s_id: SwarmID,
@@ -277,89 +423,315 @@ impl Engine {
         data_vec: Vec<Data>,
     ) {
         if c_id == 0 {
+            if let Some(first_data) = data_vec.first() {
+                self.index
+                    .set_page_hash(&s_name, c_id, sha_hash(first_data.ref_bytes()));
+            }
             let manif = Manifest::from(data_vec);
             // eprintln!(
             //     "search parse Manifest for {s_id}, app_type: {:?}",
             //     manif.app_type
             // );
-            let mut phrase = manif.description.clone();
-            self.tags.insert(s_id, manif.tags.clone());
-            for tag in manif.tags.values() {
-                phrase.push_str(" ");
-                phrase.push_str(&tag.0);
+            let mut tag_names = String::new();
+            let mut s_meta = SwarmMeta {
+                app_type: Some(manif.app_type),
+                tags: HashMap::with_capacity(manif.tags.len()),
+                d_types: HashMap::with_capacity(manif.d_types.len()),
+                tag_translations: HashMap::new(),
+            };
+            for (d_id, d_name) in &manif.d_types {
+                s_meta.d_types.insert(*d_id, d_name.0.clone());
+            }
+            // Translated Tag names are indexed along default ones,
+            // so that Contents can be found in any language
+            for (t_id, tag) in &manif.tags {
+                let mut names = manif.all_tag_names(*t_id).into_iter();
+                names.next();
+                let translations: Vec<String> = names.map(|name| name.to_string()).collect();
+                s_meta.tags.insert(*t_id, tag.0.clone());
+                if !translations.is_empty() {
+                    s_meta.tag_translations.insert(*t_id, translations);
+                }
+                for t_name in s_meta.tag_names(*t_id) {
+                    tag_names.push(' ');
+                    tag_names.push_str(t_name);
+                }
+            }
+            let mut descriptions = manif.description.clone();
+            let mut codes: Vec<&String> = manif.locales.keys().collect();
+            codes.sort();
+            for code in codes {
+                descriptions.push('\n');
+                descriptions.push_str(&manif.locales[code].description);
             }
-            let _anything_added = self.iter_queries(&s_name, c_id, &phrase);
+            self.index.set_swarm_meta(&s_name, s_meta);
+            self.index_content(
+                &s_name,
+                c_id,
+                ContentMeta {
+                    d_type,
+                    tag_ids: vec![],
+                },
+                vec![
+                    (Field::Description, descriptions),
+                    (Field::ManifestTag, tag_names),
+                ],
+                vec![],
+            )
+            .await;
+            self.content_indexed(s_id, &s_name, c_id);
             if let Some(s_link) = self.swarm_links.get_mut(&s_id) {
                 s_link.s_descr = manif.description;
                 s_link.app_type = Some(manif.app_type);
                 // eprintln!("search parse {s_id} app type: {:?}", manif.app_type);
                 s_link.s_tags = manif.tags;
-                if self.queries.is_empty() {
-                    return;
-                }
                 if self.state.is_idling() {
-                    // All swarms contents have been matched against all existing queries,
-                    // so we only need to check this swarm
+                    // All other swarms are already indexed,
+                    // so we only need to index this swarm
                     //      Processing(SwarmID, Sender<ToAppData>, Vec<ContentID>, Vec<SwarmID>),
-                    let mut queried_cids = vec![];
-                    for c_id in 1..=s_link.max_cid {
-                        queried_cids.push(c_id);
+                    if !self.start_indexing(s_id, &mut vec![]).await {
+                        self.notify_manager_about_searches().await;
                     }
-                    self.state =
-                        EngineState::Processing(s_id, s_link.sender.clone(), queried_cids, vec![]);
-                    // TODO: only query for some chunk of contents, not everything at once
-                    eprintln!("2 Requesting all first pages for: {}", s_id);
-                    let _ = s_link
-                        .sender
-                        .send(ToAppData::ReadAllFirstPages(Requestor::Search, None))
-                        .await;
                 } else {
                     self.state.enqueue_swarm(s_id);
                 }
             }
         } else if !data_vec.is_empty() {
-            // if queries is empty we ignore this message and return
-            if !self.has_queries() {
-                return;
-            }
             // in any state we process this data
             let first_data = data_vec[0].clone();
-            let (tag_bytes, mut header) = read_tags_and_header(d_type, first_data);
-            if let Some(tags) = self.tags.get(&s_id) {
-                for t_byte in tag_bytes {
-                    if let Some(tag) = tags.get(&t_byte) {
-                        header.push_str(" ");
-                        header.push_str(&tag.0);
-                    }
-                }
-            }
-            self.iter_queries(&s_name, c_id, &header);
+            let (c_meta, fields) = self.content_fields(&s_name, c_id, d_type, first_data);
+            let body = if self.wants_full_text(s_id, &s_name, d_type) {
+                data_vec
+            } else {
+                vec![]
+            };
+            self.index_content(&s_name, c_id, c_meta, fields, body)
+                .await;
+            self.content_indexed(s_id, &s_name, c_id);
             // if state is Processing, we remove this CID from list of processing cids
-            let advance_to_next_swarm = self.state.processing_done(s_id, vec![c_id]);
-            if advance_to_next_swarm {
-                let any_swarm_queried = self.advance_to_next_swarm().await;
-                if !any_swarm_queried {
-                    // TODO: we are done searching, we should send results to manager
-                    // to adjust storage policy
+            self.contents_done(s_id, vec![c_id]).await;
+        }
+    }
+
+    // A Swarm got synced, we compare it's hashes with those
+    // stored in index to decide what needs to be read.
+    pub async fn swarm_synced(&mut self, s_id: SwarmID, mut s_link: SwarmLink) {
+        let s_name = s_link.s_name.clone();
+        self.revalidate_hits(&s_name, &s_link.c_hashes).await;
+        let s_meta = self.index.swarm_meta(&s_name);
+        if s_link.app_type.is_none() {
+            s_link.app_type = s_meta.and_then(|s_m| s_m.app_type);
+        }
+        if s_meta.is_some() && self.index.root_hash(&s_name) == Some(s_link.root_hash) {
+            self.swarm_links.insert(s_id, s_link);
+            return;
+        }
+        let mut changed = self.index.changed_contents(&s_name, &s_link.c_hashes);
+        let manifest_changed = s_meta.is_none() || changed.first() == Some(&0);
+        changed.retain(|c_id| *c_id > 0);
+        self.pending.insert(s_id, changed);
+        if manifest_changed {
+            // Once Manifest is parsed we start indexing changed Contents
+            let _ = s_link
+                .sender
+                .send(ToAppData::ReadPagesRange(Requestor::Search, 0, 0, 63))
+                .await;
+            self.swarm_links.insert(s_id, s_link);
+        } else {
+            self.swarm_links.insert(s_id, s_link);
+            if self.state.is_idling() {
+                if !self.start_indexing(s_id, &mut vec![]).await {
                     self.notify_manager_about_searches().await;
                 }
-                // TODO: when we are Processing and done with current SwarmID,
-                // take next from list and change state to that one
-                // or to Idling in case all swarms have been searched for
-                // if let Some(s_id) = to_process.pop(){
-                //     if let Some(s_link) = self.s
-                // }
+            } else {
+                self.state.enqueue_swarm(s_id);
+            }
+        }
+    }
+
+    // Start reading first pages of those Contents that changed
+    // since last time given Swarm was indexed.
+    // Returns false when there was nothing to read.
+    async fn start_indexing(
+        &mut self,
+        s_id: SwarmID,
+        swarms_to_inquire: &mut Vec<SwarmID>,
+    ) -> bool {
+        let Some(s_link) = self.swarm_links.get(&s_id) else {
+            eprintln!(" Could not find SwarmLink for {}", s_id);
+            return false;
+        };
+        let sender = s_link.sender.clone();
+        let queried_cids = if let Some(changed) = self.pending.remove(&s_id) {
+            changed
+        } else {
+            (1..=s_link.max_cid).collect()
+        };
+        if queried_cids.is_empty() {
+            self.swarm_indexed(s_id).await;
+            return false;
+        }
+        self.crawler.start(&queried_cids);
+        self.state = EngineState::Processing(
+            s_id,
+            sender,
+            queried_cids,
+            std::mem::take(swarms_to_inquire),
+        );
+        self.crawl().await;
+        true
+    }
+
+    // Request next ranges of first pages, if Crawler allows it
+    async fn crawl(&mut self) {
+        let EngineState::Processing(_s_id, sender, _processing, _to_process) = &self.state else {
+            return;
+        };
+        for (first, last) in self.crawler.next_ranges() {
+            let _ = sender
+                .send(ToAppData::ReadAllFirstPages(
+                    Requestor::Search,
+                    Some((first, last)),
+                ))
+                .await;
+        }
+    }
+
+    // When to wake up in order to request next range
+    // or to write pending changes to disk
+    pub fn deadline(&self) -> Option<Instant> {
+        match (self.crawler.deadline(), self.flush_at) {
+            (Some(crawl_at), Some(flush_at)) => Some(crawl_at.min(flush_at)),
+            (crawl_at, flush_at) => crawl_at.or(flush_at),
+        }
+    }
+
+    async fn tick(&mut self) {
+        let abandoned = self.crawler.expire();
+        if let Some(s_id) = self.state.swarm_id() {
+            if !abandoned.is_empty() {
+                let mut c_ids = vec![];
+                for (first, last) in abandoned {
+                    c_ids.extend(first..=last);
+                }
+                self.contents_done(s_id, c_ids).await;
+            }
+        }
+        self.crawl().await;
+        if self
+            .flush_at
+            .is_some_and(|flush_at| flush_at <= Instant::now())
+        {
+            self.flush().await;
+        }
+    }
+
+    // Make sure pending changes get flushed within FLUSH_DELAY
+    fn schedule_flush(&mut self) {
+        if self.flush_at.is_none() {
+            self.flush_at = Some(Instant::now() + FLUSH_DELAY);
+        }
+    }
+
+    // Write pending index and Query changes to disk
+    // and tell Manager which Swarms now match differently
+    async fn flush(&mut self) {
+        self.flush_at = None;
+        self.index.flush().await;
+        for q_hash in std::mem::take(&mut self.dirty_queries) {
+            self.store_query(q_hash).await;
+        }
+        let changed_swarms = std::mem::take(&mut self.changed_swarms);
+        if !changed_swarms.is_empty() {
+            self.notify_manager_about_swarms(changed_swarms).await;
+        }
+    }
+
+    // Swarm was swapped out or otherwise terminated.
+    // If we were indexing it, we move on to next Swarm.
+    // Contents indexed so far are stored with their hashes,
+    // so once this Swarm is back we continue where we stopped.
+    async fn swarm_terminated(&mut self, s_id: SwarmID) {
+        self.pending.remove(&s_id);
+        self.partial_reads
+            .retain(|(p_s_id, _c_id), _pages| *p_s_id != s_id);
+        if self.swarm_links.remove(&s_id).is_none() {
+            return;
+        }
+        if self.state.swarm_id() != Some(s_id) {
+            return;
+        }
+        self.crawler.stop();
+        self.flush().await;
+        let state = std::mem::replace(&mut self.state, EngineState::Idling);
+        if let EngineState::Processing(_s_id, sender, _processing, to_process) = state {
+            self.state = EngineState::Processing(s_id, sender, vec![], to_process);
+            let any_swarm_queried = self.advance_to_next_swarm().await;
+            if !any_swarm_queried {
+                self.notify_manager_about_searches().await;
+            }
+        }
+    }
+
+    // Remember typed hash given Content had when we indexed it
+    fn content_indexed(&mut self, s_id: SwarmID, s_name: &SwarmName, c_id: ContentID) {
+        if let Some(s_link) = self.swarm_links.get(&s_id) {
+            if let Some(typed_hash) = s_link.c_hashes.get(c_id as usize) {
+                self.index.set_content_hash(s_name, c_id, *typed_hash);
             }
         }
     }
+
+    // All Contents of given Swarm were processed, so we store it's root hash.
+    async fn swarm_indexed(&mut self, s_id: SwarmID) {
+        if let Some(s_link) = self.swarm_links.get(&s_id) {
+            self.index
+                .set_root_hash(&s_link.s_name, s_link.root_hash, &s_link.c_hashes);
+        }
+        self.flush().await;
+    }
+
+    // Given Contents were processed, if that was the last one
+    // for current Swarm, we advance to next one.
+    async fn contents_done(&mut self, s_id: SwarmID, c_ids: Vec<ContentID>) {
+        let advance_to_next_swarm = self.state.processing_done(s_id, c_ids);
+        if advance_to_next_swarm {
+            self.swarm_indexed(s_id).await;
+            let any_swarm_queried = self.advance_to_next_swarm().await;
+            if !any_swarm_queried {
+                // we are done searching, we should send results to manager
+                // to adjust storage policy
+                self.notify_manager_about_searches().await;
+            }
+        } else {
+            self.crawl().await;
+        }
+    }
+    // Range is provided when first pages were requested by Crawler.
     pub async fn parse_first_pages(
         &mut self,
         s_id: SwarmID,
         s_name: SwarmName,
+        range: Option<(ContentID, ContentID)>,
         first_pages: Vec<(ContentID, DataType, Data)>,
     ) {
         // eprintln!("search parse_first_pages {s_id}");
         let mut processed_cids = Vec::with_capacity(first_pages.len());
+        let mut bytes = 0;
+        for (_c_id, _d_type, first_data) in &first_pages {
+            bytes += first_data.len();
+        }
+        // Contents that were requested but not received
+        // are also considered done, otherwise we would wait forever
+        if let Some((first, last)) = range {
+            if self.state.swarm_id() == Some(s_id)
+                && self.crawler.range_received((first, last), bytes)
+            {
+                processed_cids.extend(first..=last);
+            }
+        }
+        // Contents whose entire bodies we still wait for are not done yet
+        let mut pending_bodies = HashSet::new();
         let app_type = if let Some(s_l) = self.swarm_links.get(&s_id) {
             s_l.app_type
         } else {
@@ -372,19 +744,20 @@ impl Engine {
                     // eprintln!("search cid {c_id}");
                     continue;
                 }
-                let (tag_bytes, mut header) = read_tags_and_header(d_type, first_data);
-                if let Some(tags) = self.tags.get(&s_id) {
-                    for t_byte in tag_bytes {
-                        if let Some(tag) = tags.get(&t_byte) {
-                            header.push_str(" ");
-                            header.push_str(&tag.0);
-                        }
-                    }
+                if self.wants_full_text(s_id, &s_name, d_type) {
+                    // Entire body will be indexed once we read all pages
+                    self.read_all_pages(s_id, c_id).await;
+                    pending_bodies.insert(c_id);
+                    continue;
                 }
-                self.iter_queries(&s_name, c_id, &header);
-                // }
+                let (c_meta, fields) = self.content_fields(&s_name, c_id, d_type, first_data);
+                self.index_content(&s_name, c_id, c_meta, fields, vec![])
+                    .await;
+                self.content_indexed(s_id, &s_name, c_id);
                 processed_cids.push(c_id);
             }
+            processed_cids.retain(|c_id| !pending_bodies.contains(c_id));
+            self.flush().await;
         } else {
             eprintln!("search Don't know app_type, requesting Manifest 2");
             if let Some(s_l) = self.swarm_links.get(&s_id) {
@@ -394,36 +767,406 @@ impl Engine {
                     .await;
             }
         }
-        let advance_to_next_swarm = self.state.processing_done(s_id, processed_cids);
-        eprintln!(
-            "After first pages parse, should advance to next swarm?: {}",
-            advance_to_next_swarm
-        );
-        if advance_to_next_swarm {
-            let any_swarm_queried = self.advance_to_next_swarm().await;
-            if !any_swarm_queried {
-                // TODO: we are done searching, we should send results to manager
-                // to adjust storage policy
-                self.notify_manager_about_searches().await;
+        self.contents_done(s_id, processed_cids).await;
+    }
+
+    // A Content was added or changed in a Swarm, we update index
+    // and if we were not given a main page, we ask for it.
+    pub async fn content_updated(
+        &mut self,
+        s_id: SwarmID,
+        c_id: ContentID,
+        d_type: DataType,
+        main_page: Option<Data>,
+    ) {
+        let Some(s_link) = self.swarm_links.get(&s_id) else {
+            return;
+        };
+        let s_name = s_link.s_name.clone();
+        if c_id == 0 || self.wants_full_text(s_id, &s_name, d_type) {
+            // Manifest, or a body we index, can span multiple pages
+            self.read_all_pages(s_id, c_id).await;
+            return;
+        }
+        if let Some(data) = main_page {
+            let (c_meta, fields) = self.content_fields(&s_name, c_id, d_type, data);
+            self.index_content(&s_name, c_id, c_meta, fields, vec![])
+                .await;
+        } else {
+            let _ = s_link
+                .sender
+                .send(ToAppData::ReadPagesRange(Requestor::Search, c_id, 0, 0))
+                .await;
+        }
+    }
+
+    // Only UTF-8 DataTypes chosen by user get their entire bodies indexed,
+    // and only if all pages of given Swarm are stored locally.
+    fn wants_full_text(&self, s_id: SwarmID, s_name: &SwarmName, d_type: DataType) -> bool {
+        let DataType::Data(d_id) = d_type else {
+            return false;
+        };
+        if self.full_text.is_empty()
+            || !self
+                .swarm_links
+                .get(&s_id)
+                .is_some_and(|s_link| s_link.full_pages_stored)
+        {
+            return false;
+        }
+        self.index
+            .swarm_meta(s_name)
+            .and_then(|s_meta| s_meta.d_types.get(&d_id))
+            .is_some_and(|d_name| self.full_text.contains(d_name))
+    }
+
+    async fn read_all_pages(&self, s_id: SwarmID, c_id: ContentID) {
+        if let Some(s_link) = self.swarm_links.get(&s_id) {
+            let _ = s_link
+                .sender
+                .send(ToAppData::ReadAllPages(Requestor::Search, c_id))
+                .await;
+        }
+    }
+
+    // Tag ids are decoded from first page of a Content, or from a Link,
+    // and resolved into names using Tags from Swarm's Manifest.
+    // Hash of first page is also stored, for verifying federated search results.
+    fn content_fields(
+        &mut self,
+        s_name: &SwarmName,
+        c_id: ContentID,
+        d_type: DataType,
+        first_data: Data,
+    ) -> (ContentMeta, Vec<(Field, String)>) {
+        self.index
+            .set_page_hash(s_name, c_id, sha_hash(first_data.ref_bytes()));
+        let (tag_bytes, header) = read_tags_and_header(d_type, first_data);
+        let mut tag_names = String::new();
+        if let Some(s_meta) = self.index.swarm_meta(s_name) {
+            for t_byte in &tag_bytes {
+                for t_name in s_meta.tag_names(*t_byte) {
+                    tag_names.push(' ');
+                    tag_names.push_str(t_name);
+                }
+            }
+        }
+        let c_meta = ContentMeta {
+            d_type,
+            tag_ids: tag_bytes,
+        };
+        (
+            c_meta,
+            vec![(Field::Header, header), (Field::ContentTag, tag_names)],
+        )
+    }
+
+    // Update index with given Content and then refresh Hits of every Query
+    // for that Content only.
+    async fn index_content(
+        &mut self,
+        s_name: &SwarmName,
+        c_id: ContentID,
+        c_meta: ContentMeta,
+        fields: Vec<(Field, String)>,
+        body: Vec<Data>,
+    ) {
+        self.index.update(s_name, c_id, c_meta, fields, body);
+        self.schedule_flush();
+        self.refresh_hits(s_name, vec![c_id]).await;
+    }
+
+    // Evaluate every Query against given Contents and update it's Hits,
+    // this also revalidates Hits restored from disk.
+    // Changed Queries are stored and Manager is notified with next flush.
+    async fn refresh_hits(&mut self, s_name: &SwarmName, c_ids: Vec<ContentID>) {
+        // Permanent queries act as subscriptions,
+        // so we inform user about every Hit added or removed
+        let mut events = vec![];
+        let mut matching_changed = false;
+        for (q_hash, (q, hits)) in &mut self.queries {
+            let mut query_changed = false;
+            for c_id in &c_ids {
+                let c_id = *c_id;
+                let key = (s_name.clone(), c_id);
+                query_changed |= q.stale.remove(&key);
+                // We have our own view of this Content now
+                q.remote.remove(&key);
+                // eprintln!("Q: {}, score: {:?}", &q.text, score);
+                if let Some((score, matched_on)) = q.evaluate(&self.index, s_name, c_id) {
+                    let was_hit = hits.insert(key, (score, matched_on.clone())).is_some();
+                    if !was_hit {
+                        matching_changed = true;
+                        query_changed = true;
+                        if q.is_permanent {
+                            let hit = Hit(s_name.clone(), c_id, score, matched_on, HitState::Fresh);
+                            events.push(ToApp::SearchHitAdded(q.text.clone(), hit));
+                        }
+                    }
+                } else if hits.remove(&key).is_some() {
+                    matching_changed = true;
+                    query_changed = true;
+                    if q.is_permanent {
+                        events.push(ToApp::SearchHitRemoved(
+                            q.text.clone(),
+                            s_name.clone(),
+                            c_id,
+                        ));
+                    }
+                }
+            }
+            if query_changed && q.is_permanent {
+                q.last_run = now();
+                self.dirty_queries.insert(*q_hash);
             }
         }
+        for event in events {
+            let _ = self.to_user.send(event).await;
+        }
+        if matching_changed {
+            self.changed_swarms.insert(s_name.clone());
+        }
+        if !self.dirty_queries.is_empty() || matching_changed {
+            self.schedule_flush();
+        }
     }
 
-    fn iter_queries(&mut self, s_name: &SwarmName, c_id: ContentID, text: &String) -> bool {
-        eprintln!("iter_queries for: {}", text);
-        let mut anything_added = false;
-        for (_h, (q, hits)) in &mut self.queries {
-            // eprintln!("Q: {}", &q.text);
-            let score = q.compare(text);
-            eprintln!("Q: {}, score: {}", &q.text, score);
-            if score > 0 {
-                let hit = Hit(s_name.clone(), c_id, score);
-                hits.insert(hit);
-                anything_added = true;
+    // Hits restored from disk are stale until we know that their Contents
+    // did not change since they were indexed, changed Contents
+    // get revalidated once they are indexed again.
+    async fn revalidate_hits(&mut self, s_name: &SwarmName, c_hashes: &[(DataType, u64)]) {
+        let mut unchanged = HashSet::new();
+        for (query, _hits) in self.queries.values() {
+            for (st_name, c_id) in &query.stale {
+                if st_name != s_name {
+                    continue;
+                }
+                let current = c_hashes.get(*c_id as usize).copied();
+                if current.is_none() || self.index.content_hash(s_name, *c_id) == current {
+                    unchanged.insert(*c_id);
+                }
+            }
+        }
+        if !unchanged.is_empty() {
+            self.refresh_hits(s_name, unchanged.into_iter().collect())
+                .await;
+        }
+    }
+
+    // Write permanent Query along with it's Hits to disk
+    async fn store_query(&self, q_hash: u64) {
+        let Some((query, hits)) = self.queries.get(&q_hash) else {
+            return;
+        };
+        if !query.is_permanent {
+            return;
+        }
+        let mut saved_hits = Vec::with_capacity(hits.len());
+        for ((s_name, c_id), (score, _matched_on)) in hits {
+            saved_hits.push(SavedHit {
+                s_name: s_name.clone(),
+                c_id: *c_id,
+                c_hash: self.index.content_hash(s_name, *c_id),
+                score: *score,
+            });
+        }
+        let saved = SavedSearch {
+            version: 1,
+            text: query.text.clone(),
+            is_permanent: query.is_permanent,
+            stemming: self.index.tokenizer().stemming(),
+            created: query.created,
+            last_run: query.last_run,
+            hits: saved_hits,
+        };
+        let mut f_path = self.search_path.clone();
+        f_path.push(format!("{}", q_hash));
+        if let Err(e) = smol::fs::write(f_path, saved.to_bytes()).await {
+            eprintln!("Failed to store search {}: {:?}", query.text, e);
+        }
+    }
+
+    // Restore a permanent Query from disk, with it's Hits marked as stale.
+    // If it was stored in old format, or with a different tokenizer,
+    // we evaluate it against index instead.
+    async fn restore_query(&mut self, saved: SavedSearch) -> Result<(), QueryError> {
+        let phrase = saved.text.trim().to_string();
+        let q_hash = sha_hash(phrase.as_bytes());
+        if saved.version == 0 || saved.stemming != self.index.tokenizer().stemming() {
+            self.add_query(phrase, true).await?;
+            if let Some((query, _hits)) = self.queries.get_mut(&q_hash) {
+                query.created = saved.created;
             }
+            self.store_query(q_hash).await;
+            return Ok(());
+        }
+        let mut query = Query::parse(phrase, true, self.index.tokenizer())?;
+        query.created = saved.created;
+        query.last_run = saved.last_run;
+        let mut hits = HashMap::with_capacity(saved.hits.len());
+        for hit in saved.hits {
+            let matched_on = self.index.matched_on(&query.terms, &hit.s_name, hit.c_id);
+            let key = (hit.s_name, hit.c_id);
+            query.stale.insert(key.clone());
+            hits.insert(key, (hit.score, matched_on));
+        }
+        self.queries.insert(q_hash, (query, hits));
+        Ok(())
+    }
+
+    // Ask our Neighbors in every Swarm to run given Query against their indexes
+    async fn search_neighbors(&mut self, phrase: &str) {
+        if !self.federated {
+            return;
+        }
+        let phrase = phrase.trim();
+        let Some((query, _hits)) = self.queries.get_mut(&sha_hash(phrase.as_bytes())) else {
+            return;
+        };
+        let request = encode_request(MAX_REMOTE_RESULTS, phrase);
+        for s_link in self.swarm_links.values() {
+            query.asked.insert(s_link.s_name.clone(), None);
+            let _ = s_link
+                .sender
+                .send(ToAppData::SearchNeighbors(request.clone()))
+                .await;
         }
-        anything_added
     }
+
+    // A Neighbor asked us to search our index,
+    // we answer with best Hits, each with it's first page hash.
+    // Only Contents of the Swarm given request came through are searched,
+    // and Query has to contain at least one word to match.
+    async fn answer_neighbor(
+        &mut self,
+        sender: Sender<ToAppData>,
+        s_name: SwarmName,
+        g_id: GnomeId,
+        bytes: Vec<u8>,
+    ) {
+        if !self.federated {
+            return;
+        }
+        if !self.rate_limiter.allow(g_id) {
+            eprintln!("Too many search queries from {}, ignoring", g_id.0);
+            return;
+        }
+        let Some((limit, text)) = decode_request(bytes) else {
+            return;
+        };
+        let Ok(query) = Query::parse(text.clone(), false, self.index.tokenizer()) else {
+            return;
+        };
+        if query.terms.is_empty() {
+            return;
+        }
+        let mut hits = vec![];
+        for (c_s_name, c_id) in self.index.candidates(&query.terms) {
+            if c_s_name != s_name {
+                continue;
+            }
+            let Some(page_hash) = self.index.page_hash(&s_name, c_id) else {
+                continue;
+            };
+            if let Some((score, _matched_on)) = query.evaluate(&self.index, &s_name, c_id) {
+                hits.push(RemoteResult {
+                    s_name: c_s_name,
+                    c_id,
+                    score: score.value,
+                    page_hash,
+                });
+            }
+        }
+        hits.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        hits.truncate(limit as usize);
+        let response = encode_response(sha_hash(text.as_bytes()), hits);
+        let _ = sender.send(ToAppData::SearchRespond(g_id, response)).await;
+    }
+
+    // A Neighbor answered our Query.
+    // Results are kept as remote until we have first page of given Content,
+    // then it is evaluated against our own index like any other Content.
+    // Only one response per Swarm we asked through is accepted,
+    // with results from that Swarm, and at most MAX_REMOTE_RESULTS
+    // are kept for a Query.
+    // Returns phrase of given Query if it's Hits changed.
+    async fn remote_results(
+        &mut self,
+        s_name: SwarmName,
+        g_id: GnomeId,
+        bytes: Vec<u8>,
+    ) -> Option<String> {
+        if !self.federated {
+            return None;
+        }
+        let (q_hash, results) = decode_response(bytes)?;
+        let (query, hits) = self.queries.get_mut(&q_hash)?;
+        match query.asked.get_mut(&s_name) {
+            Some(answered_by @ None) => *answered_by = Some(g_id),
+            _other => {
+                eprintln!("Unsolicited search results from {}", g_id.0);
+                return None;
+            }
+        }
+        let mut verified = HashMap::new();
+        let mut to_read = vec![];
+        let mut any_added = false;
+        for result in results {
+            if result.s_name != s_name {
+                continue;
+            }
+            if query.remote.len() >= MAX_REMOTE_RESULTS as usize {
+                break;
+            }
+            let key = (result.s_name.clone(), result.c_id);
+            if hits.contains_key(&key) || query.remote.contains_key(&key) {
+                continue;
+            }
+            if self.index.page_hash(&result.s_name, result.c_id) == Some(result.page_hash) {
+                // We have indexed the same first page,
+                // so our own index decides
+                verified
+                    .entry(result.s_name)
+                    .or_insert_with(Vec::new)
+                    .push(result.c_id);
+                continue;
+            }
+            let score = Score {
+                value: result.score,
+                matched: 0,
+                total: query.terms.len().min(u8::MAX as usize) as u8,
+                phrase: false,
+            };
+            query
+                .remote
+                .insert(key.clone(), (g_id, result.page_hash, score));
+            any_added = true;
+            to_read.push(key);
+        }
+        let phrase = query.text.clone();
+        for (s_name, c_ids) in verified {
+            self.refresh_hits(&s_name, c_ids).await;
+        }
+        // For Swarms we have joined we can read first page right away
+        for (s_name, c_id) in to_read {
+            if let Some(s_link) = self.swarm_links.values().find(|s_l| s_l.s_name == s_name) {
+                let _ = s_link
+                    .sender
+                    .send(ToAppData::ReadPagesRange(Requestor::Search, c_id, 0, 0))
+                    .await;
+            }
+        }
+        if any_added {
+            Some(phrase)
+        } else {
+            None
+        }
+    }
+
     async fn advance_to_next_swarm(&mut self) -> bool {
         let mut any_swarm_inquired = false;
         eprintln!("In advance_to_next_swarm");
@@ -456,45 +1199,56 @@ impl Engine {
                                 .send(ToAppData::ReadAllPages(Requestor::Search, 0))
                                 .await;
                         }
+                    }
+                    if self.start_indexing(s_id, &mut swarms_to_inquire).await {
                         any_swarm_inquired = true;
-                        let mut queried_cids = vec![];
-                        for c_id in 1..=s_link.max_cid {
-                            queried_cids.push(c_id);
-                        }
-                        self.state = EngineState::Processing(
-                            s_id,
-                            s_link.sender.clone(),
-                            queried_cids,
-                            swarms_to_inquire,
-                        );
-                        // TODO: only query for some chunk of contents, not everything at once
-                        eprintln!("3 Requesting all first pages for: {}", s_id);
-                        let _ = s_link
-                            .sender
-                            .send(ToAppData::ReadAllFirstPages(Requestor::Search, None))
-                            .await;
                         break;
-                    } else {
-                        eprintln!(" Could not find SwarmLink for {}", s_id);
                     }
                 }
                 any_swarm_inquired
             }
         }
     }
+    // Send an update to manager only for given swarms,
+    // a swarm with no Hits is sent with an empty list,
+    // so that it's storage policy can be changed back.
+    async fn notify_manager_about_swarms(&self, s_names: HashSet<SwarmName>) {
+        if s_names.is_empty() {
+            return;
+        }
+        let mut s_res: HashMap<SwarmName, HashSet<ContentID>> = HashMap::new();
+        for s_name in s_names {
+            s_res.insert(s_name, HashSet::new());
+        }
+        for (_hsh, (_q, hits)) in &self.queries {
+            for (s_name, c_id) in hits.keys() {
+                if let Some(c_set) = s_res.get_mut(s_name) {
+                    c_set.insert(*c_id);
+                }
+            }
+        }
+        let mut s_res_vec: Vec<(SwarmName, Vec<ContentID>)> = Vec::with_capacity(s_res.len());
+        for (s_n, cid_set) in s_res {
+            s_res_vec.push((s_n, cid_set.into_iter().collect()));
+        }
+        let _ = self
+            .to_app_mgr
+            .send(ToAppMgr::SearchSummary(s_res_vec))
+            .await;
+    }
     async fn notify_manager_about_searches(&self) {
         eprintln!("in notify_manager_about_searches");
         // TODO: needs rework
         //TODO: first we need to collect CIDs by SwarmID
         let mut s_res: HashMap<SwarmName, HashSet<u16>> = HashMap::new();
         for (_hsh, (q, hits)) in &self.queries {
-            for hit in hits {
-                if let Some(c_vec) = s_res.get_mut(&hit.0) {
-                    c_vec.insert(hit.1);
+            for (s_name, c_id) in hits.keys() {
+                if let Some(c_vec) = s_res.get_mut(s_name) {
+                    c_vec.insert(*c_id);
                 } else {
                     let mut n_set = HashSet::new();
-                    n_set.insert(hit.1);
-                    s_res.insert(hit.0.clone(), n_set);
+                    n_set.insert(*c_id);
+                    s_res.insert(s_name.clone(), n_set);
                 }
             }
         }
@@ -509,48 +1263,283 @@ impl Engine {
             .await;
     }
 }
+// A Query can contain:
+// - plain words, Contents containing any of them are ranked with BM25,
+// - "quoted phrases", that have to be found within a single field,
+// - filters in form of key:value:
+//   tag:name       - Content has a Tag with given name assigned,
+//   swarm:name     - Content belongs to a Swarm with given name,
+//   founder:id     - Content belongs to a Swarm founded by given GnomeId,
+//   app:catalog|forum|N - Swarm is of given AppType,
+//   type:link|N    - Content is of given DataType.
+// Any of above can be negated by prepending it with '-'.
+// Filter values can also be quoted: tag:"two words".
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryError {
+    UnterminatedQuote,
+    EmptyFilter(String),
+    UnknownFilter(String),
+    InvalidFounder(String),
+    InvalidAppType(String),
+    InvalidDataType(String),
+    NothingToMatch,
+    TooLong(usize),
+}
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnterminatedQuote => write!(f, "Missing closing quote"),
+            Self::EmptyFilter(key) => write!(f, "No value given for {}:", key),
+            Self::UnknownFilter(key) => write!(f, "Unknown filter {}:", key),
+            Self::InvalidFounder(val) => write!(f, "Invalid founder: {}", val),
+            Self::InvalidAppType(val) => write!(f, "Invalid app type: {}", val),
+            Self::InvalidDataType(val) => write!(f, "Invalid data type: {}", val),
+            Self::NothingToMatch => write!(f, "Query has nothing to match"),
+            Self::TooLong(len) => write!(f, "Query is too long: {} bytes", len),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Filter {
+    Tag(String),
+    Swarm(String),
+    Founder(GnomeId),
+    App(AppType),
+    Type(DataType),
+}
+impl Filter {
+    fn parse(key: &str, value: String) -> Result<Self, QueryError> {
+        if value.is_empty() {
+            return Err(QueryError::EmptyFilter(key.to_string()));
+        }
+        match key {
+            "tag" => Ok(Filter::Tag(value.to_lowercase())),
+            "swarm" => Ok(Filter::Swarm(value.to_lowercase())),
+            "founder" => {
+                if let Some(g_id) = GnomeId::from_string(value.clone()) {
+                    Ok(Filter::Founder(g_id))
+                } else {
+                    Err(QueryError::InvalidFounder(value))
+                }
+            }
+            "app" => match value.to_lowercase().as_str() {
+                "catalog" => Ok(Filter::App(AppType::Catalog)),
+                "forum" => Ok(Filter::App(AppType::Forum)),
+                other => {
+                    if let Ok(byte) = other.parse::<u8>() {
+                        Ok(Filter::App(AppType::from(byte)))
+                    } else {
+                        Err(QueryError::InvalidAppType(value))
+                    }
+                }
+            },
+            "type" => match value.to_lowercase().as_str() {
+                "link" => Ok(Filter::Type(DataType::Link)),
+                other => {
+                    if let Ok(byte) = other.parse::<u8>() {
+                        Ok(Filter::Type(DataType::from(byte)))
+                    } else {
+                        Err(QueryError::InvalidDataType(value))
+                    }
+                }
+            },
+            other => Err(QueryError::UnknownFilter(other.to_string())),
+        }
+    }
+
+    fn matches(&self, index: &SearchIndex, s_name: &SwarmName, c_id: ContentID) -> bool {
+        match self {
+            Filter::Tag(name) => {
+                let Some(s_meta) = index.swarm_meta(s_name) else {
+                    return false;
+                };
+                let Some(c_meta) = index.content_meta(s_name, c_id) else {
+                    return false;
+                };
+                c_meta.tag_ids.iter().any(|t_id| {
+                    s_meta
+                        .tag_names(*t_id)
+                        .any(|t_name| t_name.trim().to_lowercase() == *name)
+                })
+            }
+            Filter::Swarm(name) => s_name.name.to_lowercase() == *name,
+            Filter::Founder(g_id) => s_name.founder.0 == g_id.0,
+            Filter::App(app_type) => index
+                .swarm_meta(s_name)
+                .is_some_and(|s_meta| s_meta.app_type == Some(*app_type)),
+            Filter::Type(d_type) => index
+                .content_meta(s_name, c_id)
+                .is_some_and(|c_meta| c_meta.d_type == *d_type),
+        }
+    }
+}
+
 struct Query {
     text: String,
-    words: HashSet<String>,
+    // all positive words, including those from phrases, used for ranking
+    terms: Vec<String>,
+    phrases: Vec<Vec<String>>,
+    // negated words and phrases
+    excluded: Vec<Vec<String>>,
+    // bool indicates if given filter is negated
+    filters: Vec<(bool, Filter)>,
     is_permanent: bool,
+    // seconds since UNIX epoch
+    created: u64,
+    last_run: u64,
+    // Hits restored from disk, not yet revalidated
+    stale: HashSet<(SwarmName, ContentID)>,
+    // Hits reported by Neighbors, with first page hash they were found for,
+    // those are never stored on disk
+    remote: HashMap<(SwarmName, ContentID), (GnomeId, u64, Score)>,
+    // Swarms we asked a Neighbor through, with that Neighbor once it answers.
+    // Request goes to any single Neighbor, so only first response
+    // received through given Swarm is accepted.
+    asked: HashMap<SwarmName, Option<GnomeId>>,
 }
 impl Query {
-    pub fn new(text: String, is_permanent: bool) -> Self {
-        let mut words = HashSet::new();
-        for word in text.split_whitespace() {
-            words.insert(word.to_string());
+    pub fn parse(
+        text: String,
+        is_permanent: bool,
+        tokenizer: Tokenizer,
+    ) -> Result<Self, QueryError> {
+        if text.len() > MAX_QUERY_LEN {
+            return Err(QueryError::TooLong(text.len()));
+        }
+        let mut terms = vec![];
+        let mut phrases = vec![];
+        let mut excluded = vec![];
+        let mut filters = vec![];
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            let negated = *c == '-';
+            if negated {
+                chars.next();
+            }
+            let (word, quoted) = read_word(&mut chars)?;
+            if !quoted {
+                if let Some((key, value)) = word.split_once(':') {
+                    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphabetic()) {
+                        let key = key.to_lowercase();
+                        let filter = Filter::parse(&key, value.to_string())?;
+                        filters.push((negated, filter));
+                        continue;
+                    }
+                }
+            }
+            let mut words = tokenizer.tokenize(&word);
+            if words.is_empty() {
+                continue;
+            }
+            if negated {
+                excluded.push(words);
+            } else {
+                if quoted && words.len() > 1 {
+                    phrases.push(words.clone());
+                }
+                terms.append(&mut words);
+            }
+        }
+        let any_positive_filter = filters.iter().any(|(negated, _f)| !negated);
+        if terms.is_empty() && !any_positive_filter {
+            return Err(QueryError::NothingToMatch);
         }
-        Query {
+        Ok(Query {
             text,
-            words,
+            terms,
+            phrases,
+            excluded,
+            filters,
             is_permanent,
-        }
+            created: now(),
+            last_run: 0,
+            stale: HashSet::new(),
+            remote: HashMap::new(),
+            asked: HashMap::new(),
+        })
     }
 
-    pub fn compare(&self, phrase: &String) -> u8 {
-        eprintln!("{} contains: {}  ?", self.text, phrase);
-        if phrase.contains(&self.text) {
-            return 101;
+    // Returns a Score and matched fields if given Content satisfies this Query
+    pub fn evaluate(
+        &self,
+        index: &SearchIndex,
+        s_name: &SwarmName,
+        c_id: ContentID,
+    ) -> Option<(Score, Vec<MatchedOn>)> {
+        let mut matched_on = vec![];
+        for (negated, filter) in &self.filters {
+            if filter.matches(index, s_name, c_id) == *negated {
+                return None;
+            }
+            if let (false, Filter::Tag(t_name)) = (*negated, filter) {
+                matched_on.push(MatchedOn::ContentTag(t_name.clone()));
+            }
         }
-        eprintln!("Words: {:?}", self.words);
-        let mut score = 0;
-        let splited = phrase.split_whitespace();
-        for word in splited {
-            eprintln!("Test word: {}", word);
-            if self.words.contains(word) {
-                score += 1;
+        for phrase in &self.phrases {
+            if !index.contains_phrase(s_name, c_id, phrase) {
+                return None;
             }
         }
-        let res = (100 * score / self.words.len()) as u8;
-        eprintln!(
-            "score: {}, total: {}, res: {}",
-            score,
-            self.words.len(),
-            res
-        );
-        res
+        for words in &self.excluded {
+            if index.contains_phrase(s_name, c_id, words) {
+                return None;
+            }
+        }
+        if self.terms.is_empty() {
+            // Only filters were given
+            let score = Score {
+                value: 0.0,
+                matched: 0,
+                total: 0,
+                phrase: false,
+            };
+            return Some((score, matched_on));
+        }
+        let score = index.score(&self.terms, s_name, c_id)?;
+        for m_on in index.matched_on(&self.terms, s_name, c_id) {
+            if !matched_on.contains(&m_on) {
+                matched_on.push(m_on);
+            }
+        }
+        Some((score, matched_on))
     }
 }
+
+// Reads a single whitespace delimited word, text within quotes
+// is read as a single word, including whitespace.
+// Returned bool indicates if entire word was quoted.
+fn read_word(chars: &mut Peekable<Chars>) -> Result<(String, bool), QueryError> {
+    let mut word = String::new();
+    let quoted = chars.peek() == Some(&'"');
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            let mut closed = false;
+            for q_c in chars.by_ref() {
+                if q_c == '"' {
+                    closed = true;
+                    break;
+                }
+                word.push(q_c);
+            }
+            if !closed {
+                return Err(QueryError::UnterminatedQuote);
+            }
+            if quoted {
+                break;
+            }
+        } else if c.is_whitespace() {
+            break;
+        } else {
+            word.push(c);
+        }
+    }
+    Ok((word, quoted))
+}
 #[derive(Debug)]
 pub enum SearchMsg {
     AddQuery(String),
@@ -559,26 +1548,66 @@ pub enum SearchMsg {
     GetResults(String),
     SetFlag(String, bool),
     SwarmSynced(SwarmID, SwarmLink),
-    FirstPages(SwarmID, SwarmName, Vec<(ContentID, DataType, Data)>),
-    ReadSuccess(SwarmID, SwarmName, ContentID, DataType, Vec<Data>),
+    FirstPages(
+        SwarmID,
+        SwarmName,
+        Option<(ContentID, ContentID)>,
+        Vec<(ContentID, DataType, Data)>,
+    ),
+    ReadSuccess(
+        SwarmID,
+        SwarmName,
+        ContentID,
+        DataType,
+        u16,
+        bool,
+        Vec<Data>,
+    ), // u16 = starting page, bool = is_last
     ReadError(SwarmID, ContentID, AppError),
+    ContentUpdated(SwarmID, ContentID, DataType, Option<Data>),
     AppDataTerminated(SwarmID),
+    Tick, // Sent internally when Crawler can request next range, or it's time to flush
+    RemoteQuery(Sender<ToAppData>, SwarmName, GnomeId, Vec<u8>),
+    RemoteResults(SwarmName, GnomeId, Vec<u8>),
+}
+
+// Wait for next message, but no longer than until given deadline
+async fn next_message(
+    response: &Receiver<SearchMsg>,
+    deadline: Option<Instant>,
+) -> Result<SearchMsg, RecvError> {
+    if let Some(deadline) = deadline {
+        response
+            .recv()
+            .or(async {
+                Timer::at(deadline).await;
+                Ok(SearchMsg::Tick)
+            })
+            .await
+    } else {
+        response.recv().await
+    }
 }
 pub async fn serve_search_engine(
     search_path: PathBuf,
+    settings: SearchSettings,
     to_user: Sender<ToApp>,
     to_app_mgr: Sender<ToAppMgr>,
     //TODO: replace LibResponse with a dedicated struct
     response: Receiver<SearchMsg>,
 ) {
-    let mut engine = Engine::new(search_path, to_app_mgr).await;
+    let mut engine = Engine::new(search_path, settings, to_user.clone(), to_app_mgr).await;
     loop {
-        while let Ok(message) = response.recv().await {
+        while let Ok(message) = next_message(&response, engine.deadline()).await {
             eprintln!("SearchEngine received: {:?}", message);
             match message {
                 SearchMsg::AddQuery(phrase) => {
                     eprintln!("Added new Search, slinks: {}", engine.swarm_links.len());
-                    engine.add_query(phrase, false).await;
+                    if let Err(error) = engine.add_query(phrase.clone(), false).await {
+                        let _ = to_user.send(ToApp::SearchQueryError(phrase, error)).await;
+                    } else {
+                        engine.search_neighbors(&phrase).await;
+                    }
                     // for link in engine.swarm_links.values() {
                     //     for c_id in 0..=link.max_cid {
                     //         link.sender
@@ -588,7 +1617,7 @@ pub async fn serve_search_engine(
                     // }
                 }
                 SearchMsg::DelQuery(phrase) => {
-                    engine.del_query(phrase);
+                    engine.del_query(phrase).await;
                 }
                 SearchMsg::ListQueries => {
                     let _ = to_user.send(ToApp::SearchQueries(engine.summary())).await;
@@ -608,50 +1637,159 @@ pub async fn serve_search_engine(
                 }
                 SearchMsg::SwarmSynced(s_id, s_link) => {
                     // eprintln!("search SwarmSynced {s_id}");
-                    // TODO: check if root_hash changed
-                    // TODO: create a mechanism to enqueue subsequent swarms
-                    //       until we are done with current one,
-                    //       or something with that flavor
-                    let _ = s_link
-                        .sender
-                        .send(ToAppData::ReadPagesRange(Requestor::Search, 0, 0, 63))
-                        .await;
-                    engine.swarm_links.insert(s_id, s_link);
+                    engine.swarm_synced(s_id, s_link).await;
                 }
-                SearchMsg::FirstPages(s_id, s_name, first_pages) => {
+                SearchMsg::FirstPages(s_id, s_name, range, first_pages) => {
                     eprintln!(
                         "SearchEngine received requested first pages from {}",
                         s_name
                     );
-                    if engine.has_queries() {
-                        engine.parse_first_pages(s_id, s_name, first_pages).await;
-                    }
-                }
-                SearchMsg::ReadSuccess(s_id, s_name, c_id, d_type, data_vec) => {
                     engine
-                        .parse_content(s_id, s_name, c_id, d_type, data_vec)
+                        .parse_first_pages(s_id, s_name, range, first_pages)
                         .await;
+                }
+                SearchMsg::ReadSuccess(s_id, s_name, c_id, d_type, start, is_last, data_vec) => {
+                    if let Some(pages) = engine.collect_pages(s_id, c_id, start, is_last, data_vec)
+                    {
+                        engine
+                            .parse_content(s_id, s_name, c_id, d_type, pages)
+                            .await;
+                    }
                     // }
                 }
                 SearchMsg::ReadError(s_id, c_id, _err) => {
                     eprintln!("search ReadError {s_id}-{c_id} {:?}", _err);
-                    let advance_to_next_swarm = engine.state.processing_done(s_id, vec![c_id]);
-                    if advance_to_next_swarm {
-                        let any_swarm_queried = engine.advance_to_next_swarm().await;
-                        if !any_swarm_queried {
-                            // TODO: we are done searching, we should send results to manager
-                            // to adjust storage policy
-                            engine.notify_manager_about_searches().await;
-                        }
-                    }
+                    engine.partial_reads.remove(&(s_id, c_id));
+                    engine.contents_done(s_id, vec![c_id]).await;
+                }
+                SearchMsg::ContentUpdated(s_id, c_id, d_type, main_page) => {
+                    engine.content_updated(s_id, c_id, d_type, main_page).await;
                 }
                 SearchMsg::AppDataTerminated(s_id) => {
-                    engine.swarm_links.remove(&s_id);
-                    engine.tags.remove(&s_id);
+                    engine.swarm_terminated(s_id).await;
+                }
+                SearchMsg::Tick => {
+                    engine.tick().await;
+                }
+                SearchMsg::RemoteQuery(sender, s_name, g_id, bytes) => {
+                    engine.answer_neighbor(sender, s_name, g_id, bytes).await;
+                }
+                SearchMsg::RemoteResults(s_name, g_id, bytes) => {
+                    if let Some(phrase) = engine.remote_results(s_name, g_id, bytes).await {
+                        let (phrase, is_permanent, results) = engine.get_query(phrase);
+                        let _ = to_user
+                            .send(ToApp::SearchResults(phrase, is_permanent, results))
+                            .await;
+                    }
                 }
             }
         }
         break;
     }
+    engine.flush().await;
     eprintln!("SearchEngine is done.");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Query, QueryError> {
+        Query::parse(text.to_string(), false, Tokenizer::new(false))
+    }
+
+    #[test]
+    fn query_splits_words_phrases_and_negations() {
+        let query = parse(r#"Gnome "swarm network" -spam -"bad words""#).unwrap();
+        assert_eq!(query.terms, vec!["gnome", "swarm", "network"]);
+        assert_eq!(query.phrases, vec![vec!["swarm", "network"]]);
+        assert_eq!(query.excluded, vec![vec!["spam"], vec!["bad", "words"]]);
+        assert!(query.filters.is_empty());
+    }
+
+    #[test]
+    fn query_parses_filters() {
+        let query =
+            parse(r#"tag:"Two Words" -swarm:Test founder:00000000000000ff app:forum type:link"#)
+                .unwrap();
+        assert!(query.terms.is_empty());
+        assert_eq!(query.filters.len(), 5);
+        assert!(matches!(&query.filters[0], (false, Filter::Tag(t)) if t == "two words"));
+        assert!(matches!(&query.filters[1], (true, Filter::Swarm(s)) if s == "test"));
+        assert!(matches!(&query.filters[2], (false, Filter::Founder(g)) if g.0 == 255));
+        assert!(matches!(
+            &query.filters[3],
+            (false, Filter::App(AppType::Forum))
+        ));
+        assert!(matches!(
+            &query.filters[4],
+            (false, Filter::Type(DataType::Link))
+        ));
+        // Not a filter key, so it is tokenized
+        let query = parse("v2:beta").unwrap();
+        assert_eq!(query.terms, vec!["v2", "beta"]);
+    }
+
+    #[test]
+    fn query_reports_errors() {
+        assert_eq!(
+            parse(r#"open "quote"#).err(),
+            Some(QueryError::UnterminatedQuote)
+        );
+        assert_eq!(
+            parse("color:red").err(),
+            Some(QueryError::UnknownFilter("color".to_string()))
+        );
+        assert_eq!(
+            parse("tag:").err(),
+            Some(QueryError::EmptyFilter("tag".to_string()))
+        );
+        assert_eq!(
+            parse("app:chess").err(),
+            Some(QueryError::InvalidAppType("chess".to_string()))
+        );
+        assert_eq!(
+            parse("-only -negated").err(),
+            Some(QueryError::NothingToMatch)
+        );
+        assert_eq!(parse(" , ").err(), Some(QueryError::NothingToMatch));
+        let long = "a".repeat(MAX_QUERY_LEN + 1);
+        assert_eq!(
+            parse(&long).err(),
+            Some(QueryError::TooLong(MAX_QUERY_LEN + 1))
+        );
+    }
+
+    #[test]
+    fn query_evaluates_filters_against_any_tag_name() {
+        let dir = std::env::temp_dir().join(format!("dapp-lib-query-{}", std::process::id()));
+        let mut index = SearchIndex::new(&dir, Tokenizer::new(false));
+        let s_name = SwarmName::new(GnomeId(1), "/test".to_string()).unwrap();
+        let mut s_meta = SwarmMeta::default();
+        s_meta.tags.insert(1, "Music".to_string());
+        s_meta
+            .tag_translations
+            .insert(1, vec!["Muzyka".to_string()]);
+        index.set_swarm_meta(&s_name, s_meta);
+        let c_meta = |tag_ids: Vec<u8>| ContentMeta {
+            d_type: DataType::from(0),
+            tag_ids,
+        };
+        let header = |text: &str| vec![(Field::Header, text.to_string())];
+        index.update(&s_name, 1, c_meta(vec![1]), header("loud song"), vec![]);
+        index.update(&s_name, 2, c_meta(vec![]), header("quiet song"), vec![]);
+
+        let query = parse("song tag:muzyka").unwrap();
+        assert!(query.evaluate(&index, &s_name, 1).is_some());
+        assert!(query.evaluate(&index, &s_name, 2).is_none());
+        let query = parse("song -tag:music").unwrap();
+        assert!(query.evaluate(&index, &s_name, 1).is_none());
+        assert!(query.evaluate(&index, &s_name, 2).is_some());
+        let query = parse(r#""quiet song""#).unwrap();
+        assert!(query.evaluate(&index, &s_name, 1).is_none());
+        let (score, matched_on) = query.evaluate(&index, &s_name, 2).unwrap();
+        assert!(score.phrase);
+        assert_eq!(matched_on, vec![MatchedOn::Header]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
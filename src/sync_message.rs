@@ -1,4 +1,4 @@
-use crate::{content::DataType, prelude::ContentID, Data};
+use crate::{content::DataType, prelude::AppError, prelude::ContentID, Data};
 pub fn serialize_requests(requests: Vec<SyncRequest>) -> Vec<u8> {
     let mut bytes = vec![];
     for req in requests {
@@ -56,12 +56,18 @@ pub fn serialize_requests(requests: Vec<SyncRequest>) -> Vec<u8> {
                     bytes.push(c_2);
                 }
             }
+            SyncRequest::MissingParts(msg_hash, part_nos) => {
+                bytes.push(5);
+                bytes.extend_from_slice(&msg_hash.to_be_bytes());
+                bytes.push(part_nos.len() as u8);
+                bytes.extend_from_slice(&part_nos);
+            }
         }
     }
     // eprintln!("serialize_requests: {:?}", bytes);
     bytes
 }
-pub fn deserialize_requests(bytes: Vec<u8>) -> Vec<SyncRequest> {
+pub fn deserialize_requests(bytes: Vec<u8>) -> Result<Vec<SyncRequest>, AppError> {
     // eprintln!("deserialize_requests: {:?}", bytes);
     let mut requests = vec![];
     let mut bytes_iter = bytes.into_iter();
@@ -132,12 +138,35 @@ pub fn deserialize_requests(bytes: Vec<u8>) -> Vec<SyncRequest> {
                 }
                 requests.push(SyncRequest::AllPages(c_ids));
             }
+            5 => {
+                let Some(request) = read_missing_parts(&mut bytes_iter) else {
+                    return Err(AppError::MalformedRequest);
+                };
+                requests.push(request);
+            }
             other => {
                 println!("Unexpected byte: {}", other);
             }
         }
     }
-    requests
+    Ok(requests)
+}
+
+// Neighbors can send anything, so we do not trust given part count
+fn read_missing_parts(bytes_iter: &mut impl Iterator<Item = u8>) -> Option<SyncRequest> {
+    let mut hash = [0; 8];
+    for byte in hash.iter_mut() {
+        *byte = bytes_iter.next()?;
+    }
+    let count = bytes_iter.next()?;
+    let mut part_nos = Vec::with_capacity(count as usize);
+    for _i in 0..count {
+        part_nos.push(bytes_iter.next()?);
+    }
+    Some(SyncRequest::MissingParts(
+        u64::from_be_bytes(hash),
+        part_nos,
+    ))
 }
 
 #[derive(Debug)]
@@ -147,6 +176,8 @@ pub enum SyncRequest {
     Hashes(ContentID, Vec<u16>),
     Pages(ContentID, DataType, Vec<u16>),
     AllPages(Vec<ContentID>),
+    // Message hash and part numbers to re-post to Swarm
+    MissingParts(u64, Vec<u8>),
 }
 
 #[derive(Debug)]
@@ -0,0 +1,305 @@
+use crate::content::ContentID;
+use crate::content::DataType;
+use crate::sync_message::SyncRequest;
+use gnome::prelude::GnomeId;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::time::Duration;
+use std::time::Instant;
+
+// Requests for Content hashes and pages that we send to our Neighbors
+// can get lost or be ignored. Every such request is tracked by serve_app_data
+// until it is answered. When a request has no answer before it's deadline
+// it is sent again, every time to a different Neighbor if we know any,
+// and deadline doubles with every attempt.
+// Any answer to given request moves it's deadline, so that large
+// Contents are not requested again while they are still being sent.
+// After MAX_ATTEMPTS we give up and report a ReadError.
+//
+// Neighbors are learned from responses they have sent us,
+// if we do not know any we ask any Neighbor excluding the one
+// we asked last time.
+const BASE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_ATTEMPTS: u8 = 4;
+// Same as when requesting pages for the first time,
+// more missing pages are split into several requests
+const MAX_PAGES_PER_REQUEST: usize = 680;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Kind {
+    Hashes,
+    AllPages,
+    Pages,
+}
+
+struct Tracked {
+    d_type: Option<DataType>,
+    missing_pages: HashSet<u16>,
+    attempt: u8,
+    deadline: Instant,
+    asked: GnomeId,
+}
+
+impl Tracked {
+    fn new(d_type: Option<DataType>, missing_pages: HashSet<u16>, asked: GnomeId) -> Self {
+        Tracked {
+            d_type,
+            missing_pages,
+            attempt: 1,
+            deadline: Instant::now() + BASE_TIMEOUT,
+            asked,
+        }
+    }
+}
+
+pub enum Retry {
+    // Neighbor to ask, one to exclude when asking any, and request to send
+    Resend(GnomeId, Option<GnomeId>, SyncRequest),
+    GiveUp(ContentID),
+}
+
+pub struct RequestTracker {
+    requests: HashMap<(ContentID, Kind), Tracked>,
+    neighbors: Vec<GnomeId>,
+    next_neighbor: usize,
+}
+
+impl RequestTracker {
+    pub fn new() -> Self {
+        RequestTracker {
+            requests: HashMap::new(),
+            neighbors: vec![],
+            next_neighbor: 0,
+        }
+    }
+
+    pub fn track_hashes(&mut self, c_id: ContentID, asked: GnomeId) {
+        self.requests.insert(
+            (c_id, Kind::Hashes),
+            Tracked::new(None, HashSet::new(), asked),
+        );
+    }
+
+    pub fn track_all_pages(&mut self, c_id: ContentID, asked: GnomeId) {
+        self.requests.insert(
+            (c_id, Kind::AllPages),
+            Tracked::new(None, HashSet::new(), asked),
+        );
+    }
+
+    pub fn track_pages(&mut self, c_id: ContentID, d_type: DataType, pages: &[u16]) {
+        let tracked = self
+            .requests
+            .entry((c_id, Kind::Pages))
+            .or_insert_with(|| Tracked::new(Some(d_type), HashSet::new(), GnomeId::any()));
+        tracked.missing_pages.extend(pages);
+    }
+
+    /// Any Neighbor that has sent us a response can be asked
+    /// when a request needs to be sent again.
+    pub fn heard_from(&mut self, neighbor: GnomeId) {
+        if !self.neighbors.iter().any(|g_id| g_id.0 == neighbor.0) {
+            self.neighbors.push(neighbor);
+        }
+    }
+
+    // part_no equal to total means last part
+    pub fn hashes_received(&mut self, c_id: ContentID, part_no: u16, total: u16) {
+        if part_no >= total {
+            self.requests.remove(&(c_id, Kind::Hashes));
+        } else if let Some(tracked) = self.requests.get_mut(&(c_id, Kind::Hashes)) {
+            tracked.deadline = Instant::now() + BASE_TIMEOUT;
+        }
+    }
+
+    pub fn page_received(&mut self, c_id: ContentID, page_no: u16, total: u16) {
+        if let Some(tracked) = self.requests.get_mut(&(c_id, Kind::Pages)) {
+            tracked.missing_pages.remove(&page_no);
+            if tracked.missing_pages.is_empty() {
+                self.requests.remove(&(c_id, Kind::Pages));
+            }
+        }
+        if page_no + 1 >= total {
+            self.requests.remove(&(c_id, Kind::AllPages));
+        } else if let Some(tracked) = self.requests.get_mut(&(c_id, Kind::AllPages)) {
+            tracked.deadline = Instant::now() + BASE_TIMEOUT;
+        }
+    }
+
+    fn rotate(&mut self, last_asked: GnomeId) -> (GnomeId, Option<GnomeId>) {
+        let candidates: Vec<GnomeId> = self
+            .neighbors
+            .iter()
+            .filter(|g_id| g_id.0 != last_asked.0)
+            .copied()
+            .collect();
+        if candidates.is_empty() {
+            let exclude = if last_asked.0 == GnomeId::any().0 {
+                None
+            } else {
+                Some(last_asked)
+            };
+            return (GnomeId::any(), exclude);
+        }
+        self.next_neighbor = (self.next_neighbor + 1) % candidates.len();
+        (candidates[self.next_neighbor], None)
+    }
+
+    /// Requests past their deadline, to be sent again or given up.
+    pub fn due(&mut self) -> Vec<Retry> {
+        let now = Instant::now();
+        let expired: Vec<(ContentID, Kind)> = self
+            .requests
+            .iter()
+            .filter(|(_key, tracked)| tracked.deadline <= now)
+            .map(|(key, _tracked)| *key)
+            .collect();
+        let mut retries = Vec::with_capacity(expired.len());
+        for (c_id, kind) in expired {
+            // Already removed when giving up on another request for this Content
+            let Some(mut tracked) = self.requests.remove(&(c_id, kind)) else {
+                continue;
+            };
+            if tracked.attempt >= MAX_ATTEMPTS {
+                eprintln!("CID-{} {:?} request unanswered, giving up", c_id, kind);
+                self.cancel(c_id);
+                retries.push(Retry::GiveUp(c_id));
+                continue;
+            }
+            let (target, exclude) = self.rotate(tracked.asked);
+            match kind {
+                Kind::Hashes => retries.push(Retry::Resend(
+                    target,
+                    exclude,
+                    SyncRequest::Hashes(c_id, vec![]),
+                )),
+                Kind::AllPages => retries.push(Retry::Resend(
+                    target,
+                    exclude,
+                    SyncRequest::AllPages(vec![c_id]),
+                )),
+                Kind::Pages => {
+                    let mut pages: Vec<u16> = tracked.missing_pages.iter().copied().collect();
+                    pages.sort();
+                    for chunk in pages.chunks(MAX_PAGES_PER_REQUEST) {
+                        retries.push(Retry::Resend(
+                            target,
+                            exclude,
+                            SyncRequest::Pages(c_id, tracked.d_type.unwrap(), chunk.to_vec()),
+                        ));
+                    }
+                }
+            }
+            tracked.deadline = now + BASE_TIMEOUT * 2u32.pow(tracked.attempt as u32);
+            tracked.attempt += 1;
+            tracked.asked = target;
+            self.requests.insert((c_id, kind), tracked);
+        }
+        retries
+    }
+
+    /// Stop tracking requests for given Content.
+    pub fn cancel(&mut self, c_id: ContentID) {
+        self.requests
+            .retain(|(r_c_id, _kind), _tracked| *r_c_id != c_id);
+    }
+
+    /// Stop tracking pages requested while reading given Content.
+    pub fn cancel_pages(&mut self, c_id: ContentID) {
+        self.requests.remove(&(c_id, Kind::Pages));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expire(tracker: &mut RequestTracker) {
+        let now = Instant::now();
+        for tracked in tracker.requests.values_mut() {
+            tracked.deadline = now;
+        }
+    }
+
+    #[test]
+    fn answered_requests_are_not_sent_again() {
+        let mut tracker = RequestTracker::new();
+        tracker.track_hashes(1, GnomeId(1));
+        tracker.track_all_pages(2, GnomeId(1));
+        tracker.track_pages(3, DataType::from(0), &[4, 5]);
+        tracker.hashes_received(1, 2, 2);
+        tracker.page_received(2, 9, 10);
+        tracker.page_received(3, 4, 10);
+        tracker.page_received(3, 5, 10);
+        assert!(tracker.requests.is_empty());
+
+        tracker.track_hashes(1, GnomeId(1));
+        tracker.track_pages(3, DataType::from(0), &[4]);
+        tracker.cancel_pages(3);
+        tracker.cancel(1);
+        expire(&mut tracker);
+        assert!(tracker.due().is_empty());
+    }
+
+    #[test]
+    fn retries_rotate_neighbors_then_give_up() {
+        let mut tracker = RequestTracker::new();
+        tracker.track_hashes(1, GnomeId(1));
+        tracker.track_all_pages(1, GnomeId(1));
+        assert!(tracker.due().is_empty());
+
+        // No Neighbors known, ask any but the one asked last time
+        expire(&mut tracker);
+        let retries = tracker.due();
+        assert_eq!(retries.len(), 2);
+        for retry in &retries {
+            assert!(matches!(
+                retry,
+                Retry::Resend(GnomeId(target), Some(GnomeId(1)), _) if *target == GnomeId::any().0
+            ));
+        }
+
+        tracker.heard_from(GnomeId(2));
+        tracker.heard_from(GnomeId(3));
+        tracker.heard_from(GnomeId(2));
+        expire(&mut tracker);
+        let asked: Vec<u64> = tracker
+            .due()
+            .into_iter()
+            .map(|retry| match retry {
+                Retry::Resend(target, None, _req) => target.0,
+                _other => panic!("Expected a resend to known Neighbor"),
+            })
+            .collect();
+        assert_eq!(asked.len(), 2);
+        assert!(asked.iter().all(|g_id| *g_id == 2 || *g_id == 3));
+
+        expire(&mut tracker);
+        assert_eq!(tracker.due().len(), 2);
+        // Both requests are given up at once
+        expire(&mut tracker);
+        let retries = tracker.due();
+        assert_eq!(retries.len(), 1);
+        assert!(matches!(retries[0], Retry::GiveUp(1)));
+        assert!(tracker.requests.is_empty());
+    }
+
+    #[test]
+    fn many_missing_pages_are_split() {
+        let mut tracker = RequestTracker::new();
+        let pages: Vec<u16> = (0..1000).collect();
+        tracker.track_pages(1, DataType::from(0), &pages);
+        tracker.page_received(1, 0, 1000);
+        expire(&mut tracker);
+        let mut requested = vec![];
+        for retry in tracker.due() {
+            let Retry::Resend(_target, _exclude, SyncRequest::Pages(1, _d_type, pages)) = retry
+            else {
+                panic!("Expected a Pages request");
+            };
+            assert!(pages.len() <= MAX_PAGES_PER_REQUEST);
+            requested.extend(pages);
+        }
+        assert_eq!(requested, (1..1000).collect::<Vec<u16>>());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::content::Content;
+use crate::content::ContentID;
+use crate::Data;
+use std::collections::VecDeque;
+
+// Once a message is applied to Datastore it's previous state is gone,
+// but message can later be proven invalid (see proof.rs).
+// So for every Swarm we keep a bounded log of operations that revert
+// recently applied messages. Every entry is keyed by message hash
+// (same as used for multi part messages) and Datastore root hash
+// it has produced, and also stores root hash from before message was applied.
+//
+// Entries have to form a chain, every entry's root_before has to be equal
+// to previous entry's root_after. If a message changed Datastore without
+// producing any inverse operations, or chain is broken for any other reason,
+// we can no longer revert beyond that point and log is cleared.
+// So every message type that changes Datastore has to record how to undo it.
+//
+// Restore operations hold entire Contents, so log is bounded by memory
+// it takes, counted in pages same as Datastore does, and oldest entries
+// are dropped first. A message that does not fit on it's own can not be
+// reverted, so log is cleared.
+const MAX_UNDO_ENTRIES: usize = 128;
+const MAX_UNDO_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum UndoOp {
+    PopData(ContentID),
+    InsertData(ContentID, u16, Data),
+    UpdateData(ContentID, u16, Data),
+    Restore(ContentID, Content),
+    // Appended Content is always the last one
+    RemoveLastContent(ContentID),
+}
+
+impl UndoOp {
+    pub fn c_id(&self) -> ContentID {
+        match self {
+            Self::PopData(c_id)
+            | Self::InsertData(c_id, _, _)
+            | Self::UpdateData(c_id, _, _)
+            | Self::Restore(c_id, _)
+            | Self::RemoveLastContent(c_id) => *c_id,
+        }
+    }
+
+    // Memory taken by this operation, approximately
+    fn bytes(&self) -> usize {
+        match self {
+            Self::PopData(_c_id) | Self::RemoveLastContent(_c_id) => 0,
+            Self::InsertData(_c_id, _d_id, data) | Self::UpdateData(_c_id, _d_id, data) => {
+                data.len()
+            }
+            Self::Restore(_c_id, content) => {
+                1024 * (1 + content.used_memory_pages() + (content.len() as usize >> 7))
+            }
+        }
+    }
+}
+
+pub struct UndoEntry {
+    pub msg_hash: u64,
+    pub root_before: u64,
+    pub root_after: u64,
+    // In order of application, have to be reverted from last one
+    pub ops: Vec<UndoOp>,
+    bytes: usize,
+}
+
+pub struct UndoLog {
+    entries: VecDeque<UndoEntry>,
+    bytes: usize,
+}
+
+impl UndoLog {
+    pub fn new() -> Self {
+        UndoLog {
+            entries: VecDeque::with_capacity(MAX_UNDO_ENTRIES),
+            bytes: 0,
+        }
+    }
+
+    pub fn record(&mut self, msg_hash: u64, root_before: u64, root_after: u64, ops: Vec<UndoOp>) {
+        if root_before == root_after {
+            return;
+        }
+        if ops.is_empty() {
+            if !self.entries.is_empty() {
+                eprintln!(
+                    "Message {} can not be reverted, clearing undo log",
+                    msg_hash
+                );
+                self.clear();
+            }
+            return;
+        }
+        if self
+            .entries
+            .back()
+            .is_some_and(|last| last.root_after != root_before)
+        {
+            eprintln!("Undo log out of sync with Datastore, clearing");
+            self.clear();
+        }
+        let bytes = ops.iter().map(|op| op.bytes()).sum();
+        if bytes > MAX_UNDO_BYTES {
+            eprintln!("Message {} is too large to be reverted", msg_hash);
+            self.clear();
+            return;
+        }
+        while self.entries.len() >= MAX_UNDO_ENTRIES || self.bytes + bytes > MAX_UNDO_BYTES {
+            let Some(oldest) = self.entries.pop_front() else {
+                break;
+            };
+            self.bytes -= oldest.bytes;
+        }
+        self.bytes += bytes;
+        self.entries.push_back(UndoEntry {
+            msg_hash,
+            root_before,
+            root_after,
+            ops,
+            bytes,
+        });
+    }
+
+    /// Root hash Datastore had before given message was applied.
+    pub fn root_before(&self, msg_hash: u64) -> Option<u64> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.msg_hash == msg_hash)
+            .map(|entry| entry.root_before)
+    }
+
+    /// Remove and return entries, newest first, that need to be reverted
+    /// in order to get from current_root to root.
+    pub fn take_until(&mut self, current_root: u64, root: u64) -> Option<Vec<UndoEntry>> {
+        if current_root == root {
+            return Some(vec![]);
+        }
+        if self.entries.back()?.root_after != current_root {
+            return None;
+        }
+        let idx = self
+            .entries
+            .iter()
+            .rposition(|entry| entry.root_before == root)?;
+        let taken: Vec<UndoEntry> = self.entries.drain(idx..).rev().collect();
+        self.bytes -= taken.iter().map(|entry| entry.bytes).sum::<usize>();
+        Some(taken)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(c_id: ContentID, count: usize) -> Vec<UndoOp> {
+        (0..count)
+            .map(|d_id| UndoOp::UpdateData(c_id, d_id as u16, Data::new(vec![1; 1024]).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn entries_are_taken_newest_first() {
+        let mut log = UndoLog::new();
+        log.record(1, 10, 11, update(1, 1));
+        log.record(2, 11, 12, vec![UndoOp::PopData(2)]);
+        log.record(3, 12, 13, update(3, 2));
+        assert_eq!(log.root_before(2), Some(11));
+        assert_eq!(log.root_before(4), None);
+
+        assert!(log.take_until(12, 11).is_none());
+        assert_eq!(log.take_until(13, 13).unwrap().len(), 0);
+        let taken = log.take_until(13, 11).unwrap();
+        let hashes: Vec<u64> = taken.iter().map(|entry| entry.msg_hash).collect();
+        assert_eq!(hashes, vec![3, 2]);
+        assert_eq!(log.bytes, 1024);
+        assert_eq!(log.take_until(11, 10).unwrap().len(), 1);
+        assert_eq!(log.bytes, 0);
+    }
+
+    #[test]
+    fn broken_chain_clears_log() {
+        let mut log = UndoLog::new();
+        log.record(1, 10, 11, update(1, 1));
+        // Root did not change, nothing to record
+        log.record(2, 11, 11, vec![]);
+        assert_eq!(log.entries.len(), 1);
+        // Can not be reverted
+        log.record(3, 11, 12, vec![]);
+        assert!(log.entries.is_empty());
+
+        log.record(4, 12, 13, update(1, 1));
+        log.record(5, 20, 21, update(1, 1));
+        assert_eq!(log.entries.len(), 1);
+        assert_eq!(log.root_before(5), Some(20));
+        assert_eq!(log.bytes, 1024);
+    }
+
+    #[test]
+    fn log_is_bounded_by_memory() {
+        let mut log = UndoLog::new();
+        let per_entry = 256;
+        let fitting = MAX_UNDO_BYTES / (per_entry * 1024);
+        for i in 0..fitting as u64 + 2 {
+            log.record(i, i, i + 1, update(1, per_entry));
+        }
+        assert_eq!(log.entries.len(), fitting);
+        assert_eq!(log.bytes, fitting * per_entry * 1024);
+        assert_eq!(log.root_before(0), None);
+        assert_eq!(log.root_before(2), Some(2));
+
+        // Too large on it's own
+        let next = fitting as u64 + 2;
+        log.record(next, next, next + 1, update(1, MAX_UNDO_BYTES / 1024 + 1));
+        assert!(log.entries.is_empty());
+        assert_eq!(log.bytes, 0);
+    }
+}
@@ -0,0 +1,10 @@
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Seconds since UNIX epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}